@@ -0,0 +1,177 @@
+use coolcoin_lib::core::block::{BlockHeader, HeaderHasher};
+use coolcoin_lib::core::hash::{merkle_tree_from_transactions, MerkleBuilder};
+use coolcoin_lib::core::miner::Miner;
+use coolcoin_lib::core::peer_connection::{encode, MessageDecoder, PeerMessage};
+use coolcoin_lib::core::transaction::{OutputIndex, TransactionInput, TransactionOutput};
+use coolcoin_lib::core::utxo_pool::UtxoView;
+use coolcoin_lib::core::{
+    Address, Block, BlockValidator, BlockchainManager, Transaction, UtxoPool,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::sync::Arc;
+
+const NUM_TRANSACTIONS: usize = 1000;
+
+/// The blocks that most benchmarks work on: the first one pays to many outputs,
+/// and the second one spends each of them in a separate transaction.
+struct Blocks {
+    funding: Block,
+    spending: Block,
+}
+
+fn blocks() -> Blocks {
+    let genesis = BlockchainManager::genesis_block();
+    let address = Address::new("alice".to_string());
+    let outputs = (0..NUM_TRANSACTIONS)
+        .map(|_| TransactionOutput::new(address.clone(), 1.into()))
+        .collect();
+    let coinbase = Transaction::new(vec![TransactionInput::new_coinbase()], outputs, 0).unwrap();
+    let header = BlockHeader::new(
+        *genesis.id(),
+        merkle_tree_from_transactions(std::slice::from_ref(&coinbase)),
+        genesis.header().timestamp() + 1,
+        genesis.header().difficulty_target(),
+        0,
+    );
+    let funding = Block::new(header, vec![coinbase.clone()]);
+
+    let transactions = (0..NUM_TRANSACTIONS)
+        .map(|index| {
+            Transaction::new(
+                vec![TransactionInput::new(
+                    *coinbase.id(),
+                    OutputIndex::new(index as i32),
+                )],
+                vec![TransactionOutput::new(
+                    Address::new(format!("bob{}", index)),
+                    1.into(),
+                )],
+                0,
+            )
+            .unwrap()
+        })
+        .collect();
+    let spending = Miner::mine_block(
+        &funding,
+        2,
+        transactions,
+        &address,
+        50.into(),
+        funding.header().timestamp() + 1,
+    );
+    Blocks { funding, spending }
+}
+
+fn proof_of_work(c: &mut Criterion) {
+    let genesis = BlockchainManager::genesis_block();
+    let header = genesis.header();
+    c.bench_function("pow", |b| {
+        b.iter(|| {
+            Miner::pow(
+                header.previous_block_hash(),
+                header.merkle_root(),
+                black_box(header.timestamp()),
+                header.difficulty_target(),
+            )
+        })
+    });
+}
+
+fn header_hashing(c: &mut Criterion) {
+    let header = BlockchainManager::genesis_block().header().clone();
+    c.bench_function("header_hash", |b| {
+        b.iter(|| {
+            BlockHeader::new(
+                *header.previous_block_hash(),
+                header.merkle_root().clone(),
+                header.timestamp(),
+                header.difficulty_target(),
+                black_box(header.nonce()),
+            )
+        })
+    });
+    let hasher = HeaderHasher::new(&header);
+    c.bench_function("header_hash_midstate", |b| {
+        b.iter(|| hasher.hash(black_box(header.nonce())))
+    });
+}
+
+fn merkle_root(c: &mut Criterion) {
+    let Blocks { spending, .. } = blocks();
+    let transactions = spending.transactions();
+    c.bench_function("merkle_root_1k", |b| {
+        b.iter(|| merkle_tree_from_transactions(black_box(transactions)))
+    });
+    let builder = MerkleBuilder::new(transactions);
+    c.bench_function("merkle_root_1k_new_coinbase", |b| {
+        b.iter(|| builder.merkle_root(black_box(&transactions[0])))
+    });
+}
+
+fn message_encoding(c: &mut Criterion) {
+    let Blocks { spending, .. } = blocks();
+    let message = PeerMessage::RelayBlock(Arc::new(spending));
+    c.bench_function("message_encode_1k", |b| {
+        b.iter(|| encode(black_box(&message)))
+    });
+    let encoded = encode(&message);
+    c.bench_function("message_decode_1k", |b| {
+        b.iter(|| {
+            let mut decoder = MessageDecoder::default();
+            let mut reader = &encoded[..];
+            loop {
+                decoder.read_from(&mut reader).unwrap();
+                if let Some(message) = decoder.next_message().unwrap() {
+                    break message;
+                }
+            }
+        })
+    });
+}
+
+fn block_validation(c: &mut Criterion) {
+    let Blocks { funding, spending } = blocks();
+    let mut utxo_pool = UtxoPool::new();
+    utxo_pool.connect_block(&funding, 1).unwrap();
+    let current_time = spending.header().timestamp();
+    // The same checks as for the blocks submitted by the miners, without the chain context.
+    c.bench_function("validate_block_1k", |b| {
+        b.iter(|| {
+            BlockValidator::validate_no_context(black_box(&spending), current_time)
+                .and_then(|()| UtxoView::new(&utxo_pool).connect_block(&spending, 2))
+                .unwrap()
+        })
+    });
+}
+
+fn utxo_updates(c: &mut Criterion) {
+    let Blocks { funding, spending } = blocks();
+    let mut utxo_pool = UtxoPool::new();
+    utxo_pool.connect_block(&funding, 1).unwrap();
+    c.bench_function("utxo_connect_disconnect_1k", |b| {
+        b.iter(|| {
+            utxo_pool.connect_block(black_box(&spending), 2).unwrap();
+            utxo_pool.disconnect_block(&spending);
+        })
+    });
+    // The way the blocks are connected when the active blockchain changes.
+    c.bench_function("utxo_view_apply_disconnect_1k", |b| {
+        b.iter(|| {
+            let mut view = UtxoView::new(&utxo_pool);
+            view.connect_block(black_box(&spending), 2).unwrap();
+            utxo_pool.apply_changes(view.into_changes());
+            utxo_pool.disconnect_block(&spending);
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    proof_of_work,
+    header_hashing,
+    merkle_root,
+    message_encoding,
+    block_validation,
+    utxo_updates
+);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+#![no_main]
+use coolcoin_lib::core::{Block, BlockValidator};
+use libfuzzer_sys::fuzz_target;
+
+// The raw blocks, e.g. from decodeblock, and the checks that run before the block is
+// connected to the chain.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(block) = Block::deserialize(data) {
+        let decoded = Block::deserialize(&block.serialize()).unwrap();
+        assert_eq!(decoded.id(), block.id());
+        let _ = BlockValidator::validate_no_context(&block, block.header().timestamp());
+    }
+});
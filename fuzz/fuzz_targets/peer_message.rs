@@ -0,0 +1,12 @@
+#![no_main]
+use coolcoin_lib::core::peer_connection::MessageDecoder;
+use libfuzzer_sys::fuzz_target;
+
+// The bytes received from a peer, which are either decoded to messages or rejected.
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = MessageDecoder::default();
+    let mut reader = data;
+    while decoder.read_from(&mut reader).unwrap() > 0 {
+        while let Ok(Some(_)) = decoder.next_message() {}
+    }
+});
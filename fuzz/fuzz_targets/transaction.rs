@@ -0,0 +1,12 @@
+#![no_main]
+use coolcoin_lib::core::Transaction;
+use libfuzzer_sys::fuzz_target;
+
+// The raw transactions, e.g. from sendrawtransaction and decoderawtransaction.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(transaction) = Transaction::deserialize(data) {
+        let decoded = Transaction::deserialize(&transaction.serialize()).unwrap();
+        assert_eq!(decoded.id(), transaction.id());
+        transaction.is_coinbase();
+    }
+});
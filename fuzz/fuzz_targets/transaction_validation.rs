@@ -0,0 +1,35 @@
+#![no_main]
+use coolcoin_lib::core::transaction::TransactionOutput;
+use coolcoin_lib::core::validation::{
+    SpentOutput, TransactionPoolContext, TransactionValidator, COINBASE_MATURITY,
+};
+use coolcoin_lib::core::{Address, FeeRate, Transaction};
+use libfuzzer_sys::fuzz_target;
+
+// Well-formed transactions that spend outputs of any amounts, as validated before they are
+// accepted to the transaction pool.
+fuzz_target!(|input: (Transaction, Vec<Option<i64>>, u32, u32)| {
+    let (transaction, spent_amounts, next_block_height, median_time_past) = input;
+    let decoded = Transaction::deserialize(&transaction.serialize()).unwrap();
+    assert_eq!(decoded.id(), transaction.id());
+
+    let spent_outputs = transaction
+        .inputs()
+        .iter()
+        .zip(spent_amounts.into_iter().chain(std::iter::repeat(None)))
+        .map(|(_, amount)| {
+            amount.map(|amount| SpentOutput {
+                output: TransactionOutput::new(Address::new("fuzz".to_string()), amount.into()),
+                coinbase_height: None,
+            })
+        })
+        .collect();
+    let context = TransactionPoolContext::new(
+        spent_outputs,
+        next_block_height,
+        median_time_past,
+        FeeRate::zero(),
+        COINBASE_MATURITY,
+    );
+    let _ = TransactionValidator::validate_transaction_pool_context(&transaction, &context);
+});
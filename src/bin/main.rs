@@ -2,18 +2,46 @@ use clap::{App, AppSettings};
 use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let matches = App::new("coolcoin")
+    let app = App::new("coolcoin")
         .about("Coolcoin blockchain CLI apps.")
         .setting(AppSettings::SubcommandRequiredElseHelp)
+        .args(coolcoin_lib::logging::logging_args())
+        .arg(coolcoin_lib::logging::hash_display_arg())
         .subcommand(coolcoin_lib::daemon_command::daemon_command())
         .subcommand(coolcoin_lib::client_command::client_command())
-        .get_matches();
+        .subcommand(coolcoin_lib::miner_command::miner_command())
+        .subcommand(coolcoin_lib::regtest_command::regtest_command())
+        .subcommand(coolcoin_lib::replay_command::replay_command())
+        .subcommand(coolcoin_lib::genesis_command::genesis_command());
+    let args = coolcoin_lib::config_file::with_config_file(&app, std::env::args().collect())?;
+    let matches = app.get_matches_from(args);
+    let log_options = coolcoin_lib::logging::LogOptions::parse(&matches)?;
+    coolcoin_lib::logging::init_logging(&log_options)?;
+    coolcoin_lib::core::hash_display::HashDisplay::set_global(
+        matches.value_of("hash_display").unwrap().parse()?,
+    );
 
-    if let Some(ref matches) = matches.subcommand_matches("daemon") {
-        let options = coolcoin_lib::daemon_command::DaemonCliOptions::parse(*matches)?;
+    if let Some(matches) = matches.subcommand_matches("daemon") {
+        let options = coolcoin_lib::daemon_command::DaemonCliOptions::parse(matches)?;
         coolcoin_lib::daemon_command::run_daemon(&options)
-    } else if let Some(ref matches) = matches.subcommand_matches("client") {
-        coolcoin_lib::client_command::run_client(*matches)
+    } else if let Some(matches) = matches.subcommand_matches("client") {
+        coolcoin_lib::client_command::run_client(matches)
+    } else if let Some(matches) = matches.subcommand_matches("miner") {
+        if let Some(matches) = matches.subcommand_matches("bench") {
+            let options = coolcoin_lib::miner_command::MinerBenchCliOptions::parse(matches)?;
+            return coolcoin_lib::miner_command::run_miner_bench(&options);
+        }
+        let options = coolcoin_lib::miner_command::MinerCliOptions::parse(matches)?;
+        coolcoin_lib::miner_command::run_miner(&options)
+    } else if let Some(matches) = matches.subcommand_matches("regtest") {
+        let options = coolcoin_lib::regtest_command::RegtestCliOptions::parse(matches)?;
+        coolcoin_lib::regtest_command::run_regtest(&options)
+    } else if let Some(matches) = matches.subcommand_matches("replay") {
+        let options = coolcoin_lib::replay_command::ReplayCliOptions::parse(matches)?;
+        coolcoin_lib::replay_command::run_replay(&options)
+    } else if let Some(matches) = matches.subcommand_matches("genesis") {
+        let options = coolcoin_lib::genesis_command::GenesisCliOptions::parse(matches)?;
+        coolcoin_lib::genesis_command::run_genesis(&options)
     } else {
         panic!("Should report help.");
     }
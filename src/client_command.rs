@@ -1,17 +1,28 @@
+use crate::client_shell::run_shell;
+use crate::client_top::run_top;
+use crate::core::account_balances::{AccountBalance, AccountBalances};
+use crate::core::address_history::history_csv;
+use crate::core::ban_list::{SetBanCommand, Subnet};
 use crate::core::block::BlockHash;
-use crate::core::coolcoin_network::NetworkParams;
+use crate::core::chain_export::ChainExportWriter;
+use crate::core::graphviz::{self, ImageFormat};
 use crate::core::hash::from_hex;
+use crate::core::hash_display::HashDisplay;
+use crate::core::json_rpc::{JsonRpcHandler, JsonRpcRequest};
 use crate::core::peer_connection::PeerMessage;
+use crate::core::peer_log::PeerEvent;
 use crate::core::transaction::{OutputIndex, TransactionId, TransactionInput, TransactionOutput};
-use crate::core::{
-    as_hex, Address, Block, BlockTree, BlockchainManager, Coolcoin, CoolcoinNetwork, CoolcoinNode,
-    PeerConnection, Sha256, Transaction,
-};
-use clap::{App, Arg, ArgMatches};
-use std::collections::HashMap;
+use crate::core::wallet::Wallet;
+use crate::core::{Address, Block, BlockchainManager, Coolcoin, CoolcoinClient, Transaction};
+use clap::{App, AppSettings, Arg, ArgMatches};
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fs;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 
 pub struct ClientCliOptions {
     server: String,
@@ -33,7 +44,26 @@ impl ClientCliOptions {
 
 fn getfullblockchain_subcommand() -> App<'static> {
     App::new("getfullblockchain")
-        .about("Retrieves the full block from the server (including non-active chains).")
+        .about("Retrieves the full block from the server (including non-active chains), and writes it as a Graphviz graph to ./blockchain.dot.")
+        .arg(Arg::new("from_height")
+            .long("from_height")
+            .value_name("HEIGHT")
+            .about("The lowest height of the blocks in the graph, e.g. to render a part of a long chain.")
+            .takes_value(true)
+            .required(false))
+        .arg(Arg::new("to_height")
+            .long("to_height")
+            .value_name("HEIGHT")
+            .about("The highest height of the blocks in the graph.")
+            .takes_value(true)
+            .required(false))
+        .arg(Arg::new("render")
+            .long("render")
+            .value_name("FORMAT")
+            .about("Also renders the graph to ./blockchain.svg or ./blockchain.png, with Graphviz's dot if it's installed. Without it, only SVG can be rendered.")
+            .possible_values(&["svg", "png"])
+            .takes_value(true)
+            .required(false))
 }
 
 fn getblock_subcommand() -> App<'static> {
@@ -42,23 +72,404 @@ fn getblock_subcommand() -> App<'static> {
         .arg(Arg::new("BLOCK_HASH").required(true).index(1))
 }
 
+fn getblockheader_subcommand() -> App<'static> {
+    App::new("getblockheader")
+        .about("Retrieves the block header from the server, with the block's height, the number of confirmations and the hash of the next block.")
+        .arg(Arg::new("BLOCK_HASH").required(true).index(1))
+}
+
+fn gettransaction_subcommand() -> App<'static> {
+    App::new("gettransaction")
+        .about("Retrieves the transaction from the server's transaction pool or active blockchain, with the number of confirmations.")
+        .arg(Arg::new("TXID").required(true).index(1))
+}
+
 fn sendrawtransaction_subcommand() -> App<'static> {
     App::new("sendrawtransaction")
-        .about("Sends the given raw transaction to the server.")
-        .arg(Arg::new("inputs")
+        .about("Sends the given raw transaction to the server, and prints its id if it has been accepted.")
+        .arg(Arg::new("HEX")
+            .about("The hex-encoded transaction. Alternatively, the transaction can be given by its inputs and outputs.")
+            .index(1)
+            .conflicts_with_all(&["inputs", "outputs", "locktime"])
+            .required(false))
+        .args(raw_transaction_args())
+        .mut_arg("inputs", |arg| arg.required(false).required_unless_present("HEX"))
+        .mut_arg("outputs", |arg| arg.required(false).required_unless_present("HEX"))
+}
+
+fn createrawtransaction_subcommand() -> App<'static> {
+    App::new("createrawtransaction")
+        .about("Prints the hex-encoded unsigned transaction with the given inputs and outputs, without sending it to the server.")
+        .args(raw_transaction_args())
+}
+
+fn raw_transaction_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("inputs")
             .long("inputs")
             .about("The list of inputs as references to the unspent outputs. Format: <TXID>:<OutputIndex> ")
             .multiple_occurrences(true)
             .takes_value(true)
             .use_delimiter(true)
-            .required(true))
-        .arg(Arg::new("outputs")
+            .required(true),
+        Arg::new("outputs")
             .long("outputs")
             .use_delimiter(true)
             .about("The list of outputs and amounts. Format: <CoolcoinAddress>:<Amount> ")
             .multiple_occurrences(true)
             .takes_value(true)
+            .required(true),
+        Arg::new("locktime")
+            .long("locktime")
+            .about("The block height or the unix timestamp (if greater than 500000000) before which the transaction can't be confirmed. 0 means the transaction can be confirmed immediately.")
+            .takes_value(true)
+            .required(false)
+            .default_value("0"),
+    ]
+}
+
+fn estimatesmartfee_subcommand() -> App<'static> {
+    App::new("estimatesmartfee")
+        .about("Estimates the fee rate for a transaction to be confirmed within the target number of blocks.")
+        .arg(Arg::new("TARGET_BLOCKS").required(true).index(1))
+}
+
+fn getblockcount_subcommand() -> App<'static> {
+    App::new("getblockcount")
+        .about("Retrieves the height of the active blockchain, i.e. the number of blocks after the genesis block.")
+}
+
+fn getbestblockhash_subcommand() -> App<'static> {
+    App::new("getbestblockhash").about("Retrieves the hash of the tip of the active blockchain.")
+}
+
+fn getblockhash_subcommand() -> App<'static> {
+    App::new("getblockhash")
+        .about("Retrieves the hash of the block at the given height in the active blockchain.")
+        .arg(Arg::new("HEIGHT").required(true).index(1))
+}
+
+fn listunspent_subcommand() -> App<'static> {
+    App::new("listunspent")
+        .about("Retrieves the unspent transaction outputs with the number of confirmations in the given range, optionally only the ones sent to the given addresses.")
+        .arg(Arg::new("min_confirmations")
+            .long("min_confirmations")
+            .takes_value(true)
+            .required(false)
+            .default_value("1"))
+        .arg(Arg::new("max_confirmations")
+            .long("max_confirmations")
+            .takes_value(true)
+            .required(false)
+            .default_value("9999999"))
+        .arg(Arg::new("addresses")
+            .long("addresses")
+            .about("The list of addresses. Format: <CoolcoinAddress>,<CoolcoinAddress>")
+            .multiple_occurrences(true)
+            .takes_value(true)
+            .use_delimiter(true)
+            .required(false))
+}
+
+fn getutxos_subcommand() -> App<'static> {
+    App::new("getutxos")
+        .about("Retrieves the server's UTXO set, optionally only the outputs sent to the given addresses, with at least the given amount.")
+        .arg(Arg::new("addresses")
+            .long("addresses")
+            .about("The list of addresses. Format: <CoolcoinAddress>,<CoolcoinAddress>")
+            .multiple_occurrences(true)
+            .takes_value(true)
+            .use_delimiter(true)
+            .required(false))
+        .arg(Arg::new("min_amount")
+            .long("min_amount")
+            .takes_value(true)
+            .required(false)
+            .default_value("0"))
+}
+
+fn getbalances_subcommand() -> App<'static> {
+    App::new("getbalances")
+        .about("Retrieves the balances of the addresses with unspent outputs, optionally only the given ones. Immature coinbase outputs are shown separately.")
+        .arg(Arg::new("min_confirmations")
+            .long("min_confirmations")
+            .takes_value(true)
+            .required(false)
+            .default_value("1"))
+        .arg(Arg::new("addresses")
+            .long("addresses")
+            .about("The list of addresses. Format: <CoolcoinAddress>,<CoolcoinAddress>")
+            .multiple_occurrences(true)
+            .takes_value(true)
+            .use_delimiter(true)
+            .required(false))
+        .arg(Arg::new("json")
+            .long("json")
+            .about("If true, the balances are printed as JSON rather than a table.")
+            .takes_value(false)
+            .required(false))
+}
+
+fn listtransactions_subcommand() -> App<'static> {
+    App::new("listtransactions")
+        .about("Retrieves the most recent transactions that send coins to, or spend the outputs of, the address, with the change of its balance and the number of confirmations. Unconfirmed transactions have zero confirmations.")
+        .arg(Arg::new("ADDRESS").required(true).index(1))
+        .arg(Arg::new("count")
+            .long("count")
+            .about("Maximum number of transactions.")
+            .takes_value(true)
+            .required(false)
+            .default_value("10"))
+}
+
+fn exporthistory_subcommand() -> App<'static> {
+    App::new("exporthistory")
+        .about("Exports all transactions of the address as CSV, with the credits, debits and the running balance, for spreadsheet analysis.")
+        .arg(Arg::new("address")
+            .long("address")
+            .value_name("ADDRESS")
+            .takes_value(true)
             .required(true))
+        .arg(Arg::new("output")
+            .long("output")
+            .value_name("PATH")
+            .about("File to which the CSV is written. Printed if not given.")
+            .takes_value(true)
+            .required(false))
+}
+
+fn exportchain_subcommand() -> App<'static> {
+    App::new("exportchain")
+        .about("Exports the active blockchain as JSON, with the heights, confirmations, transactions, inputs and outputs of the blocks, for the analysis with jq or pandas. The schema is described in the README.")
+        .arg(Arg::new("output")
+            .long("output")
+            .value_name("PATH")
+            .about("File to which the JSON is written.")
+            .takes_value(true)
+            .required(true))
+}
+
+fn decoderawtransaction_subcommand() -> App<'static> {
+    App::new("decoderawtransaction")
+        .about("Decodes the hex-encoded transaction, e.g. captured from the network, without contacting the server.")
+        .arg(Arg::new("HEX").required(true).index(1))
+}
+
+fn decodeblock_subcommand() -> App<'static> {
+    App::new("decodeblock")
+        .about("Decodes the hex-encoded block, e.g. captured from the network, without contacting the server.")
+        .arg(Arg::new("HEX").required(true).index(1))
+}
+
+fn wallet_subcommand() -> App<'static> {
+    App::new("wallet")
+        .about("Manages the addresses kept in the wallet file, and spends the coins sent to them.")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::new("wallet")
+            .long("wallet")
+            .value_name("PATH")
+            .about("The wallet file.")
+            .takes_value(true)
+            .required(false)
+            .default_value("./wallet.json"))
+        .subcommand(App::new("create")
+            .about("Creates the wallet with a new address."))
+        .subcommand(App::new("address")
+            .about("Adds a new address to the wallet, at which it can receive coins."))
+        .subcommand(App::new("balance")
+            .about("Retrieves the total balance of the wallet's addresses."))
+        .subcommand(App::new("send")
+            .about("Sends the amount from the wallet's confirmed outputs to the address, and the change back to the wallet.")
+            .arg(Arg::new("ADDRESS").required(true).index(1))
+            .arg(Arg::new("AMOUNT").required(true).index(2))
+            .arg(Arg::new("fee")
+                .long("fee")
+                .value_name("COOLCOINS")
+                .about("Fee paid to the miner.")
+                .takes_value(true)
+                .required(false)
+                .default_value("0")))
+        .subcommand(App::new("history")
+            .about("Retrieves the most recent transactions of the wallet, with the change of its balance.")
+            .arg(Arg::new("count")
+                .long("count")
+                .about("Maximum number of transactions.")
+                .takes_value(true)
+                .required(false)
+                .default_value("10")))
+}
+
+fn getinfo_subcommand() -> App<'static> {
+    App::new("getinfo").about("Retrieves a summary of the server's state, including the version, the height, the number of peers, the transaction pool size and the balance of its coinbase address.")
+}
+
+fn uptime_subcommand() -> App<'static> {
+    App::new("uptime").about("Retrieves the number of seconds since the server has started.")
+}
+
+fn getdifficulty_subcommand() -> App<'static> {
+    App::new("getdifficulty").about("Retrieves the difficulty of the tip of the active blockchain as a multiple of the minimum difficulty.")
+}
+
+fn getchaintips_subcommand() -> App<'static> {
+    App::new("getchaintips").about("Retrieves the tips of all known chains, with their height, the number of blocks that are not in the active blockchain, and their status.")
+}
+
+fn listbanned_subcommand() -> App<'static> {
+    App::new("listbanned")
+        .about("Retrieves the banned subnets, from which the server doesn't accept connections.")
+}
+
+fn setban_subcommand() -> App<'static> {
+    App::new("setban")
+        .about("Bans or unbans the subnet, e.g. 192.168.1.0/24 or a single address. Only allowed for the clients on the same machine as the server.")
+        .arg(Arg::new("SUBNET").required(true).index(1))
+        .arg(Arg::new("COMMAND")
+            .required(true)
+            .index(2)
+            .possible_values(&["add", "remove"]))
+        .arg(Arg::new("duration")
+            .long("duration")
+            .value_name("SECONDS")
+            .about("Duration of the ban.")
+            .takes_value(true)
+            .required(false)
+            .default_value("86400"))
+}
+
+fn prioritisetransaction_subcommand() -> App<'static> {
+    App::new("prioritisetransaction")
+        .about("Adds the fee delta to the transaction when the server selects transactions for the next block, without changing the fee it pays. Only allowed for the clients on the same machine as the server.")
+        .arg(Arg::new("TXID").required(true).index(1))
+        .arg(Arg::new("FEE_DELTA")
+            .about("Amount added to the fee, can be negative.")
+            .required(true)
+            .index(2)
+            .allow_hyphen_values(true))
+}
+
+fn rpc_subcommand() -> App<'static> {
+    App::new("rpc")
+        .about("Sends the JSON-RPC request with the given method and parameters, and prints the response as is. Parameters are parsed as JSON, or taken as strings if they aren't valid JSON, e.g. rpc setban 10.0.0.0/8 add 3600. Strings that are valid JSON must be quoted, e.g. '\"1234\"'.")
+        .arg(Arg::new("METHOD").required(true).index(1))
+        .arg(Arg::new("PARAMS")
+            .required(false)
+            .index(2)
+            .multiple_values(true)
+            .allow_hyphen_values(true))
+}
+
+fn shell_subcommand() -> App<'static> {
+    App::new("shell").about("Reads the JSON-RPC requests from the terminal, in the same format as the rpc subcommand, and prints their results, sending all of them over a single connection. Method names are completed with Tab, and the history is kept in ~/.coolcoin_history.")
+}
+
+fn watch_subcommand() -> App<'static> {
+    App::new("watch")
+        .about("Prints a line for each block connected to the server's active blockchain, with its height, the end of its hash, the number of transactions and the coinbase address, until interrupted.")
+        .arg(Arg::new("interval")
+            .long("interval")
+            .value_name("SECONDS")
+            .about("Time between the checks for new blocks.")
+            .takes_value(true)
+            .required(false)
+            .default_value("1"))
+}
+
+fn top_subcommand() -> App<'static> {
+    App::new("top")
+        .about("Shows the live state of the server in the terminal: the tip, the recent blocks, the transaction pool and the connected peers with their traffic, until interrupted.")
+        .arg(Arg::new("interval")
+            .long("interval")
+            .value_name("SECONDS")
+            .about("Time between the refreshes.")
+            .takes_value(true)
+            .required(false)
+            .default_value("1"))
+}
+
+fn getpeerinfo_subcommand() -> App<'static> {
+    App::new("getpeerinfo")
+        .about("Lists the peers and clients connected to the server, with the time since they have connected and sent the last message, and the bytes and messages exchanged with them by message type.")
+}
+
+fn peers_subcommand() -> App<'static> {
+    App::new("peers")
+        .about("Lists the peers and clients connected to the server, like getpeerinfo, or the server's log of the connects, disconnects and bans with their reasons.")
+        .arg(Arg::new("history")
+            .long("history")
+            .about("Shows the log of the connects, disconnects and bans instead of the current peers.")
+            .takes_value(false)
+            .required(false))
+        .arg(Arg::new("count")
+            .long("count")
+            .value_name("COUNT")
+            .about("The number of the most recent events of the log to show. All of them by default.")
+            .requires("history")
+            .takes_value(true)
+            .required(false))
+}
+
+fn dumpstate_subcommand() -> App<'static> {
+    App::new("dumpstate")
+        .about("Makes the server write a JSON snapshot of its chain tips, active blockchain, transaction pool, peers and bans to the file, e.g. to attach it to a bug report. Only allowed for the clients on the same machine as the server.")
+        .arg(Arg::new("FILE_NAME")
+            .about("Name of the file in the server's data directory to write. state_<time>.json by default.")
+            .required(false)
+            .index(1))
+}
+
+fn logging_subcommand() -> App<'static> {
+    App::new("logging")
+        .about("Changes the levels of the server's log categories while it runs, and prints the level of each category. Only allowed for the clients on the same machine as the server.")
+        .arg(Arg::new("include")
+            .long("include")
+            .value_name("CATEGORY[=LEVEL]")
+            .about("Categories whose level is set, e.g. net=debug,mempool. The category without the level is logged in full.")
+            .use_delimiter(true)
+            .takes_value(true)
+            .required(false))
+        .arg(Arg::new("exclude")
+            .long("exclude")
+            .value_name("CATEGORY")
+            .about("Categories that return to the server's default level, e.g. net,mempool.")
+            .use_delimiter(true)
+            .takes_value(true)
+            .required(false))
+}
+
+fn getforkstats_subcommand() -> App<'static> {
+    App::new("getforkstats")
+        .about("Shows the number of the reorgs and their depth, and of the stale blocks, i.e. the valid blocks received by the server that haven't become a part of its active blockchain.")
+        .arg(Arg::new("NBLOCKS")
+            .about("The number of the most recent blocks of the active blockchain over which the statistics are computed. All blocks by default.")
+            .required(false)
+            .index(1))
+}
+
+fn getnettotals_subcommand() -> App<'static> {
+    App::new("getnettotals")
+        .about("Shows the bytes and messages by type exchanged with all peers and clients since the server has started.")
+}
+
+fn stop_subcommand() -> App<'static> {
+    App::new("stop").about(
+        "Shuts the server down. Only allowed for the clients on the same machine as the server.",
+    )
+}
+
+fn getmininginfo_subcommand() -> App<'static> {
+    App::new("getmininginfo")
+        .about("Retrieves the mining state, including the miners connected to the mining server and their aggregate hash rate.")
+}
+
+fn listminedblocks_subcommand() -> App<'static> {
+    App::new("listminedblocks")
+        .about("Lists the blocks found by the node's miner, with the time and the number of nonces it took to find them.")
+}
+
+fn getmempoolinfo_subcommand() -> App<'static> {
+    App::new("getmempoolinfo")
+        .about("Retrieves the number of transactions in the transaction pool and their total size and memory usage.")
 }
 
 pub fn client_command() -> App<'static> {
@@ -93,231 +504,750 @@ pub fn client_command() -> App<'static> {
         )
         .subcommand(getfullblockchain_subcommand())
         .subcommand(getblock_subcommand())
+        .subcommand(getblockheader_subcommand())
+        .subcommand(gettransaction_subcommand())
         .subcommand(sendrawtransaction_subcommand())
+        .subcommand(createrawtransaction_subcommand())
+        .subcommand(decoderawtransaction_subcommand())
+        .subcommand(decodeblock_subcommand())
+        .subcommand(estimatesmartfee_subcommand())
+        .subcommand(getmempoolinfo_subcommand())
+        .subcommand(getmininginfo_subcommand())
+        .subcommand(getblockcount_subcommand())
+        .subcommand(getbestblockhash_subcommand())
+        .subcommand(getblockhash_subcommand())
+        .subcommand(listminedblocks_subcommand())
+        .subcommand(listunspent_subcommand())
+        .subcommand(getutxos_subcommand())
+        .subcommand(getinfo_subcommand())
+        .subcommand(uptime_subcommand())
+        .subcommand(getdifficulty_subcommand())
+        .subcommand(getchaintips_subcommand())
+        .subcommand(listbanned_subcommand())
+        .subcommand(setban_subcommand())
+        .subcommand(prioritisetransaction_subcommand())
+        .subcommand(getbalances_subcommand())
+        .subcommand(wallet_subcommand())
+        .subcommand(listtransactions_subcommand())
+        .subcommand(exporthistory_subcommand())
+        .subcommand(exportchain_subcommand())
+        .subcommand(rpc_subcommand())
+        .subcommand(shell_subcommand())
+        .subcommand(watch_subcommand())
+        .subcommand(top_subcommand())
+        .subcommand(getpeerinfo_subcommand())
+        .subcommand(peers_subcommand())
+        .subcommand(getnettotals_subcommand())
+        .subcommand(dumpstate_subcommand())
+        .subcommand(logging_subcommand())
+        .subcommand(getforkstats_subcommand())
+        .subcommand(stop_subcommand())
 }
 
-fn short_hash(hash: &BlockHash, blocks: &HashMap<BlockHash, Block>) -> String {
-    // TODO: This is a hack for now.
-    (&as_hex(&hash.as_slice())[..8]).to_string()
-}
-
-fn graphviz(blockchain: &BlockchainManager) -> Result<(), String> {
-    // TODO: Hihglight active blockchain and orphans.
-    // digraph G {
-    //
-    //   subgraph cluster_0 {
-    //     style=filled;
-    //     color=lightgrey;
-    //     node [style=filled,color=white];
-    //     a0 -> a1 -> a2 -> a3;
-    //     label = "Active";
-    //   }
-    //
-    //   a0 -> b1 -> b2 -> b3
-    //
-    //   subgraph cluster_1 {
-    //     style=filled;
-    //     color=lightgrey;
-    //     node [style=filled,color=white];
-    //     c0;
-    //     c1;
-    //     c2;
-    //     c3;
-    //     c4;
-    //     ce91c6 -> 9ce91c7
-    //     label = "Orphans";
-    //   }
-    // }
-
-    let all_blocks = blockchain.all_blocks();
-    let all_blocks = all_blocks
-        .into_iter()
-        .map(|b| (b.id().clone(), b))
-        .collect::<HashMap<BlockHash, Block>>();
-    let active_blockchain = blockchain.block_tree().active_blockchain();
-    let orphaned_blocks = blockchain.orphaned_blocks();
-
-    let mut active_blockchain_edges = Vec::new();
-    for i in 0..(active_blockchain.len() - 1) {
-        let current = active_blockchain.get(i).unwrap();
-        let next = active_blockchain.get(i + 1).unwrap();
-        active_blockchain_edges.push((current.id(), next.id()));
-    }
-    let active_blockchain_graph = active_blockchain_edges
+// Fetches all blocks known to the server, prints them and the active blockchain, and writes
+// the blocks at the given heights as a Graphviz graph to ./blockchain.dot, and optionally
+// renders it as an image.
+fn get_full_blockchain(
+    client_options: &ClientCliOptions,
+    heights: Option<RangeInclusive<u32>>,
+    render: Option<ImageFormat>,
+) -> Result<(), String> {
+    let mut client = CoolcoinClient::new(
+        client_options.server.clone(),
+        client_options.timeout,
+        client_options.enable_logging,
+    );
+    let (active_blockchain, blocks) = client.get_full_blockchain()?;
+    let json = serde_json::to_string_pretty(&blocks).unwrap();
+    println!("{}", json);
+    // The first block of the active blockchain is the genesis block of the node's network.
+    let genesis_block = blocks
         .iter()
-        .map(|(parent, child)| {
-            format!(
-                r#""{}" -> "{}";"#,
-                short_hash(parent, &all_blocks),
-                short_hash(child, &all_blocks)
-            )
-        })
-        .collect::<Vec<String>>()
-        .join("\n");
+        .find(|b| Some(b.id()) == active_blockchain.first())
+        .ok_or_else(|| "Genesis block is missing.".to_string())?;
+    let mut blockchain_manager =
+        BlockchainManager::with_genesis_block(genesis_block.as_ref().clone());
 
-    let secondary_blockchain_graph = all_blocks
-        .iter()
-        .filter(|(hash, block)| {
-            active_blockchain.iter().find(|b| b.id() == *hash).is_none()
-                && orphaned_blocks.iter().find(|b| b.id() == *hash).is_none()
-        })
-        .map(|(hash, block)| {
-            (
-                all_blocks
-                    .get(block.header().previous_block_hash())
-                    .map(|b| b.id()),
-                block.id(),
-            )
-        })
-        .map(|(parent, child)| match parent {
-            Some(parent) => format!(
-                r#""{}" -> "{}";"#,
-                short_hash(parent, &all_blocks),
-                short_hash(child, &all_blocks)
-            ),
-            None => format!(r#""{}";"#, short_hash(child, &all_blocks)),
-        })
-        .collect::<Vec<String>>()
-        .join("\n");
+    // First insert active blockchain since blockchain manager gives priority to the one
+    // that comes first (if lengths are equal).
+    // TODO: Until most work is properly implemented.
 
-    let orphaned_blocks_graph = orphaned_blocks
-        .iter()
-        .map(|block| {
-            format!(
-                r#""{}" -> "{}";"#,
-                short_hash(block.header().previous_block_hash(), &all_blocks),
-                short_hash(block.id(), &all_blocks)
-            )
-        })
-        .collect::<Vec<String>>()
-        .join("\n");
-
-    let contents = format!(
-        r#"
-    digraph G {{
-    
-        subgraph cluster_0 {{
-            style=filled;
-            color=lightgrey;
-            node [style=filled,color=white];
-            label = "Active";
-            {}
-        }}
-        
-        subgraph cluster_1 {{
-            style=filled;
-            color=lightgrey;
-            node [style=filled,color=white];
-            label = "Orphans";
-            {}
-        }}
-    
-      {}
-    }}
-    "#,
-        active_blockchain_graph, orphaned_blocks_graph, secondary_blockchain_graph
-    );
-    fs::write("./blockchain.dot", contents).map_err(|e| e.to_string())
+    for active_block_hash in active_blockchain {
+        let active_block = blocks
+            .iter()
+            .find(|b| *b.id() == active_block_hash)
+            .unwrap();
+        blockchain_manager.new_block_reinsert_orphans(active_block.clone());
+    }
+
+    // Insert remaining blocks.
+    for block in blocks {
+        blockchain_manager.new_block_reinsert_orphans(block);
+    }
+
+    println!("Active blockchain");
+    let mut width = 0;
+    for block in blockchain_manager.block_tree().active_blockchain() {
+        println!("{}{}", " ".repeat(width), block.id());
+        width += 4;
+    }
+
+    let contents = graphviz::blockchain(&blockchain_manager, heights);
+    fs::write("./blockchain.dot", &contents).map_err(|e| e.to_string())?;
+    if let Some(format) = render {
+        let path = format!("./blockchain.{}", format.extension());
+        fs::write(&path, graphviz::render(&contents, format)?).map_err(|e| e.to_string())?;
+        println!("Rendered the blockchain to: {}", path);
+    }
+    Ok(())
 }
 
 fn send_request(client_options: &ClientCliOptions, message: PeerMessage) -> Result<(), String> {
-    let mut connection =
-        PeerConnection::connect(client_options.server.clone(), client_options.enable_logging)?;
-    connection.send(&message)?;
-    let request_sent_time = SystemTime::now();
-    while request_sent_time.elapsed().unwrap() < client_options.timeout {
-        match connection.receive().unwrap() {
-            None => continue,
-            Some(PeerMessage::ResponseBlock(block)) => {
-                let json = serde_json::to_string_pretty(&block).unwrap();
-                println!("{}", json);
-                return Ok(());
+    let mut client = CoolcoinClient::new(
+        client_options.server.clone(),
+        client_options.timeout,
+        client_options.enable_logging,
+    );
+    match client.request(message)? {
+        PeerMessage::ResponseBlock(block) => {
+            let json = serde_json::to_string_pretty(&block).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseTransaction(result) => match result {
+            Ok(transaction_id) => {
+                println!("{}", transaction_id.raw());
+                Ok(())
+            }
+            Err(e) => Err(format!("Transaction rejected: {}", e)),
+        },
+        PeerMessage::ResponseFeeEstimate(fee_rate) => match fee_rate {
+            Some(fee_rate) => {
+                println!("{}", fee_rate);
+                Ok(())
+            }
+            None => Err("Insufficient data to estimate the fee rate.".to_string()),
+        },
+        PeerMessage::ResponseTransactionPoolInfo(info) => {
+            let json = serde_json::to_string_pretty(&info).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseMiningInfo(info) => {
+            let json = serde_json::to_string_pretty(&info).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseBlockCount(count) => {
+            println!("{}", count);
+            Ok(())
+        }
+        PeerMessage::ResponseBestBlockHash(hash) => {
+            println!("{}", hash);
+            Ok(())
+        }
+        PeerMessage::ResponseBlockHash(hash) => match hash {
+            Some(hash) => {
+                println!("{}", hash);
+                Ok(())
             }
-            Some(PeerMessage::ResponseTransaction) => {
+            None => Err("Block height out of range.".to_string()),
+        },
+        PeerMessage::ResponseListMinedBlocks(blocks) => {
+            let json = serde_json::to_string_pretty(&blocks).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseListTransactions(transactions) => {
+            let json = serde_json::to_string_pretty(&transactions).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseListBanned(bans) => {
+            let json = serde_json::to_string_pretty(&bans).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseSetBan(result) => match result {
+            Ok(()) => {
                 println!("Success");
-                return Ok(());
+                Ok(())
             }
-            Some(PeerMessage::ResponseFullBlockchain(active_blockchain, blocks)) => {
-                let json = serde_json::to_string_pretty(&blocks).unwrap();
+            Err(e) => Err(e),
+        },
+        PeerMessage::ResponsePrioritiseTransaction(result) => match result {
+            Ok(()) => {
+                println!("Success");
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        PeerMessage::ResponseStop(result) => match result {
+            Ok(()) => {
+                println!("Coolcoin server stopping");
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        PeerMessage::ResponseInfo(info) => {
+            let json = serde_json::to_string_pretty(&info).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponsePeerInfo(peers) => {
+            let json = serde_json::to_string_pretty(&peers).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponsePeerHistory(events) => {
+            for event in events {
+                println!("{}", format_peer_event(&event));
+            }
+            Ok(())
+        }
+        PeerMessage::ResponseForkStats(stats) => {
+            let json = serde_json::to_string_pretty(&stats).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseLogging(result) => match result {
+            Ok(levels) => {
+                for (category, level) in levels {
+                    println!("{}: {}", category, level);
+                }
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        PeerMessage::ResponseDumpState(result) => match result {
+            Ok(path) => {
+                println!("{}", path);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        PeerMessage::ResponseNetTotals(totals) => {
+            let json = serde_json::to_string_pretty(&totals).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseUptime(uptime) => {
+            println!("{}", uptime);
+            Ok(())
+        }
+        PeerMessage::ResponseDifficulty(difficulty) => {
+            println!("{}", difficulty);
+            Ok(())
+        }
+        PeerMessage::ResponseBlockHeader(info) => {
+            let json = serde_json::to_string_pretty(&info).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseTransactionInfo(info) => match info {
+            Some(info) => {
+                let json = serde_json::to_string_pretty(&info).unwrap();
                 println!("{}", json);
-                let mut blockchain_manager = BlockchainManager::new();
-
-                // First insert active blockchain since blockchain manager gives priority to the one
-                // that comes first (if lengths are equal).
-                // TODO: Until most work is properly implemented.
-
-                for active_block_hash in active_blockchain {
-                    let active_block = blocks
-                        .iter()
-                        .find(|b| *b.id() == active_block_hash)
-                        .unwrap();
-                    blockchain_manager.new_block_reinsert_orphans(active_block.clone());
+                if info.confirmations() == 0 {
+                    println!("Transaction is unconfirmed, it's in the transaction pool.");
                 }
+                Ok(())
+            }
+            None => Err(
+                "Transaction not found in the transaction pool or the active blockchain."
+                    .to_string(),
+            ),
+        },
+        PeerMessage::ResponseChainTips(tips) => {
+            let json = serde_json::to_string_pretty(&tips).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        PeerMessage::ResponseListUnspent(outputs) => {
+            let json = serde_json::to_string_pretty(&outputs).unwrap();
+            println!("{}", json);
+            Ok(())
+        }
+        unexpected => {
+            let json = serde_json::to_string_pretty(&unexpected).unwrap();
+            Err(format!("Unexpected:{}", json))
+        }
+    }
+}
 
-                // Insert remaining blocks.
-                for block in blocks {
-                    blockchain_manager.new_block_reinsert_orphans(block);
-                }
+// Fetches the blocks of the active blockchain one at a time and writes them to the file,
+// returning the height of the exported tip.
+fn export_chain(client: &mut CoolcoinClient, path: &str) -> Result<u32, String> {
+    let file = fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    let tip_height = client.get_block_count()?;
+    let mut writer = ChainExportWriter::new(BufWriter::new(file), tip_height)?;
+    for height in 0..=tip_height {
+        let block = client
+            .get_block_hash(height)?
+            .map(|hash| client.get_block(hash))
+            .transpose()?
+            .flatten()
+            .ok_or_else(|| {
+                format!(
+                    "Block at height: {} not found, the active blockchain has changed during the export.",
+                    height
+                )
+            })?;
+        writer.write_block(&block)?;
+    }
+    writer.finish()?;
+    Ok(tip_height)
+}
 
-                println!("Active blockchain");
-                let mut width = 0 as usize;
-                for block in blockchain_manager.block_tree().active_blockchain() {
-                    println!("{}{}", " ".repeat(width), block.id());
-                    width += 4;
-                }
+/// Polls the server for the blocks connected to the active blockchain and prints them,
+/// starting with the current tip. Blocks disconnected by a reorganization are reported too.
+fn watch(client_options: &ClientCliOptions, interval: Duration) -> Result<(), String> {
+    // Enough to detect any realistic reorganization.
+    const MAX_WATCHED_BLOCKS: usize = 100;
+    let mut client = CoolcoinClient::new(
+        client_options.server.clone(),
+        client_options.timeout,
+        client_options.enable_logging,
+    );
+    // The printed blocks that are in the active blockchain, by height.
+    let mut watched = BTreeMap::<u32, BlockHash>::new();
+    loop {
+        let block_count = client.get_block_count()?;
+        let mut next_height = watched
+            .keys()
+            .next_back()
+            .map_or(block_count, |height| height + 1);
+        while let Some((&height, &hash)) = watched.iter().next_back() {
+            if client.get_block_hash(height)? == Some(hash) {
+                break;
+            }
+            println!(
+                "{:>6}  {}  disconnected",
+                height,
+                HashDisplay::global().format(hash.raw())
+            );
+            watched.remove(&height);
+            next_height = height;
+        }
+        for height in next_height..=block_count {
+            // The active blockchain may have changed in the meantime, which is handled next time.
+            let block = match client.get_block_hash(height)? {
+                Some(hash) => client.get_block(hash)?,
+                None => None,
+            };
+            let block = match block {
+                Some(block) => block,
+                None => break,
+            };
+            let coinbase_address = block
+                .transactions()
+                .first()
+                .and_then(|coinbase| coinbase.outputs().first())
+                .map_or_else(String::new, |output| output.to().to_string());
+            println!(
+                "{:>6}  {}  {:>4} txs  {}",
+                height,
+                HashDisplay::global().format(block.id().raw()),
+                block.transactions().len(),
+                coinbase_address
+            );
+            watched.insert(height, *block.id());
+        }
+        while watched.len() > MAX_WATCHED_BLOCKS {
+            let lowest = *watched.keys().next().unwrap();
+            watched.remove(&lowest);
+        }
+        thread::sleep(interval);
+    }
+}
 
-                graphviz(&blockchain_manager)?;
+fn run_wallet(
+    client_options: &ClientCliOptions,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let path = PathBuf::from(matches.value_of("wallet").unwrap());
+    if matches.subcommand_matches("create").is_some() {
+        let wallet = Wallet::create(&path)?;
+        println!("{}", wallet.addresses()[0]);
+        return Ok(());
+    }
+    let mut wallet = Wallet::load(&path)?;
+    let mut client = CoolcoinClient::new(
+        client_options.server.clone(),
+        client_options.timeout,
+        client_options.enable_logging,
+    );
+    if matches.subcommand_matches("address").is_some() {
+        println!("{}", wallet.new_address()?);
+    } else if matches.subcommand_matches("balance").is_some() {
+        let outputs = client.list_unspent(1, u32::MAX, wallet.addresses().to_vec())?;
+        let balances = AccountBalances::extract_account_balances(&outputs);
+        let spendable = balances
+            .iter()
+            .map(AccountBalance::spendable)
+            .sum::<Coolcoin>();
+        let immature = balances
+            .iter()
+            .map(AccountBalance::immature)
+            .sum::<Coolcoin>();
+        println!("Spendable: {}", spendable.value());
+        println!("Immature: {}", immature.value());
+    } else if let Some(matches) = matches.subcommand_matches("send") {
+        let to = matches.value_of("ADDRESS").unwrap().parse::<Address>()?;
+        let amount = Coolcoin::new(matches.value_of_t("AMOUNT")?);
+        let fee = Coolcoin::new(matches.value_of_t("fee")?);
+        // Outputs spent by the unconfirmed transactions are still listed, in which case
+        // the server rejects the transaction as a double spend.
+        let outputs = client.list_unspent(1, u32::MAX, wallet.addresses().to_vec())?;
+        let transaction = wallet.create_transaction(&outputs, to, amount, fee)?;
+        println!("{}", client.send_transaction(transaction)?.raw());
+    } else if let Some(matches) = matches.subcommand_matches("history") {
+        let transactions =
+            client.list_transactions(wallet.addresses().to_vec(), matches.value_of_t("count")?)?;
+        println!("{}", serde_json::to_string_pretty(&transactions).unwrap());
+    }
+    Ok(())
+}
 
-                return Ok(());
-            }
-            Some(unexpected) => {
-                let json = serde_json::to_string_pretty(&unexpected).unwrap();
-                return Err(format!("Unexpected:{}", json));
-            }
+fn print_balances(balances: &[AccountBalance]) {
+    let width = balances
+        .iter()
+        .map(|balance| balance.address().as_str().len())
+        .max()
+        .unwrap_or(0)
+        .max("ADDRESS".len());
+    println!(
+        "{:<width$}  {:>15}  {:>15}  {:>7}",
+        "ADDRESS",
+        "SPENDABLE",
+        "IMMATURE",
+        "OUTPUTS",
+        width = width
+    );
+    for balance in balances {
+        println!(
+            "{:<width$}  {:>15}  {:>15}  {:>7}",
+            balance.address(),
+            balance.spendable().value(),
+            balance.immature().value(),
+            balance.num_outputs(),
+            width = width
+        );
+    }
+}
+
+// The event of the peer log, one per line, prefixed with its time in seconds from Unix Epoch.
+fn format_peer_event(event: &PeerEvent) -> String {
+    match event {
+        PeerEvent::Connected { time, address } => format!("{}  connected     {}", time, address),
+        PeerEvent::Disconnected {
+            time,
+            address,
+            reason,
+        } => format!("{}  disconnected  {}  {}", time, address, reason),
+        PeerEvent::Banned {
+            time,
+            subnet,
+            until,
+            by,
+        } => format!(
+            "{}  banned        {}  until {} by {}",
+            time, subnet, until, by
+        ),
+        PeerEvent::Unbanned { time, subnet, by } => {
+            format!("{}  unbanned      {}  by {}", time, subnet, by)
         }
     }
-    Err(format!(
-        "Request timed out after: {} seconds.",
-        client_options.timeout.as_secs()
+}
+
+/// Builds the transaction from the inputs, outputs and locktime given on the command line.
+fn raw_transaction(matches: &ArgMatches) -> Result<Transaction, Box<dyn Error>> {
+    let locktime = matches.value_of_t::<u32>("locktime")?;
+    let inputs = matches
+        .values_of("inputs")
+        .unwrap()
+        .map(raw_transaction_input)
+        .collect::<Result<Vec<TransactionInput>, String>>()?;
+    let outputs = matches
+        .values_of("outputs")
+        .unwrap()
+        .map(raw_transaction_output)
+        .collect::<Result<Vec<TransactionOutput>, String>>()?;
+    Ok(Transaction::new(inputs, outputs, locktime)?)
+}
+
+/// Parses the input given as <TXID>:<OutputIndex>.
+fn raw_transaction_input(input: &str) -> Result<TransactionInput, String> {
+    let (txid, output_index) = input
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid input: {}, expected <TXID>:<OutputIndex>", input))?;
+    let txid = from_hex(txid).map_err(|e| format!("Invalid input: {}: {}", input, e))?;
+    let output_index = output_index
+        .parse::<i32>()
+        .map_err(|e| format!("Invalid input: {}: {}", input, e))?;
+    Ok(TransactionInput::new(
+        TransactionId::new(txid),
+        OutputIndex::new(output_index),
     ))
 }
 
+/// Parses the output given as <CoolcoinAddress>:<Amount>.
+fn raw_transaction_output(output: &str) -> Result<TransactionOutput, String> {
+    let (address, amount) = output.split_once(':').ok_or_else(|| {
+        format!(
+            "Invalid output: {}, expected <CoolcoinAddress>:<Amount>",
+            output
+        )
+    })?;
+    let amount = amount
+        .parse::<i64>()
+        .map_err(|e| format!("Invalid output: {}: {}", output, e))?;
+    Ok(TransactionOutput::new(
+        address.parse::<Address>()?,
+        Coolcoin::new(amount),
+    ))
+}
+
+/// Parses the optional list of addresses given on the command line.
+fn addresses(matches: &ArgMatches) -> Result<Vec<Address>, String> {
+    matches
+        .values_of("addresses")
+        .map(|addresses| addresses.map(str::parse).collect())
+        .unwrap_or_else(|| Ok(vec![]))
+}
+
 pub fn run_client(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let client_options = ClientCliOptions::parse(matches)?;
 
-    if let Some(ref matches) = matches.subcommand_matches("getblock") {
+    if let Some(matches) = matches.subcommand_matches("getblock") {
         let hex = matches.value_of("BLOCK_HASH").unwrap();
-        let block_hash = BlockHash::new(
-            from_hex(&hex).map_err(|e| format!("Invalid block hash format: {}", e))?,
-        );
+        let block_hash =
+            BlockHash::new(from_hex(hex).map_err(|e| format!("Invalid block hash format: {}", e))?);
         send_request(&client_options, PeerMessage::GetBlock(block_hash))?;
-    } else if let Some(ref matches) = matches.subcommand_matches("sendrawtransaction") {
-        let locktime = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
-        let inputs = matches
-            .values_of("inputs")
-            .unwrap()
-            .map(|input| {
-                let mut tokens = input.split(":");
-                let txid = TransactionId::new(from_hex(tokens.next().unwrap()).unwrap());
-                let output_index = OutputIndex::new(tokens.next().unwrap().parse::<i32>().unwrap());
-                TransactionInput::new(txid, output_index)
-            })
-            .collect::<Vec<TransactionInput>>();
-        let outputs = matches
-            .values_of("outputs")
-            .unwrap()
-            .map(|output| {
-                let mut tokens = output.split(":").collect::<Vec<&str>>();
-                println!("Tokens: {:?}", tokens);
-                let address = Address::new(tokens.get(0).unwrap().to_string());
-                let amount = Coolcoin::new(tokens.get(1).unwrap().parse::<i64>().unwrap());
-                TransactionOutput::new(address, amount)
-            })
-            .collect::<Vec<TransactionOutput>>();
-        let transaction = Transaction::new(inputs, outputs, locktime)?;
+    } else if let Some(matches) = matches.subcommand_matches("getblockheader") {
+        let hex = matches.value_of("BLOCK_HASH").unwrap();
+        let block_hash =
+            BlockHash::new(from_hex(hex).map_err(|e| format!("Invalid block hash format: {}", e))?);
+        send_request(&client_options, PeerMessage::GetBlockHeader(block_hash))?;
+    } else if let Some(matches) = matches.subcommand_matches("gettransaction") {
+        let hex = matches.value_of("TXID").unwrap();
+        let transaction_id =
+            TransactionId::new(from_hex(hex).map_err(|e| format!("Invalid txid format: {}", e))?);
+        send_request(&client_options, PeerMessage::GetTransaction(transaction_id))?;
+    } else if let Some(hex) = matches
+        .subcommand_matches("sendrawtransaction")
+        .and_then(|matches| matches.value_of("HEX"))
+    {
+        let transaction = Transaction::from_hex(hex)?;
+        send_request(&client_options, PeerMessage::SendTransaction(transaction))?;
+    } else if let Some(matches) = matches.subcommand_matches("sendrawtransaction") {
+        let transaction = raw_transaction(matches)?;
         send_request(&client_options, PeerMessage::SendTransaction(transaction))?;
-    } else if let Some(ref matchesa) = matches.subcommand_matches("getfullblockchain") {
-        send_request(&client_options, PeerMessage::GetFullBlockchain)?;
+    } else if let Some(matches) = matches.subcommand_matches("createrawtransaction") {
+        let transaction = raw_transaction(matches)?;
+        println!("{}", transaction.to_hex());
+    } else if let Some(matches) = matches.subcommand_matches("decoderawtransaction") {
+        let transaction = Transaction::from_hex(matches.value_of("HEX").unwrap())?;
+        println!("{}", serde_json::to_string_pretty(&transaction).unwrap());
+    } else if let Some(matches) = matches.subcommand_matches("decodeblock") {
+        let block = Block::from_hex(matches.value_of("HEX").unwrap())?;
+        println!("{}", serde_json::to_string_pretty(&block).unwrap());
+    } else if let Some(matches) = matches.subcommand_matches("rpc") {
+        let request = JsonRpcRequest::from_args(
+            serde_json::Value::from(1),
+            matches.value_of("METHOD").unwrap(),
+            &matches
+                .values_of("PARAMS")
+                .map(|params| params.collect::<Vec<_>>())
+                .unwrap_or_default(),
+        );
+        // The request is converted to the peer message here, so the server doesn't need to run
+        // the JSON-RPC server.
+        let handler = JsonRpcHandler::new(client_options.server.clone(), client_options.timeout);
+        let response = handler.handle(&request);
+        println!("{}", serde_json::to_string_pretty(&response).unwrap());
+        if let Some(error) = response.error {
+            return Err(error.to_string().into());
+        }
+    } else if matches.subcommand_matches("shell").is_some() {
+        run_shell(client_options.server.clone(), client_options.timeout)?;
+    } else if let Some(matches) = matches.subcommand_matches("estimatesmartfee") {
+        let target_blocks = matches.value_of_t::<u32>("TARGET_BLOCKS")?;
+        send_request(
+            &client_options,
+            PeerMessage::EstimateSmartFee(target_blocks),
+        )?;
+    } else if matches.subcommand_matches("getmempoolinfo").is_some() {
+        send_request(&client_options, PeerMessage::GetTransactionPoolInfo)?;
+    } else if matches.subcommand_matches("getblockcount").is_some() {
+        send_request(&client_options, PeerMessage::GetBlockCount)?;
+    } else if let Some(matches) = matches.subcommand_matches("getbalances") {
+        let addresses = addresses(matches)?;
+        let mut client = CoolcoinClient::new(
+            client_options.server.clone(),
+            client_options.timeout,
+            client_options.enable_logging,
+        );
+        let outputs = client.list_unspent(
+            matches.value_of_t("min_confirmations")?,
+            u32::MAX,
+            addresses,
+        )?;
+        let balances = AccountBalances::extract_account_balances(&outputs);
+        if matches.is_present("json") {
+            println!("{}", serde_json::to_string_pretty(&balances).unwrap());
+        } else {
+            print_balances(&balances);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("listtransactions") {
+        let address = matches.value_of("ADDRESS").unwrap().parse::<Address>()?;
+        send_request(
+            &client_options,
+            PeerMessage::ListTransactions(vec![address], matches.value_of_t("count")?),
+        )?;
+    } else if let Some(matches) = matches.subcommand_matches("exporthistory") {
+        let address = matches.value_of("address").unwrap().parse::<Address>()?;
+        let mut client = CoolcoinClient::new(
+            client_options.server.clone(),
+            client_options.timeout,
+            client_options.enable_logging,
+        );
+        let transactions = client.list_transactions(vec![address], u32::MAX)?;
+        let csv = history_csv(&transactions);
+        match matches.value_of("output") {
+            Some(path) => {
+                fs::write(path, csv)?;
+                println!("Exported {} transactions to: {}", transactions.len(), path);
+            }
+            None => print!("{}", csv),
+        }
+    } else if let Some(matches) = matches.subcommand_matches("exportchain") {
+        let path = matches.value_of("output").unwrap();
+        let mut client = CoolcoinClient::new(
+            client_options.server.clone(),
+            client_options.timeout,
+            client_options.enable_logging,
+        );
+        let tip_height = export_chain(&mut client, path)?;
+        println!("Exported {} blocks to: {}", tip_height + 1, path);
+    } else if let Some(matches) = matches.subcommand_matches("wallet") {
+        run_wallet(&client_options, matches)?;
+    } else if let Some(matches) = matches.subcommand_matches("watch") {
+        let interval = Duration::from_secs(matches.value_of_t("interval")?);
+        watch(&client_options, interval)?;
+    } else if let Some(matches) = matches.subcommand_matches("top") {
+        let interval = Duration::from_secs(matches.value_of_t("interval")?);
+        run_top(
+            client_options.server.clone(),
+            client_options.timeout,
+            interval,
+        )?;
+    } else if matches.subcommand_matches("getpeerinfo").is_some() {
+        send_request(&client_options, PeerMessage::GetPeerInfo)?;
+    } else if let Some(matches) = matches.subcommand_matches("peers") {
+        let request = match matches.is_present("history") {
+            true => PeerMessage::GetPeerHistory(match matches.is_present("count") {
+                true => Some(matches.value_of_t::<usize>("count")?),
+                false => None,
+            }),
+            false => PeerMessage::GetPeerInfo,
+        };
+        send_request(&client_options, request)?;
+    } else if matches.subcommand_matches("getnettotals").is_some() {
+        send_request(&client_options, PeerMessage::GetNetTotals)?;
+    } else if let Some(matches) = matches.subcommand_matches("dumpstate") {
+        let file_name = matches.value_of("FILE_NAME").map(str::to_string);
+        send_request(&client_options, PeerMessage::DumpState(file_name))?;
+    } else if let Some(matches) = matches.subcommand_matches("getforkstats") {
+        let num_blocks = match matches.is_present("NBLOCKS") {
+            true => Some(matches.value_of_t::<u32>("NBLOCKS")?),
+            false => None,
+        };
+        send_request(&client_options, PeerMessage::GetForkStats(num_blocks))?;
+    } else if let Some(matches) = matches.subcommand_matches("logging") {
+        let categories = |name| {
+            matches
+                .values_of(name)
+                .unwrap_or_default()
+                .map(str::to_string)
+                .collect::<Vec<String>>()
+        };
+        send_request(
+            &client_options,
+            PeerMessage::Logging(categories("include"), categories("exclude")),
+        )?;
+    } else if matches.subcommand_matches("getbestblockhash").is_some() {
+        send_request(&client_options, PeerMessage::GetBestBlockHash)?;
+    } else if let Some(matches) = matches.subcommand_matches("getblockhash") {
+        let height = matches.value_of_t::<u32>("HEIGHT")?;
+        send_request(&client_options, PeerMessage::GetBlockHash(height))?;
+    } else if matches.subcommand_matches("getmininginfo").is_some() {
+        send_request(&client_options, PeerMessage::GetMiningInfo)?;
+    } else if matches.subcommand_matches("listminedblocks").is_some() {
+        send_request(&client_options, PeerMessage::ListMinedBlocks)?;
+    } else if matches.subcommand_matches("getinfo").is_some() {
+        send_request(&client_options, PeerMessage::GetInfo)?;
+    } else if matches.subcommand_matches("uptime").is_some() {
+        send_request(&client_options, PeerMessage::Uptime)?;
+    } else if matches.subcommand_matches("getdifficulty").is_some() {
+        send_request(&client_options, PeerMessage::GetDifficulty)?;
+    } else if matches.subcommand_matches("getchaintips").is_some() {
+        send_request(&client_options, PeerMessage::GetChainTips)?;
+    } else if matches.subcommand_matches("listbanned").is_some() {
+        send_request(&client_options, PeerMessage::ListBanned)?;
+    } else if let Some(matches) = matches.subcommand_matches("setban") {
+        let subnet = matches.value_of_t::<Subnet>("SUBNET")?;
+        let command = match matches.value_of("COMMAND").unwrap() {
+            "add" => SetBanCommand::Add(matches.value_of_t("duration")?),
+            _ => SetBanCommand::Remove,
+        };
+        send_request(&client_options, PeerMessage::SetBan(subnet, command))?;
+    } else if let Some(matches) = matches.subcommand_matches("prioritisetransaction") {
+        let transaction_id = TransactionId::new(
+            from_hex(matches.value_of("TXID").unwrap())
+                .map_err(|e| format!("Invalid txid format: {}", e))?,
+        );
+        let fee_delta = Coolcoin::new(matches.value_of_t("FEE_DELTA")?);
+        send_request(
+            &client_options,
+            PeerMessage::PrioritiseTransaction(transaction_id, fee_delta),
+        )?;
+    } else if matches.subcommand_matches("stop").is_some() {
+        send_request(&client_options, PeerMessage::Stop)?;
+    } else if let Some(matches) = matches.subcommand_matches("listunspent") {
+        let addresses = addresses(matches)?;
+        send_request(
+            &client_options,
+            PeerMessage::ListUnspent(
+                matches.value_of_t("min_confirmations")?,
+                matches.value_of_t("max_confirmations")?,
+                addresses,
+            ),
+        )?;
+    } else if let Some(matches) = matches.subcommand_matches("getutxos") {
+        let addresses = addresses(matches)?;
+        let min_amount = Coolcoin::new(matches.value_of_t("min_amount")?);
+        send_request(
+            &client_options,
+            PeerMessage::GetUtxos(addresses, min_amount),
+        )?;
+    } else if let Some(matches) = matches.subcommand_matches("getfullblockchain") {
+        let heights = match (
+            matches.value_of("from_height"),
+            matches.value_of("to_height"),
+        ) {
+            (None, None) => None,
+            (from, to) => {
+                let from = from.map(str::parse).transpose()?.unwrap_or(0);
+                let to = to.map(str::parse).transpose()?.unwrap_or(u32::MAX);
+                Some(from..=to)
+            }
+        };
+        let render = match matches.value_of("render") {
+            Some(format) => Some(format.parse()?),
+            None => None,
+        };
+        get_full_blockchain(&client_options, heights, render)?;
     } else {
         panic!("Should report help.");
     }
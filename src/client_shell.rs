@@ -0,0 +1,191 @@
+use crate::core::json_rpc::{JsonRpcHandler, JsonRpcRequest, METHODS};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde_json::Value;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const HISTORY_FILE: &str = ".coolcoin_history";
+
+/// Completes the method names, which are the first word on the line.
+struct MethodCompleter;
+
+impl Completer for MethodCompleter {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let prefix = &line[..pos];
+        if prefix.contains(char::is_whitespace) {
+            return Ok((pos, vec![]));
+        }
+        let candidates = METHODS
+            .iter()
+            .chain(["help", "exit"].iter())
+            .filter(|method| method.starts_with(prefix))
+            .map(|method| Pair {
+                display: method.to_string(),
+                replacement: format!("{} ", method),
+            })
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for MethodCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for MethodCompleter {}
+
+impl Validator for MethodCompleter {}
+
+impl Helper for MethodCompleter {}
+
+/// Reads the JSON-RPC requests from the terminal, in the same format as the rpc subcommand,
+/// and prints the results, until the end of input. All requests are sent over a single
+/// connection to the node.
+pub fn run_shell(server: String, timeout: Duration) -> Result<(), Box<dyn Error>> {
+    let handler = JsonRpcHandler::new(server.clone(), timeout);
+    let mut editor = Editor::<MethodCompleter, DefaultHistory>::new()?;
+    editor.set_helper(Some(MethodCompleter));
+    let history_path = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(HISTORY_FILE);
+    // There is no history on the first run.
+    let _ = editor.load_history(&history_path);
+    println!(
+        "Connected to: {}. Type help for the list of methods, and exit or Ctrl-D to quit.",
+        server
+    );
+
+    let mut id = 0;
+    loop {
+        let line = match editor.readline("coolcoin> ") {
+            Ok(line) => line,
+            // Ctrl-C discards the line.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let args = match split_args(&line) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        let (method, params) = match args.split_first() {
+            Some((method, params)) => (method.as_str(), params),
+            None => continue,
+        };
+        editor.add_history_entry(line.as_str())?;
+        match method {
+            "exit" | "quit" => break,
+            "help" => println!("{}", METHODS.join("\n")),
+            method => {
+                id += 1;
+                let params = params.iter().map(String::as_str).collect::<Vec<_>>();
+                let request = JsonRpcRequest::from_args(Value::from(id), method, &params);
+                let response = handler.handle(&request);
+                match (response.result, response.error) {
+                    (_, Some(error)) => eprintln!("Error: {}", error),
+                    (result, None) => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&result.unwrap_or(Value::Null))?
+                    ),
+                }
+            }
+        }
+    }
+    if let Err(e) = editor.save_history(&history_path) {
+        eprintln!(
+            "Failed to save history to: {}: {}",
+            history_path.display(),
+            e
+        );
+    }
+    Ok(())
+}
+
+/// Splits the line into the method and the parameters, by whitespace, except within quotes
+/// and JSON arrays and objects, e.g. `listunspent 1 10 ["alice", "bob"]`.
+fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for c in line.chars() {
+        if in_quotes {
+            current.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            c if c.is_whitespace() && depth == 0 => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            '"' => in_quotes = true,
+            '[' | '{' => depth += 1,
+            ']' | '}' if depth > 0 => depth -= 1,
+            _ => {}
+        }
+        current.push(c);
+    }
+    if in_quotes || depth > 0 {
+        return Err("Unterminated string, array or object.".to_string());
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_args_outside_json_values() {
+        assert_eq!(
+            split_args(r#" listunspent 1  10 ["alice", "bob"] "#),
+            Ok(vec![
+                "listunspent".to_string(),
+                "1".to_string(),
+                "10".to_string(),
+                r#"["alice", "bob"]"#.to_string()
+            ])
+        );
+        assert_eq!(
+            split_args(r#"setban "10.0.0.0/8" add {"a": "b c\" }"}"#),
+            Ok(vec![
+                "setban".to_string(),
+                r#""10.0.0.0/8""#.to_string(),
+                "add".to_string(),
+                r#"{"a": "b c\" }"}"#.to_string()
+            ])
+        );
+        assert!(split_args(r#"getblock "abc"#).is_err());
+        assert!(split_args("listunspent [1, 2").is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use crate::core::block::BlockHash;
+use crate::core::coolcoin_network::PeerInfo;
+use crate::core::hash_display::HashDisplay;
+use crate::core::transaction_pool::TransactionPoolInfo;
+use crate::core::CoolcoinClient;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Number of the most recent blocks of the active blockchain that are shown.
+const RECENT_BLOCKS: u32 = 8;
+// How often the interrupt is checked while waiting for the next refresh.
+const INTERRUPT_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+// Terminal control sequences: the alternate screen keeps the terminal's contents, which are
+// restored when the dashboard exits.
+const ENTER_ALTERNATE_SCREEN: &str = "\x1b[?1049h\x1b[?25l";
+const LEAVE_ALTERNATE_SCREEN: &str = "\x1b[?25h\x1b[?1049l";
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+
+/// The block of the active blockchain, as shown in the list of the recent blocks.
+struct BlockSummary {
+    hash: BlockHash,
+    transactions: usize,
+    timestamp: u32,
+    coinbase_address: String,
+}
+
+/// The node's state, which is queried on every refresh.
+struct Snapshot {
+    uptime: u64,
+    height: u32,
+    best_block_hash: BlockHash,
+    difficulty: f64,
+    transaction_pool: TransactionPoolInfo,
+    peers: Vec<PeerInfo>,
+}
+
+/// Shows the live state of the node in the terminal, refreshed at the interval, until
+/// interrupted with Ctrl-C. If the node can't be reached, the error is shown instead, and
+/// the dashboard keeps trying.
+pub fn run_top(
+    server: String,
+    timeout: Duration,
+    interval: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&interrupted))?;
+    }
+    let mut client = CoolcoinClient::new(server.clone(), timeout, false);
+    // The recent blocks by height, which are only fetched when the active blockchain changes.
+    let mut blocks = BTreeMap::<u32, BlockSummary>::new();
+    let mut stdout = std::io::stdout();
+    write!(stdout, "{}", ENTER_ALTERNATE_SCREEN)?;
+    while !interrupted.load(Ordering::Relaxed) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+        let screen = match refresh(&mut client, &mut blocks) {
+            Ok(snapshot) => render(&server, &snapshot, &blocks, now),
+            Err(e) => format!("coolcoin top  {}\n\nNode unavailable: {}\n", server, e),
+        };
+        write!(stdout, "{}{}", CLEAR_SCREEN, screen)?;
+        stdout.flush()?;
+        let start = Instant::now();
+        while start.elapsed() < interval && !interrupted.load(Ordering::Relaxed) {
+            thread::sleep(INTERRUPT_CHECK_INTERVAL);
+        }
+    }
+    write!(stdout, "{}", LEAVE_ALTERNATE_SCREEN)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn refresh(
+    client: &mut CoolcoinClient,
+    blocks: &mut BTreeMap<u32, BlockSummary>,
+) -> Result<Snapshot, String> {
+    let height = client.get_block_count()?;
+    let lowest = height.saturating_sub(RECENT_BLOCKS - 1);
+    blocks.retain(|block_height, _| (lowest..=height).contains(block_height));
+    for block_height in lowest..=height {
+        let hash = match client.get_block_hash(block_height)? {
+            Some(hash) => hash,
+            // The active blockchain has been reorganized in the meantime.
+            None => break,
+        };
+        if blocks.get(&block_height).map(|block| block.hash) == Some(hash) {
+            continue;
+        }
+        let block = match client.get_block(hash)? {
+            Some(block) => block,
+            None => break,
+        };
+        let coinbase_address = block
+            .transactions()
+            .first()
+            .and_then(|coinbase| coinbase.outputs().first())
+            .map_or_else(String::new, |output| output.to().to_string());
+        blocks.insert(
+            block_height,
+            BlockSummary {
+                hash,
+                transactions: block.transactions().len(),
+                timestamp: block.header().timestamp(),
+                coinbase_address,
+            },
+        );
+    }
+    Ok(Snapshot {
+        uptime: client.uptime()?,
+        height,
+        best_block_hash: client.get_best_block_hash()?,
+        difficulty: client.get_difficulty()?,
+        transaction_pool: client.get_transaction_pool_info()?,
+        peers: client.get_peer_info()?,
+    })
+}
+
+fn render(
+    server: &str,
+    snapshot: &Snapshot,
+    blocks: &BTreeMap<u32, BlockSummary>,
+    now: u32,
+) -> String {
+    let hash_display = HashDisplay::global();
+    let hashes = hash_display.format_all(blocks.values().map(|block| block.hash.raw()));
+    let pool = &snapshot.transaction_pool;
+    let mut lines = vec![
+        format!(
+            "coolcoin top  {}  up {}  (Ctrl-C to quit)",
+            server,
+            format_duration(snapshot.uptime)
+        ),
+        String::new(),
+        format!(
+            "Tip      height {}  {}  difficulty {:.2}",
+            snapshot.height,
+            hash_display.format(snapshot.best_block_hash.raw()),
+            snapshot.difficulty
+        ),
+        format!(
+            "Mempool  {} txs  {}  memory {} / {}",
+            pool.size,
+            format_bytes(pool.bytes as u64),
+            format_bytes(pool.usage as u64),
+            format_bytes(pool.max_usage as u64)
+        ),
+        String::new(),
+        "Recent blocks".to_string(),
+        format!(
+            "  {:>6}  {:<10}  {:>4}  {:>8}  {}",
+            "HEIGHT", "HASH", "TXS", "AGE", "COINBASE"
+        ),
+    ];
+    for (height, block) in blocks.iter().rev() {
+        lines.push(format!(
+            "  {:>6}  {:<10}  {:>4}  {:>8}  {}",
+            height,
+            hashes[block.hash.raw()],
+            block.transactions,
+            format_duration(now.saturating_sub(block.timestamp) as u64),
+            block.coinbase_address
+        ));
+    }
+    lines.push(String::new());
+    lines.push(format!("Peers ({})", snapshot.peers.len()));
+    lines.push(format!(
+        "  {:<22}  {:>9}  {:>9}  {:>9}  {:>6}",
+        "ADDRESS", "CONNECTED", "SENT", "RECEIVED", "IDLE"
+    ));
+    for peer in &snapshot.peers {
+        lines.push(format!(
+            "  {:<22}  {:>9}  {:>9}  {:>9}  {:>6}",
+            peer.address,
+            format_duration(peer.connected),
+            format_bytes(peer.bytes_sent),
+            format_bytes(peer.bytes_received),
+            peer.idle.map_or_else(|| "-".to_string(), format_duration)
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        3600..=86399 => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d {:02}h", seconds / 86400, seconds % 86400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Sha256;
+
+    #[test]
+    fn renders_dashboard() {
+        let hash = |byte: u8| BlockHash::new(Sha256::new([byte; 32]));
+        let snapshot = Snapshot {
+            uptime: 3725,
+            height: 11,
+            best_block_hash: hash(0xab),
+            difficulty: 1.0,
+            transaction_pool: TransactionPoolInfo {
+                size: 2,
+                bytes: 1500,
+                usage: 4000,
+                max_usage: 300_000_000,
+            },
+            peers: vec![PeerInfo {
+                address: "127.0.0.1:8335".to_string(),
+                connected: 90,
+                idle: None,
+                bytes_sent: 2_500_000,
+                bytes_received: 640,
+                traffic: Default::default(),
+            }],
+        };
+        let mut blocks = BTreeMap::new();
+        for (height, byte) in [(10, 0xcd), (11, 0xab)] {
+            blocks.insert(
+                height,
+                BlockSummary {
+                    hash: hash(byte),
+                    transactions: 3,
+                    timestamp: 1000 - 60 * (12 - height),
+                    coinbase_address: "alice".to_string(),
+                },
+            );
+        }
+
+        let screen = render("127.0.0.1:8334", &snapshot, &blocks, 1000);
+        let lines = screen.lines().collect::<Vec<&str>>();
+        assert_eq!(
+            lines[0],
+            "coolcoin top  127.0.0.1:8334  up 1h 02m  (Ctrl-C to quit)"
+        );
+        assert_eq!(lines[2], "Tip      height 11  ..abababab  difficulty 1.00");
+        assert_eq!(lines[3], "Mempool  2 txs  1.5 kB  memory 4.0 kB / 300.0 MB");
+        // The newest block first.
+        assert_eq!(lines[7], "      11  ..abababab     3    1m 00s  alice");
+        assert_eq!(lines[8], "      10  ..cdcdcdcd     3    2m 00s  alice");
+        assert_eq!(lines[10], "Peers (1)");
+        assert_eq!(
+            lines[12],
+            "  127.0.0.1:8335             1m 30s     2.5 MB      640 B       -"
+        );
+    }
+}
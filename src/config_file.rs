@@ -0,0 +1,213 @@
+use clap::{App, Arg, ArgSettings};
+use std::fs;
+use std::path::PathBuf;
+use toml::Value;
+
+pub const CONFIG_FILE: &str = "coolcoin.toml";
+
+/// The option of the daemon and the miner commands, which reads the rest of their options from
+/// the TOML file, so that e.g. long peer lists don't have to be given on the command line.
+pub fn conf_arg() -> Arg<'static> {
+    Arg::new("conf")
+        .long("conf")
+        .value_name("PATH")
+        .about("TOML file with the options of the command in the section named after it, e.g. peers = [\"127.0.0.1:8333\"] under [daemon]. Options given on the command line override the ones in the file. Defaults to coolcoin.toml in the data directory, if the command has one.")
+        .takes_value(true)
+        .required(false)
+}
+
+/// Returns the command line arguments extended by the options from the command's configuration
+/// file, unless they are already given on the command line.
+/// Commands without the conf option, and commands without the file, get the arguments unchanged.
+pub fn with_config_file(app: &App, mut args: Vec<String>) -> Result<Vec<String>, String> {
+    let command = match args.get(1).and_then(|name| app.find_subcommand(name)) {
+        Some(command) if find_arg(command, "conf").is_some() => command,
+        _ => return Ok(args),
+    };
+    let path = match option_value(command, &args[2..], "conf") {
+        Some(path) => PathBuf::from(path),
+        None => match default_path(command, &args[2..]) {
+            Some(path) if path.exists() => path,
+            _ => return Ok(args),
+        },
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read config file: {}: {}", path.display(), e))?;
+    let config = contents
+        .parse::<Value>()
+        .map_err(|e| format!("Invalid config file: {}: {}", path.display(), e))?;
+    let options = match config.get(command.get_name()) {
+        Some(Value::Table(options)) => options,
+        Some(_) => {
+            return Err(format!(
+                "Invalid config file: {}: {} must be a section.",
+                path.display(),
+                command.get_name()
+            ))
+        }
+        None => return Ok(args),
+    };
+
+    let mut config_args = vec![];
+    for (name, value) in options {
+        // Global options, such as log_level, can be given in any command's section.
+        let arg = find_arg(command, name)
+            .or_else(|| find_arg(app, name).filter(|arg| arg.get_global()))
+            .filter(|arg| arg.get_name() != "conf")
+            .ok_or_else(|| {
+                format!(
+                    "Invalid config file: {}: unknown option: {} in section: {}",
+                    path.display(),
+                    name,
+                    command.get_name()
+                )
+            })?;
+        if is_present(arg, &args[2..]) {
+            continue;
+        }
+        let long = format!("--{}", arg.get_long().unwrap());
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                // Flags, such as enable_logging, are only given when true.
+                Value::Boolean(enabled) if !arg.is_set(ArgSettings::TakesValue) => {
+                    if *enabled {
+                        config_args.push(long.clone());
+                    }
+                }
+                Value::String(value) => config_args.push(format!("{}={}", long, value)),
+                Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => {
+                    config_args.push(format!("{}={}", long, value))
+                }
+                _ => {
+                    return Err(format!(
+                        "Invalid config file: {}: unsupported value of option: {}",
+                        path.display(),
+                        name
+                    ))
+                }
+            }
+        }
+    }
+    // Options go right after the command's name, so that they don't end up in its subcommands.
+    args.splice(2..2, config_args);
+    Ok(args)
+}
+
+fn find_arg<'a, 'help>(command: &'a App<'help>, name: &str) -> Option<&'a Arg<'help>> {
+    command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(name))
+}
+
+/// Whether the option is given on the command line, by its long or short name.
+fn is_present(arg: &Arg, args: &[String]) -> bool {
+    let long = format!("--{}", arg.get_long().unwrap());
+    let short = arg.get_short().map(|short| format!("-{}", short));
+    args.iter().any(|token| {
+        *token == long
+            || token.starts_with(&format!("{}=", long))
+            || short
+                .as_ref()
+                .is_some_and(|short| token.starts_with(short.as_str()))
+    })
+}
+
+fn option_value(command: &App, args: &[String], name: &str) -> Option<String> {
+    let long = format!("--{}", find_arg(command, name)?.get_long()?);
+    let mut tokens = args.iter();
+    while let Some(token) = tokens.next() {
+        if *token == long {
+            return tokens.next().cloned();
+        }
+        if let Some(value) = token.strip_prefix(&format!("{}=", long)) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// The configuration file in the command's data directory, if it has one.
+fn default_path(command: &App, args: &[String]) -> Option<PathBuf> {
+    let data_dir = option_value(command, args, "data_dir").or_else(|| {
+        find_arg(command, "data_dir")?
+            .get_default_values()
+            .first()
+            .map(|value| value.to_string_lossy().to_string())
+    })?;
+    Some(PathBuf::from(data_dir).join(CONFIG_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> App<'static> {
+        App::new("coolcoin").subcommand(
+            App::new("daemon")
+                .arg(conf_arg())
+                .arg(
+                    Arg::new("server")
+                        .short('s')
+                        .long("server")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("peers")
+                        .long("peers")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(Arg::new("enable_logging").long("enable_logging"))
+                .arg(
+                    Arg::new("max_mempool")
+                        .long("max_mempool")
+                        .takes_value(true),
+                ),
+        )
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let path = std::env::temp_dir().join(format!("coolcoin_{}.toml", std::process::id()));
+        let conf = format!("--conf={}", path.display());
+        fs::write(
+            &path,
+            r#"
+            [daemon]
+            server = "127.0.0.1:8333"
+            peers = ["127.0.0.1:8334", "127.0.0.1:8335"]
+            enable_logging = true
+            max_mempool = 5
+            "#,
+        )
+        .unwrap();
+
+        let extended = with_config_file(
+            &app(),
+            args(&["coolcoin", "daemon", &conf, "-s", "127.0.0.1:9000"]),
+        )
+        .unwrap();
+        let matches = app().get_matches_from(extended);
+        let matches = matches.subcommand_matches("daemon").unwrap();
+        assert_eq!(matches.value_of("server"), Some("127.0.0.1:9000"));
+        assert_eq!(
+            matches.values_of("peers").unwrap().collect::<Vec<_>>(),
+            vec!["127.0.0.1:8334", "127.0.0.1:8335"]
+        );
+        assert!(matches.is_present("enable_logging"));
+        assert_eq!(matches.value_of("max_mempool"), Some("5"));
+
+        fs::write(&path, "[daemon]\nunknown = 1\n").unwrap();
+        let result = with_config_file(&app(), args(&["coolcoin", "daemon", &conf]));
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::core::utxo_pool::UnspentOutput;
+use crate::core::{Address, Coolcoin};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The balance of the address, i.e. the sum of the unspent outputs sent to it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountBalance {
+    address: Address,
+    // The amount that can be spent in the next block.
+    spendable: Coolcoin,
+    // The amount of the coinbase outputs that can't be spent until they mature.
+    immature: Coolcoin,
+    num_outputs: usize,
+}
+
+impl AccountBalance {
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    pub fn spendable(&self) -> Coolcoin {
+        self.spendable
+    }
+
+    pub fn immature(&self) -> Coolcoin {
+        self.immature
+    }
+
+    pub fn num_outputs(&self) -> usize {
+        self.num_outputs
+    }
+}
+
+pub struct AccountBalances;
+
+impl AccountBalances {
+    /// Sums the unspent outputs by their addresses, ordered by the address.
+    pub fn extract_account_balances(outputs: &[UnspentOutput]) -> Vec<AccountBalance> {
+        let mut balances = BTreeMap::<&Address, AccountBalance>::new();
+        for output in outputs {
+            let balance = balances
+                .entry(output.address())
+                .or_insert_with(|| AccountBalance {
+                    address: output.address().clone(),
+                    spendable: Coolcoin::zero(),
+                    immature: Coolcoin::zero(),
+                    num_outputs: 0,
+                });
+            // The next block is the coinbase block's height plus the confirmations.
+            if !output.is_mature() {
+                balance.immature = balance.immature + output.amount();
+            } else {
+                balance.spendable = balance.spendable + output.amount();
+            }
+            balance.num_outputs += 1;
+        }
+        balances.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hash::hash;
+    use crate::core::transaction::{OutputIndex, TransactionId};
+    use crate::core::validation::COINBASE_MATURITY;
+
+    fn output(address: &str, amount: i64, confirmations: u32, is_coinbase: bool) -> UnspentOutput {
+        UnspentOutput::new(
+            TransactionId::new(hash(&amount.to_le_bytes())),
+            OutputIndex::new(0),
+            Address::new(address.to_string()),
+            amount.into(),
+            confirmations,
+            is_coinbase,
+            COINBASE_MATURITY,
+        )
+    }
+
+    #[test]
+    fn sums_outputs_by_address() {
+        let balances = AccountBalances::extract_account_balances(&[
+            output("bob", 10, 1, false),
+            output("alice", 50, COINBASE_MATURITY, true),
+            output("bob", 50, COINBASE_MATURITY - 1, true),
+            output("bob", 5, 3, false),
+        ]);
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[0].address().as_str(), "alice");
+        assert_eq!(balances[0].spendable(), 50.into());
+        assert_eq!(balances[0].immature(), Coolcoin::zero());
+        assert_eq!(balances[1].address().as_str(), "bob");
+        assert_eq!(balances[1].spendable(), 15.into());
+        assert_eq!(balances[1].immature(), 50.into());
+        assert_eq!(balances[1].num_outputs(), 3);
+    }
+}
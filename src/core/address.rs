@@ -1,16 +1,88 @@
-use crate::core::Sha256;
+use crate::core::serialize::{Decodable, Encodable, Reader};
 use serde::{Deserialize, Serialize};
 use serde_big_array::big_array;
+use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 big_array! {BigArray;}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The longest address, in bytes, that is accepted from the user.
+pub const MAX_ADDRESS_LENGTH: usize = 128;
+
+/// Identifies the owner of the outputs, which are locked to it. It's the only identity type:
+/// the wallet's addresses, the miner's coinbase and payout addresses, and the addresses
+/// in the RPCs and on the command line are all parsed into it.
+/// Every address is validated, whether it's parsed, deserialized or decoded.
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(try_from = "String")]
 pub struct Address(String);
 
 impl Address {
+    /// Creates the address that is known to be valid, e.g. a constant or the wallet's.
+    /// Panics if it isn't, so use `parse` for the addresses that come from the user.
     pub fn new(address: String) -> Self {
-        Self(address)
+        match Self::try_from(address) {
+            Ok(address) => address,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Encodable for Address {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.0.encode(output);
+    }
+}
+
+impl Decodable for Address {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Self::try_from(String::decode(input)?)
+    }
+}
+
+/// Validates the address, e.g. the one given by the user or received from the peer.
+/// The address can be any text, except that it can't be empty, too long, start or end with
+/// whitespace, or contain control characters or the separators ':' and ',' of the lists
+/// of outputs and addresses on the command line.
+impl TryFrom<String> for Address {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.is_empty() {
+            return Err("The address can't be empty.".to_string());
+        }
+        if s.len() > MAX_ADDRESS_LENGTH {
+            return Err(format!(
+                "The address: {} is longer than {} bytes.",
+                s, MAX_ADDRESS_LENGTH
+            ));
+        }
+        if s.trim() != s {
+            return Err(format!(
+                "The address: {:?} starts or ends with whitespace.",
+                s
+            ));
+        }
+        match s.chars().find(|c| c.is_control() || *c == ':' || *c == ',') {
+            Some(c) => Err(format!(
+                "The address: {:?} contains an invalid character: {:?}.",
+                s, c
+            )),
+            None => Ok(Self(s)),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s.to_string())
     }
 }
 
@@ -19,3 +91,47 @@ impl Display for Address {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_valid_addresses() {
+        assert_eq!(
+            "nikola's pocket".parse(),
+            Ok(Address::new("nikola's pocket".to_string()))
+        );
+        assert!("a".repeat(MAX_ADDRESS_LENGTH).parse::<Address>().is_ok());
+
+        for invalid in ["", " alice", "alice\n", "alice:10", "alice,bob", "al\tice"] {
+            assert!(invalid.parse::<Address>().is_err(), "{:?}", invalid);
+        }
+        assert!("a"
+            .repeat(MAX_ADDRESS_LENGTH + 1)
+            .parse::<Address>()
+            .is_err());
+    }
+
+    #[test]
+    fn validates_deserialized_and_decoded_addresses() {
+        let alice = Address::new("alice".to_string());
+        assert_eq!(serde_json::from_str::<Address>("\"alice\"").unwrap(), alice);
+        assert_eq!(
+            bincode::deserialize::<Address>(&bincode::serialize(&alice).unwrap()).unwrap(),
+            alice
+        );
+        let mut encoded = vec![];
+        alice.encode(&mut encoded);
+        assert_eq!(Address::decode(&mut Reader::new(&encoded)), Ok(alice));
+
+        for invalid in ["", " alice", "alice:10"] {
+            assert!(serde_json::from_str::<Address>(&format!("{:?}", invalid)).is_err());
+            let serialized = bincode::serialize(invalid).unwrap();
+            assert!(bincode::deserialize::<Address>(&serialized).is_err());
+            let mut encoded = vec![];
+            invalid.to_string().encode(&mut encoded);
+            assert!(Address::decode(&mut Reader::new(&encoded)).is_err());
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use crate::core::block::BlockHash;
+use crate::core::transaction::{OutputIndex, TransactionId};
+use crate::core::{Address, Block, Coolcoin, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    // The addresses have only received coins in the transaction.
+    Received,
+    // The addresses have spent their outputs in the transaction, possibly receiving the change.
+    Sent,
+}
+
+/// The transaction that sends coins to, or spends the outputs of, the addresses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressTransaction {
+    txid: TransactionId,
+    direction: Direction,
+    // The change of the addresses' total balance, negative if they have sent more than received.
+    amount: Coolcoin,
+    // Zero if the transaction is in the transaction pool.
+    confirmations: u32,
+    block_hash: Option<BlockHash>,
+    // The timestamp of the block, if the transaction is confirmed.
+    block_time: Option<u32>,
+}
+
+impl AddressTransaction {
+    pub fn txid(&self) -> &TransactionId {
+        &self.txid
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub fn amount(&self) -> Coolcoin {
+        self.amount
+    }
+
+    pub fn confirmations(&self) -> u32 {
+        self.confirmations
+    }
+
+    pub fn block_hash(&self) -> Option<&BlockHash> {
+        self.block_hash.as_ref()
+    }
+
+    pub fn block_time(&self) -> Option<u32> {
+        self.block_time
+    }
+}
+
+/// Finds the transactions of the addresses, e.g. the wallet's, by scanning the active blockchain, followed by
+/// the unconfirmed transactions, since the node doesn't index transactions by address.
+pub struct AddressHistory<'a> {
+    addresses: HashSet<&'a Address>,
+    // The amounts of the addresses' outputs seen so far, to tell which inputs spend them.
+    outputs: HashMap<(TransactionId, OutputIndex), Coolcoin>,
+    transactions: Vec<AddressTransaction>,
+}
+
+impl<'a> AddressHistory<'a> {
+    /// Returns the transactions of the addresses, oldest first.
+    /// The blocks are the active blockchain, starting with the genesis block.
+    pub fn transactions(
+        addresses: &'a [Address],
+        active_blockchain: &[Arc<Block>],
+        unconfirmed: &[Transaction],
+    ) -> Vec<AddressTransaction> {
+        let mut history = Self {
+            addresses: addresses.iter().collect(),
+            outputs: HashMap::new(),
+            transactions: vec![],
+        };
+        let tip_height = active_blockchain.len() as u32 - 1;
+        for (height, block) in active_blockchain.iter().enumerate() {
+            for transaction in block.transactions() {
+                history.add(transaction, tip_height - height as u32 + 1, Some(block));
+            }
+        }
+        for transaction in unconfirmed {
+            history.add(transaction, 0, None);
+        }
+        history.transactions
+    }
+
+    fn add(&mut self, transaction: &Transaction, confirmations: u32, block: Option<&Block>) {
+        let sent = transaction
+            .inputs()
+            .iter()
+            .filter(|input| !input.is_coinbase())
+            .filter_map(|input| {
+                self.outputs
+                    .get(&(*input.utxo_id(), *input.output_index()))
+                    .copied()
+            })
+            .collect::<Vec<_>>();
+        let mut received = vec![];
+        for (index, output) in transaction.outputs().iter().enumerate() {
+            if self.addresses.contains(output.to()) {
+                self.outputs.insert(
+                    (*transaction.id(), OutputIndex::new(index as i32)),
+                    output.amount(),
+                );
+                received.push(output.amount());
+            }
+        }
+        if sent.is_empty() && received.is_empty() {
+            return;
+        }
+        let direction = match sent.is_empty() {
+            true => Direction::Received,
+            false => Direction::Sent,
+        };
+        self.transactions.push(AddressTransaction {
+            txid: *transaction.id(),
+            direction,
+            amount: received.into_iter().sum::<Coolcoin>() - sent.into_iter().sum(),
+            confirmations,
+            block_hash: block.map(|block| *block.id()),
+            block_time: block.map(|block| block.header().timestamp()),
+        });
+    }
+}
+
+/// Formats the transactions, oldest first, as CSV with the running balance of the addresses,
+/// one row per transaction. Unconfirmed transactions don't have the time.
+pub fn history_csv(transactions: &[AddressTransaction]) -> String {
+    let mut csv = "time,txid,direction,credit,debit,balance,confirmations,block_hash\n".to_string();
+    let mut balance = Coolcoin::zero();
+    for transaction in transactions {
+        balance = balance + transaction.amount;
+        let (credit, debit) = match transaction.amount >= Coolcoin::zero() {
+            true => (transaction.amount, Coolcoin::zero()),
+            false => (Coolcoin::zero(), Coolcoin::zero() - transaction.amount),
+        };
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{}\n",
+            transaction
+                .block_time
+                .map(|time| time.to_string())
+                .unwrap_or_default(),
+            transaction.txid.raw(),
+            match transaction.direction {
+                Direction::Received => "received",
+                Direction::Sent => "sent",
+            },
+            credit.value(),
+            debit.value(),
+            balance.value(),
+            transaction.confirmations,
+            transaction
+                .block_hash
+                .map(|hash| hash.to_string())
+                .unwrap_or_default(),
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::BlockHeader;
+    use crate::core::hash::MerkleHash;
+    use crate::core::transaction::{TransactionInput, TransactionOutput};
+    use crate::core::Sha256;
+
+    fn block(transactions: Vec<Transaction>) -> Block {
+        let header = BlockHeader::new(
+            BlockHash::new(Sha256::new([0; 32])),
+            MerkleHash::new(Sha256::new([0; 32])),
+            0,
+            0,
+            0,
+        );
+        Block::new(header, transactions)
+    }
+
+    fn output(address: &str, amount: i64) -> TransactionOutput {
+        TransactionOutput::new(Address::new(address.to_string()), amount.into())
+    }
+
+    #[test]
+    fn lists_received_and_sent_transactions() {
+        let coinbase = Transaction::new(
+            vec![TransactionInput::new_coinbase()],
+            vec![output("alice", 50)],
+            0,
+        )
+        .unwrap();
+        let payment = Transaction::new(
+            vec![TransactionInput::new(*coinbase.id(), OutputIndex::new(0))],
+            vec![output("bob", 20), output("alice", 29)],
+            0,
+        )
+        .unwrap();
+        let unrelated = Transaction::new(
+            vec![TransactionInput::new(*payment.id(), OutputIndex::new(0))],
+            vec![output("carol", 19)],
+            0,
+        )
+        .unwrap();
+        let blocks = [block(vec![coinbase.clone()]), block(vec![payment.clone()])];
+        let blocks = blocks.map(Arc::new);
+
+        let alice = [Address::new("alice".to_string())];
+        let transactions =
+            AddressHistory::transactions(&alice, &blocks, std::slice::from_ref(&unrelated));
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].txid(), coinbase.id());
+        assert_eq!(transactions[0].direction(), Direction::Received);
+        assert_eq!(transactions[0].amount(), 50.into());
+        assert_eq!(transactions[0].confirmations(), 2);
+        assert_eq!(transactions[1].direction(), Direction::Sent);
+        assert_eq!(transactions[1].amount(), Coolcoin::new(-21));
+        assert_eq!(transactions[1].confirmations(), 1);
+
+        let bob = [Address::new("bob".to_string())];
+        let transactions =
+            AddressHistory::transactions(&bob, &blocks, std::slice::from_ref(&unrelated));
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[1].direction(), Direction::Sent);
+        assert_eq!(transactions[1].amount(), Coolcoin::new(-20));
+        assert_eq!(transactions[1].confirmations(), 0);
+        assert_eq!(transactions[1].block_hash(), None);
+
+        // The payment from alice to bob only moves 1 coin (the fee) out of their wallet.
+        let both = [alice[0].clone(), bob[0].clone()];
+        let transactions = AddressHistory::transactions(&both, &blocks, &[]);
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[1].direction(), Direction::Sent);
+        assert_eq!(transactions[1].amount(), Coolcoin::new(-1));
+
+        let transactions = AddressHistory::transactions(&alice, &blocks, &[]);
+        let csv = history_csv(&transactions);
+        let rows = csv.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[0],
+            "time,txid,direction,credit,debit,balance,confirmations,block_hash"
+        );
+        assert!(rows[1].starts_with(&format!("0,{},received,50,0,50,2,", coinbase.id().raw())));
+        assert!(rows[2].starts_with(&format!("0,{},sent,0,21,29,1,", payment.id().raw())));
+    }
+}
@@ -0,0 +1,221 @@
+use crate::core::storage::{self, FileStorage};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// The default duration of the ban in seconds, i.e. one day.
+pub const DEFAULT_BAN_TIME: u32 = 24 * 60 * 60;
+
+/// An IP address or a range of addresses, e.g. 192.168.1.0/24.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Subnet {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl Subnet {
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(subnet), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_length as u32)
+                    .unwrap_or(0);
+                u32::from(subnet) & mask == u32::from(*address) & mask
+            }
+            (IpAddr::V6(subnet), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_length as u32)
+                    .unwrap_or(0);
+                u128::from(subnet) & mask == u128::from(*address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    /// Parses the subnet in the CIDR notation, or a single address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = match s.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (s, None),
+        };
+        let address = address
+            .parse::<IpAddr>()
+            .map_err(|e| format!("Invalid address: {}: {}", address, e))?;
+        let max_prefix_length = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_length| *prefix_length <= max_prefix_length)
+                .ok_or_else(|| format!("Invalid prefix length: {}", prefix_length))?,
+            None => max_prefix_length,
+        };
+        Ok(Self {
+            address,
+            prefix_length,
+        })
+    }
+}
+
+impl Display for Subnet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+impl Serialize for Subnet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Subnet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ban {
+    subnet: Subnet,
+    // Time (seconds from Unix Epoch) at which the ban expires.
+    banned_until: u32,
+}
+
+impl Ban {
+    pub fn subnet(&self) -> &Subnet {
+        &self.subnet
+    }
+
+    pub fn banned_until(&self) -> u32 {
+        self.banned_until
+    }
+}
+
+/// The change of the ban list requested by the operator.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SetBanCommand {
+    // Bans the subnet for the given number of seconds.
+    Add(u32),
+    Remove,
+}
+
+/// Addresses from which the node doesn't accept connections, e.g. the misbehaving peers.
+/// The list is persisted, so the bans survive restarts.
+#[derive(Default, Serialize, Deserialize)]
+pub struct BanList {
+    bans: Vec<Ban>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl BanList {
+    /// Loads the ban list from the given file, or starts with an empty one if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut ban_list =
+            storage::load_json::<Self>(&FileStorage, path, "ban list")?.unwrap_or_default();
+        ban_list.path = path.to_path_buf();
+        Ok(ban_list)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        storage::save(&FileStorage, &self.path, contents.as_bytes())
+    }
+
+    /// Applies the command and saves the list.
+    pub fn set_ban(
+        &mut self,
+        subnet: Subnet,
+        command: SetBanCommand,
+        current_time: u32,
+    ) -> Result<(), String> {
+        self.remove_expired(current_time);
+        match command {
+            SetBanCommand::Add(duration) => {
+                self.bans.retain(|ban| ban.subnet != subnet);
+                self.bans.push(Ban {
+                    subnet,
+                    banned_until: current_time.saturating_add(duration),
+                });
+            }
+            SetBanCommand::Remove => {
+                let num_bans = self.bans.len();
+                self.bans.retain(|ban| ban.subnet != subnet);
+                if self.bans.len() == num_bans {
+                    return Err(format!("Subnet: {} is not banned.", subnet));
+                }
+            }
+        }
+        self.save()
+    }
+
+    /// Returns the bans that haven't expired yet.
+    pub fn bans(&self, current_time: u32) -> Vec<Ban> {
+        self.bans
+            .iter()
+            .filter(|ban| ban.banned_until > current_time)
+            .cloned()
+            .collect()
+    }
+
+    pub fn is_banned(&self, address: &IpAddr, current_time: u32) -> bool {
+        self.bans
+            .iter()
+            .any(|ban| ban.banned_until > current_time && ban.subnet.contains(address))
+    }
+
+    fn remove_expired(&mut self, current_time: u32) {
+        self.bans.retain(|ban| ban.banned_until > current_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn bans_subnets_until_they_expire() {
+        let path = std::env::temp_dir().join(format!("ban_list_{}.json", std::process::id()));
+        let mut ban_list = BanList::load(&path).unwrap();
+        let subnet = "192.168.1.0/24".parse::<Subnet>().unwrap();
+        ban_list
+            .set_ban(subnet, SetBanCommand::Add(100), 1000)
+            .unwrap();
+        ban_list
+            .set_ban("::1".parse().unwrap(), SetBanCommand::Add(200), 1000)
+            .unwrap();
+
+        let ban_list = BanList::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(ban_list.is_banned(&"192.168.1.7".parse().unwrap(), 1099));
+        assert!(!ban_list.is_banned(&"192.168.1.7".parse().unwrap(), 1100));
+        assert!(!ban_list.is_banned(&"192.168.2.7".parse().unwrap(), 1099));
+        assert!(ban_list.is_banned(&"::1".parse().unwrap(), 1100));
+        assert_eq!(ban_list.bans(1100).len(), 1);
+
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+        assert!("0.0.0.0/0"
+            .parse::<Subnet>()
+            .unwrap()
+            .contains(&"10.1.2.3".parse().unwrap()));
+    }
+}
@@ -1,7 +1,9 @@
 use crate::core::hash::{hash, MerkleHash};
+use crate::core::serialize::{self, Decodable, Encodable, Reader};
 use crate::core::{Sha256, Transaction};
-use serde::{Deserialize, Serialize};
-use serde_big_array::big_array;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Digest;
 use std::fmt::{Display, Formatter};
 use std::hash::Hash;
 
@@ -22,13 +24,25 @@ impl BlockHash {
     }
 }
 
+impl Encodable for BlockHash {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.0.encode(output);
+    }
+}
+
+impl Decodable for BlockHash {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self(Sha256::decode(input)?))
+    }
+}
+
 impl Display for BlockHash {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", crate::core::as_hex(&self.0.bytes()[..]))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Clone)]
 pub struct BlockHeader {
     // Version number ignored.
     // A reference to the hash of the previous (parent) block in the chain.
@@ -41,6 +55,81 @@ pub struct BlockHeader {
     difficulty_target: u32,
     // A counter used for the Proof-of-Work algorithm.
     nonce: u32,
+    // The header is immutable, so the hash is computed once, when the header is created.
+    // It isn't serialized, but recomputed when the header is deserialized.
+    hash: BlockHash,
+}
+
+/// The fields of the header in JSON, from which the deserialized header computes its hash.
+#[derive(Deserialize)]
+struct UnhashedBlockHeader {
+    previous_block_hash: BlockHash,
+    merkle_root: MerkleHash,
+    timestamp: u32,
+    difficulty_target: u32,
+    nonce: u32,
+}
+
+impl From<UnhashedBlockHeader> for BlockHeader {
+    fn from(header: UnhashedBlockHeader) -> Self {
+        Self::new(
+            header.previous_block_hash,
+            header.merkle_root,
+            header.timestamp,
+            header.difficulty_target,
+            header.nonce,
+        )
+    }
+}
+
+/// The 76 bytes of the fields in their order, like Bitcoin's 80 byte header without
+/// the version.
+impl Encodable for BlockHeader {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.previous_block_hash.encode(output);
+        self.merkle_root.encode(output);
+        self.timestamp.encode(output);
+        self.difficulty_target.encode(output);
+        self.nonce.encode(output);
+    }
+}
+
+impl Decodable for BlockHeader {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self::new(
+            BlockHash::decode(input)?,
+            MerkleHash::decode(input)?,
+            u32::decode(input)?,
+            u32::decode(input)?,
+            u32::decode(input)?,
+        ))
+    }
+}
+
+/// The header is serialized as its canonical encoding by the binary formats, e.g. in the peer
+/// messages, and with its fields, without the hash, by the human readable ones.
+impl Serialize for BlockHeader {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serialize::serialize_bytes(self, serializer);
+        }
+        let mut state = serializer.serialize_struct("BlockHeader", 5)?;
+        state.serialize_field("previous_block_hash", &self.previous_block_hash)?;
+        state.serialize_field("merkle_root", &self.merkle_root)?;
+        state.serialize_field("timestamp", &self.timestamp)?;
+        state.serialize_field("difficulty_target", &self.difficulty_target)?;
+        state.serialize_field("nonce", &self.nonce)?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHeader {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.is_human_readable() {
+            true => Ok(UnhashedBlockHeader::deserialize(deserializer)?.into()),
+            false => serialize::deserialize_bytes(deserializer),
+        }
+    }
 }
 
 impl BlockHeader {
@@ -51,30 +140,57 @@ impl BlockHeader {
         difficulty_target: u32,
         nonce: u32,
     ) -> Self {
+        let hashed_prefix = Self::format_hashed_prefix(
+            &previous_block_hash,
+            &merkle_root,
+            timestamp,
+            difficulty_target,
+        );
         Self {
             previous_block_hash,
             merkle_root,
             timestamp,
             difficulty_target,
             nonce,
+            hash: Self::compute_hash(&hashed_prefix, nonce),
         }
     }
 
     pub fn hash(&self) -> BlockHash {
+        self.hash
+    }
+
+    fn compute_hash(hashed_prefix: &str, nonce: u32) -> BlockHash {
         // We are going to pretend that we are encoding the header with the format that
         // is machine independent.
         // However, what we are doing may not work on every platform the same way (not sure how rust represents string in memory).
         // But this is okay for learning purposes.
         // In the real production, we would encode this using universal wire format.
-        let data = format!(
-            "{}{}{}{}{}",
-            self.previous_block_hash,
-            self.merkle_root,
+        let data = format!("{}{}", hashed_prefix, nonce);
+        BlockHash::new(hash(data.as_bytes()))
+    }
+
+    /// Returns the hashed data of the header without the nonce, which is the tail of
+    /// the hashed data. Miners compute it once, and only append the nonce for each attempt.
+    pub fn hashed_prefix(&self) -> String {
+        Self::format_hashed_prefix(
+            &self.previous_block_hash,
+            &self.merkle_root,
             self.timestamp,
             self.difficulty_target,
-            self.nonce
-        );
-        BlockHash::new(hash(data.as_bytes()))
+        )
+    }
+
+    fn format_hashed_prefix(
+        previous_block_hash: &BlockHash,
+        merkle_root: &MerkleHash,
+        timestamp: u32,
+        difficulty_target: u32,
+    ) -> String {
+        format!(
+            "{}{}{}{}",
+            previous_block_hash, merkle_root, timestamp, difficulty_target,
+        )
     }
     pub fn timestamp(&self) -> u32 {
         self.timestamp
@@ -93,13 +209,122 @@ impl BlockHeader {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Hashes the header with many nonces, e.g. while mining. The state of SHA-256 after the part
+/// of the hashed data that precedes the nonce (the midstate) is computed once, so each attempt
+/// only hashes the nonce and the rest of the last block of the data.
+#[derive(Clone)]
+pub struct HeaderHasher {
+    midstate: sha2::Sha256,
+}
+
+impl HeaderHasher {
+    /// The nonce of the header is ignored.
+    pub fn new(header: &BlockHeader) -> Self {
+        let mut midstate = sha2::Sha256::new();
+        midstate.update(header.hashed_prefix().as_bytes());
+        Self { midstate }
+    }
+
+    /// Returns the same hash as the header with the given nonce.
+    pub fn hash(&self, nonce: u32) -> BlockHash {
+        // The nonce is hashed in decimal, like the rest of the header, which is at most
+        // 10 digits for u32.
+        let mut digits = [0; 10];
+        let mut start = digits.len();
+        let mut remainder = nonce;
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (remainder % 10) as u8;
+            remainder /= 10;
+            if remainder == 0 {
+                break;
+            }
+        }
+        let mut hasher = self.midstate.clone();
+        hasher.update(&digits[start..]);
+        BlockHash::new(Sha256::new(hasher.finalize().into()))
+    }
+}
+
+// The maximum total size of the serialized transactions in the block, in bytes.
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+
+#[derive(Debug, Clone)]
 pub struct Block {
+    // Always the hash of the header, including when the block is deserialized.
     id: BlockHash,
     header: BlockHeader,
     transactions: Vec<Transaction>,
 }
 
+// Blocks with arbitrary headers and well-formed transactions, for the fuzz targets.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Block {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let header = BlockHeader::new(
+            BlockHash::new(Sha256::new(u.arbitrary()?)),
+            MerkleHash::new(Sha256::new(u.arbitrary()?)),
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+        );
+        Ok(Self::new(header, u.arbitrary()?))
+    }
+}
+
+/// The block in JSON, whose id is ignored in favor of the header's hash.
+#[derive(Deserialize)]
+struct SerializedBlock {
+    #[allow(dead_code)]
+    id: BlockHash,
+    header: BlockHeader,
+    transactions: Vec<Transaction>,
+}
+
+impl From<SerializedBlock> for Block {
+    fn from(block: SerializedBlock) -> Self {
+        Self::new(block.header, block.transactions)
+    }
+}
+
+/// The header followed by the transactions. The id isn't encoded, but recomputed.
+impl Encodable for Block {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.header.encode(output);
+        self.transactions.encode(output);
+    }
+}
+
+impl Decodable for Block {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self::new(BlockHeader::decode(input)?, Vec::decode(input)?))
+    }
+}
+
+/// The block is serialized as its canonical encoding by the binary formats, e.g. in the peer
+/// messages, and with its fields, including the id, by the human readable ones.
+impl Serialize for Block {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serialize::serialize_bytes(self, serializer);
+        }
+        let mut state = serializer.serialize_struct("Block", 3)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("header", &self.header)?;
+        state.serialize_field("transactions", &self.transactions)?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Block {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.is_human_readable() {
+            true => Ok(SerializedBlock::deserialize(deserializer)?.into()),
+            false => serialize::deserialize_bytes(deserializer),
+        }
+    }
+}
+
 impl Block {
     pub fn new(header: BlockHeader, transactions: Vec<Transaction>) -> Self {
         Self {
@@ -120,4 +345,136 @@ impl Block {
     pub fn transactions(&self) -> &Vec<Transaction> {
         &self.transactions
     }
+
+    /// Returns the canonical binary serialization of the block.
+    pub fn serialize(&self) -> Vec<u8> {
+        serialize::serialize(self)
+    }
+
+    /// Parses the block from its canonical binary serialization.
+    /// The block id and the transaction ids are recomputed rather than trusted.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+        serialize::deserialize::<Self>(bytes).map_err(|e| format!("Invalid block encoding: {}", e))
+    }
+
+    /// Returns the hex-encoded canonical serialization, i.e. the raw block.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.serialize())
+    }
+
+    /// Parses the raw block, see `deserialize`.
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        let bytes = hex::decode(hex).map_err(|e| format!("Invalid hex: {}", e))?;
+        Self::deserialize(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_hasher_matches_header_hash() {
+        let previous_block_hash = BlockHash::new(Sha256::new([3; 32]));
+        let merkle_root = MerkleHash::new(Sha256::new([1; 32]));
+        let header = BlockHeader::new(previous_block_hash, merkle_root.clone(), 100, 4, 0);
+        let hasher = HeaderHasher::new(&header);
+        for nonce in (0..1000).chain([9, 10, 99_999, 1_000_000_000, u32::MAX]) {
+            let header = BlockHeader::new(previous_block_hash, merkle_root.clone(), 100, 4, nonce);
+            assert_eq!(hasher.hash(nonce), header.hash());
+        }
+    }
+
+    #[test]
+    fn deserialized_block_id_is_header_hash() {
+        let genesis = BlockHash::new(Sha256::new([0; 32]));
+        let merkle_root = MerkleHash::new(Sha256::new([1; 32]));
+        let header = BlockHeader::new(genesis, merkle_root, 100, 4, 7);
+        let block = Block::new(header.clone(), vec![]);
+        assert_eq!(*block.id(), header.hash());
+
+        // The id isn't encoded, but recomputed from the header.
+        let bytes = block.serialize();
+        assert_eq!(bytes.len(), 76 + 1);
+        let decoded = Block::deserialize(&bytes).unwrap();
+        assert_eq!(*decoded.id(), header.hash());
+        assert_eq!(decoded.header().hash(), header.hash());
+
+        // The id in JSON is ignored, even if it doesn't match the header.
+        let mut json = serde_json::to_value(&block).unwrap();
+        json["id"] = serde_json::to_value(BlockHash::new(Sha256::new([9; 32]))).unwrap();
+        let decoded = serde_json::from_value::<Block>(json).unwrap();
+        assert_eq!(*decoded.id(), header.hash());
+
+        // The messages carry the canonical encoding.
+        let decoded = bincode::deserialize::<Block>(&bincode::serialize(&block).unwrap()).unwrap();
+        assert_eq!(decoded.serialize(), bytes);
+
+        // The hash isn't part of the serialized header.
+        let json = serde_json::to_value(&header).unwrap();
+        assert!(json.get("hash").is_none());
+        let decoded = serde_json::from_value::<BlockHeader>(json).unwrap();
+        assert_eq!(decoded.hash(), header.hash());
+    }
+
+    #[test]
+    fn round_trips_raw_blocks() {
+        use crate::core::transaction::{TransactionInput, TransactionOutput};
+        use crate::core::Address;
+
+        let coinbase = |height: u32| {
+            Transaction::new(
+                vec![TransactionInput::new_coinbase_with_data(
+                    height.to_le_bytes().to_vec(),
+                )],
+                vec![TransactionOutput::new(
+                    Address::new("alice".to_string()),
+                    50.into(),
+                )],
+                height,
+            )
+            .unwrap()
+        };
+        let mut blocks = vec![];
+        for (fill, field) in [(0x00, 0), (0x5a, 1_700_000_000), (0xff, u32::MAX)] {
+            for num_transactions in [0, 1, 0xfc, 0xfd] {
+                let header = BlockHeader::new(
+                    BlockHash::new(Sha256::new([fill; 32])),
+                    MerkleHash::new(Sha256::new([!fill; 32])),
+                    field,
+                    field,
+                    field,
+                );
+                blocks.push(Block::new(
+                    header,
+                    (0..num_transactions).map(coinbase).collect(),
+                ));
+            }
+        }
+
+        for block in &blocks {
+            let hex = block.to_hex();
+            assert_eq!(hex, hex::encode(block.serialize()));
+            for hex in [hex.clone(), hex.to_uppercase()] {
+                let decoded = Block::from_hex(&hex).unwrap();
+                assert_eq!(decoded.id(), block.id());
+                assert_eq!(decoded.serialize(), block.serialize());
+                let ids = |block: &Block| {
+                    block
+                        .transactions()
+                        .iter()
+                        .map(|transaction| *transaction.id())
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(ids(&decoded), ids(block));
+            }
+            if block.transactions().len() <= 1 {
+                for len in (0..hex.len()).step_by(2) {
+                    assert!(Block::from_hex(&hex[..len]).is_err(), "{}", len);
+                }
+            }
+            assert!(Block::from_hex(&(hex.clone() + "00")).is_err());
+            assert!(Block::from_hex(&hex[1..]).is_err());
+        }
+    }
 }
@@ -1,11 +1,7 @@
-use crate::core::block::{BlockHash, BlockHeader};
-use crate::core::hash::merkle_tree_from_transactions;
-use crate::core::miner::Miner;
-use crate::core::transaction::{TransactionInput, TransactionOutput};
-use crate::core::{
-    merkle_tree, Address, Block, BlockTree, BlockValidator, ChainContext, Coolcoin, OrphanedBlocks,
-    Sha256, Transaction, TransactionPool, UtxoContext, UtxoPool,
-};
+use crate::core::block::BlockHash;
+use crate::core::chain_params::ChainParams;
+use crate::core::{Block, BlockTree, OrphanedBlocks};
+use std::sync::Arc;
 
 /// Responsible for processing new blocks and new transactions from the network.
 /// It validates that blocks and transactions are valid.
@@ -15,9 +11,19 @@ pub struct BlockchainManager {
     orphaned_blocks: OrphanedBlocks,
 }
 
+impl Default for BlockchainManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BlockchainManager {
     pub fn new() -> Self {
-        let genesis_block = Self::genesis_block();
+        Self::with_genesis_block(Self::genesis_block())
+    }
+
+    /// Creates the blockchain of the network with the given genesis block.
+    pub fn with_genesis_block(genesis_block: Block) -> Self {
         Self {
             block_tree: BlockTree::new(genesis_block),
             orphaned_blocks: OrphanedBlocks::new(),
@@ -28,7 +34,7 @@ impl BlockchainManager {
         self.block_tree.tip()
     }
 
-    pub fn all_blocks(&self) -> Vec<Block> {
+    pub fn all_blocks(&self) -> Vec<Arc<Block>> {
         let mut all_blocks = vec![];
         for block in &self.block_tree.all() {
             all_blocks.push(block.clone());
@@ -40,7 +46,7 @@ impl BlockchainManager {
         all_blocks
     }
 
-    pub fn orphaned_blocks(&self) -> Vec<Block> {
+    pub fn orphaned_blocks(&self) -> Vec<Arc<Block>> {
         self.orphaned_blocks.all()
     }
 
@@ -49,7 +55,7 @@ impl BlockchainManager {
     }
 
     /// Assumes that the block is valid.
-    pub fn new_block(&mut self, block: Block) -> Vec<Block> {
+    pub fn new_block(&mut self, block: Arc<Block>) -> Vec<Arc<Block>> {
         if self.block_tree.exists(block.header().previous_block_hash()) {
             let orphans = self.orphaned_blocks.remove(block.id());
             // If the parent exists, validate the node and insert it
@@ -63,7 +69,7 @@ impl BlockchainManager {
     }
 
     /// Useful for client-side reconstruction of the blockchain.
-    pub fn new_block_reinsert_orphans(&mut self, block: Block) {
+    pub fn new_block_reinsert_orphans(&mut self, block: Arc<Block>) {
         if !self.exists(&block) {
             let orphans = self.new_block(block);
             for orphan in orphans {
@@ -72,88 +78,38 @@ impl BlockchainManager {
         }
     }
 
+    /// Removes the invalid block and its descendants from the block tree,
+    /// see `BlockTree::invalidate`.
+    pub fn invalidate_block(&mut self, block_hash: &BlockHash, preferred_tip: &BlockHash) {
+        self.block_tree.invalidate(block_hash, preferred_tip);
+    }
+
     pub fn exists(&self, block: &Block) -> bool {
         self.orphaned_blocks.exists(block) || self.block_tree.exists(&block.header().hash())
     }
+    /// The genesis block of the coolcoin network.
     pub fn genesis_block() -> Block {
-        // 02 Sep 2021 at ~08:58
-        let timestamp = 1630569467;
-        const GENESIS_REWARD: Coolcoin = Coolcoin::new(50);
-        let genesis_address = Address::new("genesis_wallet_address".to_string());
-        let locktime = 0;
-        let inputs = vec![TransactionInput::new_coinbase()];
-        let outputs = vec![TransactionOutput::new(genesis_address, GENESIS_REWARD)];
-        let transactions = vec![Transaction::new(inputs, outputs, locktime).unwrap()];
-        let previous_block_hash = BlockHash::new(Sha256::new([0; 32]));
-        let merkle_root = merkle_tree_from_transactions(&transactions);
-        let difficulty = 8;
-        let nonce = Miner::pow(&previous_block_hash, &merkle_root, timestamp, difficulty)
-            .expect("can't find nonce for genesis block");
-
-        let header = BlockHeader::new(
-            previous_block_hash,
-            merkle_root,
-            timestamp,
-            difficulty,
-            nonce,
-        );
-        Block::new(header, transactions)
+        ChainParams::main().genesis_block()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::hash::{from_hex, MerkleHash};
+    use crate::core::chain_builder::ChainBuilder;
 
     #[test]
     fn new_block_reinsert_orphans() {
-        const DIFFICULTY_TARGET: u32 = 1;
-
         let mut blockchain = BlockchainManager::new();
-        let block_0 = BlockchainManager::genesis_block();
-        let block_1 = Block::new(
-            BlockHeader::new(
-                block_0.id().clone(),
-                MerkleHash::new(
-                    from_hex("00cf8be900cf8be900cf8be900cf8be900cf8be900cf8be900cf8be900cf8be9")
-                        .unwrap(),
-                ),
-                100,
-                DIFFICULTY_TARGET,
-                3,
-            ),
-            vec![],
-        );
-        let block_2 = Block::new(
-            BlockHeader::new(
-                block_1.id().clone(),
-                MerkleHash::new(
-                    from_hex("0005e6c10005e6c10005e6c10005e6c10005e6c10005e6c10005e6c10005e6c1")
-                        .unwrap(),
-                ),
-                100,
-                DIFFICULTY_TARGET,
-                3,
-            ),
-            vec![],
-        );
-        let block_3 = Block::new(
-            BlockHeader::new(
-                block_2.id().clone(),
-                MerkleHash::new(
-                    from_hex("00d8368100d8368100d8368100d8368100d8368100d8368100d8368100d83681")
-                        .unwrap(),
-                ),
-                100,
-                DIFFICULTY_TARGET,
-                3,
-            ),
-            vec![],
-        );
-
-        blockchain.new_block_reinsert_orphans(block_2.clone());
-        blockchain.new_block_reinsert_orphans(block_3.clone());
+        let mut chain = ChainBuilder::new(ChainParams::main());
+        chain.mine_blocks(3);
+        let block_0 = chain.block(0).clone();
+        let block_1 = chain.block(1).clone();
+        let block_2 = chain.block(2).clone();
+        let block_3 = chain.block(3).clone();
+
+        blockchain.new_block_reinsert_orphans(Arc::new(block_2.clone()));
+        blockchain.new_block_reinsert_orphans(Arc::new(block_3.clone()));
 
         {
             // Assert block_2 and block_3 are orphans, and only genesis block is in the active blockchain.
@@ -163,10 +119,11 @@ mod tests {
                     .orphaned_blocks
                     .all()
                     .iter()
-                    .map(|b| b.id().clone())
+                    .map(|b| *b.id())
                     .collect::<Vec<BlockHash>>();
                 actual.sort();
-                let expected = vec![block_3.id().clone(), block_2.id().clone()];
+                let mut expected = vec![*block_2.id(), *block_3.id()];
+                expected.sort();
                 assert_eq!(actual, expected);
             }
 
@@ -175,15 +132,15 @@ mod tests {
                 let actual = blockchain
                     .block_tree()
                     .active_blockchain()
-                    .iter()
-                    .map(|b| b.id().clone())
+                    .into_iter()
+                    .map(|b| *b.id())
                     .collect::<Vec<BlockHash>>();
-                assert_eq!(actual, vec![block_0.id().clone()]);
+                assert_eq!(actual, vec![*block_0.id()]);
             }
         }
 
         {
-            blockchain.new_block_reinsert_orphans(block_1.clone());
+            blockchain.new_block_reinsert_orphans(Arc::new(block_1.clone()));
             // Assert that inserting block_1 inserts blocks 2 and 3.
             // This leaves us with no orphans, and active blockchain should contain all nodes.
             {
@@ -192,7 +149,7 @@ mod tests {
                     .orphaned_blocks
                     .all()
                     .iter()
-                    .map(|b| b.id().clone())
+                    .map(|b| *b.id())
                     .collect::<Vec<BlockHash>>();
                 actual.sort();
                 assert_eq!(actual, vec![]);
@@ -203,17 +160,12 @@ mod tests {
                 let actual = blockchain
                     .block_tree()
                     .active_blockchain()
-                    .iter()
-                    .map(|b| b.id().clone())
+                    .into_iter()
+                    .map(|b| *b.id())
                     .collect::<Vec<BlockHash>>();
                 assert_eq!(
                     actual,
-                    vec![
-                        block_0.id().clone(),
-                        block_1.id().clone(),
-                        block_2.id().clone(),
-                        block_3.id().clone()
-                    ]
+                    vec![*block_0.id(), *block_1.id(), *block_2.id(), *block_3.id()]
                 );
             }
         }
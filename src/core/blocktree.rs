@@ -1,15 +1,19 @@
 use crate::core::block::{BlockHash, BlockHeader};
-use crate::core::miner::Miner;
-use crate::core::transaction::{TransactionInput, TransactionOutput};
-use crate::core::{merkle_tree, Address, Block, BlockValidator, Coolcoin, Sha256, Transaction};
+use crate::core::transaction::TransactionId;
+use crate::core::{Block, Transaction};
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::hash::Hash;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 struct BlockTreeEntry {
-    block: Block,
+    // Shared with the peer messages and the orphaned blocks, so the block isn't copied when
+    // it's relayed or sent in a response.
+    block: Arc<Block>,
     height: u32,
+    // An ancestor further back than the parent, at `skip_height(height)`, which makes
+    // the ancestor lookups logarithmic. The genesis block points to itself.
+    skip: BlockHash,
 }
 
 struct ActiveBlock {
@@ -17,6 +21,87 @@ struct ActiveBlock {
     total_work: u32,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChainTipStatus {
+    // The tip of the active blockchain.
+    Active,
+    // The tip of a secondary chain. All blocks in the tree are valid, because the invalid
+    // ones are removed once they fail to connect.
+    ValidFork,
+}
+
+/// The last block of a chain in the block tree, i.e. the block without children.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainTip {
+    height: u32,
+    hash: BlockHash,
+    // The number of blocks that are not in the active blockchain, 0 for the active tip.
+    branch_length: u32,
+    status: ChainTipStatus,
+}
+
+/// The block header, together with its position in the block tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockHeaderInfo {
+    hash: BlockHash,
+    height: u32,
+    // The number of blocks in the active blockchain on top of the block, including itself,
+    // or -1 if the block is not in the active blockchain.
+    confirmations: i64,
+    header: BlockHeader,
+    // The hash of the next block in the active blockchain, if any.
+    next_block_hash: Option<BlockHash>,
+}
+
+impl BlockHeaderInfo {
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn confirmations(&self) -> i64 {
+        self.confirmations
+    }
+
+    pub fn next_block_hash(&self) -> Option<&BlockHash> {
+        self.next_block_hash.as_ref()
+    }
+}
+
+/// The transaction, together with the block that has confirmed it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionInfo {
+    transaction: Transaction,
+    // The number of blocks in the active blockchain on top of the transaction's block,
+    // including itself, or 0 if the transaction is in the transaction pool.
+    confirmations: u32,
+    block_hash: Option<BlockHash>,
+    block_height: Option<u32>,
+}
+
+impl TransactionInfo {
+    pub fn unconfirmed(transaction: Transaction) -> Self {
+        Self {
+            transaction,
+            confirmations: 0,
+            block_hash: None,
+            block_height: None,
+        }
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn confirmations(&self) -> u32 {
+        self.confirmations
+    }
+
+    pub fn block_hash(&self) -> Option<&BlockHash> {
+        self.block_hash.as_ref()
+    }
+}
+
 /// The global public ledger of all transactions, which everyone in the Coolcoin network accept
 /// as the authoritative record of ownership.
 /// Block Tree is a tree of blocks with the genesis block as a root.
@@ -34,18 +119,14 @@ pub struct BlockTree {
 
 impl BlockTree {
     pub fn new(genesis_block: Block) -> Self {
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            // Bitcoin timestamp runs out in year 2106.
-            .as_secs() as u32;
         let mut tree = HashMap::new();
         let genesis_hash = genesis_block.header().hash();
         tree.insert(
             genesis_hash,
             BlockTreeEntry {
-                block: genesis_block,
+                block: Arc::new(genesis_block),
                 height: 0,
+                skip: genesis_hash,
             },
         );
         Self {
@@ -57,24 +138,31 @@ impl BlockTree {
         }
     }
 
-    pub fn all(&self) -> Vec<Block> {
+    pub fn all(&self) -> Vec<Arc<Block>> {
         self.tree.values().map(|e| e.block.clone()).collect()
     }
 
-    pub fn active_blockchain(&self) -> Vec<Block> {
+    /// Returns the blocks of the active blockchain, starting with the genesis block.
+    /// The blocks are shared with the tree, not copied.
+    pub fn active_blockchain(&self) -> Vec<Arc<Block>> {
         let mut blockchain = vec![];
         let mut current_entry = Some(self.tree.get(&self.active_block.hash).unwrap());
         while let Some(tree_entry) = current_entry {
             blockchain.push(tree_entry.block.clone());
             current_entry = self
                 .tree
-                .get(&tree_entry.block.header().previous_block_hash());
+                .get(tree_entry.block.header().previous_block_hash());
         }
         blockchain.into_iter().rev().collect()
     }
 
     pub fn get(&self, block_hash: &BlockHash) -> Option<&Block> {
-        self.tree.get(block_hash).map(|entry| &entry.block)
+        self.tree.get(block_hash).map(|entry| entry.block.as_ref())
+    }
+
+    /// Returns the block shared with the tree, e.g. to send it to a peer without copying it.
+    pub fn get_shared(&self, block_hash: &BlockHash) -> Option<Arc<Block>> {
+        self.tree.get(block_hash).map(|entry| entry.block.clone())
     }
 
     /// Adds new block to the blockchain. It assumes that the block is valid and all
@@ -82,16 +170,20 @@ impl BlockTree {
     ///
     /// Preconditions:
     ///   - Parent exists.
-    pub fn insert(&mut self, block: Block) {
+    pub fn insert(&mut self, block: Arc<Block>) {
         let parent_hash = block.header().previous_block_hash();
         let block_hash = block.header().hash();
         let parent = self.tree.get(parent_hash).unwrap();
         let block_height = parent.height + 1;
+        let skip = self
+            .ancestor(parent_hash, skip_height(block_height))
+            .unwrap();
         let previous = self.tree.insert(
             block.header().hash(),
             BlockTreeEntry {
                 block,
                 height: block_height,
+                skip,
             },
         );
         assert!(previous.is_none());
@@ -159,6 +251,151 @@ impl BlockTree {
         }
     }
 
+    /// Returns the median timestamp of the given block and its ancestors, up to 11 blocks in
+    /// total. Unlike the timestamp of a single block, the median time past can't be moved
+    /// forward by a single miner, so it is used for time-based locktimes.
+    pub fn median_time_past(&self, hash: &BlockHash) -> Option<u32> {
+        const NUM_BLOCKS: usize = 11;
+        let mut timestamps = vec![];
+        let mut current_entry = Some(self.tree.get(hash)?);
+        while let Some(tree_entry) = current_entry {
+            if timestamps.len() == NUM_BLOCKS {
+                break;
+            }
+            timestamps.push(tree_entry.block.header().timestamp());
+            current_entry = self
+                .tree
+                .get(tree_entry.block.header().previous_block_hash());
+        }
+        timestamps.sort_unstable();
+        Some(timestamps[timestamps.len() / 2])
+    }
+
+    /// Returns the hash of the block at the given height in the active blockchain.
+    pub fn active_block_hash(&self, height: u32) -> Option<BlockHash> {
+        self.ancestor(&self.active_block.hash, height)
+    }
+
+    /// Returns the hash of the block's ancestor at the given height, or the block itself if
+    /// it's at that height. It follows the skip pointers where they don't overshoot the height,
+    /// so it visits O(log n) blocks.
+    pub fn ancestor(&self, hash: &BlockHash, height: u32) -> Option<BlockHash> {
+        let mut current_hash = *hash;
+        let mut current_entry = self.tree.get(hash)?;
+        if height > current_entry.height {
+            return None;
+        }
+        while current_entry.height > height {
+            let skip = skip_height(current_entry.height);
+            let previous_skip = skip_height(current_entry.height - 1);
+            // The parent's skip pointer is taken instead if it gets closer to the height.
+            let is_skip_better = skip == height
+                || (skip > height && !(previous_skip + 2 < skip && previous_skip >= height));
+            current_hash = match is_skip_better {
+                true => current_entry.skip,
+                false => *current_entry.block.header().previous_block_hash(),
+            };
+            current_entry = &self.tree[&current_hash];
+        }
+        Some(current_hash)
+    }
+
+    /// Returns the tips of all chains in the tree, the active one first and the rest by
+    /// height from the highest.
+    pub fn chain_tips(&self) -> Vec<ChainTip> {
+        let parents = self
+            .tree
+            .values()
+            .map(|entry| *entry.block.header().previous_block_hash())
+            .collect::<HashSet<BlockHash>>();
+        let mut tips = self
+            .tree
+            .iter()
+            .filter(|(hash, _)| !parents.contains(*hash))
+            .map(|(hash, entry)| {
+                let mut branch_length = 0;
+                let mut current = entry;
+                while self.active_block_hash(current.height) != Some(current.block.header().hash())
+                {
+                    branch_length += 1;
+                    current = &self.tree[current.block.header().previous_block_hash()];
+                }
+                ChainTip {
+                    height: entry.height,
+                    hash: *hash,
+                    branch_length,
+                    status: match branch_length {
+                        0 => ChainTipStatus::Active,
+                        _ => ChainTipStatus::ValidFork,
+                    },
+                }
+            })
+            .collect::<Vec<ChainTip>>();
+        tips.sort_by_key(|tip| {
+            (
+                tip.branch_length != 0,
+                std::cmp::Reverse(tip.height),
+                tip.hash,
+            )
+        });
+        tips
+    }
+
+    /// Returns the difficulty of the block as a multiple of the minimum difficulty,
+    /// which is the difficulty of the genesis block.
+    pub fn difficulty(&self, hash: &BlockHash) -> Option<f64> {
+        let genesis_hash = self.active_block_hash(0).unwrap();
+        let min_difficulty_target = self.tree[&genesis_hash].block.header().difficulty_target();
+        let difficulty_target = self.tree.get(hash)?.block.header().difficulty_target();
+        // Each leading zero bit of the target doubles the expected number of hashes.
+        Some(2f64.powi(difficulty_target as i32 - min_difficulty_target as i32))
+    }
+
+    /// Returns the block's header, without the transactions.
+    pub fn header_info(&self, hash: &BlockHash) -> Option<BlockHeaderInfo> {
+        let entry = self.tree.get(hash)?;
+        let is_active = self.active_block_hash(entry.height) == Some(*hash);
+        let tip_height = self.tree[&self.active_block.hash].height;
+        let (confirmations, next_block_hash) = match is_active {
+            true => (
+                (tip_height - entry.height + 1) as i64,
+                self.active_block_hash(entry.height + 1),
+            ),
+            false => (-1, None),
+        };
+        Some(BlockHeaderInfo {
+            hash: *hash,
+            height: entry.height,
+            confirmations,
+            header: entry.block.header().clone(),
+            next_block_hash,
+        })
+    }
+
+    /// Finds the transaction in the active blockchain, starting from the tip.
+    /// There is no transaction index, so the blocks are scanned until the transaction is found.
+    pub fn find_transaction(&self, txid: &TransactionId) -> Option<TransactionInfo> {
+        let tip_height = self.tree[&self.active_block.hash].height;
+        let mut current_entry = self.tree.get(&self.active_block.hash);
+        while let Some(entry) = current_entry {
+            if let Some(transaction) = entry
+                .block
+                .transactions()
+                .iter()
+                .find(|transaction| transaction.id() == txid)
+            {
+                return Some(TransactionInfo {
+                    transaction: transaction.clone(),
+                    confirmations: tip_height - entry.height + 1,
+                    block_hash: Some(*entry.block.id()),
+                    block_height: Some(entry.height),
+                });
+            }
+            current_entry = self.tree.get(entry.block.header().previous_block_hash());
+        }
+        None
+    }
+
     pub fn height(&self, hash: &BlockHash) -> Option<u32> {
         self.tree.get(hash).map(|entry| entry.height)
     }
@@ -167,6 +404,36 @@ impl BlockTree {
         self.tree.contains_key(block_hash)
     }
 
+    /// Removes the block that turned out to be invalid, e.g. it spends an output that doesn't
+    /// exist, together with its descendants. The active blockchain becomes the chain with
+    /// the most work among the remaining ones, or the preferred one if it has as much work,
+    /// e.g. the active blockchain before the invalid block was inserted.
+    pub fn invalidate(&mut self, block_hash: &BlockHash, preferred_tip: &BlockHash) {
+        let mut invalid = HashSet::from([*block_hash]);
+        let mut entries = self
+            .tree
+            .iter()
+            .collect::<Vec<(&BlockHash, &BlockTreeEntry)>>();
+        // Parents come before their children.
+        entries.sort_by_key(|(_, entry)| entry.height);
+        for (hash, entry) in entries {
+            if invalid.contains(entry.block.header().previous_block_hash()) {
+                invalid.insert(*hash);
+            }
+        }
+        self.tree.retain(|hash, _| !invalid.contains(hash));
+        let (hash, height) = self
+            .tree
+            .iter()
+            .map(|(hash, entry)| (*hash, entry.height))
+            .max_by_key(|(hash, height)| (*height, hash == preferred_tip))
+            .unwrap();
+        self.active_block = ActiveBlock {
+            hash,
+            total_work: height,
+        };
+    }
+
     fn maybe_update_active_block(&mut self, block_hash: BlockHash, new_block_total_work: u32) {
         if self.active_block.total_work < new_block_total_work {
             self.active_block = ActiveBlock {
@@ -176,3 +443,137 @@ impl BlockTree {
         }
     }
 }
+
+/// Returns the height that the block at the given height skips to. Any height can be reached
+/// from any of its descendants by following the skip pointers and the parents in O(log n) steps.
+fn skip_height(height: u32) -> u32 {
+    // Clears the lowest set bit.
+    let invert_lowest_one = |n: u32| n & n.saturating_sub(1);
+    if height < 2 {
+        0
+    } else if height & 1 == 1 {
+        invert_lowest_one(invert_lowest_one(height - 1)) + 1
+    } else {
+        invert_lowest_one(height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::chain_builder::ChainBuilder;
+    use crate::core::chain_params::ChainParams;
+
+    #[test]
+    fn finds_active_block_at_height() {
+        let mut chain = ChainBuilder::new(ChainParams::main());
+        let genesis = chain.genesis_block().clone();
+        let mut block_tree = BlockTree::new(genesis.clone());
+        let block_1 = chain.mine();
+        let block_2 = chain.mine();
+        // A secondary chain with the block at height 1.
+        let fork_1 = chain.fork_at(0).mine();
+        block_tree.insert(Arc::new(block_1.clone()));
+        block_tree.insert(Arc::new(fork_1.clone()));
+        block_tree.insert(Arc::new(block_2.clone()));
+
+        assert_eq!(block_tree.active_block_hash(0), Some(*genesis.id()));
+        assert_eq!(block_tree.active_block_hash(1), Some(*block_1.id()));
+        assert_eq!(block_tree.active_block_hash(2), Some(*block_2.id()));
+        assert_eq!(block_tree.active_block_hash(3), None);
+
+        assert_eq!(
+            block_tree.chain_tips(),
+            vec![
+                ChainTip {
+                    height: 2,
+                    hash: *block_2.id(),
+                    branch_length: 0,
+                    status: ChainTipStatus::Active,
+                },
+                ChainTip {
+                    height: 1,
+                    hash: *fork_1.id(),
+                    branch_length: 1,
+                    status: ChainTipStatus::ValidFork,
+                },
+            ]
+        );
+        assert_eq!(block_tree.difficulty(block_2.id()), Some(1.0));
+
+        let info = block_tree.header_info(block_1.id()).unwrap();
+        assert_eq!(info.confirmations(), 2);
+        assert_eq!(info.next_block_hash(), Some(block_2.id()));
+        let info = block_tree.header_info(fork_1.id()).unwrap();
+        assert_eq!(info.confirmations(), -1);
+        assert_eq!(info.next_block_hash(), None);
+
+        let coinbase = &block_2.transactions()[0];
+        let info = block_tree.find_transaction(coinbase.id()).unwrap();
+        assert_eq!(info.confirmations(), 1);
+        assert_eq!(info.block_hash(), Some(block_2.id()));
+        let missing = TransactionId::new(crate::core::hash::hash(b"missing"));
+        assert!(block_tree.find_transaction(&missing).is_none());
+    }
+
+    #[test]
+    fn invalidates_block_and_descendants() {
+        let mut chain = ChainBuilder::new(ChainParams::main());
+        let mut block_tree = BlockTree::new(chain.genesis_block().clone());
+        let block_1 = chain.mine();
+        let mut fork = chain.fork_at(0);
+        let fork_1 = fork.mine();
+        let fork_2 = fork.mine();
+        for block in [&block_1, &fork_1, &fork_2] {
+            block_tree.insert(Arc::new(block.clone()));
+        }
+        assert_eq!(block_tree.tip(), fork_2.id());
+
+        block_tree.invalidate(fork_1.id(), block_1.id());
+        assert_eq!(block_tree.tip(), block_1.id());
+        assert!(!block_tree.exists(fork_1.id()));
+        assert!(!block_tree.exists(fork_2.id()));
+        assert_eq!(block_tree.chain_tips().len(), 1);
+    }
+
+    #[test]
+    fn finds_ancestors_through_skip_pointers() {
+        let mut builder = ChainBuilder::new(ChainParams::main());
+        let mut block_tree = BlockTree::new(builder.genesis_block().clone());
+        for block in builder.mine_blocks(100) {
+            block_tree.insert(Arc::new(block));
+        }
+        let chain = (0..=100)
+            .map(|height| builder.block(height))
+            .collect::<Vec<_>>();
+        // A secondary chain that forks off at height 60.
+        let mut fork_builder = builder.fork_at(60);
+        for block in fork_builder.mine_blocks(10) {
+            block_tree.insert(Arc::new(block));
+        }
+        let fork = fork_builder.tip();
+
+        for (height, block) in chain.iter().enumerate() {
+            assert_eq!(
+                block_tree.active_block_hash(height as u32),
+                Some(*block.id())
+            );
+            assert_eq!(
+                block_tree.ancestor(fork.id(), height as u32),
+                match height {
+                    0..=60 => Some(*block.id()),
+                    70 => Some(*fork.id()),
+                    71..=100 => None,
+                    _ => block_tree
+                        .ancestor(fork.id(), height as u32 + 1)
+                        .map(|child| *block_tree
+                            .get(&child)
+                            .unwrap()
+                            .header()
+                            .previous_block_hash()),
+                }
+            );
+        }
+        assert_eq!(block_tree.active_block_hash(101), None);
+    }
+}
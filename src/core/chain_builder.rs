@@ -0,0 +1,359 @@
+use crate::core::block::BlockHeader;
+use crate::core::chain_params::ChainParams;
+use crate::core::hash::merkle_tree_from_transactions;
+use crate::core::miner::Miner;
+use crate::core::transaction::{OutputIndex, TransactionId, TransactionInput, TransactionOutput};
+use crate::core::{target_hash, Address, Block, Coolcoin, Transaction, UtxoPool};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Builds valid chains for the tests: each block has a correct merkle root, meets its
+/// difficulty target, and has a coinbase transaction that pays the block reward and the fees
+/// of the spends to the miner.
+///
+/// The builder keeps track of the unspent outputs of its chain, so the tests can fund
+/// addresses and spend their coins without assembling the transactions by hand.
+/// The forks are builders of their own, which share the blocks up to the fork point.
+pub struct ChainBuilder {
+    params: ChainParams,
+    // The blocks from the genesis block to the tip.
+    blocks: Vec<Block>,
+    utxo_pool: UtxoPool,
+    // The transactions of the next block, which are mined by `mine`.
+    transactions: Vec<Transaction>,
+    // The outputs spent by the transactions of the next block.
+    spent: HashSet<(TransactionId, OutputIndex)>,
+    // The fees of the transactions of the next block, which the miner collects.
+    fees: Coolcoin,
+    miner: Address,
+    difficulty_target: u32,
+    // The coinbase transactions of the forks are tagged with the branch, so that they have
+    // different ids than the ones at the same height in the other branches.
+    branch: u32,
+    num_branches: Rc<Cell<u32>>,
+}
+
+impl ChainBuilder {
+    /// Starts the chain at the genesis block of the network, with the blocks mined at its
+    /// difficulty target.
+    pub fn new(params: ChainParams) -> Self {
+        let genesis_block = params.genesis_block();
+        let difficulty_target = genesis_block.header().difficulty_target();
+        let mut utxo_pool = UtxoPool::new();
+        utxo_pool.connect_block(&genesis_block, 0).unwrap();
+        Self {
+            params,
+            blocks: vec![genesis_block],
+            utxo_pool,
+            transactions: vec![],
+            spent: HashSet::new(),
+            fees: Coolcoin::zero(),
+            miner: Address::new("miner".to_string()),
+            difficulty_target,
+            branch: 0,
+            num_branches: Rc::new(Cell::new(1)),
+        }
+    }
+
+    /// Sets the address that the next blocks pay their rewards to.
+    pub fn miner(&mut self, address: &Address) -> &mut Self {
+        self.miner = address.clone();
+        self
+    }
+
+    /// Sets the difficulty target of the next blocks, e.g. to build blocks that don't meet
+    /// the target of the network.
+    pub fn difficulty_target(&mut self, difficulty_target: u32) -> &mut Self {
+        self.difficulty_target = difficulty_target;
+        self
+    }
+
+    /// Mines the block that pays its reward to the address, followed by the blocks that make
+    /// the reward spendable.
+    pub fn fund(&mut self, address: &Address) -> &mut Self {
+        let miner = std::mem::replace(&mut self.miner, address.clone());
+        self.mine();
+        self.miner = miner;
+        self.mine_blocks(self.params.coinbase_maturity().saturating_sub(1));
+        self
+    }
+
+    /// Adds the transaction that sends the amount from one address to the other to the next
+    /// block, and returns it. It spends the oldest spendable outputs of the sender, pays
+    /// the fee to the miner, and returns the change to the sender.
+    pub fn spend(
+        &mut self,
+        from: &Address,
+        to: &Address,
+        amount: Coolcoin,
+        fee: Coolcoin,
+    ) -> Result<Transaction, String> {
+        let next_height = self.height() + 1;
+        let mut outputs = self
+            .utxo_pool
+            .unspent_outputs(std::slice::from_ref(from))
+            .into_iter()
+            .filter(|(output_id, utxo)| {
+                !self.spent.contains(output_id)
+                    && (!utxo.is_coinbase()
+                        || utxo.height() + self.params.coinbase_maturity() <= next_height)
+            })
+            .map(|(output_id, utxo)| (utxo.height(), output_id, utxo.output().amount()))
+            .collect::<Vec<_>>();
+        // The order of the unspent outputs is random, so the same spends in the same chain
+        // would create different transactions.
+        outputs.sort_by_key(|(height, (txid, output_index), _)| {
+            (*height, *txid.raw(), output_index.value())
+        });
+
+        let mut inputs = vec![];
+        let mut total_input = Coolcoin::zero();
+        for (_, (txid, output_index), output_amount) in outputs {
+            if total_input >= amount + fee {
+                break;
+            }
+            inputs.push(TransactionInput::new(txid, output_index));
+            total_input = total_input + output_amount;
+        }
+        if total_input < amount + fee {
+            return Err(format!(
+                "Address: {} has only: {} to spend, which is less than: {}",
+                from,
+                total_input,
+                amount + fee
+            ));
+        }
+
+        let mut outputs = vec![TransactionOutput::new(to.clone(), amount)];
+        let change = total_input - amount - fee;
+        if change > Coolcoin::zero() {
+            outputs.push(TransactionOutput::new(from.clone(), change));
+        }
+        let transaction = Transaction::new(inputs, outputs, 0)?;
+        for input in transaction.inputs() {
+            self.spent.insert((*input.utxo_id(), *input.output_index()));
+        }
+        self.fees = self.fees + fee;
+        self.transactions.push(transaction.clone());
+        Ok(transaction)
+    }
+
+    /// Adds the transaction to the next block as it is, e.g. to build an invalid block.
+    /// Unlike `spend`, its fee isn't collected by the miner.
+    pub fn add_transaction(&mut self, transaction: Transaction) -> &mut Self {
+        self.transactions.push(transaction);
+        self
+    }
+
+    /// Mines the next block with the added transactions, and returns it.
+    pub fn mine(&mut self) -> Block {
+        let previous_block_hash = *self.tip().id();
+        let mut timestamp = self.tip().header().timestamp() + 1;
+        let height = self.height() + 1;
+        let reward = self.params.block_reward(height) + self.fees;
+        let coinbase_tag = match self.branch {
+            0 => vec![],
+            branch => format!("branch {}", branch).into_bytes(),
+        };
+        let mut transactions = vec![Miner::coinbase_transaction(
+            vec![TransactionOutput::new(self.miner.clone(), reward)],
+            timestamp,
+            Miner::coinbase_data(height, &coinbase_tag, &[]),
+        )];
+        transactions.append(&mut self.transactions);
+        let merkle_root = merkle_tree_from_transactions(&transactions);
+        let header = loop {
+            if let Some(nonce) = Miner::pow(
+                &previous_block_hash,
+                &merkle_root,
+                timestamp,
+                self.difficulty_target,
+            ) {
+                let header = BlockHeader::new(
+                    previous_block_hash,
+                    merkle_root.clone(),
+                    timestamp,
+                    self.difficulty_target,
+                    nonce,
+                );
+                // Blocks must be strictly below the target to be valid.
+                if header.hash() < target_hash(self.difficulty_target) {
+                    break header;
+                }
+            }
+            // The coinbase transaction's id doesn't depend on the timestamp, so the merkle
+            // root stays the same.
+            timestamp += 1;
+        };
+
+        let block = Block::new(header, transactions);
+        // The blocks with the transactions added by `add_transaction` may spend the outputs
+        // that don't exist, in which case they are not tracked.
+        let _ = self.utxo_pool.connect_block(&block, height);
+        self.spent.clear();
+        self.fees = Coolcoin::zero();
+        self.blocks.push(block.clone());
+        block
+    }
+
+    /// Mines the given number of empty blocks, and returns them.
+    pub fn mine_blocks(&mut self, count: u32) -> Vec<Block> {
+        (0..count).map(|_| self.mine()).collect()
+    }
+
+    /// Returns the builder of the new branch that forks off the chain after the block at
+    /// the given height, with the same miner and difficulty target.
+    pub fn fork_at(&self, height: u32) -> Self {
+        assert!(height <= self.height(), "No block at height: {}", height);
+        let blocks = self.blocks[..=height as usize].to_vec();
+        let mut utxo_pool = UtxoPool::new();
+        for (height, block) in blocks.iter().enumerate() {
+            let _ = utxo_pool.connect_block(block, height as u32);
+        }
+        let branch = self.num_branches.get();
+        self.num_branches.set(branch + 1);
+        Self {
+            params: self.params.clone(),
+            blocks,
+            utxo_pool,
+            transactions: vec![],
+            spent: HashSet::new(),
+            fees: Coolcoin::zero(),
+            miner: self.miner.clone(),
+            difficulty_target: self.difficulty_target,
+            branch,
+            num_branches: self.num_branches.clone(),
+        }
+    }
+
+    /// Returns the sum of the unspent outputs of the address in the chain.
+    pub fn balance(&self, address: &Address) -> Coolcoin {
+        self.utxo_pool
+            .unspent_outputs(std::slice::from_ref(address))
+            .into_iter()
+            .map(|(_, utxo)| utxo.output().amount())
+            .sum()
+    }
+
+    pub fn genesis_block(&self) -> &Block {
+        &self.blocks[0]
+    }
+
+    pub fn tip(&self) -> &Block {
+        self.blocks.last().unwrap()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.blocks.len() as u32 - 1
+    }
+
+    pub fn block(&self, height: u32) -> &Block {
+        &self.blocks[height as usize]
+    }
+
+    /// Returns the blocks after the genesis block.
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks[1..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::validation::SpentOutput;
+    use crate::core::{
+        BlockTree, BlockValidator, ChainContext, FeeRate, TransactionPoolContext,
+        TransactionValidator,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn builds_valid_chain_with_spends() {
+        let alice = Address::new("alice".to_string());
+        let bob = Address::new("bob".to_string());
+        let mut chain = ChainBuilder::new(ChainParams::regtest());
+        chain.fund(&alice);
+        assert!(chain
+            .spend(&alice, &bob, 51.into(), Coolcoin::zero())
+            .is_err());
+        let spend = chain.spend(&alice, &bob, 30.into(), 5.into()).unwrap();
+
+        // The spend would be accepted to the transaction pool.
+        let spent_outputs = spend
+            .inputs()
+            .iter()
+            .map(|input| {
+                let utxo = chain
+                    .utxo_pool
+                    .get(input.utxo_id(), input.output_index())
+                    .unwrap();
+                Some(SpentOutput {
+                    output: utxo.output().clone(),
+                    coinbase_height: Some(utxo.height()).filter(|_| utxo.is_coinbase()),
+                })
+            })
+            .collect();
+        let context = TransactionPoolContext::new(
+            spent_outputs,
+            chain.height() + 1,
+            chain.tip().header().timestamp(),
+            FeeRate::zero(),
+            ChainParams::regtest().coinbase_maturity(),
+        );
+        assert_eq!(
+            TransactionValidator::validate_transaction_pool_context(&spend, &context),
+            Ok(5.into())
+        );
+
+        let block = chain.mine();
+        assert_eq!(block.transactions()[1].id(), spend.id());
+        assert_eq!(chain.balance(&alice), 15.into());
+        assert_eq!(chain.balance(&bob), 30.into());
+        assert_eq!(
+            chain.balance(&Address::new("miner".to_string())),
+            105.into()
+        );
+
+        let mut block_tree = BlockTree::new(chain.genesis_block().clone());
+        for block in chain.blocks() {
+            BlockValidator::validate_no_context(block, block.header().timestamp()).unwrap();
+            let parent = block.header().previous_block_hash();
+            let context = ChainContext::new(
+                target_hash(block.header().difficulty_target()),
+                block_tree.median_time_past(parent).unwrap(),
+            );
+            BlockValidator::validate_chain_context(block, &context, 0).unwrap();
+            block_tree.insert(Arc::new(block.clone()));
+        }
+        assert_eq!(block_tree.tip(), chain.tip().id());
+    }
+
+    #[test]
+    fn forks_have_distinct_blocks() {
+        let mut chain = ChainBuilder::new(ChainParams::regtest());
+        chain.mine_blocks(2);
+        let mut fork = chain.fork_at(1);
+        let mut other_fork = chain.fork_at(1);
+        fork.mine_blocks(2);
+        other_fork.difficulty_target(1).mine();
+
+        let mut block_tree = BlockTree::new(chain.genesis_block().clone());
+        for block in chain
+            .blocks()
+            .iter()
+            .chain(&fork.blocks()[1..])
+            .chain(&other_fork.blocks()[1..])
+        {
+            block_tree.insert(Arc::new(block.clone()));
+        }
+        assert_eq!(block_tree.chain_tips().len(), 3);
+        assert_eq!(block_tree.tip(), fork.tip().id());
+        assert_eq!(fork.block(1).id(), chain.block(1).id());
+        assert_ne!(
+            fork.block(2).transactions()[0].id(),
+            chain.block(2).transactions()[0].id()
+        );
+        assert_eq!(other_fork.tip().header().difficulty_target(), 1);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::core::block::BlockHash;
+use crate::core::serialize::Encodable;
+use crate::core::transaction::{OutputIndex, TransactionId};
+use crate::core::{Address, Block, Coolcoin, Transaction};
+use serde::Serialize;
+use std::io::Write;
+
+/// Version of the export's schema, which is changed whenever a field is removed, renamed or
+/// changes its meaning, so that the analysis scripts can tell which exports they understand.
+/// Adding a field doesn't change the version.
+pub const CHAIN_EXPORT_VERSION: u32 = 1;
+
+/// The block of the active blockchain, as exported.
+#[derive(Debug, Serialize)]
+struct ExportedBlock<'a> {
+    height: u32,
+    hash: &'a BlockHash,
+    // Null for the genesis block.
+    previous_block_hash: Option<&'a BlockHash>,
+    // Time (seconds from Unix Epoch) of the block.
+    timestamp: u32,
+    difficulty_target: u32,
+    nonce: u32,
+    // The number of blocks on top of it and including it, at the time of the export.
+    confirmations: u32,
+    // Size of the block in the canonical encoding, in bytes.
+    size: usize,
+    transactions: Vec<ExportedTransaction<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedTransaction<'a> {
+    txid: &'a TransactionId,
+    coinbase: bool,
+    size: usize,
+    locktime: u32,
+    inputs: Vec<ExportedInput<'a>>,
+    outputs: Vec<ExportedOutput<'a>>,
+}
+
+/// The input spends the output of the earlier transaction, except for the coinbase's input,
+/// which only carries the miner's data.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum ExportedInput<'a> {
+    Coinbase {
+        // The hex-encoded data.
+        coinbase_data: String,
+    },
+    Spend {
+        txid: &'a TransactionId,
+        output_index: &'a OutputIndex,
+    },
+}
+
+/// The output is locked to the address, which is the only condition for spending it,
+/// so there is no script to decode.
+#[derive(Debug, Serialize)]
+struct ExportedOutput<'a> {
+    index: usize,
+    address: &'a Address,
+    amount: Coolcoin,
+}
+
+impl<'a> ExportedTransaction<'a> {
+    fn new(transaction: &'a Transaction) -> Self {
+        Self {
+            txid: transaction.id(),
+            coinbase: transaction.is_coinbase(),
+            size: transaction.serialized_size(),
+            locktime: transaction.locktime(),
+            inputs: transaction
+                .inputs()
+                .iter()
+                .map(|input| match input.is_coinbase() {
+                    true => ExportedInput::Coinbase {
+                        coinbase_data: hex::encode(input.coinbase_data()),
+                    },
+                    false => ExportedInput::Spend {
+                        txid: input.utxo_id(),
+                        output_index: input.output_index(),
+                    },
+                })
+                .collect(),
+            outputs: transaction
+                .outputs()
+                .iter()
+                .enumerate()
+                .map(|(index, output)| ExportedOutput {
+                    index,
+                    address: output.to(),
+                    amount: output.amount(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Writes the active blockchain as a single JSON document, for the analysis with jq or pandas:
+///
+/// `{"version": 1, "tip_height": N, "blocks": [...]}`
+///
+/// The blocks are written one at a time, starting with the genesis block, so the whole
+/// blockchain is never held in memory.
+pub struct ChainExportWriter<W: Write> {
+    writer: W,
+    tip_height: u32,
+    // The number of the written blocks, which is the height of the next block.
+    block_count: u32,
+    // The hash of the last written block, which the next block must build on.
+    last_hash: Option<BlockHash>,
+}
+
+impl<W: Write> ChainExportWriter<W> {
+    /// Starts the document for the active blockchain with the given tip height.
+    pub fn new(mut writer: W, tip_height: u32) -> Result<Self, String> {
+        write!(
+            writer,
+            "{{\"version\":{},\"tip_height\":{},\"blocks\":[",
+            CHAIN_EXPORT_VERSION, tip_height
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Self {
+            writer,
+            tip_height,
+            block_count: 0,
+            last_hash: None,
+        })
+    }
+
+    /// Writes the block at the next height, which must build on the previously written block.
+    pub fn write_block(&mut self, block: &Block) -> Result<(), String> {
+        let height = self.block_count;
+        if let Some(last_hash) = &self.last_hash {
+            if last_hash != block.header().previous_block_hash() {
+                return Err(format!(
+                    "Block: {} doesn't build on the previously exported block, the active blockchain has changed during the export.",
+                    block.id()
+                ));
+            }
+        }
+        if height > self.tip_height {
+            return Err(format!(
+                "Block: {} is above the tip height: {}.",
+                block.id(),
+                self.tip_height
+            ));
+        }
+        let exported = ExportedBlock {
+            height,
+            hash: block.id(),
+            previous_block_hash: match height {
+                0 => None,
+                _ => Some(block.header().previous_block_hash()),
+            },
+            timestamp: block.header().timestamp(),
+            difficulty_target: block.header().difficulty_target(),
+            nonce: block.header().nonce(),
+            confirmations: self.tip_height - height + 1,
+            size: block.encoded_size(),
+            transactions: block
+                .transactions()
+                .iter()
+                .map(ExportedTransaction::new)
+                .collect(),
+        };
+        if height > 0 {
+            self.writer.write_all(b",").map_err(|e| e.to_string())?;
+        }
+        serde_json::to_writer(&mut self.writer, &exported).map_err(|e| e.to_string())?;
+        self.last_hash = Some(*block.id());
+        self.block_count += 1;
+        Ok(())
+    }
+
+    /// Ends the document, once all blocks up to the tip have been written.
+    pub fn finish(mut self) -> Result<W, String> {
+        if self.block_count != self.tip_height + 1 {
+            return Err(format!(
+                "Exported {} blocks, but expected {}.",
+                self.block_count,
+                self.tip_height + 1
+            ));
+        }
+        self.writer.write_all(b"]}\n").map_err(|e| e.to_string())?;
+        self.writer.flush().map_err(|e| e.to_string())?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::chain_builder::ChainBuilder;
+    use crate::core::chain_params::ChainParams;
+    use serde_json::Value;
+
+    #[test]
+    fn exports_active_blockchain() {
+        let alice = Address::new("alice".to_string());
+        let bob = Address::new("bob".to_string());
+        let mut chain = ChainBuilder::new(ChainParams::regtest());
+        chain.fund(&alice);
+        let transaction = chain.spend(&alice, &bob, 10.into(), 1.into()).unwrap();
+        chain.mine();
+
+        let mut writer = ChainExportWriter::new(vec![], chain.height()).unwrap();
+        for height in 0..=chain.height() {
+            writer.write_block(chain.block(height)).unwrap();
+        }
+        let json: Value = serde_json::from_slice(&writer.finish().unwrap()).unwrap();
+        assert_eq!(json["version"], CHAIN_EXPORT_VERSION);
+        assert_eq!(json["tip_height"], chain.height());
+        let blocks = json["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len() as u32, chain.height() + 1);
+        assert_eq!(blocks[0]["previous_block_hash"], Value::Null);
+        assert_eq!(blocks[0]["confirmations"], chain.height() + 1);
+
+        let tip = blocks.last().unwrap();
+        assert_eq!(tip["hash"], chain.tip().id().to_string());
+        assert_eq!(tip["confirmations"], 1);
+        let exported = &tip["transactions"][1];
+        assert_eq!(exported["txid"], transaction.id().raw().to_string());
+        assert_eq!(exported["coinbase"], false);
+        assert_eq!(
+            exported["inputs"][0]["txid"],
+            transaction.inputs()[0].utxo_id().raw().to_string()
+        );
+        assert_eq!(exported["outputs"][0]["address"], "bob");
+        assert_eq!(exported["outputs"][0]["amount"], 10);
+        assert!(tip["transactions"][0]["inputs"][0]["coinbase_data"].is_string());
+    }
+
+    #[test]
+    fn rejects_blocks_off_the_chain() {
+        let mut chain = ChainBuilder::new(ChainParams::regtest());
+        chain.mine_blocks(2);
+        let mut writer = ChainExportWriter::new(vec![], chain.height()).unwrap();
+        writer.write_block(chain.block(0)).unwrap();
+        assert!(writer.write_block(chain.block(2)).is_err());
+        writer.write_block(chain.block(1)).unwrap();
+        // The tip hasn't been written.
+        assert!(writer.finish().is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::core::block::{BlockHash, BlockHeader};
+use crate::core::hash::merkle_tree_from_transactions;
+use crate::core::miner::Miner;
+use crate::core::transaction::{TransactionInput, TransactionOutput};
+use crate::core::validation::COINBASE_MATURITY;
+use crate::core::{Address, Block, Coolcoin, Sha256, Transaction};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// The parameters of the blockchain that all nodes of the network must agree on,
+/// most importantly the genesis block, which is built from them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainParams {
+    // The message embedded in the genesis block's coinbase transaction.
+    genesis_message: String,
+    genesis_timestamp: u32,
+    // The difficulty target of the genesis block, which all blocks share.
+    genesis_difficulty_target: u32,
+    genesis_nonce: u32,
+    genesis_reward_address: Address,
+    // The reward of the genesis block, which halves every halving interval.
+    block_reward: Coolcoin,
+    // The number of blocks after which the block reward halves, or 0 if it never does.
+    #[serde(default)]
+    halving_interval: u32,
+    // The number of blocks after which the outputs of the coinbase transaction can be spent.
+    #[serde(default = "default_coinbase_maturity")]
+    coinbase_maturity: u32,
+}
+
+fn default_coinbase_maturity() -> u32 {
+    COINBASE_MATURITY
+}
+
+impl ChainParams {
+    /// The parameters of the coolcoin network.
+    pub fn main() -> Self {
+        Self {
+            genesis_message: "".to_string(),
+            // 02 Sep 2021 at ~08:58
+            genesis_timestamp: 1630569467,
+            genesis_difficulty_target: 8,
+            genesis_nonce: 30,
+            genesis_reward_address: Address::new("genesis_wallet_address".to_string()),
+            block_reward: Coolcoin::new(50),
+            halving_interval: 0,
+            coinbase_maturity: COINBASE_MATURITY,
+        }
+    }
+
+    /// The parameters of the local test network, where any hash meets the difficulty target,
+    /// so the blocks are mined instantly, the coinbase outputs mature after two blocks, and
+    /// the reward halves often enough to be tested.
+    pub fn regtest() -> Self {
+        Self {
+            genesis_message: "regtest".to_string(),
+            // 01 Jan 2022 at 00:00
+            genesis_timestamp: 1640995200,
+            genesis_difficulty_target: 0,
+            genesis_nonce: 0,
+            genesis_reward_address: Address::new("genesis_wallet_address".to_string()),
+            block_reward: Coolcoin::new(50),
+            halving_interval: 150,
+            coinbase_maturity: 2,
+        }
+    }
+
+    /// Returns the parameters of the network with the given name: main or regtest.
+    pub fn by_name(name: &str) -> Result<Self, String> {
+        match name {
+            "main" => Ok(Self::main()),
+            "regtest" => Ok(Self::regtest()),
+            _ => Err(format!("Unknown network: {}", name)),
+        }
+    }
+
+    /// Mines the genesis block of the new network, which pays the reward to the address.
+    pub fn mine_genesis(
+        message: String,
+        timestamp: u32,
+        difficulty_target: u32,
+        reward_address: Address,
+        block_reward: Coolcoin,
+    ) -> Result<Self, String> {
+        let mut params = Self {
+            genesis_message: message,
+            genesis_timestamp: timestamp,
+            genesis_difficulty_target: difficulty_target,
+            genesis_nonce: 0,
+            genesis_reward_address: reward_address,
+            block_reward,
+            halving_interval: 0,
+            coinbase_maturity: COINBASE_MATURITY,
+        };
+        let transactions = params.genesis_transactions()?;
+        params.genesis_nonce = Miner::pow(
+            &BlockHash::new(Sha256::new([0; 32])),
+            &merkle_tree_from_transactions(&transactions),
+            timestamp,
+            difficulty_target,
+        )
+        .ok_or_else(|| {
+            format!(
+                "Can't find the nonce for the genesis block at timestamp: {}, try another one.",
+                timestamp
+            )
+        })?;
+        Ok(params)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Failed to load chain params from: {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Invalid chain params: {}: {}", path.display(), e))
+    }
+
+    /// Returns the parameters in the format read by `load`.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+
+    /// Returns the reward for mining the block at the height, without the fees.
+    pub fn block_reward(&self, height: u32) -> Coolcoin {
+        let halvings = match self.halving_interval {
+            0 => 0,
+            interval => height / interval,
+        };
+        match halvings {
+            0..=62 => Coolcoin::new(self.block_reward.value() >> halvings),
+            _ => Coolcoin::zero(),
+        }
+    }
+
+    pub fn coinbase_maturity(&self) -> u32 {
+        self.coinbase_maturity
+    }
+
+    pub fn genesis_block(&self) -> Block {
+        let transactions = self
+            .genesis_transactions()
+            .expect("invalid genesis transaction");
+        let header = BlockHeader::new(
+            BlockHash::new(Sha256::new([0; 32])),
+            merkle_tree_from_transactions(&transactions),
+            self.genesis_timestamp,
+            self.genesis_difficulty_target,
+            self.genesis_nonce,
+        );
+        Block::new(header, transactions)
+    }
+
+    fn genesis_transactions(&self) -> Result<Vec<Transaction>, String> {
+        let inputs = vec![TransactionInput::new_coinbase_with_data(
+            self.genesis_message.as_bytes().to_vec(),
+        )];
+        let outputs = vec![TransactionOutput::new(
+            self.genesis_reward_address.clone(),
+            self.block_reward(0),
+        )];
+        Ok(vec![Transaction::new(inputs, outputs, 0)?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hash::from_hex;
+    use crate::core::BlockValidator;
+
+    #[test]
+    fn main_genesis_block() {
+        let genesis = ChainParams::main().genesis_block();
+        assert_eq!(
+            *genesis.id(),
+            BlockHash::new(
+                from_hex("000ac69f173b62e51cc6263fa20c22d22107389404a28693308c851435fa59dd")
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn regtest_mines_instantly_and_halves_reward() {
+        let params = ChainParams::regtest();
+        let genesis = params.genesis_block();
+        assert_eq!(
+            BlockValidator::validate_no_context(&genesis, genesis.header().timestamp()),
+            Ok(())
+        );
+        assert_ne!(*genesis.id(), *ChainParams::main().genesis_block().id());
+        assert_eq!(params.block_reward(149), 50.into());
+        assert_eq!(params.block_reward(150), 25.into());
+        assert_eq!(params.block_reward(450), 6.into());
+        assert_eq!(params.block_reward(u32::MAX), Coolcoin::zero());
+        assert_eq!(ChainParams::main().block_reward(u32::MAX), 50.into());
+        assert_eq!(ChainParams::by_name("regtest"), Ok(params));
+        assert!(ChainParams::by_name("testnet").is_err());
+    }
+
+    #[test]
+    fn mines_genesis_block_of_new_network() {
+        let params = ChainParams::mine_genesis(
+            "Hello, coolcoin!".to_string(),
+            1640995200,
+            4,
+            Address::new("satoshi".to_string()),
+            100.into(),
+        )
+        .unwrap();
+        let genesis = params.genesis_block();
+        assert_eq!(
+            BlockValidator::validate_no_context(&genesis, 1640995200),
+            Ok(())
+        );
+        assert_eq!(
+            genesis.transactions()[0].inputs()[0].coinbase_data(),
+            b"Hello, coolcoin!"
+        );
+        assert_eq!(genesis.transactions()[0].outputs()[0].amount(), 100.into());
+        assert_eq!(toml::from_str::<ChainParams>(&params.to_toml()), Ok(params));
+    }
+}
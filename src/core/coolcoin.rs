@@ -1,3 +1,4 @@
+use crate::core::serialize::{Decodable, Encodable, Reader};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 use std::iter::Sum;
@@ -14,6 +15,30 @@ impl Coolcoin {
     pub fn zero() -> Self {
         Self::new(0)
     }
+
+    pub const fn value(&self) -> i64 {
+        self.0
+    }
+
+    /// Returns the sum of the amounts, or None if it overflows, e.g. for the outputs of
+    /// a transaction received from a peer.
+    pub fn checked_sum(amounts: impl IntoIterator<Item = Coolcoin>) -> Option<Coolcoin> {
+        amounts.into_iter().try_fold(Self::zero(), |sum, amount| {
+            sum.0.checked_add(amount.0).map(Self)
+        })
+    }
+}
+
+impl Encodable for Coolcoin {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.0.encode(output);
+    }
+}
+
+impl Decodable for Coolcoin {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self(i64::decode(input)?))
+    }
 }
 
 impl Add for Coolcoin {
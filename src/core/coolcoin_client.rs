@@ -0,0 +1,451 @@
+use crate::core::address_history::AddressTransaction;
+use crate::core::ban_list::{Ban, SetBanCommand, Subnet};
+use crate::core::block::BlockHash;
+use crate::core::blocktree::{BlockHeaderInfo, ChainTip, TransactionInfo};
+use crate::core::coolcoin_network::{NetTotals, PeerInfo};
+use crate::core::coolcoin_node::NodeInfo;
+use crate::core::fork_log::ForkStats;
+use crate::core::mined_block_log::MinedBlock;
+use crate::core::mining_server::MiningInfo;
+use crate::core::peer_connection::PeerMessage;
+use crate::core::peer_log::PeerEvent;
+use crate::core::poller::{wait_readable, wait_writable};
+use crate::core::transaction::TransactionId;
+use crate::core::transaction_pool::TransactionPoolInfo;
+use crate::core::utxo_pool::UnspentOutput;
+use crate::core::{Address, Block, Coolcoin, FeeRate, PeerConnection, Transaction};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// The client of the node's request API, so that other programs (tests, explorers, bots) can
+/// query and operate the node without handling the peer messages themselves.
+///
+/// Requests are sent one at a time over a single connection, which is established on the first
+/// request and reestablished when it's lost.
+pub struct CoolcoinClient {
+    server: String,
+    timeout: Duration,
+    // The number of times the request is sent before giving up, if the connection is lost.
+    max_attempts: u32,
+    enable_logging: bool,
+    connection: Option<PeerConnection>,
+    // Whether the connection is reestablished when it's lost, which is only possible if
+    // the client has established it.
+    can_reconnect: bool,
+}
+
+impl CoolcoinClient {
+    pub fn new(server: String, timeout: Duration, enable_logging: bool) -> Self {
+        Self {
+            server,
+            timeout,
+            max_attempts: 2,
+            enable_logging,
+            connection: None,
+            can_reconnect: true,
+        }
+    }
+
+    /// Creates the client that sends the requests over the given connection, e.g. to a node
+    /// running in the same process. The requests fail once the connection is lost.
+    pub fn with_connection(connection: PeerConnection, timeout: Duration) -> Self {
+        Self {
+            server: connection.address().to_string(),
+            timeout,
+            max_attempts: 1,
+            enable_logging: false,
+            connection: Some(connection),
+            can_reconnect: false,
+        }
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sends the request to the node and waits for the response.
+    /// If the connection fails, the client reconnects and sends the request again,
+    /// but not if the node doesn't respond in time, because the request may have been applied.
+    pub fn request(&mut self, message: PeerMessage) -> Result<PeerMessage, String> {
+        let mut attempt = 1;
+        loop {
+            match self.try_request(&message) {
+                Ok(Some(response)) => return Ok(response),
+                Ok(None) => {
+                    // The response may still arrive, so it can't be matched with the next request.
+                    self.connection = None;
+                    return Err(format!(
+                        "Request timed out after: {} seconds.",
+                        self.timeout.as_secs()
+                    ));
+                }
+                Err(e) => {
+                    self.connection = None;
+                    if attempt >= self.max_attempts {
+                        return Err(e);
+                    }
+                    if self.enable_logging {
+                        info!(target: "net", "Request to: {} failed: {}. Reconnecting.", self.server, e);
+                    }
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    pub fn get_block(&mut self, hash: BlockHash) -> Result<Option<Block>, String> {
+        match self.request(PeerMessage::GetBlock(hash))? {
+            PeerMessage::ResponseBlock(block) => Ok(block.map(Arc::unwrap_or_clone)),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    /// Returns the id of the transaction accepted to the node's transaction pool.
+    pub fn send_transaction(&mut self, transaction: Transaction) -> Result<TransactionId, String> {
+        match self.request(PeerMessage::SendTransaction(transaction))? {
+            PeerMessage::ResponseTransaction(result) => {
+                result.map_err(|e| format!("Transaction rejected: {}", e))
+            }
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    /// Returns the hashes of the blocks in the active blockchain and all blocks known to the node.
+    pub fn get_full_blockchain(&mut self) -> Result<(Vec<BlockHash>, Vec<Arc<Block>>), String> {
+        match self.request(PeerMessage::GetFullBlockchain)? {
+            PeerMessage::ResponseFullBlockchain(active_blockchain, blocks) => {
+                Ok((active_blockchain, blocks))
+            }
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn estimate_smart_fee(&mut self, target: u32) -> Result<Option<FeeRate>, String> {
+        match self.request(PeerMessage::EstimateSmartFee(target))? {
+            PeerMessage::ResponseFeeEstimate(fee_rate) => Ok(fee_rate),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_transaction_pool_info(&mut self) -> Result<TransactionPoolInfo, String> {
+        match self.request(PeerMessage::GetTransactionPoolInfo)? {
+            PeerMessage::ResponseTransactionPoolInfo(info) => Ok(info),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_mining_info(&mut self) -> Result<MiningInfo, String> {
+        match self.request(PeerMessage::GetMiningInfo)? {
+            PeerMessage::ResponseMiningInfo(info) => Ok(info),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn list_mined_blocks(&mut self) -> Result<Vec<MinedBlock>, String> {
+        match self.request(PeerMessage::ListMinedBlocks)? {
+            PeerMessage::ResponseListMinedBlocks(blocks) => Ok(blocks),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_block_count(&mut self) -> Result<u32, String> {
+        match self.request(PeerMessage::GetBlockCount)? {
+            PeerMessage::ResponseBlockCount(count) => Ok(count),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_best_block_hash(&mut self) -> Result<BlockHash, String> {
+        match self.request(PeerMessage::GetBestBlockHash)? {
+            PeerMessage::ResponseBestBlockHash(hash) => Ok(hash),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_block_hash(&mut self, height: u32) -> Result<Option<BlockHash>, String> {
+        match self.request(PeerMessage::GetBlockHash(height))? {
+            PeerMessage::ResponseBlockHash(hash) => Ok(hash),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn list_unspent(
+        &mut self,
+        min_confirmations: u32,
+        max_confirmations: u32,
+        addresses: Vec<Address>,
+    ) -> Result<Vec<UnspentOutput>, String> {
+        match self.request(PeerMessage::ListUnspent(
+            min_confirmations,
+            max_confirmations,
+            addresses,
+        ))? {
+            PeerMessage::ResponseListUnspent(outputs) => Ok(outputs),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    /// Returns the UTXO set, optionally only the outputs sent to the addresses,
+    /// with at least the given amount.
+    pub fn get_utxos(
+        &mut self,
+        addresses: Vec<Address>,
+        min_amount: Coolcoin,
+    ) -> Result<Vec<UnspentOutput>, String> {
+        match self.request(PeerMessage::GetUtxos(addresses, min_amount))? {
+            PeerMessage::ResponseListUnspent(outputs) => Ok(outputs),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    /// Returns at most the given number of the addresses' most recent transactions,
+    /// oldest first.
+    pub fn list_transactions(
+        &mut self,
+        addresses: Vec<Address>,
+        count: u32,
+    ) -> Result<Vec<AddressTransaction>, String> {
+        match self.request(PeerMessage::ListTransactions(addresses, count))? {
+            PeerMessage::ResponseListTransactions(transactions) => Ok(transactions),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn stop(&mut self) -> Result<(), String> {
+        let result = match self.request(PeerMessage::Stop)? {
+            PeerMessage::ResponseStop(result) => result,
+            unexpected => Err(unexpected_response(unexpected)),
+        };
+        // The node closes the connection when it stops.
+        self.connection = None;
+        result
+    }
+
+    pub fn get_peer_info(&mut self) -> Result<Vec<PeerInfo>, String> {
+        match self.request(PeerMessage::GetPeerInfo)? {
+            PeerMessage::ResponsePeerInfo(peers) => Ok(peers),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_net_totals(&mut self) -> Result<NetTotals, String> {
+        match self.request(PeerMessage::GetNetTotals)? {
+            PeerMessage::ResponseNetTotals(totals) => Ok(totals),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    /// Makes the node write its state to the file, and returns the file's path.
+    pub fn dump_state(&mut self, file_name: Option<String>) -> Result<String, String> {
+        match self.request(PeerMessage::DumpState(file_name))? {
+            PeerMessage::ResponseDumpState(result) => result,
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    /// Changes the levels of the node's log categories, and returns the level of each category.
+    pub fn logging(
+        &mut self,
+        include: Vec<String>,
+        exclude: Vec<String>,
+    ) -> Result<BTreeMap<String, String>, String> {
+        match self.request(PeerMessage::Logging(include, exclude))? {
+            PeerMessage::ResponseLogging(result) => result,
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_fork_stats(&mut self, num_blocks: Option<u32>) -> Result<ForkStats, String> {
+        match self.request(PeerMessage::GetForkStats(num_blocks))? {
+            PeerMessage::ResponseForkStats(stats) => Ok(stats),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_peer_history(&mut self, count: Option<usize>) -> Result<Vec<PeerEvent>, String> {
+        match self.request(PeerMessage::GetPeerHistory(count))? {
+            PeerMessage::ResponsePeerHistory(events) => Ok(events),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_info(&mut self) -> Result<NodeInfo, String> {
+        match self.request(PeerMessage::GetInfo)? {
+            PeerMessage::ResponseInfo(info) => Ok(info),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    /// Returns the number of seconds since the node has started.
+    pub fn uptime(&mut self) -> Result<u64, String> {
+        match self.request(PeerMessage::Uptime)? {
+            PeerMessage::ResponseUptime(uptime) => Ok(uptime),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_difficulty(&mut self) -> Result<f64, String> {
+        match self.request(PeerMessage::GetDifficulty)? {
+            PeerMessage::ResponseDifficulty(difficulty) => Ok(difficulty),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_chain_tips(&mut self) -> Result<Vec<ChainTip>, String> {
+        match self.request(PeerMessage::GetChainTips)? {
+            PeerMessage::ResponseChainTips(tips) => Ok(tips),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn get_block_header(&mut self, hash: BlockHash) -> Result<Option<BlockHeaderInfo>, String> {
+        match self.request(PeerMessage::GetBlockHeader(hash))? {
+            PeerMessage::ResponseBlockHeader(info) => Ok(info),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    /// Returns the transaction with its confirmations, which are 0 if it's unconfirmed,
+    /// or None if the node doesn't know about it.
+    pub fn get_transaction(
+        &mut self,
+        transaction_id: TransactionId,
+    ) -> Result<Option<TransactionInfo>, String> {
+        match self.request(PeerMessage::GetTransaction(transaction_id))? {
+            PeerMessage::ResponseTransactionInfo(info) => Ok(info),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn list_banned(&mut self) -> Result<Vec<Ban>, String> {
+        match self.request(PeerMessage::ListBanned)? {
+            PeerMessage::ResponseListBanned(bans) => Ok(bans),
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn set_ban(&mut self, subnet: Subnet, command: SetBanCommand) -> Result<(), String> {
+        match self.request(PeerMessage::SetBan(subnet, command))? {
+            PeerMessage::ResponseSetBan(result) => result,
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    pub fn prioritise_transaction(
+        &mut self,
+        transaction_id: TransactionId,
+        fee_delta: Coolcoin,
+    ) -> Result<(), String> {
+        match self.request(PeerMessage::PrioritiseTransaction(
+            transaction_id,
+            fee_delta,
+        ))? {
+            PeerMessage::ResponsePrioritiseTransaction(result) => result,
+            unexpected => Err(unexpected_response(unexpected)),
+        }
+    }
+
+    /// Returns the response, None if the node hasn't responded in time,
+    /// or an error if the connection has failed.
+    fn try_request(&mut self, message: &PeerMessage) -> Result<Option<PeerMessage>, String> {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None if !self.can_reconnect => {
+                return Err(format!("Connection to: {} has been lost.", self.server))
+            }
+            None => self.connection.insert(PeerConnection::connect(
+                self.server.clone(),
+                self.enable_logging,
+            )?),
+        };
+        if !connection.send(message)? {
+            return Err(format!(
+                "Failed to send the request to: {}.",
+                connection.address()
+            ));
+        }
+        let request_sent_time = Instant::now();
+        while request_sent_time.elapsed() < self.timeout {
+            match connection.receive()? {
+                // The node relays new blocks and transactions to all connections, including
+                // the clients', which is not the response. The same goes for the inventory
+                // request, which the node sends to all connections when it starts.
+                Some(PeerMessage::RelayBlock(_))
+                | Some(PeerMessage::RelayTransaction(_))
+                | Some(PeerMessage::GetInventory()) => {}
+                Some(response) => return Ok(Some(response)),
+                // The rest of the request is written before waiting for the response.
+                None if connection.has_pending_sends() => wait_writable(
+                    connection.raw_fd(),
+                    self.timeout.saturating_sub(request_sent_time.elapsed()),
+                )?,
+                None => wait_readable(
+                    connection.raw_fd(),
+                    self.timeout.saturating_sub(request_sent_time.elapsed()),
+                )?,
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn unexpected_response(response: PeerMessage) -> String {
+    format!(
+        "Unexpected response: {}",
+        serde_json::to_string_pretty(&response).unwrap()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn reconnects_when_connection_is_lost() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let node = thread::spawn(move || {
+            // The first connection is lost before the node responds.
+            let (tcp_stream, _) = listener.accept().unwrap();
+            drop(tcp_stream);
+            let (tcp_stream, address) = listener.accept().unwrap();
+            tcp_stream.set_nonblocking(true).unwrap();
+            let mut connection = PeerConnection::from_tcp_stream(address, tcp_stream, false);
+            for _ in 0..2 {
+                let request = loop {
+                    if let Some(request) = connection.receive().unwrap() {
+                        break request;
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                };
+                let response = match request {
+                    PeerMessage::GetBlockCount => PeerMessage::ResponseBlockCount(7),
+                    _ => PeerMessage::ResponseUptime(42),
+                };
+                connection.send(&response).unwrap();
+            }
+        });
+
+        let mut client = CoolcoinClient::new(server, Duration::from_secs(5), false);
+        assert_eq!(client.get_block_count(), Ok(7));
+        // The response doesn't match the request.
+        assert!(client.get_difficulty().is_err());
+        node.join().unwrap();
+    }
+
+    #[test]
+    fn times_out_when_node_does_not_respond() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let mut client = CoolcoinClient::new(server, Duration::from_millis(50), false);
+        assert_eq!(
+            client.uptime(),
+            Err("Request timed out after: 0 seconds.".to_string())
+        );
+    }
+}
@@ -1,8 +1,14 @@
-use crate::core::peer_connection::PeerMessage;
+use crate::core::message_capture::MessageCapture;
+use crate::core::peer_connection::{PeerMessage, TrafficStats};
 use crate::core::PeerConnection;
-use std::collections::HashSet;
-use std::io::{Error, ErrorKind, Read};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
 use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::warn;
 
 pub struct NetworkParams {
     // Address at which TCP server (which listens for peer connections) runs.
@@ -11,6 +17,11 @@ pub struct NetworkParams {
     peers: Vec<String>,
     // Whether or not the messages that are sent and received through the network are logged.
     enable_logging: bool,
+    // File to which the received messages are recorded, so that they can be replayed.
+    capture_file: Option<PathBuf>,
+    // Peers that the node has been connected to before the restart. Unlike the given peers,
+    // the node starts even if they can't be reached.
+    saved_peers: Vec<String>,
 }
 
 impl NetworkParams {
@@ -19,15 +30,62 @@ impl NetworkParams {
             server_address,
             peers: peer_addresses,
             enable_logging,
+            capture_file: None,
+            saved_peers: vec![],
         }
     }
+
+    pub fn with_capture_file(mut self, capture_file: PathBuf) -> Self {
+        self.capture_file = Some(capture_file);
+        self
+    }
+
+    pub fn with_saved_peers(mut self, saved_peers: Vec<String>) -> Self {
+        self.saved_peers = saved_peers;
+        self
+    }
+}
+
+/// The state of the connection to a peer or a client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub address: String,
+    // Seconds since the connection has been established.
+    pub connected: u64,
+    // Seconds since the last message has been received, if any.
+    pub idle: Option<u64>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub traffic: TrafficStats,
+}
+
+/// The traffic with all peers and clients since the node has started.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetTotals {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub traffic: TrafficStats,
 }
 
 pub struct CoolcoinNetwork {
-    peer_connections: Vec<(String, PeerConnection)>,
+    // Connections by the peer address, which identifies the peer while it's connected.
+    peer_connections: HashMap<String, PeerConnection>,
     enable_logging: bool,
-    tcp_listener: TcpListener,
-    send_queue: Vec<(String, PeerMessage)>,
+    // None when the connections are established by the owner, e.g. between the nodes
+    // of the same process.
+    tcp_listener: Option<TcpListener>,
+    capture: Option<MessageCapture>,
+    // Bytes exchanged with the peers that have been disconnected since.
+    dropped_bytes_sent: u64,
+    dropped_bytes_received: u64,
+    dropped_traffic: TrafficStats,
+    // Peers that have connected since the last call of take_new_peers.
+    new_peers: Vec<String>,
+    // Peers that have been disconnected since the last call of take_disconnected_peers,
+    // with the reasons.
+    disconnected_peers: Vec<(String, String)>,
+    // Addresses of the peers that the node has connected to, rather than accepted.
+    outbound_peers: HashSet<String>,
 }
 
 impl CoolcoinNetwork {
@@ -37,23 +95,76 @@ impl CoolcoinNetwork {
             .set_nonblocking(true)
             .map_err(|e| e.to_string())?;
 
-        let mut peer_connections = Vec::new();
+        let mut peer_connections = HashMap::new();
         for address in &params.peers {
             let peer_connection = PeerConnection::connect(address.clone(), params.enable_logging)?;
-            peer_connections.push((address.clone(), peer_connection));
+            peer_connections.insert(address.clone(), peer_connection);
         }
+        for address in &params.saved_peers {
+            if peer_connections.contains_key(address) {
+                continue;
+            }
+            match PeerConnection::connect(address.clone(), params.enable_logging) {
+                Ok(peer_connection) => {
+                    peer_connections.insert(address.clone(), peer_connection);
+                }
+                Err(e) => {
+                    warn!(target: "net", "Failed to connect to saved peer: {}: {}", address, e)
+                }
+            }
+        }
+        let capture = match &params.capture_file {
+            Some(path) => Some(MessageCapture::create(path)?),
+            None => None,
+        };
         Ok(Self {
+            new_peers: peer_connections.keys().cloned().collect(),
+            outbound_peers: peer_connections.keys().cloned().collect(),
             peer_connections,
-            tcp_listener,
-            send_queue: vec![],
+            tcp_listener: Some(tcp_listener),
             enable_logging: params.enable_logging,
+            capture,
+            dropped_bytes_sent: 0,
+            dropped_bytes_received: 0,
+            dropped_traffic: TrafficStats::default(),
+            disconnected_peers: vec![],
         })
     }
 
+    /// Creates the network with the given connections, which doesn't accept new peers.
+    pub fn with_connections(peer_connections: Vec<PeerConnection>, enable_logging: bool) -> Self {
+        Self {
+            new_peers: peer_connections
+                .iter()
+                .map(|connection| connection.address().to_string())
+                .collect(),
+            peer_connections: peer_connections
+                .into_iter()
+                .map(|connection| (connection.address().to_string(), connection))
+                .collect(),
+            tcp_listener: None,
+            enable_logging,
+            capture: None,
+            dropped_bytes_sent: 0,
+            dropped_bytes_received: 0,
+            dropped_traffic: TrafficStats::default(),
+            disconnected_peers: vec![],
+            outbound_peers: HashSet::new(),
+        }
+    }
+
     pub fn accept_new_peers(&mut self) -> Result<(), String> {
         loop {
-            match self.tcp_listener.accept() {
+            let accepted = match &self.tcp_listener {
+                Some(tcp_listener) => tcp_listener.accept(),
+                None => return Ok(()),
+            };
+            match accepted {
                 Ok((tcp_stream, socket_address)) => {
+                    // Accepted streams don't inherit the non-blocking mode of the listener.
+                    tcp_stream
+                        .set_nonblocking(true)
+                        .map_err(|e| e.to_string())?;
                     self.on_new_peer_connected(socket_address, tcp_stream);
                 }
                 Err(e) => match e.kind() {
@@ -69,9 +180,117 @@ impl CoolcoinNetwork {
         Ok(())
     }
 
+    /// Returns the peers that have connected since the last call, including the ones that
+    /// the network has connected to when it was created.
+    pub fn take_new_peers(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.new_peers)
+    }
+
+    /// Returns the peers that have been disconnected since the last call, with the reasons.
+    pub fn take_disconnected_peers(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.disconnected_peers)
+    }
+
+    /// The sockets that become readable when there is a new peer or a new message.
+    pub fn raw_fds(&self) -> Vec<RawFd> {
+        let mut fds = self
+            .tcp_listener
+            .iter()
+            .map(TcpListener::as_raw_fd)
+            .collect::<Vec<RawFd>>();
+        fds.extend(self.peer_connections.values().map(PeerConnection::raw_fd));
+        fds
+    }
+
+    /// The sockets of the connections with messages waiting to be written.
+    pub fn pending_send_fds(&self) -> Vec<RawFd> {
+        self.peer_connections
+            .values()
+            .filter(|connection| connection.has_pending_sends())
+            .map(PeerConnection::raw_fd)
+            .collect()
+    }
+
+    pub fn peer_raw_fd(&self, peer_address: &str) -> Option<RawFd> {
+        self.peer_connections
+            .get(peer_address)
+            .map(PeerConnection::raw_fd)
+    }
+
+    pub fn num_peers(&self) -> usize {
+        self.peer_connections.len()
+    }
+
+    /// Total number of bytes sent to all peers, including the disconnected ones.
+    pub fn bytes_sent(&self) -> u64 {
+        self.dropped_bytes_sent
+            + self
+                .peer_connections
+                .values()
+                .map(PeerConnection::bytes_sent)
+                .sum::<u64>()
+    }
+
+    /// Total number of bytes received from all peers, including the disconnected ones.
+    pub fn bytes_received(&self) -> u64 {
+        self.dropped_bytes_received
+            + self
+                .peer_connections
+                .values()
+                .map(PeerConnection::bytes_received)
+                .sum::<u64>()
+    }
+
+    /// Returns the traffic with all peers, including the disconnected ones.
+    pub fn net_totals(&self) -> NetTotals {
+        let mut traffic = self.dropped_traffic.clone();
+        for connection in self.peer_connections.values() {
+            traffic.add(connection.traffic());
+        }
+        NetTotals {
+            bytes_sent: self.bytes_sent(),
+            bytes_received: self.bytes_received(),
+            traffic,
+        }
+    }
+
+    /// Returns the addresses of the connected peers that the node has connected to, ordered.
+    /// The inbound peers are left out, since their addresses can't be connected to.
+    pub fn outbound_peers(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self
+            .outbound_peers
+            .iter()
+            .filter(|address| self.peer_connections.contains_key(*address))
+            .cloned()
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    /// Returns the connected peers, ordered by their address.
+    pub fn peer_info(&self) -> Vec<PeerInfo> {
+        let now = Instant::now();
+        let mut peers = self
+            .peer_connections
+            .values()
+            .map(|connection| PeerInfo {
+                address: connection.address().to_string(),
+                connected: now.duration_since(connection.connected_at()).as_secs(),
+                idle: connection
+                    .last_received_at()
+                    .map(|time| now.duration_since(time).as_secs()),
+                bytes_sent: connection.bytes_sent(),
+                bytes_received: connection.bytes_received(),
+                traffic: connection.traffic().clone(),
+            })
+            .collect::<Vec<PeerInfo>>();
+        peers.sort_by(|a, b| a.address.cmp(&b.address));
+        peers
+    }
+
     pub fn receive_all(&mut self) -> Vec<(String, PeerMessage)> {
         let mut all_messages = vec![];
-        let mut to_drop = HashSet::new();
+        let mut to_drop = HashMap::new();
         for (sender, peer_connection) in &mut self.peer_connections {
             match peer_connection.receive_all() {
                 Ok(messages) => {
@@ -80,15 +299,26 @@ impl CoolcoinNetwork {
                     }
                 }
                 Err(e) => {
-                    eprintln!("{}", e);
-                    to_drop.insert(sender.clone());
+                    warn!(target: "net", "{}", e);
+                    to_drop.insert(sender.clone(), e);
                     continue;
                 }
             }
         }
 
-        for peer_address in to_drop {
-            self.drop_connection(&peer_address);
+        for (peer_address, reason) in to_drop {
+            self.drop_connection(&peer_address, reason);
+        }
+
+        if let Some(capture) = &mut self.capture {
+            for (sender, message) in &all_messages {
+                if let Err(e) = capture.record(sender, message) {
+                    // The node keeps running without the capture rather than stopping.
+                    warn!(target: "net", "Failed to capture the message, capture is stopped: {}", e);
+                    self.capture = None;
+                    break;
+                }
+            }
         }
 
         all_messages
@@ -96,22 +326,22 @@ impl CoolcoinNetwork {
 
     pub fn multicast(&mut self, message: PeerMessage, skipped: Vec<String>) -> Result<(), String> {
         let mut errors = vec![];
-        let mut to_drop = HashSet::new();
+        let mut to_drop = HashMap::new();
         for (receiver, connection) in &mut self.peer_connections {
             match skipped.contains(receiver) {
                 true => {}
                 false => match connection.send(&message) {
                     Ok(_) => {}
                     Err(e) => {
-                        to_drop.insert(receiver.to_string());
+                        to_drop.insert(receiver.to_string(), e.clone());
                         errors.push(e);
                     }
                 },
             }
         }
 
-        for peer_address in to_drop {
-            self.drop_connection(&peer_address);
+        for (peer_address, reason) in to_drop {
+            self.drop_connection(&peer_address, reason);
         }
 
         if errors.is_empty() {
@@ -125,31 +355,46 @@ impl CoolcoinNetwork {
         self.multicast(message, vec![])
     }
 
+    pub fn is_connected(&self, peer_address: &str) -> bool {
+        self.peer_connections.contains_key(peer_address)
+    }
+
     pub fn send_to(&mut self, receiver: &str, message: PeerMessage) -> Result<bool, String> {
-        match self
-            .peer_connections
-            .iter_mut()
-            .find(|(address, _)| address == receiver)
-        {
+        match self.peer_connections.get_mut(receiver) {
             None => Err(format!("Peer: {} doesn't exist.", receiver)),
-            Some((_, peer)) => peer.send(&message),
+            Some(peer) => peer.send(&message),
         }
     }
 
+    /// Drops the connections to the peers matching the predicate for the given reason, and
+    /// returns their addresses.
+    pub fn drop_peers(&mut self, predicate: impl Fn(&str) -> bool, reason: &str) -> Vec<String> {
+        let dropped = self
+            .peer_connections
+            .keys()
+            .filter(|peer_address| predicate(peer_address))
+            .cloned()
+            .collect::<Vec<String>>();
+        for peer_address in &dropped {
+            self.drop_connection(peer_address, reason.to_string());
+        }
+        dropped
+    }
+
     fn on_new_peer_connected(&mut self, socket_address: SocketAddr, tcp_stream: TcpStream) {
         let peer_connection =
             PeerConnection::from_tcp_stream(socket_address, tcp_stream, self.enable_logging);
+        self.new_peers.push(peer_connection.address().to_string());
         self.peer_connections
-            .push((peer_connection.address().to_string(), peer_connection));
+            .insert(peer_connection.address().to_string(), peer_connection);
     }
 
-    fn drop_connection(&mut self, sender: &str) {
-        for i in 0..self.peer_connections.len() {
-            let (peer_address, _) = self.peer_connections.get(i).unwrap();
-            if peer_address == sender {
-                self.peer_connections.remove(i);
-                break;
-            }
+    fn drop_connection(&mut self, sender: &str, reason: String) {
+        if let Some(connection) = self.peer_connections.remove(sender) {
+            self.disconnected_peers.push((sender.to_string(), reason));
+            self.dropped_bytes_sent += connection.bytes_sent();
+            self.dropped_bytes_received += connection.bytes_received();
+            self.dropped_traffic.add(connection.traffic());
         }
     }
 }
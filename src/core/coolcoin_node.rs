@@ -1,30 +1,59 @@
-use crate::core::block::BlockHash;
-use crate::core::coolcoin_network::NetworkParams;
-use crate::core::miner::{Miner, MinerRequest, MinerResponse};
+use crate::core::address_history::AddressHistory;
+use crate::core::ban_list::{Ban, BanList, SetBanCommand, Subnet};
+use crate::core::block::{BlockHash, MAX_BLOCK_SIZE};
+use crate::core::blocktree::{ChainTip, TransactionInfo};
+use crate::core::chain_params::ChainParams;
+use crate::core::coolcoin_network::{NetworkParams, PeerInfo};
+use crate::core::fork_log::{ForkEvent, ForkLog};
+use crate::core::hash_display::HashDisplay;
+use crate::core::log_filter;
+use crate::core::metrics::{Metrics, SlowThresholds};
+use crate::core::mined_block_log::MinedBlock;
+use crate::core::miner::{
+    Miner, MinerChannel, MinerRequest, MinerResponse, MiningStats, RewardSplit,
+};
+use crate::core::mining_server::{MinerInfo, MiningInfo, MiningServerParams, SubmitResult};
+use crate::core::node_event::{EventBus, NodeEvent};
 use crate::core::peer_connection::PeerMessage;
+use crate::core::peer_log::{PeerEvent, PeerLog};
+use crate::core::poller::Poller;
+use crate::core::recently_seen::RecentlySeen;
+use crate::core::storage::{self, FileStorage};
+use crate::core::timers::Timers;
+use crate::core::transaction::{TransactionId, TransactionInput};
+use crate::core::transaction_pool::{
+    PackageLimits, RemovalReason, TransactionPoolEntryInfo, TransactionPoolEvent,
+    TransactionPoolInfo,
+};
+use crate::core::utxo_pool::{UnspentOutput, UtxoView};
 use crate::core::{
-    Address, Block, BlockchainManager, ChainContext, Coolcoin, CoolcoinNetwork, Transaction,
-    TransactionPool, UtxoContext, UtxoPool,
+    target_hash, Address, Block, BlockValidator, BlockchainManager, ChainContext, Coolcoin,
+    CoolcoinNetwork, FeeEstimator, FeeRate, MinedBlockLog, MiningServer, OrphanedTransactionPool,
+    PayoutLedger, SpentOutput, Transaction, TransactionPool, TransactionPoolContext,
+    TransactionRebroadcaster, TransactionValidator, UtxoContext, UtxoPool,
 };
-use std::net::TcpStream;
-use std::sync::mpsc::TryRecvError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
 use std::sync::Arc;
 use std::thread;
-use std::thread::sleep;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
 
 /// There are four roles in the Coolcoin P2P network:
 ///   - Wallet: A function of a wallet is to send and receive Coolcoins.
-///             It may be part of the full node, which is usually the case with desktop clients.
+///     It may be part of the full node, which is usually the case with desktop clients.
 ///   - Miner: A function of the mining node is to produce new blocks with unconfirmed transactions.
-///            Some mining nodes are also full nodes.
+///     Some mining nodes are also full nodes.
 ///   - Full Blockchain: Responsible for validating transactions and blocks.
-///                      Full blockchain nodes can autonomously and authoritatively verify
-///                      blocks and transactions without external reference.
+///     Full blockchain nodes can autonomously and authoritatively verify
+///     blocks and transactions without external reference.
 ///   - Network routing node: A function of the routing node is to relay information about blocks
 ///     and transactions to the blockchain network.
 ///     All nodes have this role.
-///             
+///
 /// CoolcoinNode has the following roles:
 ///   - Miner
 ///   - Full Blockchain
@@ -34,37 +63,317 @@ pub struct CoolcoinNode {
     blockchain_manager: BlockchainManager,
     outstanding_get_inventory_requests: Vec<String>,
     transaction_pool: TransactionPool,
+    orphaned_transaction_pool: OrphanedTransactionPool,
     utxo_pool: UtxoPool,
-    coinbase_address: Address,
+    // The genesis block, the block reward and the coinbase maturity of the network.
+    chain_params: ChainParams,
+    reward_split: RewardSplit,
+    // Data embedded in the coinbase transactions of the mined blocks.
+    coinbase_tag: Vec<u8>,
+    // The minimum fee rate for transactions to be accepted to the pool.
+    min_relay_fee_rate: FeeRate,
+    fee_estimator: FeeEstimator,
+    mined_block_log: MinedBlockLog,
+    fork_log: ForkLog,
+    peer_log: PeerLog,
+    ban_list: BanList,
+    transaction_pool_events: Receiver<TransactionPoolEvent>,
+    transaction_rebroadcaster: TransactionRebroadcaster,
+    // The transactions that have been relayed to the node, whether accepted or not.
+    recently_seen_transactions: RecentlySeen,
+    // The blocks that have been announced to the peers.
+    recently_announced_blocks: RecentlySeen,
+    // The template of the block that the miner is working on, if any.
+    mining_template: Option<MiningTemplate>,
+    // Serves the mining template to external miners, if enabled.
+    mining_server: Option<MiningServer>,
+    events: EventBus,
+    // Transactions saved from the transaction pool at the previous shutdown, which are waiting
+    // for their parents, since the blockchain is synced from the peers after the restart.
+    saved_transactions: Vec<Transaction>,
+    // Set by the stop request, after which the node shuts down and `run` returns.
+    is_stopping: bool,
+    started: Instant,
+    metrics: Arc<Metrics>,
+    status_interval: Option<Duration>,
+    // The hash rate of the node's own miner, as measured on the last block it has mined.
+    local_hashrate: Option<u64>,
+    slow_thresholds: SlowThresholds,
+    // Directory in which the node keeps its persistent data.
+    data_dir: PathBuf,
+}
+
+/// The periodic tasks of the node, which run even when there are no events.
+#[derive(Debug, Clone, Copy)]
+enum NodeTimer {
+    TemplateRefresh,
+    Rebroadcast,
+    OrphanExpiry,
+    Status,
 }
 
+struct MiningTemplate {
+    previous_block_hash: BlockHash,
+    transaction_pool_sequence: u64,
+    // Time when the template has been sent to the miner.
+    time: u32,
+    // Number of transactions in the template, excluding the coinbase transaction.
+    num_transactions: usize,
+}
+
+/// A summary of the node's state for quick health checks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeInfo {
+    version: String,
+    // Height of the active blockchain.
+    blocks: u32,
+    // Number of connected peers, including the clients.
+    peers: usize,
+    // Number of transactions in the transaction pool.
+    transactions: usize,
+    // Confirmed balance of the node's coinbase address.
+    balance: Coolcoin,
+    // Seconds since the node has started.
+    uptime: u64,
+}
+
+/// A snapshot of the node's state, written by `dumpstate` to be attached to bug reports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateDump {
+    // Time (seconds from Unix Epoch) at which the state has been dumped.
+    time: u32,
+    info: NodeInfo,
+    best_block_hash: BlockHash,
+    difficulty: f64,
+    median_time_past: u32,
+    chain_tips: Vec<ChainTip>,
+    // Blocks received before their ancestors, which the node is waiting for.
+    orphaned_blocks: Vec<BlockHash>,
+    transaction_pool: TransactionPoolInfo,
+    transaction_pool_entries: Vec<TransactionPoolEntryInfo>,
+    peers: Vec<PeerInfo>,
+    bans: Vec<Ban>,
+}
+
+/// Policies of the node, which are not part of the consensus rules.
+pub struct NodeParams {
+    // Addresses at which the miner gets paid for successfully mining a block.
+    pub reward_split: RewardSplit,
+    // Data embedded in the coinbase transactions of the mined blocks.
+    pub coinbase_tag: Vec<u8>,
+    pub package_limits: PackageLimits,
+    // The maximum memory usage of the transaction pool in bytes.
+    pub max_transaction_pool_usage: usize,
+    // The minimum fee rate for transactions to be accepted to the pool.
+    pub min_relay_fee_rate: FeeRate,
+    // Directory in which the node keeps its persistent data.
+    pub data_dir: PathBuf,
+    pub chain_params: ChainParams,
+    // How often the node logs its status line, if at all.
+    pub status_interval: Option<Duration>,
+    pub slow_thresholds: SlowThresholds,
+}
+
+// The maximum number of orphaned transactions kept per peer, and in total.
+const MAX_ORPHANED_TRANSACTIONS_PER_PEER: usize = 100;
+const MAX_ORPHANED_TRANSACTIONS: usize = 1000;
+// Orphaned transactions are dropped if their parents don't arrive in this time, which is
+// checked at the given interval.
+const ORPHANED_TRANSACTION_EXPIRY: Duration = Duration::from_secs(20 * 60);
+const ORPHANED_TRANSACTION_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Transactions sent by clients are rebroadcast if they haven't been confirmed after this many
+// blocks, and at most once per the given interval (in seconds).
+const REBROADCAST_AFTER_BLOCKS: u32 = 3;
+const MIN_REBROADCAST_INTERVAL: u32 = 60;
+// The minimum time between two templates (in seconds) when only the transaction pool changes.
+// Templates are updated immediately when the active blockchain changes.
+const MIN_TEMPLATE_REFRESH_INTERVAL: u32 = 5;
+// How often the time-based tasks are checked, when there are no events in the meantime.
+const TEMPLATE_REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const REBROADCAST_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// Space reserved in the block for the header and the coinbase transaction, which may pay
+// many miners in the pool mode.
+const BLOCK_RESERVED_SIZE: usize = 10_000;
+// The number of relayed transactions and blocks that are remembered, so that the duplicates
+// from other peers are skipped, and the rate of the unseen ones that are skipped as well.
+const RECENTLY_SEEN_TRANSACTIONS: usize = 50_000;
+const RECENTLY_SEEN_BLOCKS: usize = 5_000;
+const RECENTLY_SEEN_FALSE_POSITIVE_RATE: f64 = 0.000_001;
+// Files in the data directory to which the transaction pool and the outbound peers are saved
+// at shutdown, and from which they are loaded at startup.
+const TRANSACTION_POOL_FILE: &str = "mempool.json";
+const SAVED_PEERS_FILE: &str = "saved_peers.json";
+
 impl CoolcoinNode {
     pub fn connect(
         network_params: NetworkParams,
-        coinbase_address: Address,
+        mining_server_params: Option<MiningServerParams>,
+        params: NodeParams,
     ) -> Result<Self, String> {
-        let network = CoolcoinNetwork::connect(&network_params)?;
+        let saved_peers = storage::load_json::<Vec<String>>(
+            &FileStorage,
+            &params.data_dir.join(SAVED_PEERS_FILE),
+            "saved peers",
+        )?
+        .unwrap_or_default();
+        let network = CoolcoinNetwork::connect(&network_params.with_saved_peers(saved_peers))?;
+        Self::with_network(network, mining_server_params, params)
+    }
+
+    /// Creates the node that communicates with the peers over the given network.
+    pub fn with_network(
+        network: CoolcoinNetwork,
+        mining_server_params: Option<MiningServerParams>,
+        params: NodeParams,
+    ) -> Result<Self, String> {
+        let NodeParams {
+            reward_split,
+            coinbase_tag,
+            package_limits,
+            max_transaction_pool_usage,
+            min_relay_fee_rate,
+            data_dir,
+            chain_params,
+            status_interval,
+            slow_thresholds,
+        } = params;
+        let mining_server = match &mining_server_params {
+            None => None,
+            Some(params) => {
+                let ledger = PayoutLedger::load(&data_dir.join("payout_ledger.json"))?;
+                Some(MiningServer::bind(
+                    params,
+                    reward_split.clone(),
+                    coinbase_tag.clone(),
+                    ledger,
+                )?)
+            }
+        };
+        let fee_estimator = FeeEstimator::load(&data_dir.join("fee_estimates.json"))?;
+        let mined_block_log = MinedBlockLog::load(&data_dir.join("mined_blocks.jsonl"))?;
+        let fork_log = ForkLog::load(&data_dir.join("forks.jsonl"))?;
+        let peer_log = PeerLog::load(&data_dir.join("peers.jsonl"))?;
+        let ban_list = BanList::load(&data_dir.join("ban_list.json"))?;
+        let saved_transactions = storage::load_json::<Vec<Transaction>>(
+            &FileStorage,
+            &data_dir.join(TRANSACTION_POOL_FILE),
+            "transaction pool",
+        )?
+        .unwrap_or_default();
+        let blockchain_manager =
+            BlockchainManager::with_genesis_block(chain_params.genesis_block());
+        let mut utxo_pool = UtxoPool::new();
+        let genesis_block = blockchain_manager
+            .block_tree()
+            .get(blockchain_manager.tip())
+            .unwrap();
+        utxo_pool.connect_block(genesis_block, 0)?;
+        let mut transaction_pool = TransactionPool::new(package_limits, max_transaction_pool_usage);
+        let transaction_pool_events = transaction_pool.subscribe();
+        let events = EventBus::default();
+        let metrics = Arc::new(Metrics::default());
+        metrics.record_events(events.subscribe());
         Ok(Self {
             network,
-            blockchain_manager: BlockchainManager::new(),
+            blockchain_manager,
             outstanding_get_inventory_requests: Vec::new(),
-            transaction_pool: TransactionPool::new(),
-            utxo_pool: UtxoPool::new(),
-            coinbase_address,
+            transaction_pool,
+            orphaned_transaction_pool: OrphanedTransactionPool::new(
+                MAX_ORPHANED_TRANSACTIONS_PER_PEER,
+                MAX_ORPHANED_TRANSACTIONS,
+                ORPHANED_TRANSACTION_EXPIRY,
+            ),
+            utxo_pool,
+            chain_params,
+            reward_split,
+            coinbase_tag,
+            min_relay_fee_rate,
+            fee_estimator,
+            mined_block_log,
+            fork_log,
+            peer_log,
+            ban_list,
+            transaction_pool_events,
+            transaction_rebroadcaster: TransactionRebroadcaster::new(
+                REBROADCAST_AFTER_BLOCKS,
+                MIN_REBROADCAST_INTERVAL,
+            ),
+            recently_seen_transactions: RecentlySeen::new(
+                RECENTLY_SEEN_TRANSACTIONS,
+                RECENTLY_SEEN_FALSE_POSITIVE_RATE,
+            ),
+            recently_announced_blocks: RecentlySeen::new(
+                RECENTLY_SEEN_BLOCKS,
+                RECENTLY_SEEN_FALSE_POSITIVE_RATE,
+            ),
+            mining_template: None,
+            mining_server,
+            events,
+            saved_transactions,
+            is_stopping: false,
+            started: Instant::now(),
+            metrics,
+            status_interval,
+            local_hashrate: None,
+            slow_thresholds,
+            data_dir,
         })
     }
 
+    /// Returns the receiver of all node events published from now on.
+    pub fn subscribe(&self) -> Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns the bus on which the node publishes its events, to which the consumers can
+    /// subscribe once the node is running, e.g. from other threads.
+    pub fn event_bus(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// Returns the metrics that the node updates while it runs, e.g. for the metrics server.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    fn publish(&self, event: NodeEvent) {
+        self.events.publish(event);
+    }
+
     pub fn run(mut self) {
         // If we can't send messages to all nodes immediately, then there is no point in trying
         // to recover since this is part of the startup.
         // It is okay for the process to fail since retrying would mean rerunning the process.
         // Of course, in production like implementation we would handle that in code.
         self.network.broadcast(PeerMessage::GetInventory()).unwrap();
+        let mut poller = Poller::new().unwrap();
+        let mut timers = Timers::new();
+        timers.add(
+            NodeTimer::TemplateRefresh,
+            TEMPLATE_REFRESH_CHECK_INTERVAL,
+            Instant::now(),
+        );
+        timers.add(
+            NodeTimer::Rebroadcast,
+            REBROADCAST_CHECK_INTERVAL,
+            Instant::now(),
+        );
+        timers.add(
+            NodeTimer::OrphanExpiry,
+            ORPHANED_TRANSACTION_EXPIRY_CHECK_INTERVAL,
+            Instant::now(),
+        );
+        if let Some(status_interval) = self.status_interval {
+            timers.add(NodeTimer::Status, status_interval, Instant::now());
+        }
 
-        let reward = Coolcoin::new(50);
-        let mut miner = Miner::start_async(self.coinbase_address.clone(), reward);
+        let mut miner = Miner::start_async(
+            self.reward_split.clone(),
+            self.coinbase_tag.clone(),
+            poller.waker(),
+        );
 
-        loop {
+        while !self.is_stopping {
             let current_time = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
@@ -74,9 +383,11 @@ impl CoolcoinNode {
             match self.network.accept_new_peers() {
                 Ok(()) => {}
                 Err(e) => {
-                    eprintln!("Error while accepting new peers: {}", e);
+                    error!(target: "net", "Error while accepting new peers: {}", e);
                 }
             }
+            self.drop_banned_peers(current_time);
+            self.record_connection_changes(current_time);
 
             // Process outstanding inventory requests.
             let outstanding_requests = self.outstanding_get_inventory_requests.clone();
@@ -85,7 +396,7 @@ impl CoolcoinNode {
                 match self.on_get_inventory(&request) {
                     Ok(()) => {}
                     Err(e) => {
-                        eprintln!("Error while processing outstanding requests: {}", e);
+                        error!(target: "net", "Error while processing outstanding requests: {}", e);
                     }
                 }
             }
@@ -93,62 +404,322 @@ impl CoolcoinNode {
             // Receive data from the network.
             let messages = self.network.receive_all();
             for (sender, message) in messages {
-                match self.on_message(&sender, message, current_time) {
-                    Ok(()) => {}
-                    Err(e) => {
-                        eprintln!("Error while processing new message: {}", e);
-                    }
+                let message_name = message.name();
+                let start = Instant::now();
+                let result = self.on_message(&sender, message, current_time);
+                let elapsed = start.elapsed();
+                self.metrics.message_handling().observe(elapsed);
+                if elapsed > self.slow_thresholds.message_handling {
+                    warn!(target: "net", "Slow message: {} from: {} took: {:?}", message_name, sender, elapsed);
+                }
+                if let Err(e) = result {
+                    error!(target: "net", "Error while processing new message: {}", e);
                 }
             }
 
             // Update miner and check if there are any new blocks.
             match miner.read() {
                 Ok(MinerResponse::None(request)) => {
-                    println!("Miner failed to mine a block for request: {:#?}", request);
+                    warn!(target: "miner", "Miner failed to mine a block for request: {:#?}", request);
+                    self.mining_template = None;
                 }
-                Ok(MinerResponse::Mined(block)) => {
-                    println!(
+                Ok(MinerResponse::Mined(block, stats)) => {
+                    self.mining_template = None;
+                    info!(target: "miner",
                         "Miner has successfully mined a new block: {}",
                         serde_json::to_string_pretty(&block).unwrap()
                     );
-                    self.process_new_block_and_update_active_blockchain(block);
+                    let hash = *block.id();
+                    match self.submit_block(block, current_time) {
+                        SubmitResult::Accepted => self.record_mined_block(&hash, &stats),
+                        result => {
+                            warn!(target: "validation", "Mined block has been rejected: {:?}", result)
+                        }
+                    }
                 }
                 Err(TryRecvError::Empty) => {}
                 Err(TryRecvError::Disconnected) => {
-                    eprintln!("Miner has been disconnected!")
+                    error!(target: "miner", "Miner has been disconnected!")
                 }
             }
 
-            if miner.num_outstanding_requests() == 0 && !self.transaction_pool.is_empty() {
-                let previous_block_hash = self.blockchain_manager.tip().clone();
-                let transactions = self.transaction_pool.all().clone();
-                // TODO: Difficulty target should be returned by the blockchain manager,
-                // and it should be adjusted for each chain.
-                let difficulty_target = self
-                    .blockchain_manager
-                    .block_tree()
-                    .get(self.blockchain_manager.tip())
-                    .unwrap()
-                    .header()
-                    .difficulty_target();
-                match miner.send(MinerRequest::new(
-                    previous_block_hash,
-                    transactions,
-                    difficulty_target,
-                )) {
-                    Ok(()) => {
-                        println!("Requested from miner to mine block.");
+            self.process_transaction_pool_events();
+
+            // Check if external miners have found any new blocks.
+            self.process_mining_server_blocks(current_time);
+
+            for timer in timers.expired(Instant::now()) {
+                match timer {
+                    // The template is updated below, on every wake-up.
+                    NodeTimer::TemplateRefresh => {}
+                    NodeTimer::Rebroadcast => {
+                        if let Err(e) = self.rebroadcast_transactions(current_time) {
+                            error!(target: "mempool", "Error while rebroadcasting transactions: {}", e);
+                        }
                     }
-                    Err(e) => {
-                        eprintln!("{}", e.to_string());
+                    NodeTimer::OrphanExpiry => {
+                        let expired = self.orphaned_transaction_pool.expire(Instant::now());
+                        if expired > 0 {
+                            debug!(target: "mempool", "Expired {} orphaned transactions", expired);
+                        }
+                        if self.started.elapsed() >= ORPHANED_TRANSACTION_EXPIRY {
+                            self.saved_transactions.clear();
+                        }
                     }
+                    NodeTimer::Status => self.log_status(),
+                }
+            }
+
+            // The template is updated as soon as the active blockchain changes, and the timer
+            // picks up the changes of the transaction pool that have been held back.
+            if let Err(e) = self.update_mining_template(&mut miner, current_time) {
+                error!(target: "miner", "Error while updating the mining template: {}", e);
+            }
+
+            self.update_metrics();
+            if !self.is_stopping {
+                self.wait_for_events(&mut poller, &timers);
+            }
+        }
+        self.shutdown(&mut miner);
+    }
+
+    /// Blocks until there is a new peer, a message from a peer or a miner, space to send
+    /// the pending messages or the outstanding responses, or a timer is due.
+    fn wait_for_events(&mut self, poller: &mut Poller, timers: &Timers<NodeTimer>) {
+        let mut readable = self.network.raw_fds();
+        let mut writable = self.network.pending_send_fds();
+        if let Some(mining_server) = self.mining_server.as_ref() {
+            readable.extend(mining_server.raw_fds());
+            writable.extend(mining_server.pending_send_fds());
+        }
+        writable.extend(
+            self.outstanding_get_inventory_requests
+                .iter()
+                .filter_map(|peer_address| self.network.peer_raw_fd(peer_address)),
+        );
+        let timeout = timers
+            .time_until_next(Instant::now())
+            .unwrap_or(REBROADCAST_CHECK_INTERVAL);
+        if let Err(e) = poller.wait(&readable, &writable, timeout) {
+            error!(target: "net", "{}", e);
+            // Don't spin if the poller keeps failing.
+            thread::sleep(timeout);
+        }
+    }
+
+    // Updates the gauges that are cheap enough to read on every wake-up. The rest of
+    // the metrics are recorded from the node events.
+    fn update_metrics(&self) {
+        self.metrics.set_peers(self.network.num_peers());
+        let transaction_pool = self.transaction_pool.info();
+        self.metrics
+            .set_transaction_pool(transaction_pool.size, transaction_pool.bytes);
+        self.metrics
+            .set_network_traffic(self.network.bytes_sent(), self.network.bytes_received());
+    }
+
+    /// Logs a single line with the node's progress, so that it can be followed from the logs
+    /// without querying the node. The blocks ahead are the ones received before their
+    /// ancestors, which the node is still waiting for. The hash rate is only known once
+    /// the node's miner has found a block, or the external miners have reported it.
+    fn log_status(&self) {
+        let pool_hashrate = self
+            .mining_server
+            .as_ref()
+            .map(|mining_server| {
+                mining_server
+                    .miners()
+                    .iter()
+                    .map(MinerInfo::hashrate)
+                    .sum::<u64>()
+            })
+            .filter(|hashrate| *hashrate > 0);
+        let hashrate = match (self.local_hashrate, pool_hashrate) {
+            (None, None) => None,
+            (local, pool) => Some(local.unwrap_or(0) + pool.unwrap_or(0)),
+        };
+        // The end of the tip's hash is enough to tell whether the nodes agree on the tip.
+        let tip = HashDisplay::global().format(self.blockchain_manager.tip().raw());
+        info!(
+            target: "status",
+            height = self.tip_height(),
+            tip = %tip,
+            peers = self.network.num_peers(),
+            mempool = self.transaction_pool.info().size,
+            blocks_ahead = self.blockchain_manager.orphaned_blocks().len(),
+            hashrate,
+            "Status"
+        );
+    }
+
+    /// Stops the miners and persists the state that is saved to the data directory.
+    /// Connections to the peers are closed when the node is dropped.
+    fn shutdown(&mut self, miner: &mut MinerChannel) {
+        info!("Shutting down the node.");
+        if let Err(e) = miner.stop() {
+            error!(target: "miner", "Error while stopping the miner: {}", e);
+        }
+        if let Some(mining_server) = self.mining_server.as_mut() {
+            if let Err(e) = mining_server.shutdown() {
+                error!(target: "miner", "Error while shutting down the mining server: {}", e);
+            }
+        }
+        if let Err(e) = self.fee_estimator.save() {
+            error!("Error while saving the fee estimates: {}", e);
+        }
+        if let Err(e) = self.save_transaction_pool() {
+            error!(target: "mempool", "Error while saving the transaction pool: {}", e);
+        }
+        if let Err(e) = self.save_peers() {
+            error!(target: "net", "Error while saving the peers: {}", e);
+        }
+        info!("Node has stopped.");
+    }
+
+    // The saved transactions that haven't been accepted yet are kept for the next start.
+    fn save_transaction_pool(&self) -> Result<(), String> {
+        let mut transactions = self.transaction_pool.all();
+        transactions.extend(self.saved_transactions.iter().cloned());
+        let contents = serde_json::to_string(&transactions).map_err(|e| e.to_string())?;
+        storage::save(
+            &FileStorage,
+            &self.data_dir.join(TRANSACTION_POOL_FILE),
+            contents.as_bytes(),
+        )
+    }
+
+    fn save_peers(&self) -> Result<(), String> {
+        let contents =
+            serde_json::to_string(&self.network.outbound_peers()).map_err(|e| e.to_string())?;
+        storage::save(
+            &FileStorage,
+            &self.data_dir.join(SAVED_PEERS_FILE),
+            contents.as_bytes(),
+        )
+    }
+
+    /// Accepts the saved transactions that are valid by now. The blockchain may not have
+    /// caught up with the one they have been accepted on, so the rest are retried later,
+    /// until they expire like the orphaned transactions. The transactions aren't saved in any
+    /// particular order, so the parents in the pool are accepted in the earlier rounds.
+    fn accept_saved_transactions(&mut self) {
+        let mut has_accepted = true;
+        while has_accepted {
+            has_accepted = false;
+            for transaction in std::mem::take(&mut self.saved_transactions) {
+                if self.transaction_pool.contains(transaction.id()) {
+                    continue;
+                }
+                if self.missing_parents(&transaction).is_empty()
+                    && self.accept_to_transaction_pool(&transaction).is_ok()
+                {
+                    has_accepted = true;
+                } else {
+                    self.saved_transactions.push(transaction);
                 }
             }
+        }
+    }
 
-            thread::sleep(Duration::from_millis(100));
+    fn process_mining_server_blocks(&mut self, current_time: u32) {
+        let blocks = match self.mining_server.as_mut() {
+            None => return,
+            Some(mining_server) => mining_server.receive_blocks(),
+        };
+        for (miner, job_id, block) in blocks {
+            info!(target: "miner",
+                "Miner: {} has mined a new block: {}",
+                miner,
+                serde_json::to_string_pretty(&block).unwrap()
+            );
+            let result = self.submit_block(block, current_time);
+            if result != SubmitResult::Accepted {
+                warn!(target: "validation", "Block mined by: {} has been rejected: {:?}", miner, result);
+            }
+            self.mining_server
+                .as_mut()
+                .unwrap()
+                .on_block_processed(&miner, job_id, result);
         }
     }
 
+    /// Sends the new template to the miners if the one they are working on is outdated, i.e.
+    /// the active blockchain has changed, or the transaction pool has changed since the
+    /// last update.
+    fn update_mining_template(
+        &mut self,
+        miner: &mut MinerChannel,
+        current_time: u32,
+    ) -> Result<(), String> {
+        let previous_block_hash = *self.blockchain_manager.tip();
+        let transaction_pool_sequence = self.transaction_pool.sequence();
+        let is_outdated = match &self.mining_template {
+            None => true,
+            Some(template) => {
+                template.previous_block_hash != previous_block_hash
+                    || (template.transaction_pool_sequence != transaction_pool_sequence
+                        && current_time >= template.time + MIN_TEMPLATE_REFRESH_INTERVAL)
+            }
+        };
+        if !is_outdated {
+            return Ok(());
+        }
+
+        // Don't mine empty blocks.
+        if self.transaction_pool.is_empty() {
+            if self.mining_template.take().is_some() {
+                miner.stop()?;
+                if let Some(mining_server) = self.mining_server.as_mut() {
+                    mining_server.stop();
+                }
+            }
+            return Ok(());
+        }
+
+        let template = self
+            .transaction_pool
+            .block_template(MAX_BLOCK_SIZE - BLOCK_RESERVED_SIZE);
+        let height = self.tip_height() + 1;
+        let reward = self.chain_params.block_reward(height) + template.fees();
+        let transactions = template.into_transactions();
+        let num_transactions = transactions.len();
+        // TODO: Difficulty target should be returned by the blockchain manager,
+        // and it should be adjusted for each chain.
+        let difficulty_target = self
+            .blockchain_manager
+            .block_tree()
+            .get(&previous_block_hash)
+            .unwrap()
+            .header()
+            .difficulty_target();
+        if let Some(mining_server) = self.mining_server.as_mut() {
+            mining_server.new_job(
+                previous_block_hash,
+                height,
+                transactions.clone(),
+                difficulty_target,
+                reward,
+                current_time,
+            );
+        }
+        miner.send(MinerRequest::new(
+            previous_block_hash,
+            height,
+            transactions,
+            reward,
+            difficulty_target,
+        ))?;
+        debug!(target: "miner", "Requested from miner to mine block.");
+        self.mining_template = Some(MiningTemplate {
+            previous_block_hash,
+            transaction_pool_sequence,
+            time: current_time,
+            num_transactions,
+        });
+        Ok(())
+    }
+
     fn on_message(
         &mut self,
         sender: &str,
@@ -165,30 +736,533 @@ impl CoolcoinNode {
                 self.on_relay_transaction(sender, transaction)
             }
             PeerMessage::GetBlock(block_hash) => self.on_get_block(sender, block_hash),
-            PeerMessage::ResponseBlock(_block) => {
-                todo!()
-            }
             PeerMessage::SendTransaction(transaction) => {
-                self.on_send_transaction(sender, transaction)
-            }
-            PeerMessage::ResponseTransaction => {
-                todo!()
+                self.on_send_transaction(sender, transaction, current_time)
             }
             PeerMessage::GetFullBlockchain => self.on_get_full_blockchain(sender),
-            PeerMessage::ResponseFullBlockchain(_active_blockchain, _blocks) => {
-                todo!()
+            PeerMessage::EstimateSmartFee(target_blocks) => {
+                self.on_estimate_smart_fee(sender, target_blocks)
+            }
+            PeerMessage::GetTransactionPoolInfo => self.on_get_transaction_pool_info(sender),
+            PeerMessage::MiningSubscribe(_)
+            | PeerMessage::ResponseMiningSubscribe(_)
+            | PeerMessage::MiningNotify(_)
+            | PeerMessage::MiningSubmit(_)
+            | PeerMessage::ResponseMiningSubmit(_)
+            | PeerMessage::MiningReportHashrate(_) => Err(format!(
+                "Peer: {} has sent a mining message, which is only accepted by the mining server.",
+                sender
+            )),
+            PeerMessage::GetMiningInfo => self.on_get_mining_info(sender),
+            PeerMessage::ListMinedBlocks => self.on_list_mined_blocks(sender),
+            PeerMessage::GetBlockCount => self.on_get_block_count(sender),
+            PeerMessage::GetBestBlockHash => self.on_get_best_block_hash(sender),
+            PeerMessage::GetBlockHash(height) => self.on_get_block_hash(sender, height),
+            PeerMessage::ListUnspent(min_confirmations, max_confirmations, addresses) => {
+                self.on_list_unspent(sender, min_confirmations, max_confirmations, &addresses)
+            }
+            PeerMessage::GetUtxos(addresses, min_amount) => {
+                self.on_get_utxos(sender, &addresses, min_amount)
+            }
+            PeerMessage::Stop => self.on_stop(sender),
+            PeerMessage::GetInfo => self.on_get_info(sender),
+            PeerMessage::Uptime => self.on_uptime(sender),
+            PeerMessage::GetDifficulty => self.on_get_difficulty(sender),
+            PeerMessage::GetChainTips => self.on_get_chain_tips(sender),
+            PeerMessage::GetBlockHeader(block_hash) => {
+                self.on_get_block_header(sender, &block_hash)
+            }
+            PeerMessage::GetTransaction(transaction_id) => {
+                self.on_get_transaction(sender, &transaction_id)
+            }
+            PeerMessage::ListBanned => self.on_list_banned(sender, current_time),
+            PeerMessage::SetBan(subnet, command) => {
+                self.on_set_ban(sender, subnet, command, current_time)
+            }
+            PeerMessage::PrioritiseTransaction(transaction_id, fee_delta) => {
+                self.on_prioritise_transaction(sender, transaction_id, fee_delta)
             }
+            PeerMessage::ListTransactions(addresses, count) => {
+                self.on_list_transactions(sender, &addresses, count)
+            }
+            PeerMessage::GetPeerInfo => self.on_get_peer_info(sender),
+            PeerMessage::GetNetTotals => self.on_get_net_totals(sender),
+            PeerMessage::DumpState(path) => self.on_dump_state(sender, path, current_time),
+            PeerMessage::Logging(include, exclude) => self.on_logging(sender, &include, &exclude),
+            PeerMessage::GetForkStats(num_blocks) => self.on_get_fork_stats(sender, num_blocks),
+            PeerMessage::GetPeerHistory(count) => self.on_get_peer_history(sender, count),
+            // The node doesn't send the requests of these responses, so they are unexpected.
+            PeerMessage::ResponseBlock(..)
+            | PeerMessage::ResponseTransaction(..)
+            | PeerMessage::ResponseFullBlockchain(..)
+            | PeerMessage::ResponseFeeEstimate(..)
+            | PeerMessage::ResponseTransactionPoolInfo(..)
+            | PeerMessage::ResponseMiningInfo(..)
+            | PeerMessage::ResponseListMinedBlocks(..)
+            | PeerMessage::ResponseBlockCount(..)
+            | PeerMessage::ResponseBestBlockHash(..)
+            | PeerMessage::ResponseBlockHash(..)
+            | PeerMessage::ResponseListUnspent(..)
+            | PeerMessage::ResponseInfo(..)
+            | PeerMessage::ResponseUptime(..)
+            | PeerMessage::ResponseDifficulty(..)
+            | PeerMessage::ResponseChainTips(..)
+            | PeerMessage::ResponseBlockHeader(..)
+            | PeerMessage::ResponseTransactionInfo(..)
+            | PeerMessage::ResponseListBanned(..)
+            | PeerMessage::ResponseSetBan(..)
+            | PeerMessage::ResponsePrioritiseTransaction(..)
+            | PeerMessage::ResponseListTransactions(..)
+            | PeerMessage::ResponsePeerInfo(..)
+            | PeerMessage::ResponseNetTotals(..)
+            | PeerMessage::ResponseDumpState(..)
+            | PeerMessage::ResponseLogging(..)
+            | PeerMessage::ResponseForkStats(..)
+            | PeerMessage::ResponsePeerHistory(..)
+            | PeerMessage::ResponseStop(..) => Err(format!(
+                "Peer: {} has sent an unexpected response: {}.",
+                sender,
+                message.name()
+            )),
         }
     }
 
+    fn on_get_block_count(&mut self, sender: &str) -> Result<(), String> {
+        let count = self.tip_height();
+        self.network
+            .send_to(sender, PeerMessage::ResponseBlockCount(count))?;
+        Ok(())
+    }
+
+    fn on_get_best_block_hash(&mut self, sender: &str) -> Result<(), String> {
+        let hash = *self.blockchain_manager.tip();
+        self.network
+            .send_to(sender, PeerMessage::ResponseBestBlockHash(hash))?;
+        Ok(())
+    }
+
+    fn on_get_block_hash(&mut self, sender: &str, height: u32) -> Result<(), String> {
+        let hash = self
+            .blockchain_manager
+            .block_tree()
+            .active_block_hash(height);
+        self.network
+            .send_to(sender, PeerMessage::ResponseBlockHash(hash))?;
+        Ok(())
+    }
+
+    fn on_get_info(&mut self, sender: &str) -> Result<(), String> {
+        let info = self.info();
+        self.network
+            .send_to(sender, PeerMessage::ResponseInfo(info))?;
+        Ok(())
+    }
+
+    fn on_uptime(&mut self, sender: &str) -> Result<(), String> {
+        let uptime = self.started.elapsed().as_secs();
+        self.network
+            .send_to(sender, PeerMessage::ResponseUptime(uptime))?;
+        Ok(())
+    }
+
+    fn on_get_difficulty(&mut self, sender: &str) -> Result<(), String> {
+        let block_tree = self.blockchain_manager.block_tree();
+        let tip = block_tree.tip();
+        let difficulty = block_tree
+            .difficulty(tip)
+            .ok_or_else(|| format!("Tip: {} is not in the block tree.", tip))?;
+        self.network
+            .send_to(sender, PeerMessage::ResponseDifficulty(difficulty))?;
+        Ok(())
+    }
+
+    fn on_get_chain_tips(&mut self, sender: &str) -> Result<(), String> {
+        let tips = self.blockchain_manager.block_tree().chain_tips();
+        self.network
+            .send_to(sender, PeerMessage::ResponseChainTips(tips))?;
+        Ok(())
+    }
+
+    fn on_get_block_header(&mut self, sender: &str, block_hash: &BlockHash) -> Result<(), String> {
+        let info = self.blockchain_manager.block_tree().header_info(block_hash);
+        self.network
+            .send_to(sender, PeerMessage::ResponseBlockHeader(info))?;
+        Ok(())
+    }
+
+    fn info(&self) -> NodeInfo {
+        let balance = self
+            .utxo_pool
+            .unspent_outputs(&[self.reward_split.coinbase_address().clone()])
+            .into_iter()
+            .map(|(_, utxo)| utxo.output().amount())
+            .sum();
+        NodeInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            blocks: self.tip_height(),
+            peers: self.network.num_peers(),
+            transactions: self.transaction_pool.info().size,
+            balance,
+            uptime: self.started.elapsed().as_secs(),
+        }
+    }
+
+    fn on_dump_state(
+        &mut self,
+        sender: &str,
+        file_name: Option<String>,
+        current_time: u32,
+    ) -> Result<(), String> {
+        let result = if Self::is_local(sender) {
+            self.dump_state(file_name, current_time)
+        } else {
+            Err(format!(
+                "Peer: {} is not allowed to dump the state.",
+                sender
+            ))
+        };
+        self.network
+            .send_to(sender, PeerMessage::ResponseDumpState(result))?;
+        Ok(())
+    }
+
+    /// Writes the state to the file with the given name in the data directory, and returns
+    /// its path. The callers can only name the file, so they can't overwrite anything else.
+    fn dump_state(&self, file_name: Option<String>, current_time: u32) -> Result<String, String> {
+        let path = match file_name {
+            Some(file_name) => match Path::new(&file_name).file_name() {
+                Some(name) if name == file_name.as_str() && !file_name.starts_with('.') => {
+                    self.data_dir.join(file_name)
+                }
+                _ => {
+                    return Err(format!(
+                        "Invalid file name: {}, the state can only be written to a file in the data directory.",
+                        file_name
+                    ))
+                }
+            },
+            None => self.data_dir.join(format!("state_{}.json", current_time)),
+        };
+        let block_tree = self.blockchain_manager.block_tree();
+        let tip = block_tree.tip();
+        let state = StateDump {
+            time: current_time,
+            info: self.info(),
+            best_block_hash: *tip,
+            difficulty: block_tree
+                .difficulty(tip)
+                .ok_or_else(|| format!("Tip: {} is not in the block tree.", tip))?,
+            median_time_past: block_tree
+                .median_time_past(tip)
+                .ok_or_else(|| format!("Tip: {} is not in the block tree.", tip))?,
+            chain_tips: block_tree.chain_tips(),
+            orphaned_blocks: self
+                .blockchain_manager
+                .orphaned_blocks()
+                .iter()
+                .map(|block| *block.id())
+                .collect(),
+            transaction_pool: self.transaction_pool.info(),
+            transaction_pool_entries: self.transaction_pool.entries(),
+            peers: self.network.peer_info(),
+            bans: self.ban_list.bans(current_time),
+        };
+        let contents = serde_json::to_string_pretty(&state).map_err(|e| e.to_string())?;
+        storage::save(&FileStorage, &path, contents.as_bytes())
+            .map_err(|e| format!("Failed to dump the state to: {}: {}", path.display(), e))?;
+        Ok(path.display().to_string())
+    }
+
+    fn drop_banned_peers(&mut self, current_time: u32) {
+        let ban_list = &self.ban_list;
+        let dropped = self.network.drop_peers(
+            |peer_address| {
+                peer_address
+                    .parse::<SocketAddr>()
+                    .map(|address| ban_list.is_banned(&address.ip(), current_time))
+                    .unwrap_or(false)
+            },
+            "Banned",
+        );
+        for peer_address in dropped {
+            info!(target: "net", "Disconnected banned peer: {}", peer_address);
+        }
+    }
+
+    // Publishes the new connections, and records them and the dropped ones in the peer log.
+    // The orphaned transactions of the dropped peers are dropped as well.
+    fn record_connection_changes(&mut self, current_time: u32) {
+        for address in self.network.take_new_peers() {
+            self.record_peer_event(PeerEvent::Connected {
+                time: current_time,
+                address: address.clone(),
+            });
+            self.publish(NodeEvent::PeerConnected { address });
+        }
+        for (address, reason) in self.network.take_disconnected_peers() {
+            self.orphaned_transaction_pool.remove_for_peer(&address);
+            self.record_peer_event(PeerEvent::Disconnected {
+                time: current_time,
+                address,
+                reason,
+            });
+        }
+    }
+
+    fn record_peer_event(&mut self, event: PeerEvent) {
+        if let Err(e) = self.peer_log.record(event) {
+            error!(target: "net", "Error while recording the peer event: {}", e);
+        }
+    }
+
+    fn on_get_peer_info(&mut self, sender: &str) -> Result<(), String> {
+        let peers = self.network.peer_info();
+        self.network
+            .send_to(sender, PeerMessage::ResponsePeerInfo(peers))?;
+        Ok(())
+    }
+
+    fn on_get_net_totals(&mut self, sender: &str) -> Result<(), String> {
+        let totals = self.network.net_totals();
+        self.network
+            .send_to(sender, PeerMessage::ResponseNetTotals(totals))?;
+        Ok(())
+    }
+
+    fn on_get_fork_stats(&mut self, sender: &str, num_blocks: Option<u32>) -> Result<(), String> {
+        let stats = self.fork_log.stats(self.tip_height(), num_blocks);
+        self.network
+            .send_to(sender, PeerMessage::ResponseForkStats(stats))?;
+        Ok(())
+    }
+
+    fn on_get_peer_history(&mut self, sender: &str, count: Option<usize>) -> Result<(), String> {
+        let events = self.peer_log.history(count);
+        self.network
+            .send_to(sender, PeerMessage::ResponsePeerHistory(events))?;
+        Ok(())
+    }
+
+    fn on_list_banned(&mut self, sender: &str, current_time: u32) -> Result<(), String> {
+        let bans = self.ban_list.bans(current_time);
+        self.network
+            .send_to(sender, PeerMessage::ResponseListBanned(bans))?;
+        Ok(())
+    }
+
+    fn on_set_ban(
+        &mut self,
+        sender: &str,
+        subnet: Subnet,
+        command: SetBanCommand,
+        current_time: u32,
+    ) -> Result<(), String> {
+        let result = if Self::is_local(sender) {
+            let result = self.ban_list.set_ban(subnet, command, current_time);
+            if result.is_ok() {
+                let event = match command {
+                    SetBanCommand::Add(seconds) => {
+                        let until = current_time.saturating_add(seconds);
+                        self.publish(NodeEvent::PeerBanned { subnet, until });
+                        PeerEvent::Banned {
+                            time: current_time,
+                            subnet,
+                            until,
+                            by: sender.to_string(),
+                        }
+                    }
+                    SetBanCommand::Remove => PeerEvent::Unbanned {
+                        time: current_time,
+                        subnet,
+                        by: sender.to_string(),
+                    },
+                };
+                self.record_peer_event(event);
+            }
+            result
+        } else {
+            Err(format!(
+                "Peer: {} is not allowed to change the bans.",
+                sender
+            ))
+        };
+        self.network
+            .send_to(sender, PeerMessage::ResponseSetBan(result))?;
+        // Connections from the newly banned addresses are dropped on the next iteration.
+        Ok(())
+    }
+
+    fn on_prioritise_transaction(
+        &mut self,
+        sender: &str,
+        transaction_id: TransactionId,
+        fee_delta: Coolcoin,
+    ) -> Result<(), String> {
+        let result = if Self::is_local(sender) {
+            self.transaction_pool.prioritise(transaction_id, fee_delta);
+            Ok(())
+        } else {
+            Err(format!(
+                "Peer: {} is not allowed to prioritise transactions.",
+                sender
+            ))
+        };
+        self.network
+            .send_to(sender, PeerMessage::ResponsePrioritiseTransaction(result))?;
+        Ok(())
+    }
+
+    // Anyone can connect to the node, so only the local clients may operate it.
+    fn is_local(sender: &str) -> bool {
+        sender
+            .parse::<SocketAddr>()
+            .map(|address| address.ip().is_loopback())
+            .unwrap_or(false)
+    }
+
+    fn on_logging(
+        &mut self,
+        sender: &str,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<(), String> {
+        // Anyone can query the levels, but only the local clients can change them.
+        let result = if (include.is_empty() && exclude.is_empty()) || Self::is_local(sender) {
+            log_filter::update(include, exclude)
+        } else {
+            Err(format!(
+                "Peer: {} is not allowed to change the log levels.",
+                sender
+            ))
+        };
+        self.network
+            .send_to(sender, PeerMessage::ResponseLogging(result))?;
+        Ok(())
+    }
+
+    fn on_stop(&mut self, sender: &str) -> Result<(), String> {
+        let result = if Self::is_local(sender) {
+            self.is_stopping = true;
+            Ok(())
+        } else {
+            Err(format!("Peer: {} is not allowed to stop the node.", sender))
+        };
+        self.network
+            .send_to(sender, PeerMessage::ResponseStop(result))?;
+        Ok(())
+    }
+
+    fn on_list_unspent(
+        &mut self,
+        sender: &str,
+        min_confirmations: u32,
+        max_confirmations: u32,
+        addresses: &[Address],
+    ) -> Result<(), String> {
+        let outputs = self.unspent_outputs(min_confirmations, max_confirmations, addresses);
+        self.network
+            .send_to(sender, PeerMessage::ResponseListUnspent(outputs))?;
+        Ok(())
+    }
+
+    /// Sends the confirmed outputs sent to the addresses, whose amount is at least the given one.
+    fn on_get_utxos(
+        &mut self,
+        sender: &str,
+        addresses: &[Address],
+        min_amount: Coolcoin,
+    ) -> Result<(), String> {
+        let outputs = self
+            .unspent_outputs(1, u32::MAX, addresses)
+            .into_iter()
+            .filter(|output| output.amount() >= min_amount)
+            .collect();
+        self.network
+            .send_to(sender, PeerMessage::ResponseListUnspent(outputs))?;
+        Ok(())
+    }
+
+    /// Returns the outputs in the UTXO pool with the number of confirmations in the range,
+    /// sent to the addresses, or all of them if there are no addresses. Oldest first.
+    fn unspent_outputs(
+        &self,
+        min_confirmations: u32,
+        max_confirmations: u32,
+        addresses: &[Address],
+    ) -> Vec<UnspentOutput> {
+        let tip_height = self.tip_height();
+        let mut utxos = self
+            .utxo_pool
+            .unspent_outputs(addresses)
+            .into_iter()
+            .map(|((txid, vout), utxo)| (utxo.height(), txid, vout, utxo))
+            .filter(|(height, ..)| {
+                let confirmations = tip_height - height + 1;
+                (min_confirmations..=max_confirmations).contains(&confirmations)
+            })
+            .collect::<Vec<_>>();
+        // Oldest first.
+        utxos.sort_by_key(|(height, txid, vout, _)| (*height, *txid.raw(), vout.value()));
+        utxos
+            .into_iter()
+            .map(|(height, txid, vout, utxo)| {
+                UnspentOutput::new(
+                    txid,
+                    vout,
+                    utxo.output().to().clone(),
+                    utxo.output().amount(),
+                    tip_height - height + 1,
+                    utxo.is_coinbase(),
+                    self.chain_params.coinbase_maturity(),
+                )
+            })
+            .collect()
+    }
+
+    fn on_list_transactions(
+        &mut self,
+        sender: &str,
+        addresses: &[Address],
+        count: u32,
+    ) -> Result<(), String> {
+        let active_blockchain = self.blockchain_manager.block_tree().active_blockchain();
+        let mut transactions = AddressHistory::transactions(
+            addresses,
+            &active_blockchain,
+            &self.transaction_pool.all(),
+        );
+        let skip = transactions.len().saturating_sub(count as usize);
+        transactions.drain(..skip);
+        self.network
+            .send_to(sender, PeerMessage::ResponseListTransactions(transactions))?;
+        Ok(())
+    }
+
+    fn on_get_transaction(
+        &mut self,
+        sender: &str,
+        transaction_id: &TransactionId,
+    ) -> Result<(), String> {
+        let info = match self.transaction_pool.get(transaction_id) {
+            Some(transaction) => Some(TransactionInfo::unconfirmed(transaction.clone())),
+            None => self
+                .blockchain_manager
+                .block_tree()
+                .find_transaction(transaction_id),
+        };
+        self.network
+            .send_to(sender, PeerMessage::ResponseTransactionInfo(info))?;
+        Ok(())
+    }
+
     fn on_get_full_blockchain(&mut self, sender: &str) -> Result<(), String> {
         let blocks = self.blockchain_manager.all_blocks();
         let active_blockchain = self
             .blockchain_manager
             .block_tree()
             .active_blockchain()
-            .iter()
-            .map(|b| b.id().clone())
+            .into_iter()
+            .map(|b| *b.id())
             .collect::<Vec<BlockHash>>();
         self.network.send_to(
             sender,
@@ -197,12 +1271,73 @@ impl CoolcoinNode {
         Ok(())
     }
 
-    fn on_get_block(&mut self, sender: &str, block_hash: BlockHash) -> Result<(), String> {
-        let block = self
+    fn on_estimate_smart_fee(&mut self, sender: &str, target_blocks: u32) -> Result<(), String> {
+        let fee_rate = self.fee_estimator.estimate_smart_fee(target_blocks);
+        self.network
+            .send_to(sender, PeerMessage::ResponseFeeEstimate(fee_rate))?;
+        Ok(())
+    }
+
+    fn on_get_mining_info(&mut self, sender: &str) -> Result<(), String> {
+        let difficulty_target = self
             .blockchain_manager
             .block_tree()
-            .get(&block_hash)
-            .map(|b| b.clone());
+            .get(self.blockchain_manager.tip())
+            .unwrap()
+            .header()
+            .difficulty_target();
+        let miners = self
+            .mining_server
+            .as_ref()
+            .map(MiningServer::miners)
+            .unwrap_or_default();
+        let info = MiningInfo::new(
+            self.tip_height(),
+            difficulty_target,
+            self.mining_template
+                .as_ref()
+                .map(|template| template.num_transactions),
+            miners,
+        );
+        self.network
+            .send_to(sender, PeerMessage::ResponseMiningInfo(info))?;
+        Ok(())
+    }
+
+    fn on_list_mined_blocks(&mut self, sender: &str) -> Result<(), String> {
+        let blocks = self.mined_block_log.blocks().clone();
+        self.network
+            .send_to(sender, PeerMessage::ResponseListMinedBlocks(blocks))?;
+        Ok(())
+    }
+
+    fn record_mined_block(&mut self, hash: &BlockHash, stats: &MiningStats) {
+        let block_tree = self.blockchain_manager.block_tree();
+        let mined_block = MinedBlock::new(
+            block_tree.height(hash).unwrap(),
+            *hash,
+            block_tree.get(hash).unwrap().header().timestamp(),
+            stats.time_to_find().as_secs_f64(),
+            stats.attempts(),
+        );
+        let seconds = stats.time_to_find().as_secs_f64();
+        if seconds > 0.0 {
+            self.local_hashrate = Some((stats.attempts() as f64 / seconds) as u64);
+        }
+        if let Err(e) = self.mined_block_log.record(mined_block) {
+            error!(target: "miner", "Error while recording the mined block: {}", e);
+        }
+    }
+
+    fn on_get_transaction_pool_info(&mut self, sender: &str) -> Result<(), String> {
+        let info = self.transaction_pool.info();
+        self.network
+            .send_to(sender, PeerMessage::ResponseTransactionPoolInfo(info))?;
+        Ok(())
+    }
+
+    fn on_get_block(&mut self, sender: &str, block_hash: BlockHash) -> Result<(), String> {
+        let block = self.blockchain_manager.block_tree().get_shared(&block_hash);
         self.network
             .send_to(sender, PeerMessage::ResponseBlock(block))?;
         Ok(())
@@ -212,11 +1347,33 @@ impl CoolcoinNode {
         &mut self,
         sender: &str,
         transaction: Transaction,
+        current_time: u32,
     ) -> Result<(), String> {
-        self.on_new_transaction(sender, transaction)?;
+        // Let the sender know whether the transaction has been accepted, e.g. a double spend
+        // is rejected with a description of the conflict.
+        // Unlike relayed transactions, transactions sent by clients are not kept as orphans
+        // because clients are expected to only spend outputs that are known to the node.
+        let missing_parents = self.missing_parents(&transaction);
+        let accepted = if missing_parents.is_empty() {
+            self.accept_to_transaction_pool(&transaction)
+                .map(|()| *transaction.id())
+        } else {
+            Err(format!(
+                "Transaction: {} spends outputs of unknown transactions: {}",
+                transaction.id(),
+                missing_parents
+                    .iter()
+                    .map(|parent| parent.raw().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ))
+        };
         self.network
-            .send_to(sender, PeerMessage::ResponseTransaction)?;
-        Ok(())
+            .send_to(sender, PeerMessage::ResponseTransaction(accepted.clone()))?;
+        accepted?;
+        self.transaction_rebroadcaster
+            .track(*transaction.id(), self.tip_height(), current_time);
+        self.on_transaction_accepted(sender, transaction)
     }
 
     fn on_get_inventory(&mut self, sender: &str) -> Result<(), String> {
@@ -239,7 +1396,7 @@ impl CoolcoinNode {
     fn on_response_inventory(
         &mut self,
         _sender: &str,
-        inventory: Vec<Block>,
+        inventory: Vec<Arc<Block>>,
         _current_time: u32,
     ) -> Result<(), String> {
         // Skip the genesis block.
@@ -249,31 +1406,169 @@ impl CoolcoinNode {
         Ok(())
     }
 
-    fn on_relay_block(&mut self, _sender: &str, block: Block) -> Result<(), String> {
-        self.process_new_block_and_update_active_blockchain(block)
+    fn on_relay_block(&mut self, sender: &str, block: Arc<Block>) -> Result<(), String> {
+        let is_orphan = !self.blockchain_manager.exists(&block)
+            && !self
+                .blockchain_manager
+                .block_tree()
+                .exists(block.header().previous_block_hash());
+        let start = Instant::now();
+        let hash = *block.id();
+        let num_transactions = block.transactions().len();
+        let result = self.process_new_block_and_update_active_blockchain(block);
+        self.observe_block_validation(&hash, num_transactions, start.elapsed());
+        result?;
+        // The node has missed the block's ancestors, e.g. while it was partitioned from the
+        // peer, so it catches up with the peer's active blockchain.
+        if is_orphan {
+            self.network.send_to(sender, PeerMessage::GetInventory())?;
+        }
+        Ok(())
+    }
+
+    /// Validates the block mined by the local miners, and processes it if it is valid.
+    /// Unlike the relayed blocks, the mined blocks must build on the tip of the active
+    /// blockchain, otherwise the miner has been working on stale work.
+    fn submit_block(&mut self, block: Block, current_time: u32) -> SubmitResult {
+        if self.blockchain_manager.exists(&block) {
+            return SubmitResult::Duplicate;
+        }
+        if block.header().previous_block_hash() != self.blockchain_manager.tip() {
+            return SubmitResult::Stale;
+        }
+        let start = Instant::now();
+        let hash = *block.id();
+        let num_transactions = block.transactions().len();
+        let validation = BlockValidator::validate_no_context(&block, current_time).and_then(|()| {
+            let chain_context = self.fetch_chain_context(&block)?;
+            BlockValidator::validate_chain_context(&block, &chain_context, current_time)?;
+            // The block extends the tip, so it's connected in the view that is then discarded,
+            // which checks that its transactions spend the existing outputs. Its transactions
+            // are validated against those outputs before it's relayed to the peers.
+            let height = self.tip_height() + 1;
+            let mut utxo_view = UtxoView::new(&self.utxo_pool);
+            utxo_view.connect_block(&block, height)?;
+            let utxo_context =
+                Self::fetch_utxo_context(&utxo_view, &block, height, &self.chain_params);
+            BlockValidator::validate_utxo_context(&block, &utxo_context)
+        });
+        let result = validation
+            .and_then(|()| self.process_new_block_and_update_active_blockchain(Arc::new(block)));
+        self.observe_block_validation(&hash, num_transactions, start.elapsed());
+        match result {
+            Ok(()) => SubmitResult::Accepted,
+            Err(e) => SubmitResult::Invalid(e),
+        }
+    }
+
+    fn observe_block_validation(
+        &self,
+        hash: &BlockHash,
+        num_transactions: usize,
+        elapsed: Duration,
+    ) {
+        self.metrics.block_validation().observe(elapsed);
+        if elapsed > self.slow_thresholds.block_validation {
+            warn!(
+                target: "validation",
+                "Slow block validation: {} with {} transactions at height: {:?} took: {:?}",
+                hash,
+                num_transactions,
+                self.blockchain_manager.block_tree().height(hash),
+                elapsed
+            );
+        }
     }
 
     fn process_new_block_and_update_active_blockchain(
         &mut self,
-        block: Block,
+        block: Arc<Block>,
     ) -> Result<(), String> {
-        let old_tip = self.blockchain_manager.tip().clone();
+        let old_tip = *self.blockchain_manager.tip();
+        let hash = *block.id();
+        let is_new = !self.blockchain_manager.exists(&block);
         self.process_new_block(block)?;
-        let new_tip = self.blockchain_manager.tip().clone();
-        self.on_active_blockchain_changed(&old_tip, &new_tip);
+        let new_tip = *self.blockchain_manager.tip();
+        self.on_active_blockchain_changed(&old_tip, &new_tip)?;
+        if is_new {
+            self.record_if_stale(&hash);
+        }
         Ok(())
     }
 
+    // Records the new block that hasn't become a part of the active blockchain, unless it's
+    // an orphan, which isn't in the block tree yet.
+    fn record_if_stale(&mut self, hash: &BlockHash) {
+        let block_tree = self.blockchain_manager.block_tree();
+        let height = match block_tree.height(hash) {
+            Some(height) => height,
+            None => return,
+        };
+        if block_tree.active_block_hash(height) == Some(*hash) {
+            return;
+        }
+        self.metrics.stale_block();
+        let event = ForkEvent::StaleBlock {
+            time: Miner::current_time(),
+            height,
+            hash: *hash,
+        };
+        if let Err(e) = self.fork_log.record(event) {
+            error!(target: "validation", "Error while recording the stale block: {}", e);
+        }
+    }
+
+    fn record_reorg(
+        &mut self,
+        old_tip: &BlockHash,
+        new_tip: &BlockHash,
+        path_old: &[BlockHash],
+        path_new: &[BlockHash],
+        duration: Duration,
+    ) {
+        let new_tip_height = self
+            .blockchain_manager
+            .block_tree()
+            .height(new_tip)
+            .unwrap();
+        info!(
+            target: "validation",
+            "Reorg from: {} to: {} disconnected {} and connected {} blocks in: {:?}",
+            old_tip,
+            new_tip,
+            path_old.len(),
+            path_new.len(),
+            duration
+        );
+        let event = ForkEvent::Reorg {
+            time: Miner::current_time(),
+            fork_height: new_tip_height - path_new.len() as u32,
+            depth: path_old.len() as u32,
+            connected: path_new.len() as u32,
+            old_tip: *old_tip,
+            new_tip: *new_tip,
+            duration_secs: duration.as_secs_f64(),
+        };
+        if let Err(e) = self.fork_log.record(event) {
+            error!(target: "validation", "Error while recording the reorg: {}", e);
+        }
+    }
+
     /// Should only be called by process_new_block_and_update_active_blockchain
-    fn process_new_block(&mut self, block: Block) -> Result<(), String> {
+    fn process_new_block(&mut self, block: Arc<Block>) -> Result<(), String> {
         // TODO: This method is useful for client as well, extract it as a library.
         if self.blockchain_manager.exists(&block) {
             Ok(())
         } else {
+            // Orphaned blocks don't exist in the block tree until their parents arrive,
+            // so they're announced only the first time they're received.
+            let is_announced = !self.recently_announced_blocks.insert(block.id().raw());
             let orphans = self.blockchain_manager.new_block(block.clone());
             // Broadcast is fine here because the sender would drop it given that it already
             // has it.
-            self.network.broadcast(PeerMessage::RelayBlock(block));
+            if !is_announced {
+                let _ = self.network.broadcast(PeerMessage::RelayBlock(block));
+            }
 
             // TODO: Validate block.
             // TODO: If the validation fails, we should disconnect the peer.
@@ -298,19 +1593,233 @@ impl CoolcoinNode {
         sender: &str,
         transaction: Transaction,
     ) -> Result<(), String> {
+        // The same transaction is relayed by all peers that accept it, and it only needs to be
+        // validated once. Rejected transactions are skipped as well.
+        if !self
+            .recently_seen_transactions
+            .insert(transaction.id().raw())
+        {
+            debug!(target: "net", "Transaction: {} has been seen recently, skipping it", HashDisplay::global().format(transaction.id().raw()));
+            return Ok(());
+        }
         self.on_new_transaction(sender, transaction)
     }
 
     fn on_new_transaction(&mut self, sender: &str, transaction: Transaction) -> Result<(), String> {
-        // TODO: If validation fails, we should disconnect the peers and do not insert it.
-        self.transaction_pool.insert(transaction.clone());
+        let missing_parents = self.missing_parents(&transaction);
+        if !missing_parents.is_empty() {
+            // The transaction is accepted once all of its parents arrive.
+            return self.orphaned_transaction_pool.insert(
+                transaction,
+                sender,
+                missing_parents,
+                Instant::now(),
+            );
+        }
+        // TODO: If validation fails, we should disconnect the peer.
+        self.accept_to_transaction_pool(&transaction)?;
+        self.on_transaction_accepted(sender, transaction)
+    }
+
+    /// Validates the transaction and inserts it to the transaction pool.
+    fn accept_to_transaction_pool(&mut self, transaction: &Transaction) -> Result<(), String> {
+        let start = Instant::now();
+        let validation = self.validate_transaction(transaction);
+        self.metrics
+            .transaction_validation()
+            .observe(start.elapsed());
+        let fee = validation.map_err(|e| {
+            debug!(target: "validation", "Transaction: {} is invalid: {}", HashDisplay::global().format(transaction.id().raw()), e);
+            e
+        })?;
+        self.transaction_pool.insert(transaction.clone(), fee)?;
+        debug!(
+            target: "mempool",
+            "Transaction: {} has been accepted with fee: {}",
+            transaction.id(),
+            fee.value()
+        );
+        let height = self.tip_height();
+        self.fee_estimator.track_transaction(
+            *transaction.id(),
+            fee,
+            transaction.serialized_size(),
+            height,
+        );
+        self.publish(NodeEvent::TransactionAccepted {
+            transaction: transaction.clone(),
+        });
+        Ok(())
+    }
+
+    /// Validates the transaction against the active blockchain and the transaction pool,
+    /// and returns the fee paid by the transaction.
+    /// Should only be called once all parents of the transaction are known.
+    fn validate_transaction(&self, transaction: &Transaction) -> Result<Coolcoin, String> {
+        let spent_outputs = transaction
+            .inputs()
+            .iter()
+            .map(|input| self.spent_output(input))
+            .collect();
+        let next_block_height = self.tip_height() + 1;
+        let median_time_past = self
+            .blockchain_manager
+            .block_tree()
+            .median_time_past(self.blockchain_manager.tip())
+            .unwrap();
+        let context = TransactionPoolContext::new(
+            spent_outputs,
+            next_block_height,
+            median_time_past,
+            self.min_relay_fee_rate,
+            self.chain_params.coinbase_maturity(),
+        );
+        TransactionValidator::validate_transaction_pool_context(transaction, &context)
+    }
+
+    /// Returns the output spent by the input, which is either confirmed and unspent,
+    /// or created by the transaction in the pool.
+    fn spent_output(&self, input: &TransactionInput) -> Option<SpentOutput> {
+        match self.utxo_pool.get(input.utxo_id(), input.output_index()) {
+            Some(utxo) => Some(SpentOutput {
+                output: utxo.output().clone(),
+                coinbase_height: if utxo.is_coinbase() {
+                    Some(utxo.height())
+                } else {
+                    None
+                },
+            }),
+            None => self
+                .transaction_pool
+                .get(input.utxo_id())
+                .and_then(|parent| parent.outputs().get(input.output_index().value() as usize))
+                .map(|output| SpentOutput {
+                    output: output.clone(),
+                    coinbase_height: None,
+                }),
+        }
+    }
+
+    /// Relays the transaction that has been accepted to the transaction pool, and accepts
+    /// any orphaned transactions that have been waiting for it.
+    fn on_transaction_accepted(
+        &mut self,
+        sender: &str,
+        transaction: Transaction,
+    ) -> Result<(), String> {
+        let transaction_id = *transaction.id();
+        let mut errors = vec![];
+        if let Err(e) = self.relay_transaction(sender, transaction) {
+            errors.push(e);
+        }
+        if let Err(e) = self.process_orphaned_transactions(&transaction_id) {
+            errors.push(e);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    /// Re-attempts acceptance of the orphaned transactions that spend outputs of the given
+    /// transaction, which is now known to the node.
+    fn process_orphaned_transactions(&mut self, parent: &TransactionId) -> Result<(), String> {
+        let mut errors = vec![];
+        for (orphan, sender) in self.orphaned_transaction_pool.remove_children(parent) {
+            match self.on_new_transaction(&sender, orphan) {
+                Ok(()) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    /// Returns the transactions whose outputs are spent by the given transaction, but are
+    /// neither confirmed nor in the transaction pool.
+    fn missing_parents(&self, transaction: &Transaction) -> HashSet<TransactionId> {
+        transaction
+            .inputs()
+            .iter()
+            .filter(|input| !input.is_coinbase())
+            .filter(|input| {
+                !self
+                    .utxo_pool
+                    .contains(input.utxo_id(), input.output_index())
+            })
+            .filter(|input| !self.transaction_pool.contains(input.utxo_id()))
+            .map(|input| *input.utxo_id())
+            .collect()
+    }
+
+    // Forwards the removals of the unconfirmed transactions to the node's subscribers.
+    fn process_transaction_pool_events(&mut self) {
+        let events = self
+            .transaction_pool_events
+            .try_iter()
+            .collect::<Vec<TransactionPoolEvent>>();
+        for event in events {
+            if let TransactionPoolEvent::Removed(transaction_id, reason) = event {
+                // Transactions that are no longer in the pool are either confirmed or invalid.
+                self.transaction_rebroadcaster.forget(&transaction_id);
+                if reason != RemovalReason::Confirmed {
+                    self.publish(NodeEvent::TransactionEvicted {
+                        transaction_id,
+                        reason,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Re-announces the transactions sent by clients that haven't been confirmed for a while.
+    fn rebroadcast_transactions(&mut self, current_time: u32) -> Result<(), String> {
+        let mut errors = vec![];
+        let height = self.tip_height();
+        for transaction_id in self.transaction_rebroadcaster.due(height, current_time) {
+            let transaction = self.transaction_pool.get(&transaction_id).unwrap().clone();
+            info!(target: "mempool", "Rebroadcasting transaction: {}", transaction_id);
+            if let Err(e) = self
+                .network
+                .broadcast(PeerMessage::RelayTransaction(transaction))
+            {
+                errors.push(e);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    fn tip_height(&self) -> u32 {
+        self.blockchain_manager
+            .block_tree()
+            .height(self.blockchain_manager.tip())
+            .unwrap()
+    }
+
+    fn relay_transaction(&mut self, sender: &str, transaction: Transaction) -> Result<(), String> {
         self.network.multicast(
             PeerMessage::RelayTransaction(transaction),
             vec![sender.to_string()],
         )
     }
 
-    fn on_active_blockchain_changed(&mut self, old_tip: &BlockHash, new_tip: &BlockHash) {
+    fn on_active_blockchain_changed(
+        &mut self,
+        old_tip: &BlockHash,
+        new_tip: &BlockHash,
+    ) -> Result<(), String> {
+        let reorg_start = Instant::now();
         // The fork is always expected to exist at this stage because only the nodes with a
         // parent have been inserted in the block tree.
         // If fork block is the same as old_tip, then this is an extension of the already active
@@ -318,34 +1827,236 @@ impl CoolcoinNode {
         // As a matter of fact, we don't have to special-case this scenario because the old path
         // would be empty since it doesn't include the fork.
         // TODO: Write a unit test to ensure this is correct.
-        let (_fork, path_old, path_new) = self
-            .blockchain_manager
-            .block_tree()
-            .find_fork(old_tip, new_tip)
-            .unwrap();
-
-        for old_block in &path_old {
-            self.transaction_pool
+        let mut errors = vec![];
+        let mut new_tip = *new_tip;
+        let (path_old, path_new, utxo_changes) = loop {
+            let (_fork, path_old, path_new) = self
+                .blockchain_manager
+                .block_tree()
+                .find_fork(old_tip, &new_tip)
+                .unwrap();
+            // Paths are ordered from the tips towards the fork, so the old blocks are
+            // disconnected starting from the old tip, and the new blocks are connected starting
+            // from the fork. The UTXO pool is only updated if all new blocks can be connected.
+            let block_tree = self.blockchain_manager.block_tree();
+            let mut utxo_view = UtxoView::new(&self.utxo_pool);
+            for old_block in &path_old {
                 // TODO: Fork should return full blocks not just hash.
-                .undo_active_block(self.blockchain_manager.block_tree().get(old_block).unwrap());
+                utxo_view.disconnect_block(block_tree.get(old_block).unwrap());
+            }
+            let chain_params = &self.chain_params;
+            let invalid_block = path_new.iter().rev().find_map(|new_block_hash| {
+                let new_block = block_tree.get(new_block_hash).unwrap();
+                let height = block_tree.height(new_block_hash).unwrap();
+                utxo_view
+                    .connect_block(new_block, height)
+                    .and_then(|()| {
+                        let utxo_context =
+                            Self::fetch_utxo_context(&utxo_view, new_block, height, chain_params);
+                        BlockValidator::validate_utxo_context(new_block, &utxo_context)
+                    })
+                    .err()
+                    .map(|e| (*new_block_hash, e))
+            });
+            match invalid_block {
+                None => break (path_old, path_new, utxo_view.into_changes()),
+                // The invalid block and its descendants are removed, so that the block tree
+                // doesn't point at a chain that the UTXO pool has never applied, and the blocks
+                // are connected again towards the best remaining tip, usually the old one.
+                Some((invalid_block, e)) => {
+                    warn!(target: "validation", "Invalid block: {}: {}", invalid_block, e);
+                    self.blockchain_manager
+                        .invalidate_block(&invalid_block, old_tip);
+                    new_tip = *self.blockchain_manager.tip();
+                    errors.push(e);
+                }
+            }
+        };
+        let new_tip = &new_tip;
+        let num_utxos = utxo_changes.num_utxos();
+        let start = Instant::now();
+        self.utxo_pool.apply_changes(utxo_changes);
+        let elapsed = start.elapsed();
+        self.metrics.utxo_flush().observe(elapsed);
+        if elapsed > self.slow_thresholds.utxo_flush {
+            warn!(
+                target: "validation",
+                "Slow UTXO flush: {} outputs of {} disconnected and {} connected blocks took: {:?}",
+                num_utxos,
+                path_old.len(),
+                path_new.len(),
+                elapsed
+            );
+        }
+
+        if !path_old.is_empty() {
+            self.publish(NodeEvent::Reorg {
+                old_tip: *old_tip,
+                new_tip: *new_tip,
+                disconnected: path_old.clone(),
+                connected: path_new.clone(),
+            });
+        }
+        for old_block_hash in &path_old {
+            let height = self
+                .blockchain_manager
+                .block_tree()
+                .height(old_block_hash)
+                .unwrap();
+            self.publish(NodeEvent::BlockDisconnected {
+                hash: *old_block_hash,
+                height,
+            });
+        }
+
+        for new_block_hash in path_new.iter().rev() {
+            let new_block = self
+                .blockchain_manager
+                .block_tree()
+                .get(new_block_hash)
+                .unwrap();
+            let height = self
+                .blockchain_manager
+                .block_tree()
+                .height(new_block_hash)
+                .unwrap();
+            self.transaction_pool.new_active_block(new_block);
+            self.fee_estimator.new_active_block(new_block, height);
+            self.publish(NodeEvent::BlockConnected {
+                hash: *new_block_hash,
+                height,
+            });
+        }
+
+        // Transactions from the disconnected blocks are not lost, they are returned to the pool
+        // if they are still valid on the new active blockchain.
+        // They are resurrected in the order in which they appeared in the old blockchain, so
+        // the parents are resurrected before their children.
+        let confirmed_transactions = path_new
+            .iter()
+            .flat_map(|new_block| {
+                self.blockchain_manager
+                    .block_tree()
+                    .get(new_block)
+                    .unwrap()
+                    .transactions()
+                    .iter()
+                    .map(|transaction| *transaction.id())
+            })
+            .collect::<HashSet<TransactionId>>();
+        let disconnected_transactions = path_old
+            .iter()
+            .rev()
+            .flat_map(|old_block| {
+                self.blockchain_manager
+                    .block_tree()
+                    .get(old_block)
+                    .unwrap()
+                    .transactions()
+                    .iter()
+                    .filter(|transaction| !transaction.is_coinbase())
+                    .filter(|transaction| !confirmed_transactions.contains(transaction.id()))
+                    .cloned()
+                    .collect::<Vec<Transaction>>()
+            })
+            .collect::<Vec<Transaction>>();
+        for transaction in disconnected_transactions {
+            self.resurrect_transaction(transaction);
+        }
+        if !path_old.is_empty() {
+            self.record_reorg(
+                old_tip,
+                new_tip,
+                &path_old,
+                &path_new,
+                reorg_start.elapsed(),
+            );
+        }
+
+        if !path_new.is_empty() {
+            if let Err(e) = self.fee_estimator.save() {
+                errors.push(format!("Failed to save fee estimates: {}", e));
+            }
+        }
+
+        // Orphaned and saved transactions may be waiting for the transactions in the new blocks.
+        self.accept_saved_transactions();
+        for new_block in path_new.iter().rev() {
+            let transaction_ids = self
+                .blockchain_manager
+                .block_tree()
+                .get(new_block)
+                .unwrap()
+                .transactions()
+                .iter()
+                .map(|transaction| *transaction.id())
+                .collect::<Vec<TransactionId>>();
+            for transaction_id in transaction_ids {
+                if let Err(e) = self.process_orphaned_transactions(&transaction_id) {
+                    errors.push(e);
+                }
+            }
         }
 
-        for new_block in &path_new {
-            self.transaction_pool
-                .new_active_block(self.blockchain_manager.block_tree().get(new_block).unwrap());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
         }
     }
 
-    // Below are required for validation.
-    fn fetch_chain_context(&self, _block: &Block) -> ChainContext {
-        todo!()
+    /// Returns the transaction from the disconnected block to the pool.
+    /// If the transaction is no longer valid, e.g. it conflicts with the new active blockchain,
+    /// then its in-pool descendants are removed from the pool as well.
+    fn resurrect_transaction(&mut self, transaction: Transaction) {
+        if let Err(e) = self.accept_to_transaction_pool(&transaction) {
+            info!(target: "mempool",
+                "Transaction: {} from the disconnected block is dropped: {}",
+                transaction.id(),
+                e
+            );
+            self.transaction_pool.remove_spenders(&transaction);
+        }
     }
 
-    fn fetch_utxo_context(&self, _block: &Block) -> UtxoContext {
-        todo!()
+    // Below are required for validation.
+    fn fetch_chain_context(&self, block: &Block) -> Result<ChainContext, String> {
+        let previous_block_hash = block.header().previous_block_hash();
+        let block_tree = self.blockchain_manager.block_tree();
+        let previous_block = block_tree
+            .get(previous_block_hash)
+            .ok_or_else(|| format!("Previous block: {} is unknown.", previous_block_hash))?;
+        // TODO: The difficulty target should be adjusted for each chain.
+        Ok(ChainContext::new(
+            target_hash(previous_block.header().difficulty_target()),
+            block_tree.median_time_past(previous_block_hash).unwrap(),
+        ))
     }
 
-    fn update_utxo_pool(&self) {
-        todo!("Handle UTXO pool")
+    /// Returns the outputs spent by the block, which has just been connected to the view.
+    fn fetch_utxo_context(
+        utxo_view: &UtxoView,
+        block: &Block,
+        height: u32,
+        chain_params: &ChainParams,
+    ) -> UtxoContext {
+        let spent_outputs = utxo_view
+            .spent_outputs(block.id())
+            .into_iter()
+            .map(|utxo| SpentOutput {
+                output: utxo.output().clone(),
+                coinbase_height: if utxo.is_coinbase() {
+                    Some(utxo.height())
+                } else {
+                    None
+                },
+            })
+            .collect();
+        UtxoContext::new(
+            spent_outputs,
+            height,
+            chain_params.coinbase_maturity(),
+            chain_params.block_reward(height),
+        )
     }
 }
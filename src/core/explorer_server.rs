@@ -0,0 +1,226 @@
+use crate::core::json_rpc::{
+    JsonRpcError, JsonRpcErrorKind, JsonRpcHandler, JsonRpcRequest, INVALID_PARAMS,
+};
+use crate::core::rpc_server::ConnectionLimit;
+use crate::core::RpcServer;
+use serde_json::{json, Value};
+use std::io::{BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// The page of the explorer, which renders the responses of the REST endpoints.
+const EXPLORER_HTML: &str = include_str!("explorer.html");
+// The number of the recent blocks on the front page, and the most that can be requested.
+const DEFAULT_RECENT_BLOCKS: u32 = 20;
+const MAX_RECENT_BLOCKS: u32 = 100;
+// The number of the most recent transactions on the address page.
+const ADDRESS_TRANSACTIONS: u32 = 100;
+
+/// Serves the block explorer: the page at GET /, and the REST endpoints that it reads,
+/// which can also be used directly:
+///
+/// - GET /api/blocks?count=N: the most recent blocks of the active blockchain.
+/// - GET /api/block/HASH: the block, with its height and confirmations.
+/// - GET /api/height/HEIGHT: the hash of the block at the height of the active blockchain.
+/// - GET /api/tx/TXID: the transaction, confirmed or in the transaction pool.
+/// - GET /api/address/ADDRESS: the balance, unspent outputs and transactions of the address.
+///
+/// The requests are forwarded to the node in the same way as the JSON-RPC requests.
+pub struct ExplorerServer {}
+
+impl ExplorerServer {
+    /// Starts serving the requests in a separate thread. The address may be only the port,
+    /// in which case the server listens on the loopback interface, like the RPC server.
+    pub fn start_async(address: &str, node_address: String) -> Result<(), String> {
+        const NODE_TIMEOUT: Duration = Duration::from_secs(10);
+        const MAX_CONNECTIONS: usize = 32;
+        let listener =
+            TcpListener::bind(RpcServer::bind_address(address)).map_err(|e| e.to_string())?;
+        let handler = JsonRpcHandler::new(node_address, NODE_TIMEOUT);
+        thread::spawn(move || {
+            ConnectionLimit::new(MAX_CONNECTIONS).serve_incoming(
+                listener,
+                "explorer",
+                move |stream| Self::serve(stream, &handler),
+            )
+        });
+        Ok(())
+    }
+
+    fn serve(stream: TcpStream, handler: &JsonRpcHandler) -> Result<(), String> {
+        const READ_TIMEOUT: Duration = Duration::from_secs(30);
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut stream = stream;
+        let (status, content_type, body) = match RpcServer::read_request(&mut reader) {
+            Ok(request) if request.method == "GET" => Self::handle(&request.path, handler),
+            Ok(_) => ("405 Method Not Allowed", "text/plain", String::new()),
+            Err(e) => ("400 Bad Request", "text/plain", e),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(response.as_bytes())
+            .map_err(|e| e.to_string())
+    }
+
+    // Returns the status, the content type and the body of the response.
+    fn handle(path: &str, handler: &JsonRpcHandler) -> (&'static str, &'static str, String) {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let segments = path
+            .trim_matches('/')
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<&str>>();
+        let result = match segments.as_slice() {
+            [] => {
+                return (
+                    "200 OK",
+                    "text/html; charset=utf-8",
+                    EXPLORER_HTML.to_string(),
+                )
+            }
+            ["api", "blocks"] => Self::recent_blocks(handler, query),
+            ["api", "block", hash] => Self::block(handler, hash),
+            ["api", "height", height] => match height.parse::<u32>() {
+                Ok(height) => call(handler, "getblockhash", vec![Value::from(height)]),
+                Err(e) => Err(JsonRpcError::new(
+                    INVALID_PARAMS,
+                    format!("Invalid height: {}", e),
+                )),
+            },
+            ["api", "tx", txid] => call(handler, "gettransaction", vec![Value::from(*txid)]),
+            ["api", "address", address] => Self::address(handler, address),
+            _ => return ("404 Not Found", "text/plain", String::new()),
+        };
+        let (status, body) = match result {
+            Ok(value) => ("200 OK", value),
+            Err(e) => {
+                let status = match e.kind() {
+                    JsonRpcErrorKind::InvalidParams => "400 Bad Request",
+                    JsonRpcErrorKind::BlockNotFound | JsonRpcErrorKind::TransactionNotFound => {
+                        "404 Not Found"
+                    }
+                    JsonRpcErrorKind::NodeUnavailable => "502 Bad Gateway",
+                    _ => "500 Internal Server Error",
+                };
+                (status, json!({ "error": e.message }))
+            }
+        };
+        (status, "application/json", body.to_string())
+    }
+
+    fn recent_blocks(handler: &JsonRpcHandler, query: &str) -> Result<Value, JsonRpcError> {
+        let count = match query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("count="))
+        {
+            Some(count) => count
+                .parse::<u32>()
+                .map_err(|e| JsonRpcError::new(INVALID_PARAMS, format!("Invalid count: {}", e)))?
+                .min(MAX_RECENT_BLOCKS),
+            None => DEFAULT_RECENT_BLOCKS,
+        };
+        let tip_height = call(handler, "getblockcount", vec![])?
+            .as_u64()
+            .unwrap_or_default() as u32;
+        let mut blocks = vec![];
+        for height in (0..=tip_height).rev().take(count as usize) {
+            let hash = call(handler, "getblockhash", vec![Value::from(height)])?;
+            let block = call(handler, "getblock", vec![hash.clone()])?;
+            blocks.push(json!({
+                "height": height,
+                "hash": hash,
+                "timestamp": block["header"]["timestamp"],
+                "transactions": block["transactions"].as_array().map_or(0, Vec::len),
+            }));
+        }
+        Ok(json!({ "tip_height": tip_height, "blocks": blocks }))
+    }
+
+    fn block(handler: &JsonRpcHandler, hash: &str) -> Result<Value, JsonRpcError> {
+        let info = call(handler, "getblockheader", vec![Value::from(hash)])?;
+        let block = call(handler, "getblock", vec![Value::from(hash)])?;
+        Ok(json!({ "info": info, "block": block }))
+    }
+
+    fn address(handler: &JsonRpcHandler, address: &str) -> Result<Value, JsonRpcError> {
+        let unspent = call(
+            handler,
+            "listunspent",
+            vec![Value::from(0), Value::from(9999999), json!([address])],
+        )?;
+        let balance = unspent.as_array().map_or(0, |outputs| {
+            outputs
+                .iter()
+                .filter_map(|output| output["amount"].as_i64())
+                .sum::<i64>()
+        });
+        let transactions = call(
+            handler,
+            "listtransactions",
+            vec![Value::from(address), Value::from(ADDRESS_TRANSACTIONS)],
+        )?;
+        Ok(json!({
+            "address": address,
+            "balance": balance,
+            "unspent": unspent,
+            "transactions": transactions,
+        }))
+    }
+}
+
+// Calls the JSON-RPC method, as if it was requested over HTTP.
+fn call(handler: &JsonRpcHandler, method: &str, params: Vec<Value>) -> Result<Value, JsonRpcError> {
+    let response = handler.handle(&JsonRpcRequest {
+        jsonrpc: None,
+        id: Value::Null,
+        method: method.to_string(),
+        params,
+    });
+    match response.error {
+        Some(error) => Err(error),
+        None => Ok(response.result.unwrap_or(Value::Null)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_requests() {
+        let handler = JsonRpcHandler::new("127.0.0.1:0".to_string(), Duration::from_secs(1));
+        let (status, content_type, body) = ExplorerServer::handle("/", &handler);
+        assert_eq!(
+            (status, content_type),
+            ("200 OK", "text/html; charset=utf-8")
+        );
+        assert!(body.contains("/api/blocks"));
+
+        assert_eq!(ExplorerServer::handle("/foo", &handler).0, "404 Not Found");
+        assert_eq!(
+            ExplorerServer::handle("/api/height/x", &handler).0,
+            "400 Bad Request"
+        );
+        assert_eq!(
+            ExplorerServer::handle("/api/blocks?count=x", &handler).0,
+            "400 Bad Request"
+        );
+        // The node isn't running.
+        let (status, content_type, body) = ExplorerServer::handle("/api/blocks", &handler);
+        assert_eq!(
+            (status, content_type),
+            ("502 Bad Gateway", "application/json")
+        );
+        assert!(serde_json::from_str::<Value>(&body).unwrap()["error"].is_string());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::core::storage::{self, FileStorage};
+use crate::core::transaction::TransactionId;
+use crate::core::{Block, Coolcoin, FeeRate};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+// The number of most recent blocks whose confirmations are used for fee estimation.
+const MAX_TRACKED_BLOCKS: u32 = 1008;
+// The fraction of transactions paying at least the estimated fee rate that must have been
+// confirmed within the target number of blocks.
+const SUCCESS_THRESHOLD: f64 = 0.85;
+// The minimum number of confirmed transactions paying at least the fee rate for the estimate
+// to be considered reliable.
+const MIN_SAMPLES: usize = 5;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+struct Confirmation {
+    fee_rate: FeeRate,
+    // Number of blocks it took for the transaction to be confirmed since it entered the pool.
+    blocks_to_confirm: u32,
+    // Height of the block in which the transaction has been confirmed.
+    height: u32,
+}
+
+struct UnconfirmedTransaction {
+    fee_rate: FeeRate,
+    // Height of the active blockchain when the transaction entered the pool.
+    entry_height: u32,
+}
+
+/// Estimates the fee rate that a transaction needs to pay in order to be confirmed within
+/// the target number of blocks.
+/// The estimator tracks the transactions from the moment they enter the transaction pool
+/// until they are confirmed, and keeps a rolling dataset of confirmations from the most
+/// recent blocks. The dataset is persisted, so that the estimates survive restarts.
+///
+/// Blocks that are disconnected from the active blockchain are not taken into account,
+/// which is fine given that the estimates are approximate anyway.
+#[derive(Default, Serialize, Deserialize)]
+pub struct FeeEstimator {
+    confirmations: VecDeque<Confirmation>,
+    // Transactions in the pool are not persisted, so neither are the unconfirmed transactions.
+    #[serde(skip)]
+    unconfirmed: HashMap<TransactionId, UnconfirmedTransaction>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl FeeEstimator {
+    /// Loads the dataset from the given file, or starts with an empty one if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut fee_estimator =
+            storage::load_json::<Self>(&FileStorage, path, "fee estimates")?.unwrap_or_default();
+        fee_estimator.path = path.to_path_buf();
+        Ok(fee_estimator)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        storage::save(&FileStorage, &self.path, contents.as_bytes())
+    }
+
+    /// Starts tracking the transaction that has entered the transaction pool
+    /// when the active blockchain was at the given height.
+    pub fn track_transaction(
+        &mut self,
+        transaction_id: TransactionId,
+        fee: Coolcoin,
+        size: usize,
+        height: u32,
+    ) {
+        self.unconfirmed.insert(
+            transaction_id,
+            UnconfirmedTransaction {
+                fee_rate: FeeRate::new(fee, size),
+                entry_height: height,
+            },
+        );
+    }
+
+    /// Records the confirmations of the tracked transactions in the block at the given height,
+    /// and forgets the data that is too old.
+    pub fn new_active_block(&mut self, block: &Block, height: u32) {
+        for transaction in block.transactions() {
+            if let Some(unconfirmed) = self.unconfirmed.remove(transaction.id()) {
+                self.confirmations.push_back(Confirmation {
+                    fee_rate: unconfirmed.fee_rate,
+                    blocks_to_confirm: height.saturating_sub(unconfirmed.entry_height),
+                    height,
+                });
+            }
+        }
+
+        let is_recent = |h: u32| h + MAX_TRACKED_BLOCKS > height;
+        while let Some(confirmation) = self.confirmations.front() {
+            if is_recent(confirmation.height) {
+                break;
+            }
+            self.confirmations.pop_front();
+        }
+        // Transactions that have been evicted from the pool are never confirmed.
+        self.unconfirmed
+            .retain(|_, unconfirmed| is_recent(unconfirmed.entry_height));
+    }
+
+    /// Returns the lowest fee rate such that most of the recent
+    /// transactions paying at least that much have been confirmed within the target number
+    /// of blocks. Returns None if there isn't enough data.
+    pub fn estimate_smart_fee(&self, target_blocks: u32) -> Option<FeeRate> {
+        let mut confirmations = self.confirmations.iter().collect::<Vec<&Confirmation>>();
+        confirmations.sort_by_key(|confirmation| std::cmp::Reverse(confirmation.fee_rate));
+
+        let mut estimate = None;
+        let mut total = 0;
+        let mut confirmed_within_target = 0;
+        for (i, confirmation) in confirmations.iter().enumerate() {
+            total += 1;
+            if confirmation.blocks_to_confirm <= target_blocks {
+                confirmed_within_target += 1;
+            }
+            // Evaluate once all confirmations with the same fee rate have been counted.
+            let is_last_with_fee_rate = confirmations
+                .get(i + 1)
+                .is_none_or(|next| next.fee_rate != confirmation.fee_rate);
+            if !is_last_with_fee_rate || total < MIN_SAMPLES {
+                continue;
+            }
+            if confirmed_within_target as f64 / total as f64 >= SUCCESS_THRESHOLD {
+                estimate = Some(confirmation.fee_rate);
+            } else {
+                break;
+            }
+        }
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimator_with(confirmations: &[(i64, u32)]) -> FeeEstimator {
+        let mut fee_estimator = FeeEstimator::default();
+        for (fee_rate, blocks_to_confirm) in confirmations {
+            fee_estimator.confirmations.push_back(Confirmation {
+                fee_rate: FeeRate::per_kilobyte((*fee_rate).into()),
+                blocks_to_confirm: *blocks_to_confirm,
+                height: 0,
+            });
+        }
+        fee_estimator
+    }
+
+    #[test]
+    fn estimates_lowest_fee_rate_confirmed_within_target() {
+        let mut confirmations = vec![];
+        for _ in 0..5 {
+            confirmations.push((100, 1));
+            confirmations.push((50, 2));
+            confirmations.push((10, 6));
+        }
+        let fee_estimator = estimator_with(&confirmations);
+
+        assert_eq!(
+            fee_estimator.estimate_smart_fee(1),
+            Some(FeeRate::per_kilobyte(100.into()))
+        );
+        assert_eq!(
+            fee_estimator.estimate_smart_fee(2),
+            Some(FeeRate::per_kilobyte(50.into()))
+        );
+        assert_eq!(
+            fee_estimator.estimate_smart_fee(6),
+            Some(FeeRate::per_kilobyte(10.into()))
+        );
+    }
+
+    #[test]
+    fn no_estimate_without_enough_data() {
+        let fee_estimator = estimator_with(&[(100, 1), (50, 1)]);
+        assert_eq!(fee_estimator.estimate_smart_fee(1), None);
+    }
+}
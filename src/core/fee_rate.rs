@@ -0,0 +1,111 @@
+use crate::core::Coolcoin;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+/// The fee paid per byte of the serialized transaction (or a package of transactions).
+/// Fee rates are compared exactly, i.e. without rounding the fee per byte.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct FeeRate {
+    fee: Coolcoin,
+    // Size in bytes, never zero.
+    size: usize,
+}
+
+impl FeeRate {
+    pub fn new(fee: Coolcoin, size: usize) -> Self {
+        Self {
+            fee,
+            size: size.max(1),
+        }
+    }
+
+    pub fn per_kilobyte(fee: Coolcoin) -> Self {
+        Self::new(fee, 1000)
+    }
+
+    pub fn zero() -> Self {
+        Self::per_kilobyte(Coolcoin::zero())
+    }
+
+    /// Returns the fee for 1000 bytes, rounded down.
+    /// Saturates at the bounds of the amount if the fee doesn't fit into it.
+    pub fn fee_per_kilobyte(&self) -> Coolcoin {
+        let fee = self.fee.value() as i128 * 1000;
+        Self::saturate(fee / self.size as i128)
+    }
+
+    /// Returns the fee for the given size at this rate, rounded up, so that paying
+    /// the returned fee never results in a lower fee rate.
+    /// Saturates at the bounds of the amount if the fee doesn't fit into it.
+    pub fn fee(&self, size: usize) -> Coolcoin {
+        let fee = self.fee.value() as i128 * size as i128;
+        let size = self.size as i128;
+        Self::saturate((fee + size - 1).div_euclid(size))
+    }
+
+    fn saturate(value: i128) -> Coolcoin {
+        Coolcoin::new(value.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    }
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // fee_a / size_a < fee_b / size_b, avoiding the division.
+        (self.fee.value() as i128 * other.size as i128)
+            .cmp(&(other.fee.value() as i128 * self.size as i128))
+    }
+}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+impl Display for FeeRate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/kB", self.fee_per_kilobyte())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_exactly() {
+        assert!(FeeRate::new(1.into(), 3) > FeeRate::new(333.into(), 1000));
+        assert_eq!(FeeRate::new(2.into(), 6), FeeRate::new(1.into(), 3));
+        assert_eq!(FeeRate::new(1.into(), 3).fee_per_kilobyte(), 333.into());
+    }
+
+    #[test]
+    fn fee_is_rounded_up() {
+        let fee_rate = FeeRate::per_kilobyte(1.into());
+        assert_eq!(fee_rate.fee(0), 0.into());
+        assert_eq!(fee_rate.fee(1), 1.into());
+        assert_eq!(fee_rate.fee(1000), 1.into());
+        assert_eq!(fee_rate.fee(1001), 2.into());
+    }
+
+    #[test]
+    fn saturates_instead_of_overflowing() {
+        let fee_rate = FeeRate::new(i64::MAX.into(), 1);
+        assert_eq!(fee_rate.fee_per_kilobyte(), i64::MAX.into());
+        assert_eq!(fee_rate.fee(2), i64::MAX.into());
+        assert_eq!(fee_rate.to_string(), format!("{} CLC/kB", i64::MAX));
+
+        let fee_rate = FeeRate::new(i64::MIN.into(), 1);
+        assert_eq!(fee_rate.fee_per_kilobyte(), i64::MIN.into());
+        assert_eq!(fee_rate.fee(2), i64::MIN.into());
+    }
+}
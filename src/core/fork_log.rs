@@ -0,0 +1,182 @@
+use crate::core::block::BlockHash;
+use crate::core::storage::{FileStorage, RecordLog};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The forks observed by the node, as recorded in the fork log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForkEvent {
+    // The active blockchain has switched to another branch.
+    Reorg {
+        // Time (seconds from Unix Epoch) of the reorg.
+        time: u32,
+        // Height of the last block that both branches have in common.
+        fork_height: u32,
+        // Number of the disconnected and of the connected blocks.
+        depth: u32,
+        connected: u32,
+        old_tip: BlockHash,
+        new_tip: BlockHash,
+        // Time it took to disconnect and connect the blocks.
+        duration_secs: f64,
+    },
+    // The valid block that isn't in the active blockchain once the node has received it.
+    StaleBlock {
+        time: u32,
+        height: u32,
+        hash: BlockHash,
+    },
+}
+
+impl ForkEvent {
+    // The height at which the event has happened, which is the height of the new tip for
+    // the reorg.
+    fn height(&self) -> u32 {
+        match self {
+            ForkEvent::Reorg {
+                fork_height,
+                connected,
+                ..
+            } => fork_height + connected,
+            ForkEvent::StaleBlock { height, .. } => *height,
+        }
+    }
+}
+
+/// How often the network forks within the recent blocks of the active blockchain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForkStats {
+    // The heights of the window, which ends at the tip.
+    window_start_height: u32,
+    window_block_count: u32,
+    reorgs: usize,
+    max_reorg_depth: u32,
+    average_reorg_depth: f64,
+    stale_blocks: usize,
+    // The stale blocks per block of the active blockchain in the window.
+    stale_rate: f64,
+    // The reorgs in the window, the oldest first.
+    recent_reorgs: Vec<ForkEvent>,
+}
+
+impl ForkStats {
+    pub fn reorgs(&self) -> usize {
+        self.reorgs
+    }
+
+    pub fn stale_blocks(&self) -> usize {
+        self.stale_blocks
+    }
+}
+
+/// Log of the reorgs and the stale blocks, which shows how often the network forks, e.g. under
+/// different difficulty settings.
+/// The log is stored as a file with one JSON record per line, which is kept across restarts.
+pub struct ForkLog {
+    log: RecordLog<ForkEvent>,
+}
+
+impl ForkLog {
+    /// Loads the log from the given file, or starts with an empty one if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Ok(Self {
+            log: RecordLog::load(Arc::new(FileStorage), path, "fork events")?,
+        })
+    }
+
+    pub fn record(&mut self, event: ForkEvent) -> Result<(), String> {
+        self.log.record(event)
+    }
+
+    /// Returns the statistics of the events at the given number of the most recent heights
+    /// of the active blockchain, or at all heights.
+    pub fn stats(&self, tip_height: u32, num_blocks: Option<u32>) -> ForkStats {
+        let window_block_count = num_blocks.unwrap_or(tip_height).clamp(1, tip_height.max(1));
+        let window_start_height = (tip_height + 1).saturating_sub(window_block_count);
+        let events = self
+            .log
+            .records()
+            .iter()
+            .filter(|event| (window_start_height..=tip_height).contains(&event.height()));
+        let recent_reorgs = events
+            .clone()
+            .filter(|event| matches!(event, ForkEvent::Reorg { .. }))
+            .cloned()
+            .collect::<Vec<ForkEvent>>();
+        let depths = recent_reorgs
+            .iter()
+            .map(|event| match event {
+                ForkEvent::Reorg { depth, .. } => *depth,
+                ForkEvent::StaleBlock { .. } => 0,
+            })
+            .collect::<Vec<u32>>();
+        let stale_blocks = events
+            .filter(|event| matches!(event, ForkEvent::StaleBlock { .. }))
+            .count();
+        ForkStats {
+            window_start_height,
+            window_block_count,
+            reorgs: recent_reorgs.len(),
+            max_reorg_depth: depths.iter().copied().max().unwrap_or(0),
+            average_reorg_depth: match depths.len() {
+                0 => 0.0,
+                n => depths.iter().sum::<u32>() as f64 / n as f64,
+            },
+            stale_blocks,
+            stale_rate: stale_blocks as f64 / window_block_count as f64,
+            recent_reorgs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Sha256;
+    use std::fs;
+
+    #[test]
+    fn computes_stats_within_window() {
+        let dir = std::env::temp_dir().join(format!("fork_log_{}", std::process::id()));
+        let path = dir.join("forks.jsonl");
+        let _ = fs::remove_dir_all(&dir);
+        let hash = |byte: u8| BlockHash::new(Sha256::new([byte; 32]));
+        let reorg = |fork_height: u32, depth: u32| ForkEvent::Reorg {
+            time: 100,
+            fork_height,
+            depth,
+            connected: depth + 1,
+            old_tip: hash(1),
+            new_tip: hash(2),
+            duration_secs: 0.01,
+        };
+        let stale_block = |height: u32| ForkEvent::StaleBlock {
+            time: 100,
+            height,
+            hash: hash(3),
+        };
+
+        let mut log = ForkLog::load(&path).unwrap();
+        log.record(reorg(2, 1)).unwrap();
+        log.record(stale_block(5)).unwrap();
+        log.record(reorg(6, 3)).unwrap();
+        log.record(stale_block(9)).unwrap();
+
+        let log = ForkLog::load(&path).unwrap();
+        let all = log.stats(10, None);
+        assert_eq!((all.window_start_height, all.window_block_count), (1, 10));
+        assert_eq!((all.reorgs(), all.stale_blocks()), (2, 2));
+        assert_eq!(all.max_reorg_depth, 3);
+        assert_eq!(all.average_reorg_depth, 2.0);
+        assert_eq!(all.stale_rate, 0.2);
+
+        // The first reorg's new tip is at height 4, below the window.
+        let recent = log.stats(10, Some(6));
+        assert_eq!(recent.window_start_height, 5);
+        assert_eq!((recent.reorgs(), recent.stale_blocks()), (1, 2));
+        assert_eq!(recent.max_reorg_depth, 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+use crate::core::block::BlockHash;
+use crate::core::hash_display::HashDisplay;
+use crate::core::{Block, BlockchainManager, Sha256};
+use layout::backends::svg::SVGWriter;
+use layout::gv::{DotParser, GraphBuilder};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{ErrorKind, Write as _};
+use std::ops::RangeInclusive;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Fill colors of the stale branches, which are reused if there are more branches.
+const STALE_BRANCH_COLORS: [&str; 5] = ["salmon", "lightblue", "palegreen", "khaki", "plum"];
+
+/// Returns the blocks known to the node as a Graphviz digraph, to be rendered with `dot`.
+/// The active blockchain and the orphaned blocks are clustered separately, and each stale
+/// branch, which forks off the active blockchain, has its own color.
+/// Only the blocks at the given heights are included, if given, so that long chains remain
+/// legible. The orphaned blocks have no known height, so they are always included.
+pub fn blockchain(blockchain: &BlockchainManager, heights: Option<RangeInclusive<u32>>) -> String {
+    let block_tree = blockchain.block_tree();
+    let in_range = |hash: &BlockHash| match (&heights, block_tree.height(hash)) {
+        (Some(heights), Some(height)) => heights.contains(&height),
+        (None, Some(_)) => true,
+        (_, None) => false,
+    };
+    let active_blockchain = block_tree
+        .active_blockchain()
+        .into_iter()
+        .filter(|block| in_range(block.id()))
+        .collect::<Vec<Arc<Block>>>();
+    let mut stale_blocks = block_tree
+        .all()
+        .into_iter()
+        .filter(|block| in_range(block.id()) && !is_active(blockchain, block.id()))
+        .collect::<Vec<Arc<Block>>>();
+    stale_blocks.sort_by_key(|block| (block_tree.height(block.id()), *block.id()));
+    let orphaned_blocks = blockchain.orphaned_blocks();
+    // The hashes are told apart from all blocks known to the node, and the missing parents.
+    let mut missing_parents = orphaned_blocks
+        .iter()
+        .map(|block| block.header().previous_block_hash())
+        .filter(|parent| {
+            !block_tree.exists(parent)
+                && orphaned_blocks.iter().all(|orphan| orphan.id() != *parent)
+        })
+        .collect::<Vec<&BlockHash>>();
+    missing_parents.sort();
+    missing_parents.dedup();
+    let known = blockchain.all_blocks();
+    let hashes = HashDisplay::global().format_all(
+        known
+            .iter()
+            .map(|block| block.id())
+            .chain(missing_parents.iter().copied())
+            .map(|hash| hash.raw()),
+    );
+
+    let mut output = String::new();
+    writeln!(output, "digraph G {{").unwrap();
+    writeln!(output, "    rankdir=LR;").unwrap();
+    writeln!(output, "    node [shape=box,style=filled,fillcolor=white];").unwrap();
+
+    writeln!(output, "    subgraph cluster_active {{").unwrap();
+    writeln!(output, "        style=filled;").unwrap();
+    writeln!(output, "        color=lightgrey;").unwrap();
+    writeln!(output, "        label = \"Active\";").unwrap();
+    for block in &active_blockchain {
+        write_node(&mut output, blockchain, &hashes, block, "white");
+    }
+    writeln!(output, "    }}").unwrap();
+
+    writeln!(output, "    subgraph cluster_orphans {{").unwrap();
+    writeln!(output, "        style=dashed;").unwrap();
+    writeln!(output, "        label = \"Orphans\";").unwrap();
+    for block in &orphaned_blocks {
+        write_node(&mut output, blockchain, &hashes, block, "white");
+    }
+    writeln!(output, "    }}").unwrap();
+
+    // The first block of each stale branch gets the next color, and the blocks on top of it
+    // inherit it.
+    let mut colors = HashMap::new();
+    let mut branches = 0;
+    for block in &stale_blocks {
+        let parent = block.header().previous_block_hash();
+        let color = match colors.get(parent) {
+            Some(color) => *color,
+            None => {
+                branches += 1;
+                STALE_BRANCH_COLORS[(branches - 1) % STALE_BRANCH_COLORS.len()]
+            }
+        };
+        colors.insert(*block.id(), color);
+        write_node(&mut output, blockchain, &hashes, block, color);
+    }
+
+    // The parents of the orphaned blocks are missing, so they are drawn as dashed outlines.
+    for parent in &missing_parents {
+        writeln!(
+            output,
+            "    \"{}\" [label=\"{}\\nmissing\",style=dashed];",
+            parent,
+            hashes[parent.raw()]
+        )
+        .unwrap();
+    }
+
+    for block in active_blockchain
+        .iter()
+        .chain(stale_blocks.iter())
+        .chain(orphaned_blocks.iter())
+    {
+        let parent = block.header().previous_block_hash();
+        // The parents below the range are left out.
+        if block_tree.exists(parent) && !in_range(parent) {
+            continue;
+        }
+        if block_tree.height(block.id()) == Some(0) {
+            continue;
+        }
+        writeln!(output, "    \"{}\" -> \"{}\";", parent, block.id()).unwrap();
+    }
+    writeln!(output, "}}").unwrap();
+    output
+}
+
+/// The image formats to which the graph can be rendered.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageFormat {
+    Svg,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Svg => "svg",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "svg" => Ok(ImageFormat::Svg),
+            "png" => Ok(ImageFormat::Png),
+            _ => Err(format!("Unknown image format: {}, expected svg or png.", s)),
+        }
+    }
+}
+
+/// Renders the graph as an image with Graphviz's `dot`, if it's installed. Otherwise,
+/// the SVG image is laid out in Rust, without the clusters, and the PNG image can't be
+/// rendered.
+pub fn render(graph: &str, format: ImageFormat) -> Result<Vec<u8>, String> {
+    match render_with_dot(graph, format) {
+        Err(e) if e.kind() == ErrorKind::NotFound => match format {
+            ImageFormat::Svg => render_svg(graph),
+            ImageFormat::Png => Err(
+                "Rendering PNG requires Graphviz's dot, which wasn't found in PATH. Install Graphviz, or render SVG instead."
+                    .to_string(),
+            ),
+        },
+        Err(e) => Err(format!("Failed to run dot: {}", e)),
+        Ok(image) => image,
+    }
+}
+
+fn render_with_dot(graph: &str, format: ImageFormat) -> std::io::Result<Result<Vec<u8>, String>> {
+    let mut dot = Command::new("dot")
+        .arg(format!("-T{}", format.extension()))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Dot reads the whole graph before it writes the image, so the pipes can't fill up.
+    dot.stdin.take().unwrap().write_all(graph.as_bytes())?;
+    let output = dot.wait_with_output()?;
+    Ok(match output.status.success() {
+        true => Ok(output.stdout),
+        false => Err(format!(
+            "dot failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    })
+}
+
+fn render_svg(graph: &str) -> Result<Vec<u8>, String> {
+    let graph = DotParser::new(graph)
+        .process()
+        .map_err(|e| format!("Failed to parse the graph: {}", e))?;
+    let mut builder = GraphBuilder::new();
+    builder.visit_graph(&graph);
+    let mut svg = SVGWriter::new();
+    builder.get().do_it(false, false, false, &mut svg);
+    Ok(svg.finalize().into_bytes())
+}
+
+fn is_active(blockchain: &BlockchainManager, hash: &BlockHash) -> bool {
+    let block_tree = blockchain.block_tree();
+    match block_tree.height(hash) {
+        Some(height) => block_tree.active_block_hash(height).as_ref() == Some(hash),
+        None => false,
+    }
+}
+
+fn write_node(
+    output: &mut String,
+    blockchain: &BlockchainManager,
+    hashes: &HashMap<Sha256, String>,
+    block: &Block,
+    color: &str,
+) {
+    let height = blockchain.block_tree().height(block.id());
+    let height_label = match height {
+        Some(height) => height.to_string(),
+        None => "?".to_string(),
+    };
+    let mut label = format!(
+        "{}\\nheight {}\\ntime {}\\n{} txs",
+        hashes[block.id().raw()],
+        height_label,
+        block.header().timestamp(),
+        block.transactions().len()
+    );
+    // The genesis block's coinbase data isn't prefixed with the height.
+    if let Some(tag) = miner_tag(block).filter(|_| height != Some(0)) {
+        write!(label, "\\n{}", tag).unwrap();
+    }
+    writeln!(
+        output,
+        "        \"{}\" [label=\"{}\",fillcolor={}];",
+        block.id(),
+        label,
+        color
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+fn short_hash(hash: &BlockHash) -> String {
+    HashDisplay::global().format(hash.raw())
+}
+
+// The printable part of the miner's tag in the coinbase data, which follows the height.
+fn miner_tag(block: &Block) -> Option<String> {
+    let coinbase = block.transactions().first()?;
+    let coinbase_data = coinbase.inputs().first()?.coinbase_data();
+    let tag = coinbase_data
+        .get(4..)?
+        .iter()
+        .take_while(|byte| byte.is_ascii_graphic() || **byte == b' ')
+        .map(|byte| *byte as char)
+        .filter(|c| *c != '"' && *c != '\\')
+        .collect::<String>();
+    match tag.trim().is_empty() {
+        true => None,
+        false => Some(tag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::chain_builder::ChainBuilder;
+    use crate::core::chain_params::ChainParams;
+
+    #[test]
+    fn renders_active_stale_and_orphaned_blocks() {
+        let mut chain = ChainBuilder::new(ChainParams::regtest());
+        chain.mine_blocks(4);
+        let mut stale = chain.fork_at(1);
+        stale.mine_blocks(2);
+        let mut orphans = chain.fork_at(2);
+        orphans.mine_blocks(2);
+
+        let mut blockchain = BlockchainManager::with_genesis_block(chain.block(0).clone());
+        for block in chain.blocks().iter().chain(&stale.blocks()[1..]) {
+            blockchain.new_block(Arc::new(block.clone()));
+        }
+        // The parent of the orphaned block hasn't been received.
+        blockchain.new_block(Arc::new(orphans.block(4).clone()));
+
+        let node =
+            |block: &Block| format!("\"{}\" [label=\"{}", block.id(), short_hash(block.id()));
+        let graph = super::blockchain(&blockchain, None);
+        let orphans_cluster = graph.find("subgraph cluster_orphans").unwrap();
+        let active = graph.find(&node(chain.tip())).unwrap();
+        assert!(active < orphans_cluster);
+        assert!(graph.find(&node(orphans.tip())).unwrap() > orphans_cluster);
+        assert!(graph.contains(&format!(
+            "\\nheight 3\\ntime {}\\n1 txs\\nbranch 1\",fillcolor=salmon];",
+            stale.tip().header().timestamp()
+        )));
+        assert!(graph.contains(&format!(
+            "\"{}\" [label=\"{}\\nmissing\",style=dashed];",
+            orphans.block(3).id(),
+            short_hash(orphans.block(3).id())
+        )));
+        assert!(graph.contains(&format!(
+            "\"{}\" -> \"{}\";",
+            chain.block(1).id(),
+            stale.block(2).id()
+        )));
+
+        // Only the blocks from height 3 are included, without the edges to their parents.
+        let graph = super::blockchain(&blockchain, Some(3..=4));
+        assert!(!graph.contains(&node(chain.block(2))));
+        assert!(graph.contains(&node(chain.block(3))));
+        assert!(graph.contains(&node(stale.tip())));
+        assert!(!graph.contains(&format!("\"{}\" ->", chain.block(2).id())));
+        assert!(graph.contains(&node(orphans.tip())));
+    }
+
+    #[test]
+    fn renders_svg_without_graphviz() {
+        let mut chain = ChainBuilder::new(ChainParams::regtest());
+        chain.mine_blocks(2);
+        let mut blockchain = BlockchainManager::with_genesis_block(chain.block(0).clone());
+        for block in chain.blocks() {
+            blockchain.new_block(Arc::new(block.clone()));
+        }
+        let svg =
+            String::from_utf8(render_svg(&super::blockchain(&blockchain, None)).unwrap()).unwrap();
+        assert!(svg.contains("<svg"), "{}", svg);
+        assert!(svg.contains(&short_hash(chain.tip().id())));
+    }
+}
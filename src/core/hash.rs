@@ -1,7 +1,7 @@
 use crate::core::block::BlockHash;
+use crate::core::serialize::{Decodable, Encodable, Reader};
 use crate::core::Transaction;
-use hex::FromHexError;
-use serde::de::{EnumAccess, Error, MapAccess, SeqAccess, Visitor};
+use serde::de::{Error, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::Digest;
 use std::fmt::{Display, Formatter};
@@ -53,6 +53,18 @@ impl<'de> Deserialize<'de> for Sha256 {
     }
 }
 
+impl Encodable for Sha256 {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.0.encode(output);
+    }
+}
+
+impl Decodable for Sha256 {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self(Decodable::decode(input)?))
+    }
+}
+
 impl Display for Sha256 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", as_hex(&self.bytes()[..]))
@@ -76,6 +88,18 @@ impl MerkleHash {
     }
 }
 
+impl Encodable for MerkleHash {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.0.encode(output);
+    }
+}
+
+impl Decodable for MerkleHash {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self(Sha256::decode(input)?))
+    }
+}
+
 impl Display for MerkleHash {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", hex::encode(self.0.bytes()))
@@ -86,13 +110,11 @@ pub fn as_hex(bytes: &[u8]) -> String {
     hex::encode(bytes)
 }
 pub fn from_hex(s: &str) -> Result<Sha256, String> {
-    match hex::decode(&s) {
+    match hex::decode(s) {
         Ok(bytes) => {
             let mut sha = [0; 32];
             if bytes.len() == 32 {
-                for i in 0..32 {
-                    sha[i] = *bytes.get(i).unwrap();
-                }
+                sha.copy_from_slice(&bytes);
                 Ok(Sha256::new(sha))
             } else {
                 Err(format!(
@@ -126,10 +148,14 @@ pub fn hash(data: &[u8]) -> Sha256 {
 /// I.e. this means that difficulty represents how many leading zeroes the block hash must have.
 pub fn target_hash(n_zero_bits: u32) -> BlockHash {
     let mut hash = [0xff; 32];
+    // The difficulty of a received header may be anything, and no hash is less than zero.
+    if n_zero_bits >= 256 {
+        return BlockHash::new(Sha256::new([0; 32]));
+    }
 
     let num_zero_bytes = (n_zero_bits / 8) as usize;
-    for i in 0..num_zero_bytes {
-        hash[i] = 0;
+    for byte in hash.iter_mut().take(num_zero_bytes) {
+        *byte = 0;
     }
 
     let remainder = 8 - (n_zero_bits % 8);
@@ -141,7 +167,7 @@ pub fn target_hash(n_zero_bits: u32) -> BlockHash {
     BlockHash::new(Sha256::new(hash))
 }
 
-pub fn merkle_tree_from_transactions(transactions: &Vec<Transaction>) -> MerkleHash {
+pub fn merkle_tree_from_transactions(transactions: &[Transaction]) -> MerkleHash {
     let leaves = transactions
         .iter()
         .map(|tx| &tx.id().raw().bytes()[..])
@@ -149,37 +175,79 @@ pub fn merkle_tree_from_transactions(transactions: &Vec<Transaction>) -> MerkleH
     merkle_tree(&leaves)
 }
 
-pub fn merkle_tree(leaves: &Vec<&[u8]>) -> MerkleHash {
+pub fn merkle_tree(leaves: &[&[u8]]) -> MerkleHash {
     assert!(!leaves.is_empty());
     let mut hashes = leaves
         .iter()
-        .map(|leaf| hash(*leaf))
+        .map(|leaf| hash(leaf))
         .collect::<Vec<Sha256>>();
 
     while hashes.len() != 1 {
-        if hashes.len() % 2 == 1 {
-            hashes.push(hashes.last().unwrap().clone());
-        }
+        hashes = next_level(hashes);
+    }
+    MerkleHash::new(hashes.into_iter().next().unwrap())
+}
 
-        let mut next_level_hashes = vec![];
+fn next_level(mut hashes: Vec<Sha256>) -> Vec<Sha256> {
+    if hashes.len() % 2 == 1 {
+        hashes.push(*hashes.last().unwrap());
+    }
 
-        for i in (0..hashes.len()).step_by(2) {
-            let lhs = hashes.get(i).unwrap();
-            let rhs = hashes.get(i + 1).unwrap();
-            let mut concat = lhs.bytes().iter().map(|x| *x).collect::<Vec<u8>>();
-            concat.extend_from_slice(rhs.bytes());
-            next_level_hashes.push(hash(&concat))
+    let mut next_level_hashes = vec![];
+
+    for i in (0..hashes.len()).step_by(2) {
+        let lhs = hashes.get(i).unwrap();
+        let rhs = hashes.get(i + 1).unwrap();
+        next_level_hashes.push(hash_pair(lhs, rhs))
+    }
+    next_level_hashes
+}
+
+fn hash_pair(lhs: &Sha256, rhs: &Sha256) -> Sha256 {
+    let mut concat = lhs.bytes().to_vec();
+    concat.extend_from_slice(rhs.bytes());
+    hash(&concat)
+}
+
+/// Computes the merkle root of the block whose coinbase transaction changes while the other
+/// transactions stay the same, e.g. for each extranonce or timestamp of the block template.
+/// The hashes that don't depend on the coinbase transaction are computed once, so the root
+/// for the new coinbase transaction only rehashes the left spine of the tree, one hash per level.
+#[derive(Debug, Clone)]
+pub struct MerkleBuilder {
+    // The right sibling of the left spine at each level of the tree, from the leaves up.
+    branch: Vec<Sha256>,
+}
+
+impl MerkleBuilder {
+    /// The transactions exclude the coinbase transaction.
+    pub fn new(transactions: &[Transaction]) -> Self {
+        // The first leaf is a placeholder for the coinbase transaction. Only the left spine
+        // depends on it, and the spine is not part of the branch.
+        let mut hashes = vec![Sha256::new([0; 32])];
+        hashes.extend(transactions.iter().map(|tx| hash(tx.id().raw().bytes())));
+        let mut branch = vec![];
+        while hashes.len() != 1 {
+            branch.push(hashes[1]);
+            hashes = next_level(hashes);
         }
+        Self { branch }
+    }
 
-        hashes = next_level_hashes
+    pub fn merkle_root(&self, coinbase_transaction: &Transaction) -> MerkleHash {
+        let root = self.branch.iter().fold(
+            hash(coinbase_transaction.id().raw().bytes()),
+            |spine, sibling| hash_pair(&spine, sibling),
+        );
+        MerkleHash::new(root)
     }
-    MerkleHash::new(hashes.into_iter().next().unwrap())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::{as_hex, merkle_tree};
+    use crate::core::transaction::{TransactionInput, TransactionOutput};
+    use crate::core::{as_hex, merkle_tree, Address, Coolcoin};
 
     #[test]
     fn hash_test() {
@@ -190,9 +258,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn merkle_builder_matches_merkle_tree() {
+        let transaction = |seed: u32| {
+            Transaction::new(
+                vec![TransactionInput::new_coinbase_with_data(
+                    seed.to_le_bytes().to_vec(),
+                )],
+                vec![TransactionOutput::new(
+                    Address::new("satoshi".to_string()),
+                    Coolcoin::new(50),
+                )],
+                0,
+            )
+            .unwrap()
+        };
+        for num_transactions in 0..12 {
+            let transactions = (1..=num_transactions).map(transaction).collect::<Vec<_>>();
+            let builder = MerkleBuilder::new(&transactions);
+            for coinbase_seed in [100, 200] {
+                let mut block_transactions = vec![transaction(coinbase_seed)];
+                block_transactions.extend(transactions.iter().cloned());
+                assert_eq!(
+                    builder.merkle_root(&block_transactions[0]).raw(),
+                    merkle_tree_from_transactions(&block_transactions).raw()
+                );
+            }
+        }
+    }
+
     #[test]
     fn merkle_tree_even() {
-        let merkle_root = merkle_tree(&vec![b"hello", b"world", b"this is", b"coolcoin"]);
+        let leaves: Vec<&[u8]> = vec![b"hello", b"world", b"this is", b"coolcoin"];
+        let merkle_root = merkle_tree(&leaves);
         assert_eq!(
             as_hex(merkle_root.as_slice()),
             "9a78c5b0f711a613e62660182f4357c7befd179d27c57cf8abb6e31a23d1cd7b"
@@ -201,7 +299,8 @@ mod tests {
 
     #[test]
     fn merkle_tree_odd() {
-        let merkle_root = merkle_tree(&vec![b"hello", b"world", b"this is"]);
+        let leaves: Vec<&[u8]> = vec![b"hello", b"world", b"this is"];
+        let merkle_root = merkle_tree(&leaves);
         assert_eq!(
             as_hex(merkle_root.as_slice()),
             "be1257a768ca532e01caed9b6cdc420a52f3de14dd5adcb353066cf581334c35"
@@ -210,7 +309,8 @@ mod tests {
 
     #[test]
     fn merkle_tree_even_same_as_previous_odd() {
-        let merkle_root = merkle_tree(&vec![b"hello", b"world", b"this is", b"this is"]);
+        let leaves: Vec<&[u8]> = vec![b"hello", b"world", b"this is", b"this is"];
+        let merkle_root = merkle_tree(&leaves);
         assert_eq!(
             as_hex(merkle_root.as_slice()),
             "be1257a768ca532e01caed9b6cdc420a52f3de14dd5adcb353066cf581334c35"
@@ -243,5 +343,8 @@ mod tests {
             as_hex(target_hash(20).as_slice()),
             "00000fffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"
         );
+        for n_zero_bits in &[256, u32::MAX] {
+            assert_eq!(target_hash(*n_zero_bits).as_slice(), &[0; 32]);
+        }
     }
 }
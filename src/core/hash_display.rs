@@ -0,0 +1,133 @@
+use crate::core::{as_hex, Sha256};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The number of the hex characters of the hash's suffix, or zero for the full hash.
+static HASH_DISPLAY: AtomicUsize = AtomicUsize::new(8);
+
+/// How the hashes of the blocks and the transactions are shown to the user: in the logs,
+/// the client's output, the blockchain graph and the top view.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HashDisplay {
+    Full,
+    // The last characters of the hex-encoded hash, which are enough to tell the blocks apart,
+    // but not to look them up.
+    Suffix(usize),
+}
+
+impl HashDisplay {
+    /// Returns the display used by the whole process, which is set once from the command line.
+    pub fn global() -> Self {
+        match HASH_DISPLAY.load(Ordering::Relaxed) {
+            0 => HashDisplay::Full,
+            len => HashDisplay::Suffix(len),
+        }
+    }
+
+    pub fn set_global(display: HashDisplay) {
+        let len = match display {
+            HashDisplay::Full => 0,
+            HashDisplay::Suffix(len) => len,
+        };
+        HASH_DISPLAY.store(len, Ordering::Relaxed);
+    }
+
+    pub fn format(&self, hash: &Sha256) -> String {
+        let hex = as_hex(hash.bytes());
+        match self {
+            HashDisplay::Full => hex,
+            HashDisplay::Suffix(len) => format!("..{}", &hex[hex.len() - len..]),
+        }
+    }
+
+    /// Formats the hashes, e.g. of all blocks in the graph, with the suffixes that are long
+    /// enough to tell each hash apart from the others, so that no two of them look the same.
+    pub fn format_all<'a>(
+        &self,
+        hashes: impl IntoIterator<Item = &'a Sha256>,
+    ) -> HashMap<Sha256, String> {
+        let len = match self {
+            HashDisplay::Full => {
+                return hashes
+                    .into_iter()
+                    .map(|hash| (*hash, self.format(hash)))
+                    .collect()
+            }
+            HashDisplay::Suffix(len) => *len,
+        };
+        // Sorted by the reversed hex, the hash shares the longest suffix with its neighbours.
+        let mut hexes = hashes
+            .into_iter()
+            .map(|hash| {
+                (
+                    as_hex(hash.bytes()).chars().rev().collect::<String>(),
+                    *hash,
+                )
+            })
+            .collect::<Vec<(String, Sha256)>>();
+        hexes.sort();
+        hexes.dedup();
+        let shared =
+            |a: &str, b: &str| a.chars().zip(b.chars()).take_while(|(a, b)| a == b).count();
+        let mut formatted = HashMap::new();
+        for (i, (reversed, hash)) in hexes.iter().enumerate() {
+            let previous = i
+                .checked_sub(1)
+                .map_or(0, |j| shared(reversed, &hexes[j].0));
+            let next = hexes
+                .get(i + 1)
+                .map_or(0, |(other, _)| shared(reversed, other));
+            let display = match (previous.max(next) + 1).max(len) {
+                len if len >= reversed.len() => HashDisplay::Full,
+                len => HashDisplay::Suffix(len),
+            };
+            formatted.insert(*hash, display.format(hash));
+        }
+        formatted
+    }
+}
+
+impl FromStr for HashDisplay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(HashDisplay::Full),
+            len => match len.parse::<usize>() {
+                Ok(len) if (1..64).contains(&len) => Ok(HashDisplay::Suffix(len)),
+                Ok(64) => Ok(HashDisplay::Full),
+                _ => Err(format!(
+                    "Invalid hash display: {}, expected full or the number of characters from 1 to 64.",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extends_suffix_on_collisions() {
+        let hash = |hex: &str| crate::core::hash::from_hex(hex).unwrap();
+        let a = hash("00000000000000000000000000000000000000000000000000000000000012ab");
+        let b = hash("00000000000000000000000000000000000000000000000000000000000022ab");
+        let c = hash("0000000000000000000000000000000000000000000000000000000000000000");
+
+        assert_eq!(HashDisplay::Suffix(3).format(&a), "..2ab");
+        let formatted = HashDisplay::Suffix(3).format_all([&a, &b, &c]);
+        assert_eq!(formatted[&a], "..12ab");
+        assert_eq!(formatted[&b], "..22ab");
+        assert_eq!(formatted[&c], "..000");
+        assert_eq!(HashDisplay::Suffix(3).format_all([&a, &c])[&a], "..2ab");
+        assert_eq!(HashDisplay::Full.format_all([&a])[&a], as_hex(a.bytes()));
+
+        assert_eq!("full".parse(), Ok(HashDisplay::Full));
+        assert_eq!("12".parse(), Ok(HashDisplay::Suffix(12)));
+        assert!("0".parse::<HashDisplay>().is_err());
+        assert!("65".parse::<HashDisplay>().is_err());
+    }
+}
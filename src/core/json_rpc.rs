@@ -0,0 +1,705 @@
+use crate::core::ban_list::{SetBanCommand, Subnet, DEFAULT_BAN_TIME};
+use crate::core::block::BlockHash;
+use crate::core::hash::from_hex;
+use crate::core::peer_connection::PeerMessage;
+use crate::core::serialize;
+use crate::core::transaction::{OutputIndex, TransactionId, TransactionInput, TransactionOutput};
+use crate::core::{Address, Block, Coolcoin, CoolcoinClient, Transaction};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Error codes defined by the JSON-RPC 2.0 specification.
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const INTERNAL_ERROR: i32 = -32603;
+// Error codes of the node, in the range reserved for the implementation-defined errors.
+pub const TRANSACTION_REJECTED: i32 = -32001;
+pub const INSUFFICIENT_DATA: i32 = -32002;
+pub const BLOCK_NOT_FOUND: i32 = -32003;
+pub const NODE_UNAVAILABLE: i32 = -32004;
+pub const TRANSACTION_NOT_FOUND: i32 = -32005;
+pub const FORBIDDEN: i32 = -32006;
+
+/// The names of all methods, e.g. for the completion in the client's shell.
+pub const METHODS: [&str; 33] = [
+    "createrawtransaction",
+    "decodeblock",
+    "decoderawtransaction",
+    "dumpstate",
+    "estimatesmartfee",
+    "getbestblockhash",
+    "getblock",
+    "getblockcount",
+    "getblockhash",
+    "getblockheader",
+    "getchaintips",
+    "getdifficulty",
+    "getforkstats",
+    "getfullblockchain",
+    "getinfo",
+    "getmempoolinfo",
+    "getmininginfo",
+    "getnettotals",
+    "getpeerhistory",
+    "getpeerinfo",
+    "gettransaction",
+    "getutxos",
+    "listbanned",
+    "listminedblocks",
+    "listtransactions",
+    "listunspent",
+    "logging",
+    "prioritisetransaction",
+    "sendrawtransaction",
+    "sendtransaction",
+    "setban",
+    "stop",
+    "uptime",
+];
+
+/// The kind of the JSON-RPC error, so the clients can branch on it instead of on the codes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum JsonRpcErrorKind {
+    ParseError,
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    InternalError,
+    TransactionRejected,
+    InsufficientData,
+    BlockNotFound,
+    // The node couldn't be reached, or it hasn't responded in time.
+    NodeUnavailable,
+    TransactionNotFound,
+    // The method isn't allowed for the caller, e.g. stop for the remote callers.
+    Forbidden,
+    // The error code that is not known to this version.
+    Other(i32),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcRequest {
+    #[serde(default)]
+    pub jsonrpc: Option<String>,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Vec<Value>,
+}
+
+impl JsonRpcRequest {
+    /// Creates the request from the command line arguments, which are parsed as JSON,
+    /// or taken as strings if they aren't valid JSON.
+    pub fn from_args(id: Value, method: &str, args: &[&str]) -> Self {
+        Self {
+            jsonrpc: Some("2.0".to_string()),
+            id,
+            method: method.to_string(),
+            params: args
+                .iter()
+                .map(|arg| serde_json::from_str(arg).unwrap_or_else(|_| Value::from(*arg)))
+                .collect(),
+        }
+    }
+}
+
+/// The reference to the unspent output, as given to createrawtransaction.
+#[derive(Debug, Deserialize)]
+struct RawTransactionInput {
+    txid: String,
+    vout: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcError {
+    pub code: i32,
+    pub message: String,
+    // Additional information about the error, e.g. the reason why the transaction is rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl JsonRpcError {
+    pub fn new(code: i32, message: String) -> Self {
+        Self {
+            code,
+            message,
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn kind(&self) -> JsonRpcErrorKind {
+        match self.code {
+            PARSE_ERROR => JsonRpcErrorKind::ParseError,
+            INVALID_REQUEST => JsonRpcErrorKind::InvalidRequest,
+            METHOD_NOT_FOUND => JsonRpcErrorKind::MethodNotFound,
+            INVALID_PARAMS => JsonRpcErrorKind::InvalidParams,
+            INTERNAL_ERROR => JsonRpcErrorKind::InternalError,
+            TRANSACTION_REJECTED => JsonRpcErrorKind::TransactionRejected,
+            INSUFFICIENT_DATA => JsonRpcErrorKind::InsufficientData,
+            BLOCK_NOT_FOUND => JsonRpcErrorKind::BlockNotFound,
+            NODE_UNAVAILABLE => JsonRpcErrorKind::NodeUnavailable,
+            TRANSACTION_NOT_FOUND => JsonRpcErrorKind::TransactionNotFound,
+            FORBIDDEN => JsonRpcErrorKind::Forbidden,
+            code => JsonRpcErrorKind::Other(code),
+        }
+    }
+}
+
+impl Display for JsonRpcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.data {
+            Some(data) => write!(f, "{} ({}): {}", self.message, self.code, data),
+            None => write!(f, "{} ({})", self.message, self.code),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+}
+
+impl JsonRpcResponse {
+    pub fn new(id: Value, result: Result<Value, JsonRpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// Serves the JSON-RPC requests by forwarding them to the node as peer messages, so that
+/// the requests are handled the same way as the ones sent by the client command.
+pub struct JsonRpcHandler {
+    // The connection to the node, shared by all requests, which are sent one at a time.
+    client: Mutex<CoolcoinClient>,
+}
+
+impl JsonRpcHandler {
+    pub fn new(node_address: String, timeout: Duration) -> Self {
+        Self {
+            client: Mutex::new(CoolcoinClient::new(node_address, timeout, false)),
+        }
+    }
+
+    pub fn handle(&self, request: &JsonRpcRequest) -> JsonRpcResponse {
+        let result = match request.method.as_str() {
+            // Doesn't need anything from the node.
+            "createrawtransaction" => Self::create_raw_transaction(&request.params),
+            "decoderawtransaction" => param::<String>(&request.params, 0)
+                .and_then(|hex| {
+                    Transaction::from_hex(&hex).map_err(|e| JsonRpcError::new(INVALID_PARAMS, e))
+                })
+                .and_then(|transaction| to_json(&transaction)),
+            "decodeblock" => param::<String>(&request.params, 0)
+                .and_then(|hex| {
+                    Block::from_hex(&hex).map_err(|e| JsonRpcError::new(INVALID_PARAMS, e))
+                })
+                .and_then(|block| to_json(&block)),
+            // The raw header is encoded from the decoded one, which the node always returns.
+            "getblockheader" if optional_param(&request.params, 1) == Ok(Some(false)) => {
+                Self::request_message(request)
+                    .and_then(|message| self.call(message))
+                    .and_then(|response| match response {
+                        PeerMessage::ResponseBlockHeader(Some(info)) => Ok(Value::from(
+                            hex::encode(serialize::serialize(info.header())),
+                        )),
+                        response => Self::response_result(response),
+                    })
+            }
+            _ => Self::request_message(request)
+                .and_then(|message| self.call(message))
+                .and_then(Self::response_result),
+        };
+        JsonRpcResponse::new(request.id.clone(), result)
+    }
+
+    /// Builds the unsigned transaction from the inputs: [{"txid": TXID, "vout": N}, ...],
+    /// the outputs: {ADDRESS: AMOUNT, ...} and the optional locktime,
+    /// and returns it hex-encoded. The outputs are ordered by the address.
+    fn create_raw_transaction(params: &[Value]) -> Result<Value, JsonRpcError> {
+        let inputs = param::<Vec<RawTransactionInput>>(params, 0)?
+            .into_iter()
+            .map(|input| {
+                let utxo_id = from_hex(&input.txid).map_err(|e| {
+                    JsonRpcError::new(INVALID_PARAMS, format!("Invalid txid: {}", e))
+                })?;
+                Ok(TransactionInput::new(
+                    TransactionId::new(utxo_id),
+                    OutputIndex::new(input.vout),
+                ))
+            })
+            .collect::<Result<Vec<_>, JsonRpcError>>()?;
+        let outputs = param::<BTreeMap<String, i64>>(params, 1)?
+            .into_iter()
+            .map(|(address, amount)| {
+                Ok(TransactionOutput::new(
+                    parse_address(&address)?,
+                    Coolcoin::new(amount),
+                ))
+            })
+            .collect::<Result<Vec<_>, JsonRpcError>>()?;
+        let locktime = optional_param(params, 2)?.unwrap_or(0);
+        let transaction = Transaction::new(inputs, outputs, locktime)
+            .map_err(|e| JsonRpcError::new(INVALID_PARAMS, e))?;
+        Ok(Value::from(transaction.to_hex()))
+    }
+
+    /// Converts the JSON-RPC request to the peer message requesting the same from the node.
+    fn request_message(request: &JsonRpcRequest) -> Result<PeerMessage, JsonRpcError> {
+        let params = &request.params;
+        match request.method.as_str() {
+            "getblock" => Ok(PeerMessage::GetBlock(block_hash_param(params, 0)?)),
+            "sendtransaction" => Ok(PeerMessage::SendTransaction(param(params, 0)?)),
+            "sendrawtransaction" => {
+                let hex = param::<String>(params, 0)?;
+                let transaction = Transaction::from_hex(&hex)
+                    .map_err(|e| JsonRpcError::new(INVALID_PARAMS, e))?;
+                Ok(PeerMessage::SendTransaction(transaction))
+            }
+            "estimatesmartfee" => Ok(PeerMessage::EstimateSmartFee(param(params, 0)?)),
+            "getmempoolinfo" => Ok(PeerMessage::GetTransactionPoolInfo),
+            "getmininginfo" => Ok(PeerMessage::GetMiningInfo),
+            "listminedblocks" => Ok(PeerMessage::ListMinedBlocks),
+            "getfullblockchain" => Ok(PeerMessage::GetFullBlockchain),
+            "getblockcount" => Ok(PeerMessage::GetBlockCount),
+            "getbestblockhash" => Ok(PeerMessage::GetBestBlockHash),
+            "getblockhash" => Ok(PeerMessage::GetBlockHash(param(params, 0)?)),
+            "stop" => Ok(PeerMessage::Stop),
+            "getinfo" => Ok(PeerMessage::GetInfo),
+            "getpeerinfo" => Ok(PeerMessage::GetPeerInfo),
+            "getpeerhistory" => Ok(PeerMessage::GetPeerHistory(optional_param(params, 0)?)),
+            "getnettotals" => Ok(PeerMessage::GetNetTotals),
+            "dumpstate" => Ok(PeerMessage::DumpState(optional_param(params, 0)?)),
+            "getforkstats" => Ok(PeerMessage::GetForkStats(optional_param(params, 0)?)),
+            "logging" => Ok(PeerMessage::Logging(
+                optional_param(params, 0)?.unwrap_or_default(),
+                optional_param(params, 1)?.unwrap_or_default(),
+            )),
+            "uptime" => Ok(PeerMessage::Uptime),
+            "getdifficulty" => Ok(PeerMessage::GetDifficulty),
+            "listbanned" => Ok(PeerMessage::ListBanned),
+            "setban" => {
+                let subnet = param::<String>(params, 0)?
+                    .parse::<Subnet>()
+                    .map_err(|e| JsonRpcError::new(INVALID_PARAMS, e))?;
+                let command = match param::<String>(params, 1)?.as_str() {
+                    "add" => {
+                        SetBanCommand::Add(optional_param(params, 2)?.unwrap_or(DEFAULT_BAN_TIME))
+                    }
+                    "remove" => SetBanCommand::Remove,
+                    command => {
+                        return Err(JsonRpcError::new(
+                            INVALID_PARAMS,
+                            format!("Invalid command: {}. Expected add or remove.", command),
+                        ))
+                    }
+                };
+                Ok(PeerMessage::SetBan(subnet, command))
+            }
+            "prioritisetransaction" => {
+                let transaction_id = from_hex(&param::<String>(params, 0)?).map_err(|e| {
+                    JsonRpcError::new(INVALID_PARAMS, format!("Invalid txid: {}", e))
+                })?;
+                Ok(PeerMessage::PrioritiseTransaction(
+                    TransactionId::new(transaction_id),
+                    Coolcoin::new(param(params, 1)?),
+                ))
+            }
+            "listtransactions" => Ok(PeerMessage::ListTransactions(
+                vec![parse_address(&param::<String>(params, 0)?)?],
+                optional_param(params, 1)?.unwrap_or(10),
+            )),
+            "getblockheader" => Ok(PeerMessage::GetBlockHeader(block_hash_param(params, 0)?)),
+            "gettransaction" => {
+                let transaction_id = from_hex(&param::<String>(params, 0)?).map_err(|e| {
+                    JsonRpcError::new(INVALID_PARAMS, format!("Invalid txid: {}", e))
+                })?;
+                Ok(PeerMessage::GetTransaction(TransactionId::new(
+                    transaction_id,
+                )))
+            }
+            "getchaintips" => Ok(PeerMessage::GetChainTips),
+            "getutxos" => Ok(PeerMessage::GetUtxos(
+                addresses_param(params, 0)?,
+                Coolcoin::new(optional_param(params, 1)?.unwrap_or(0)),
+            )),
+            "listunspent" => Ok(PeerMessage::ListUnspent(
+                optional_param(params, 0)?.unwrap_or(1),
+                optional_param(params, 1)?.unwrap_or(9999999),
+                addresses_param(params, 2)?,
+            )),
+            method => Err(JsonRpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Method not found: {}", method),
+            )),
+        }
+    }
+
+    /// Converts the node's response to the result of the JSON-RPC request.
+    fn response_result(response: PeerMessage) -> Result<Value, JsonRpcError> {
+        let to_value = |value: Result<Value, serde_json::Error>| {
+            value.map_err(|e| JsonRpcError::new(INTERNAL_ERROR, e.to_string()))
+        };
+        match response {
+            PeerMessage::ResponseBlock(block) => match block {
+                Some(block) => to_value(serde_json::to_value(block)),
+                None => Err(JsonRpcError::new(
+                    BLOCK_NOT_FOUND,
+                    "Block not found.".to_string(),
+                )),
+            },
+            PeerMessage::ResponseTransaction(result) => match result {
+                Ok(transaction_id) => Ok(Value::from(transaction_id.raw().to_string())),
+                Err(e) => Err(JsonRpcError::new(
+                    TRANSACTION_REJECTED,
+                    "Transaction rejected.".to_string(),
+                )
+                .with_data(Value::from(e))),
+            },
+            PeerMessage::ResponseFeeEstimate(fee_rate) => match fee_rate {
+                Some(fee_rate) => to_value(serde_json::to_value(fee_rate)),
+                None => Err(JsonRpcError::new(
+                    INSUFFICIENT_DATA,
+                    "Insufficient data to estimate the fee rate.".to_string(),
+                )),
+            },
+            PeerMessage::ResponseTransactionPoolInfo(info) => to_value(serde_json::to_value(info)),
+            PeerMessage::ResponseMiningInfo(info) => to_value(serde_json::to_value(info)),
+            PeerMessage::ResponseListMinedBlocks(blocks) => to_value(serde_json::to_value(blocks)),
+            PeerMessage::ResponseListUnspent(outputs) => to_value(serde_json::to_value(outputs)),
+            PeerMessage::ResponseListTransactions(transactions) => {
+                to_value(serde_json::to_value(transactions))
+            }
+            PeerMessage::ResponseListBanned(bans) => to_value(serde_json::to_value(bans)),
+            PeerMessage::ResponseSetBan(result) => match result {
+                Ok(()) => Ok(Value::Null),
+                Err(e) => Err(JsonRpcError::new(INVALID_REQUEST, e)),
+            },
+            PeerMessage::ResponsePrioritiseTransaction(result) => match result {
+                Ok(()) => Ok(Value::from(true)),
+                Err(e) => Err(JsonRpcError::new(INVALID_REQUEST, e)),
+            },
+            PeerMessage::ResponseStop(result) => match result {
+                Ok(()) => Ok(Value::from("Coolcoin server stopping")),
+                Err(e) => Err(JsonRpcError::new(INVALID_REQUEST, e)),
+            },
+            PeerMessage::ResponseInfo(info) => to_value(serde_json::to_value(info)),
+            PeerMessage::ResponsePeerInfo(peers) => to_value(serde_json::to_value(peers)),
+            PeerMessage::ResponseNetTotals(totals) => to_value(serde_json::to_value(totals)),
+            PeerMessage::ResponseForkStats(stats) => to_value(serde_json::to_value(stats)),
+            PeerMessage::ResponsePeerHistory(events) => to_value(serde_json::to_value(events)),
+            PeerMessage::ResponseLogging(result) => match result {
+                Ok(levels) => to_value(serde_json::to_value(levels)),
+                Err(e) => Err(JsonRpcError::new(INVALID_REQUEST, e)),
+            },
+            PeerMessage::ResponseDumpState(result) => match result {
+                Ok(path) => Ok(Value::from(path)),
+                Err(e) => Err(JsonRpcError::new(INVALID_REQUEST, e)),
+            },
+            PeerMessage::ResponseUptime(uptime) => Ok(Value::from(uptime)),
+            PeerMessage::ResponseDifficulty(difficulty) => Ok(Value::from(difficulty)),
+            PeerMessage::ResponseChainTips(tips) => to_value(serde_json::to_value(tips)),
+            PeerMessage::ResponseBlockHeader(info) => match info {
+                Some(info) => to_value(serde_json::to_value(info)),
+                None => Err(JsonRpcError::new(
+                    BLOCK_NOT_FOUND,
+                    "Block not found.".to_string(),
+                )),
+            },
+            PeerMessage::ResponseTransactionInfo(info) => match info {
+                Some(info) => to_value(serde_json::to_value(info)),
+                None => Err(JsonRpcError::new(
+                    TRANSACTION_NOT_FOUND,
+                    "Transaction not found in the transaction pool or the active blockchain."
+                        .to_string(),
+                )),
+            },
+            PeerMessage::ResponseBlockCount(count) => Ok(Value::from(count)),
+            PeerMessage::ResponseBestBlockHash(hash) => Ok(Value::from(hash.to_string())),
+            PeerMessage::ResponseBlockHash(hash) => match hash {
+                Some(hash) => Ok(Value::from(hash.to_string())),
+                None => Err(JsonRpcError::new(
+                    INVALID_PARAMS,
+                    "Block height out of range.".to_string(),
+                )),
+            },
+            PeerMessage::ResponseFullBlockchain(active_blockchain, blocks) => Ok(json!({
+                "active_blockchain": to_value(serde_json::to_value(active_blockchain))?,
+                "blocks": to_value(serde_json::to_value(blocks))?,
+            })),
+            unexpected => Err(JsonRpcError::new(
+                INTERNAL_ERROR,
+                format!("Unexpected response from the node: {:?}", unexpected),
+            )),
+        }
+    }
+
+    fn call(&self, message: PeerMessage) -> Result<PeerMessage, JsonRpcError> {
+        self.client
+            .lock()
+            .unwrap()
+            .request(message)
+            .map_err(|e| JsonRpcError::new(NODE_UNAVAILABLE, e))
+    }
+}
+
+fn param<T: DeserializeOwned>(params: &[Value], index: usize) -> Result<T, JsonRpcError> {
+    let value = params.get(index).ok_or_else(|| {
+        JsonRpcError::new(INVALID_PARAMS, format!("Missing parameter: {}", index))
+    })?;
+    serde_json::from_value(value.clone()).map_err(|e| {
+        JsonRpcError::new(
+            INVALID_PARAMS,
+            format!("Invalid parameter: {}: {}", index, e),
+        )
+    })
+}
+
+// The addresses are parsed from the strings, so that they are validated as on the command line.
+fn parse_address(address: &str) -> Result<Address, JsonRpcError> {
+    address
+        .parse()
+        .map_err(|e| JsonRpcError::new(INVALID_PARAMS, e))
+}
+
+// The optional list of addresses, which is empty if not given.
+fn addresses_param(params: &[Value], index: usize) -> Result<Vec<Address>, JsonRpcError> {
+    optional_param::<Vec<String>>(params, index)?
+        .unwrap_or_default()
+        .iter()
+        .map(|address| parse_address(address))
+        .collect()
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Value, JsonRpcError> {
+    serde_json::to_value(value).map_err(|e| JsonRpcError::new(INTERNAL_ERROR, e.to_string()))
+}
+
+fn block_hash_param(params: &[Value], index: usize) -> Result<BlockHash, JsonRpcError> {
+    let hex = param::<String>(params, index)?;
+    let block_hash = from_hex(&hex)
+        .map_err(|e| JsonRpcError::new(INVALID_PARAMS, format!("Invalid block hash: {}", e)))?;
+    Ok(BlockHash::new(block_hash))
+}
+
+fn optional_param<T: DeserializeOwned>(
+    params: &[Value],
+    index: usize,
+) -> Result<Option<T>, JsonRpcError> {
+    match params.get(index) {
+        Some(Value::Null) | None => Ok(None),
+        Some(_) => param(params, index).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::BlockchainManager;
+
+    fn request(method: &str, params: Vec<Value>) -> JsonRpcRequest {
+        JsonRpcRequest {
+            jsonrpc: Some("2.0".to_string()),
+            id: json!(1),
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    #[test]
+    fn converts_requests_to_peer_messages() {
+        assert!(matches!(
+            JsonRpcHandler::request_message(&request("estimatesmartfee", vec![json!(6)])),
+            Ok(PeerMessage::EstimateSmartFee(6))
+        ));
+        assert_eq!(
+            JsonRpcHandler::request_message(&request("estimatesmartfee", vec![]))
+                .unwrap_err()
+                .code,
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            JsonRpcHandler::request_message(&request("getblock", vec![json!("xyz")]))
+                .unwrap_err()
+                .code,
+            INVALID_PARAMS
+        );
+        assert!(matches!(
+            JsonRpcHandler::request_message(&request("listunspent", vec![json!(6), json!(null)])),
+            Ok(PeerMessage::ListUnspent(6, 9999999, addresses)) if addresses.is_empty()
+        ));
+        assert!(matches!(
+            JsonRpcHandler::request_message(&request("getutxos", vec![json!(["alice"]), json!(5)])),
+            Ok(PeerMessage::GetUtxos(addresses, min_amount))
+                if addresses == vec![Address::new("alice".to_string())] && min_amount == 5.into()
+        ));
+        assert_eq!(
+            JsonRpcHandler::request_message(&request("listtransactions", vec![json!("")]))
+                .unwrap_err()
+                .code,
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            JsonRpcHandler::request_message(&request("getbalance", vec![]))
+                .unwrap_err()
+                .code,
+            METHOD_NOT_FOUND
+        );
+        // The node isn't running, but all methods are known.
+        let handler = JsonRpcHandler::new("127.0.0.1:0".to_string(), Duration::from_secs(1));
+        for method in METHODS {
+            let error = handler.handle(&request(method, vec![])).error;
+            assert_ne!(error.map(|e| e.code), Some(METHOD_NOT_FOUND), "{}", method);
+        }
+    }
+
+    #[test]
+    fn decodes_raw_transactions() {
+        let transaction = Transaction::new(
+            vec![TransactionInput::new_coinbase()],
+            vec![TransactionOutput::new(
+                Address::new("alice".to_string()),
+                Coolcoin::new(50),
+            )],
+            0,
+        )
+        .unwrap();
+        let hex = transaction.to_hex();
+        match JsonRpcHandler::request_message(&request("sendrawtransaction", vec![json!(hex)])) {
+            Ok(PeerMessage::SendTransaction(decoded)) => assert_eq!(decoded.id(), transaction.id()),
+            unexpected => panic!("Unexpected result: {:?}", unexpected),
+        }
+        assert_eq!(
+            JsonRpcHandler::request_message(&request("sendrawtransaction", vec![json!("00ff")]))
+                .unwrap_err()
+                .code,
+            INVALID_PARAMS
+        );
+
+        let transaction_id = *transaction.id();
+        assert_eq!(
+            JsonRpcHandler::response_result(PeerMessage::ResponseTransaction(Ok(transaction_id))),
+            Ok(Value::from(transaction_id.raw().to_string()))
+        );
+        let error = JsonRpcHandler::response_result(PeerMessage::ResponseTransaction(Err(
+            "Double spend.".to_string(),
+        )))
+        .unwrap_err();
+        assert_eq!(error.kind(), JsonRpcErrorKind::TransactionRejected);
+        assert_eq!(error.data, Some(Value::from("Double spend.")));
+    }
+
+    #[test]
+    fn decodes_blocks_and_transactions_locally() {
+        let handler = JsonRpcHandler::new("127.0.0.1:0".to_string(), Duration::from_secs(1));
+        let block = BlockchainManager::genesis_block();
+        let response = handler.handle(&request("decodeblock", vec![json!(block.to_hex())]));
+        assert_eq!(
+            response.result.unwrap()["id"],
+            json!(block.id().to_string())
+        );
+
+        let transaction = &block.transactions()[0];
+        let response = handler.handle(&request(
+            "decoderawtransaction",
+            vec![json!(transaction.to_hex())],
+        ));
+        assert_eq!(
+            response.result.unwrap()["outputs"][0]["to"],
+            json!("genesis_wallet_address")
+        );
+
+        let response = handler.handle(&request("decodeblock", vec![json!("00ff")]));
+        assert_eq!(response.error.unwrap().code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn creates_raw_transactions() {
+        let txid = "9961b01dcd9b5263716e858b7a059570037642c469a5e097fe11c0a2763805e2";
+        let hex = JsonRpcHandler::create_raw_transaction(&[
+            json!([{"txid": txid, "vout": 1}]),
+            json!({"bob": 15, "alice": 35}),
+        ])
+        .unwrap();
+        let transaction = Transaction::from_hex(hex.as_str().unwrap()).unwrap();
+        assert_eq!(transaction.inputs()[0].utxo_id().raw().to_string(), txid);
+        assert_eq!(*transaction.inputs()[0].output_index(), OutputIndex::new(1));
+        let outputs = transaction
+            .outputs()
+            .iter()
+            .map(|output| output.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            outputs,
+            vec![
+                TransactionOutput::new(Address::new("alice".to_string()), Coolcoin::new(35))
+                    .to_string(),
+                TransactionOutput::new(Address::new("bob".to_string()), Coolcoin::new(15))
+                    .to_string(),
+            ]
+        );
+        assert_eq!(transaction.locktime(), 0);
+
+        assert_eq!(
+            JsonRpcHandler::create_raw_transaction(&[
+                json!([{"txid": "xyz", "vout": 0}]),
+                json!({"bob": 15}),
+            ])
+            .unwrap_err()
+            .code,
+            INVALID_PARAMS
+        );
+    }
+
+    #[test]
+    fn converts_responses_to_results() {
+        assert_eq!(
+            JsonRpcHandler::response_result(PeerMessage::ResponseFeeEstimate(None))
+                .unwrap_err()
+                .code,
+            INSUFFICIENT_DATA
+        );
+        assert_eq!(
+            JsonRpcHandler::response_result(PeerMessage::ResponseBlock(None))
+                .unwrap_err()
+                .kind(),
+            JsonRpcErrorKind::BlockNotFound
+        );
+        assert_eq!(
+            JsonRpcError::new(-1, "Unknown.".to_string()).kind(),
+            JsonRpcErrorKind::Other(-1)
+        );
+
+        let response = JsonRpcResponse::new(json!(7), Ok(json!([])));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({"jsonrpc": "2.0", "id": 7, "result": []})
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::{reload, Registry};
+
+/// The categories of the log messages, which are the targets of the tracing events.
+/// The messages without the category are logged at the default level.
+pub const CATEGORIES: [&str; 6] = ["net", "validation", "mempool", "miner", "rpc", "status"];
+
+/// The filter installed by `reloadable`, with the levels from which it has been built.
+static FILTER: OnceLock<(Mutex<LogLevels>, reload::Handle<Targets, Registry>)> = OnceLock::new();
+
+/// The levels of the log messages: the default one, and the levels of the categories that
+/// differ from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLevels {
+    default: LevelFilter,
+    categories: BTreeMap<String, LevelFilter>,
+}
+
+impl LogLevels {
+    pub fn new(default: LevelFilter, categories: &[(String, LevelFilter)]) -> Self {
+        Self {
+            default,
+            categories: categories.iter().cloned().collect(),
+        }
+    }
+
+    /// Sets the levels of the included categories, given as CATEGORY[=LEVEL], where the category
+    /// without the level is logged in full, and returns the excluded categories to the default
+    /// level. Nothing changes if any of them is invalid.
+    pub fn update(&mut self, include: &[String], exclude: &[String]) -> Result<(), String> {
+        let include = include
+            .iter()
+            .map(|category| parse_category(category))
+            .collect::<Result<Vec<_>, String>>()?;
+        for category in exclude {
+            check_category(category)?;
+        }
+        self.categories.extend(include);
+        for category in exclude {
+            self.categories.remove(category);
+        }
+        Ok(())
+    }
+
+    /// Returns the level of each category, including the ones at the default level.
+    pub fn category_levels(&self) -> BTreeMap<String, String> {
+        CATEGORIES
+            .iter()
+            .map(|category| {
+                let level = self.categories.get(*category).unwrap_or(&self.default);
+                (category.to_string(), level.to_string().to_lowercase())
+            })
+            .collect()
+    }
+
+    fn targets(&self) -> Targets {
+        Targets::new()
+            .with_default(self.default)
+            .with_targets(self.categories.clone())
+    }
+}
+
+/// Parses the category with the optional level, e.g. net=debug. The category without the level
+/// is logged in full.
+pub fn parse_category(category: &str) -> Result<(String, LevelFilter), String> {
+    let (name, level) = match category.split_once('=') {
+        Some((name, level)) => (name, parse_level(level)?),
+        None => (category, LevelFilter::TRACE),
+    };
+    check_category(name)?;
+    Ok((name.to_string(), level))
+}
+
+pub fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level).map_err(|_| {
+        format!(
+            "Invalid log level: {}, expected one of: off, error, warn, info, debug, trace",
+            level
+        )
+    })
+}
+
+fn check_category(name: &str) -> Result<(), String> {
+    match CATEGORIES.contains(&name) {
+        true => Ok(()),
+        false => Err(format!(
+            "Unknown log category: {}, expected one of: {}",
+            name,
+            CATEGORIES.join(", ")
+        )),
+    }
+}
+
+/// Returns the filter of the log messages with the levels, which can then be changed while
+/// the process runs with `update`. Only one filter can be installed per process.
+pub fn reloadable(levels: LogLevels) -> Result<reload::Layer<Targets, Registry>, String> {
+    let (filter, handle) = reload::Layer::new(levels.targets());
+    FILTER
+        .set((Mutex::new(levels), handle))
+        .map_err(|_| "The log filter has already been installed.".to_string())?;
+    Ok(filter)
+}
+
+/// Changes the levels of the installed filter, see `LogLevels::update`, and returns the level
+/// of each category.
+pub fn update(include: &[String], exclude: &[String]) -> Result<BTreeMap<String, String>, String> {
+    let (levels, handle) = FILTER
+        .get()
+        .ok_or_else(|| "Logging hasn't been initialized.".to_string())?;
+    let mut levels = levels.lock().unwrap();
+    let mut updated = levels.clone();
+    updated.update(include, exclude)?;
+    handle
+        .reload(updated.targets())
+        .map_err(|e| format!("Failed to update the log filter: {}", e))?;
+    *levels = updated;
+    Ok(levels.category_levels())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_category_levels() {
+        let mut levels = LogLevels::new(
+            LevelFilter::INFO,
+            &[("net".to_string(), LevelFilter::DEBUG)],
+        );
+        levels
+            .update(
+                &["mempool".to_string(), "miner=off".to_string()],
+                &["net".to_string()],
+            )
+            .unwrap();
+        let category_levels = levels.category_levels();
+        assert_eq!(category_levels["net"], "info");
+        assert_eq!(category_levels["mempool"], "trace");
+        assert_eq!(category_levels["miner"], "off");
+        assert_eq!(category_levels["rpc"], "info");
+
+        // The invalid update is rejected as a whole.
+        let before = levels.clone();
+        assert!(levels
+            .update(&["validation".to_string()], &["wallet".to_string()])
+            .is_err());
+        assert!(levels.update(&["net=loud".to_string()], &[]).is_err());
+        assert_eq!(levels, before);
+    }
+}
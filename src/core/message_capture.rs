@@ -0,0 +1,319 @@
+use crate::core::block::BlockHash;
+use crate::core::blocktree::ChainTip;
+use crate::core::chain_params::ChainParams;
+use crate::core::coolcoin_node::{NodeInfo, NodeParams};
+use crate::core::metrics::SlowThresholds;
+use crate::core::miner::RewardSplit;
+use crate::core::peer_connection::{encode, MessageDecoder, PeerMessage};
+use crate::core::poller::wait_readable;
+use crate::core::storage::{self, FileStorage};
+use crate::core::transaction_pool::{PackageLimits, TransactionPoolInfo};
+use crate::core::{
+    as_hex, Address, CoolcoinClient, CoolcoinNetwork, CoolcoinNode, FeeRate, PeerConnection,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long the replayed node is waited for to process each message.
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(30);
+// The names of the replayed node and of the client that queries its state. The client's
+// address is a loopback one, so it can stop the node.
+const NODE_ADDRESS: &str = "replay";
+const CLIENT_ADDRESS: &str = "127.0.0.1:0";
+// The policies of the replayed node, which are the daemon's defaults.
+const MAX_TRANSACTION_POOL_USAGE: usize = 300_000_000;
+
+/// The message received from the peer, as recorded in the capture file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CapturedMessage {
+    peer: String,
+    // Milliseconds since the capture has started.
+    time_millis: u64,
+    // The hex-encoded message as it has been sent over the network.
+    message: String,
+}
+
+impl CapturedMessage {
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    pub fn message(&self) -> Result<PeerMessage, String> {
+        let bytes = hex::decode(&self.message).map_err(|e| e.to_string())?;
+        let mut decoder = MessageDecoder::default();
+        decoder
+            .read_from(&mut &bytes[..])
+            .map_err(|e| e.to_string())?;
+        decoder
+            .next_message()?
+            .ok_or_else(|| format!("Incomplete message from: {}", self.peer))
+    }
+}
+
+/// Records the messages that the node receives from its peers and clients to the file, one
+/// JSON record per line, so that the same sequence can be fed to a fresh node with `replay`,
+/// e.g. to reproduce a bug observed on the live network.
+pub struct MessageCapture {
+    file: File,
+    started: Instant,
+}
+
+impl MessageCapture {
+    /// Starts the capture, replacing the file if it exists.
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create capture file: {}: {}", path.display(), e))?;
+        Ok(Self {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, peer: &str, message: &PeerMessage) -> Result<(), String> {
+        let captured = CapturedMessage {
+            peer: peer.to_string(),
+            time_millis: self.started.elapsed().as_millis() as u64,
+            message: as_hex(&encode(message)),
+        };
+        let line = serde_json::to_string(&captured).map_err(|e| e.to_string())?;
+        writeln!(self.file, "{}", line).map_err(|e| e.to_string())
+    }
+}
+
+/// Loads the messages recorded by `MessageCapture`. The last one is dropped if the node has
+/// stopped while recording it.
+pub fn load(path: &Path) -> Result<Vec<CapturedMessage>, String> {
+    storage::read_records(&FileStorage, path)?
+        .iter()
+        .map(|line| {
+            serde_json::from_str(line).map_err(|e| {
+                format!(
+                    "Failed to load captured messages from: {}: {}",
+                    path.display(),
+                    e
+                )
+            })
+        })
+        .collect()
+}
+
+/// The state of the node after the replay.
+#[derive(Debug, Serialize)]
+pub struct ReplayReport {
+    // Number of the replayed messages, and of the peers in the capture.
+    messages: usize,
+    peers: usize,
+    // The stop requests, which aren't replayed, so that the node's state can be queried.
+    skipped: usize,
+    block_count: u32,
+    best_block_hash: BlockHash,
+    chain_tips: Vec<ChainTip>,
+    transaction_pool: TransactionPoolInfo,
+    info: NodeInfo,
+}
+
+impl ReplayReport {
+    pub fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    pub fn best_block_hash(&self) -> &BlockHash {
+        &self.best_block_hash
+    }
+}
+
+/// One of the captured peers, whose messages are sent to the replayed node over its own
+/// connection.
+struct ReplayedPeer {
+    connection: PeerConnection,
+    // The number of block count requests sent to the node without the response yet.
+    pending_block_counts: usize,
+}
+
+/// Feeds the messages to a fresh node of the network, which runs in this process with
+/// the default policies, and returns its state once it has processed all of them.
+///
+/// Each peer is connected to the node the way the clients and the peers are connected in
+/// the tests, and the messages are sent in the captured order: the next message is sent only
+/// after the node has responded to a block count request that follows the previous one.
+/// The node mines and responds to its peers as usual, but their responses are dropped.
+pub fn replay(
+    messages: &[CapturedMessage],
+    chain_params: ChainParams,
+    data_dir: &Path,
+) -> Result<ReplayReport, String> {
+    let mut peers = HashMap::new();
+    let mut node_connections = vec![];
+    for captured in messages {
+        if !peers.contains_key(captured.peer()) && captured.peer() != CLIENT_ADDRESS {
+            let (connection, node_connection) =
+                PeerConnection::pair(captured.peer(), NODE_ADDRESS)?;
+            node_connections.push(node_connection);
+            peers.insert(
+                captured.peer().to_string(),
+                ReplayedPeer {
+                    connection,
+                    pending_block_counts: 0,
+                },
+            );
+        }
+    }
+    let (client, node_client) = PeerConnection::pair(CLIENT_ADDRESS, NODE_ADDRESS)?;
+    node_connections.push(node_client);
+    let node = CoolcoinNode::with_network(
+        CoolcoinNetwork::with_connections(node_connections, false),
+        None,
+        NodeParams {
+            reward_split: RewardSplit::new(Address::new(NODE_ADDRESS.to_string()), vec![])?,
+            coinbase_tag: vec![],
+            package_limits: PackageLimits::default(),
+            max_transaction_pool_usage: MAX_TRANSACTION_POOL_USAGE,
+            min_relay_fee_rate: FeeRate::zero(),
+            data_dir: PathBuf::from(data_dir),
+            chain_params,
+            status_interval: None,
+            slow_thresholds: SlowThresholds::default(),
+        },
+    )?;
+    let node_thread = thread::spawn(move || node.run());
+    let mut client = CoolcoinClient::with_connection(client, REPLAY_TIMEOUT);
+
+    let mut skipped = 0;
+    let result = messages.iter().try_for_each(|captured| {
+        let message = captured.message()?;
+        let peer = match (&message, peers.get_mut(captured.peer())) {
+            (PeerMessage::Stop, _) | (_, None) => {
+                skipped += 1;
+                return Ok(());
+            }
+            (_, Some(peer)) => peer,
+        };
+        if let PeerMessage::GetBlockCount = message {
+            peer.pending_block_counts += 1;
+        }
+        send(peer, &message)?;
+        peer.pending_block_counts += 1;
+        send(peer, &PeerMessage::GetBlockCount)?;
+        wait_for_responses(captured.peer(), &mut peers)
+    });
+    let report = result.and_then(|()| {
+        Ok(ReplayReport {
+            messages: messages.len() - skipped,
+            peers: peers.len(),
+            skipped,
+            block_count: client.get_block_count()?,
+            best_block_hash: client.get_best_block_hash()?,
+            chain_tips: client.get_chain_tips()?,
+            transaction_pool: client.get_transaction_pool_info()?,
+            info: client.get_info()?,
+        })
+    });
+    client.stop()?;
+    node_thread
+        .join()
+        .map_err(|_| "The replayed node has panicked.".to_string())?;
+    report
+}
+
+fn send(peer: &mut ReplayedPeer, message: &PeerMessage) -> Result<(), String> {
+    let start = Instant::now();
+    // The send buffer is full until the node reads the previous messages.
+    while !peer.connection.send(message)? {
+        if start.elapsed() > REPLAY_TIMEOUT {
+            return Err(format!(
+                "The node doesn't read the messages from: {}",
+                peer.connection.address()
+            ));
+        }
+        peer.connection.receive_all()?;
+        wait_readable(peer.connection.raw_fd(), Duration::from_millis(10))?;
+    }
+    Ok(())
+}
+
+// Waits until the node has responded to all block count requests of the peer, while dropping
+// the other messages that it sends to any of the peers, so that their buffers don't fill up.
+fn wait_for_responses(
+    address: &str,
+    peers: &mut HashMap<String, ReplayedPeer>,
+) -> Result<(), String> {
+    let start = Instant::now();
+    while peers[address].pending_block_counts > 0 {
+        if start.elapsed() > REPLAY_TIMEOUT {
+            return Err(format!(
+                "The node hasn't processed the message from: {} in time.",
+                address
+            ));
+        }
+        wait_readable(
+            peers[address].connection.raw_fd(),
+            Duration::from_millis(10),
+        )?;
+        for peer in peers.values_mut() {
+            for message in peer.connection.receive_all()? {
+                if let PeerMessage::ResponseBlockCount(_) = message {
+                    peer.pending_block_counts = peer.pending_block_counts.saturating_sub(1);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::chain_builder::ChainBuilder;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn replays_captured_messages() {
+        let dir = std::env::temp_dir().join(format!("message_capture_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("capture.jsonl");
+
+        let mut chain = ChainBuilder::new(ChainParams::regtest());
+        let blocks = chain.mine_blocks(3);
+        let mut capture = MessageCapture::create(&path).unwrap();
+        // The blocks arrive from different peers, the last one before its parent.
+        capture
+            .record(
+                "10.0.0.1:8333",
+                &PeerMessage::RelayBlock(Arc::new(blocks[0].clone())),
+            )
+            .unwrap();
+        capture
+            .record(
+                "10.0.0.2:8333",
+                &PeerMessage::RelayBlock(Arc::new(blocks[2].clone())),
+            )
+            .unwrap();
+        capture
+            .record("10.0.0.1:8333", &PeerMessage::GetBlockCount)
+            .unwrap();
+        capture
+            .record(
+                "10.0.0.1:8333",
+                &PeerMessage::RelayBlock(Arc::new(blocks[1].clone())),
+            )
+            .unwrap();
+        capture
+            .record("127.0.0.1:5000", &PeerMessage::Stop)
+            .unwrap();
+
+        let messages = load(&path).unwrap();
+        assert_eq!(messages.len(), 5);
+        let report = replay(&messages, ChainParams::regtest(), &dir.join("node")).unwrap();
+        assert_eq!(report.block_count(), 3);
+        assert_eq!(report.best_block_hash(), chain.tip().id());
+        assert_eq!((report.messages, report.peers, report.skipped), (4, 3, 1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+use crate::core::node_event::NodeEvent;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Upper bounds of the duration histograms' buckets, in seconds.
+const DURATION_BUCKETS: [f64; 7] = [0.0001, 0.001, 0.01, 0.1, 1.0, 10.0, 60.0];
+
+/// The distribution of the durations of an operation, as the cumulative counts of the
+/// durations that are at most each bucket's upper bound.
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(DURATION_BUCKETS.iter()) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, output: &mut String, name: &str, help: &str) {
+        writeln!(output, "# HELP {} {}", name, help).unwrap();
+        writeln!(output, "# TYPE {} histogram", name).unwrap();
+        for (bucket, bound) in self.buckets.iter().zip(DURATION_BUCKETS.iter()) {
+            writeln!(
+                output,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                bound,
+                bucket.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        writeln!(output, "{}_bucket{{le=\"+Inf\"}} {}", name, count).unwrap();
+        writeln!(
+            output,
+            "{}_sum {}",
+            name,
+            self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+        )
+        .unwrap();
+        writeln!(output, "{}_count {}", name, count).unwrap();
+    }
+}
+
+/// The durations above which the node's operations are logged as slow, with their details.
+#[derive(Debug, Clone, Copy)]
+pub struct SlowThresholds {
+    pub block_validation: Duration,
+    // Applying the changes of the connected and disconnected blocks to the UTXO pool.
+    pub utxo_flush: Duration,
+    pub message_handling: Duration,
+}
+
+impl Default for SlowThresholds {
+    fn default() -> Self {
+        Self {
+            block_validation: Duration::from_millis(1000),
+            utxo_flush: Duration::from_millis(500),
+            message_handling: Duration::from_millis(1000),
+        }
+    }
+}
+
+/// The node's counters and gauges, which are updated from the node's events and by the node
+/// as it runs, and which are exported
+/// in the Prometheus text format by the metrics server.
+/// They are shared between the threads, so the updates are cheap enough to be made on every
+/// message.
+#[derive(Default)]
+pub struct Metrics {
+    tip_height: AtomicU64,
+    peers: AtomicU64,
+    transaction_pool_size: AtomicU64,
+    transaction_pool_bytes: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    reorgs: AtomicU64,
+    reorg_disconnected_blocks: AtomicU64,
+    stale_blocks: AtomicU64,
+    peer_connections: AtomicU64,
+    transaction_evictions: AtomicU64,
+    bans: AtomicU64,
+    block_validation: Histogram,
+    transaction_validation: Histogram,
+    utxo_flush: Histogram,
+    message_handling: Histogram,
+}
+
+impl Metrics {
+    fn set_tip_height(&self, height: u32) {
+        self.tip_height.store(height as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_peers(&self, peers: usize) {
+        self.peers.store(peers as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_transaction_pool(&self, size: usize, bytes: usize) {
+        self.transaction_pool_size
+            .store(size as u64, Ordering::Relaxed);
+        self.transaction_pool_bytes
+            .store(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Sets the total traffic since the node has started, which only grows.
+    pub fn set_network_traffic(&self, bytes_sent: u64, bytes_received: u64) {
+        self.bytes_sent.store(bytes_sent, Ordering::Relaxed);
+        self.bytes_received.store(bytes_received, Ordering::Relaxed);
+    }
+
+    /// Records the node's events in a separate thread, until the node is dropped.
+    pub fn record_events(self: &Arc<Self>, events: Receiver<NodeEvent>) {
+        let metrics = self.clone();
+        thread::spawn(move || {
+            for event in events {
+                metrics.record(&event);
+            }
+        });
+    }
+
+    fn record(&self, event: &NodeEvent) {
+        match event {
+            NodeEvent::BlockConnected { height, .. } => self.set_tip_height(*height),
+            NodeEvent::BlockDisconnected { height, .. } => {
+                self.set_tip_height(height.saturating_sub(1))
+            }
+            NodeEvent::Reorg { disconnected, .. } => {
+                self.reorgs.fetch_add(1, Ordering::Relaxed);
+                self.reorg_disconnected_blocks
+                    .fetch_add(disconnected.len() as u64, Ordering::Relaxed);
+            }
+            NodeEvent::TransactionEvicted { .. } => {
+                self.transaction_evictions.fetch_add(1, Ordering::Relaxed);
+            }
+            NodeEvent::PeerConnected { .. } => {
+                self.peer_connections.fetch_add(1, Ordering::Relaxed);
+            }
+            NodeEvent::PeerBanned { .. } => {
+                self.bans.fetch_add(1, Ordering::Relaxed);
+            }
+            NodeEvent::TransactionAccepted { .. } => {}
+        }
+    }
+
+    /// Counts the valid block that isn't in the active blockchain once received.
+    pub fn stale_block(&self) {
+        self.stale_blocks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn block_validation(&self) -> &Histogram {
+        &self.block_validation
+    }
+
+    pub fn transaction_validation(&self) -> &Histogram {
+        &self.transaction_validation
+    }
+
+    pub fn utxo_flush(&self) -> &Histogram {
+        &self.utxo_flush
+    }
+
+    pub fn message_handling(&self) -> &Histogram {
+        &self.message_handling
+    }
+
+    /// Returns the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let values = [
+            (
+                "coolcoin_tip_height",
+                "gauge",
+                "Height of the tip of the active blockchain.",
+                &self.tip_height,
+            ),
+            (
+                "coolcoin_peers",
+                "gauge",
+                "Number of connected peers and clients.",
+                &self.peers,
+            ),
+            (
+                "coolcoin_mempool_transactions",
+                "gauge",
+                "Number of transactions in the transaction pool.",
+                &self.transaction_pool_size,
+            ),
+            (
+                "coolcoin_mempool_bytes",
+                "gauge",
+                "Total size of the serialized transactions in the transaction pool.",
+                &self.transaction_pool_bytes,
+            ),
+            (
+                "coolcoin_network_sent_bytes_total",
+                "counter",
+                "Bytes sent to the peers and clients.",
+                &self.bytes_sent,
+            ),
+            (
+                "coolcoin_network_received_bytes_total",
+                "counter",
+                "Bytes received from the peers and clients.",
+                &self.bytes_received,
+            ),
+            (
+                "coolcoin_reorgs_total",
+                "counter",
+                "Number of times the active blockchain has switched to another branch.",
+                &self.reorgs,
+            ),
+            (
+                "coolcoin_reorg_disconnected_blocks_total",
+                "counter",
+                "Number of blocks disconnected from the active blockchain by the reorgs.",
+                &self.reorg_disconnected_blocks,
+            ),
+            (
+                "coolcoin_stale_blocks_total",
+                "counter",
+                "Number of the received valid blocks that haven't become a part of the active blockchain.",
+                &self.stale_blocks,
+            ),
+            (
+                "coolcoin_peer_connections_total",
+                "counter",
+                "Number of connections from or to the peers and clients.",
+                &self.peer_connections,
+            ),
+            (
+                "coolcoin_mempool_evictions_total",
+                "counter",
+                "Number of transactions removed from the transaction pool without being confirmed.",
+                &self.transaction_evictions,
+            ),
+            (
+                "coolcoin_bans_total",
+                "counter",
+                "Number of subnets banned by the operator.",
+                &self.bans,
+            ),
+        ];
+        for (name, metric_type, help, value) in values.iter() {
+            writeln!(output, "# HELP {} {}", name, help).unwrap();
+            writeln!(output, "# TYPE {} {}", name, metric_type).unwrap();
+            writeln!(output, "{} {}", name, value.load(Ordering::Relaxed)).unwrap();
+        }
+        self.block_validation.render(
+            &mut output,
+            "coolcoin_block_validation_seconds",
+            "Time to validate the received blocks and connect them to the active blockchain.",
+        );
+        self.transaction_validation.render(
+            &mut output,
+            "coolcoin_transaction_validation_seconds",
+            "Time to validate the received transactions.",
+        );
+        self.utxo_flush.render(
+            &mut output,
+            "coolcoin_utxo_flush_seconds",
+            "Time to apply the changes of the connected and disconnected blocks to the UTXO pool.",
+        );
+        self.message_handling.render(
+            &mut output,
+            "coolcoin_message_handling_seconds",
+            "Time to handle the messages from the peers and clients.",
+        );
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::BlockHash;
+    use crate::core::Sha256;
+
+    #[test]
+    fn renders_prometheus_text_format() {
+        let hash = |byte: u8| BlockHash::new(Sha256::new([byte; 32]));
+        let metrics = Metrics::default();
+        metrics.set_tip_height(12);
+        metrics.set_transaction_pool(3, 750);
+        metrics.record(&NodeEvent::Reorg {
+            old_tip: hash(1),
+            new_tip: hash(2),
+            disconnected: vec![hash(1)],
+            connected: vec![hash(2)],
+        });
+        metrics.record(&NodeEvent::BlockDisconnected {
+            hash: hash(1),
+            height: 13,
+        });
+        metrics
+            .block_validation()
+            .observe(Duration::from_micros(500));
+        metrics.block_validation().observe(Duration::from_secs(2));
+        metrics.message_handling().observe(Duration::from_millis(5));
+
+        let output = metrics.render();
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert!(lines.contains(&"# TYPE coolcoin_tip_height gauge"));
+        assert!(lines.contains(&"coolcoin_tip_height 12"));
+        assert!(lines.contains(&"coolcoin_mempool_transactions 3"));
+        assert!(lines.contains(&"coolcoin_mempool_bytes 750"));
+        assert!(lines.contains(&"coolcoin_reorgs_total 1"));
+        assert!(lines.contains(&"coolcoin_reorg_disconnected_blocks_total 1"));
+        assert!(lines.contains(&"coolcoin_block_validation_seconds_bucket{le=\"0.0001\"} 0"));
+        assert!(lines.contains(&"coolcoin_block_validation_seconds_bucket{le=\"0.001\"} 1"));
+        assert!(lines.contains(&"coolcoin_block_validation_seconds_bucket{le=\"10\"} 2"));
+        assert!(lines.contains(&"coolcoin_block_validation_seconds_bucket{le=\"+Inf\"} 2"));
+        assert!(lines.contains(&"coolcoin_block_validation_seconds_sum 2.0005"));
+        assert!(lines.contains(&"coolcoin_transaction_validation_seconds_count 0"));
+        assert!(lines.contains(&"coolcoin_utxo_flush_seconds_count 0"));
+        assert!(lines.contains(&"coolcoin_message_handling_seconds_bucket{le=\"0.001\"} 0"));
+        assert!(lines.contains(&"coolcoin_message_handling_seconds_bucket{le=\"0.01\"} 1"));
+    }
+}
@@ -0,0 +1,60 @@
+use crate::core::metrics::Metrics;
+use crate::core::RpcServer;
+use std::io::{BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// Serves the node's metrics at GET /metrics, for Prometheus to scrape.
+/// The requests are served one at a time, which is enough for the scrapers.
+pub struct MetricsServer {}
+
+impl MetricsServer {
+    /// Starts serving the requests in a separate thread.
+    pub fn start_async(address: &str, metrics: Arc<Metrics>) -> Result<(), String> {
+        let listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(|e| e.to_string())
+                    .and_then(|stream| Self::serve(stream, &metrics));
+                if let Err(e) = result {
+                    warn!(target: "rpc", "Error while serving the metrics request: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn serve(stream: TcpStream, metrics: &Metrics) -> Result<(), String> {
+        const READ_TIMEOUT: Duration = Duration::from_secs(5);
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut stream = stream;
+        let request = match RpcServer::read_request(&mut reader) {
+            Ok(request) => request,
+            Err(e) => return Self::write_response(&mut stream, "400 Bad Request", &e),
+        };
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => Self::write_response(&mut stream, "200 OK", &metrics.render()),
+            (_, "/metrics") => Self::write_response(&mut stream, "405 Method Not Allowed", ""),
+            _ => Self::write_response(&mut stream, "404 Not Found", ""),
+        }
+    }
+
+    fn write_response(stream: &mut TcpStream, status: &str, body: &str) -> Result<(), String> {
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(response.as_bytes())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,133 @@
+use crate::core::block::BlockHash;
+use crate::core::storage::{FileStorage, RecordLog, Storage};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The block found by the node's miner.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MinedBlock {
+    height: u32,
+    hash: BlockHash,
+    timestamp: u32,
+    // Time it took the miner to find the block since it started working on the template.
+    time_to_find_secs: f64,
+    // Number of nonces the miner has tried.
+    attempts: u64,
+}
+
+impl MinedBlock {
+    pub fn new(
+        height: u32,
+        hash: BlockHash,
+        timestamp: u32,
+        time_to_find_secs: f64,
+        attempts: u64,
+    ) -> Self {
+        Self {
+            height,
+            hash,
+            timestamp,
+            time_to_find_secs,
+            attempts,
+        }
+    }
+}
+
+/// Log of the blocks found by the node's miner, which provides real data about the variance
+/// of the time it takes to find a block.
+/// The log is stored as a file with one JSON record per line, which is appended to whenever
+/// a block is found.
+///
+/// Blocks that are disconnected from the active blockchain stay in the log.
+pub struct MinedBlockLog {
+    log: RecordLog<MinedBlock>,
+}
+
+impl MinedBlockLog {
+    /// Loads the log from the given file, or starts with an empty one if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Self::load_from(Arc::new(FileStorage), path)
+    }
+
+    /// Loads the log from the file in the given storage.
+    pub fn load_from(storage: Arc<dyn Storage>, path: &Path) -> Result<Self, String> {
+        Ok(Self {
+            log: RecordLog::load(storage, path, "mined blocks")?,
+        })
+    }
+
+    pub fn blocks(&self) -> &Vec<MinedBlock> {
+        self.log.records()
+    }
+
+    pub fn record(&mut self, block: MinedBlock) -> Result<(), String> {
+        self.log.record(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::storage::FaultyStorage;
+    use crate::core::Sha256;
+    use std::fs;
+
+    #[test]
+    fn appends_blocks_to_the_log() {
+        let dir = std::env::temp_dir().join(format!("mined_block_log_{}", std::process::id()));
+        let path = dir.join("mined_blocks.jsonl");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut log = MinedBlockLog::load(&path).unwrap();
+        assert!(log.blocks().is_empty());
+        let hash = BlockHash::new(Sha256::new([1; 32]));
+        log.record(MinedBlock::new(1, hash, 100, 1.5, 1000))
+            .unwrap();
+        log.record(MinedBlock::new(2, hash, 200, 0.5, 300)).unwrap();
+
+        let log = MinedBlockLog::load(&path).unwrap();
+        let heights = log
+            .blocks()
+            .iter()
+            .map(|block| (block.height, block.attempts))
+            .collect::<Vec<(u32, u64)>>();
+        assert_eq!(heights, vec![(1, 1000), (2, 300)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recovers_from_interrupted_record() {
+        let storage = Arc::new(FaultyStorage::default());
+        let path = Path::new("mined_blocks.jsonl");
+        let mut log = MinedBlockLog::load_from(storage.clone(), path).unwrap();
+        let hash = BlockHash::new(Sha256::new([1; 32]));
+        log.record(MinedBlock::new(1, hash, 100, 1.5, 1000))
+            .unwrap();
+        storage.fail_at_byte(10);
+        assert!(log.record(MinedBlock::new(2, hash, 200, 0.5, 300)).is_err());
+
+        // The torn record is dropped when the log is loaded after a crash,
+        // and overwritten if the node keeps running.
+        storage.clear_faults();
+        let heights = |log: &MinedBlockLog| {
+            log.blocks()
+                .iter()
+                .map(|block| block.height)
+                .collect::<Vec<u32>>()
+        };
+        assert_eq!(
+            heights(&MinedBlockLog::load_from(storage.clone(), path).unwrap()),
+            vec![1]
+        );
+        storage.fail_at_byte(10);
+        assert!(log.record(MinedBlock::new(2, hash, 200, 0.5, 300)).is_err());
+        storage.clear_faults();
+        log.record(MinedBlock::new(3, hash, 300, 0.5, 300)).unwrap();
+        assert_eq!(heights(&log), vec![1, 3]);
+        assert_eq!(
+            heights(&MinedBlockLog::load_from(storage, path).unwrap()),
+            vec![1, 3]
+        );
+    }
+}
@@ -1,34 +1,113 @@
-use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
-use std::sync::mpsc::{
-    Receiver, RecvTimeoutError, SendError, Sender, SyncSender, TryRecvError, TrySendError,
-};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::core::block::{BlockHash, BlockHeader};
-use crate::core::hash::{merkle_tree_from_transactions, MerkleHash};
+use crate::core::block::{BlockHash, BlockHeader, HeaderHasher};
+use crate::core::hash::{merkle_tree_from_transactions, MerkleBuilder, MerkleHash};
+use crate::core::poller::Waker;
 use crate::core::transaction::{TransactionInput, TransactionOutput};
-use crate::core::{merkle_tree, target_hash, Address, Block, Coolcoin, Sha256, Transaction};
+use crate::core::{target_hash, Address, Block, Coolcoin, Sha256, Transaction};
+use tracing::debug;
+
+// The maximum size of the miner's tag in the coinbase data, which leaves enough room for
+// the block height and the extranonce.
+pub const MAX_COINBASE_TAG_SIZE: usize = 64;
 
 pub struct Miner {}
 
+/// Divides the block reward between several addresses by percentage, e.g. to pay the
+/// operator's fee. The rest of the reward goes to the coinbase address.
+#[derive(Debug, Clone)]
+pub struct RewardSplit {
+    coinbase_address: Address,
+    // Addresses that get the given percentage of the reward.
+    payees: Vec<(Address, u32)>,
+}
+
+impl RewardSplit {
+    pub fn new(coinbase_address: Address, payees: Vec<(Address, u32)>) -> Result<Self, String> {
+        if payees.iter().any(|(_, percentage)| *percentage == 0) {
+            return Err("Reward split percentages must be positive.".to_string());
+        }
+        let total = payees.iter().try_fold(0u32, |total, (_, percentage)| {
+            total.checked_add(*percentage)
+        });
+        match total {
+            Some(total) if total <= 100 => {}
+            Some(total) => {
+                return Err(format!(
+                    "Reward split percentages add up to: {}%, which is more than 100%.",
+                    total
+                ))
+            }
+            None => return Err("Reward split percentages add up to more than 100%.".to_string()),
+        }
+        Ok(Self {
+            coinbase_address,
+            payees,
+        })
+    }
+
+    pub fn coinbase_address(&self) -> &Address {
+        &self.coinbase_address
+    }
+
+    /// Returns the outputs paying the payees, and the remainder of the reward that goes to
+    /// the coinbase address.
+    pub fn split(&self, reward: Coolcoin) -> (Vec<TransactionOutput>, Coolcoin) {
+        let mut outputs = vec![];
+        let mut remainder = reward;
+        for (payee, percentage) in &self.payees {
+            let amount = Coolcoin::new(reward.value() * *percentage as i64 / 100);
+            if amount > Coolcoin::zero() {
+                outputs.push(TransactionOutput::new(payee.clone(), amount));
+                remainder = remainder - amount;
+            }
+        }
+        (outputs, remainder)
+    }
+
+    /// Returns the coinbase outputs that divide the given reward.
+    pub fn outputs(&self, reward: Coolcoin) -> Vec<TransactionOutput> {
+        let (mut outputs, remainder) = self.split(reward);
+        if remainder > Coolcoin::zero() || outputs.is_empty() {
+            outputs.push(TransactionOutput::new(
+                self.coinbase_address.clone(),
+                remainder,
+            ));
+        }
+        outputs
+    }
+}
+
 #[derive(Debug)]
 pub struct MinerRequest {
     previous_block_hash: BlockHash,
+    // Height of the block to mine.
+    height: u32,
     transactions: Vec<Transaction>,
+    // The block reward plus the total fee paid by the transactions, paid by the coinbase.
+    reward: Coolcoin,
     difficulty_target: u32,
 }
 
 impl MinerRequest {
     pub fn new(
         previous_block_hash: BlockHash,
+        height: u32,
         transactions: Vec<Transaction>,
+        reward: Coolcoin,
         difficulty_target: u32,
     ) -> Self {
         Self {
             previous_block_hash,
+            height,
             transactions,
+            reward,
             difficulty_target,
         }
     }
@@ -37,162 +116,573 @@ impl MinerRequest {
 #[derive(Debug)]
 pub enum MinerResponse {
     None(MinerRequest),
-    Mined(Block),
+    Mined(Block, MiningStats),
+}
+
+/// How much work it took the miner to find the block.
+#[derive(Debug, Copy, Clone)]
+pub struct MiningStats {
+    // Time since the miner has started working on the request.
+    time_to_find: Duration,
+    // Number of nonces tried, including the ones for the previous timestamps.
+    attempts: u64,
+}
+
+impl MiningStats {
+    pub fn time_to_find(&self) -> Duration {
+        self.time_to_find
+    }
+
+    pub fn attempts(&self) -> u64 {
+        self.attempts
+    }
+}
+
+enum MinerCommand {
+    // Mine the block for the given request, abandoning the current one.
+    Mine(MinerRequest),
+    // Abandon the current request without mining a new one.
+    Stop,
+}
+
+/// The request that the miner is currently working on.
+struct MinerWork {
+    request: MinerRequest,
+    // Computes the merkle root when the coinbase transaction is rebuilt.
+    merkle_builder: MerkleBuilder,
+    merkle_root: MerkleHash,
+    timestamp: u32,
+    // The next nonce to try, or None if all nonces have been tried.
+    next_nonce: Option<u32>,
+    started: Instant,
+    attempts: u64,
+}
+
+impl MinerWork {
+    /// Moves the block to the given timestamp. The coinbase transaction, which is locked
+    /// at the block timestamp, is rebuilt and the nonces have to be searched again.
+    fn set_timestamp(&mut self, timestamp: u32, reward_split: &RewardSplit, coinbase_tag: &[u8]) {
+        self.request.transactions[0] = Miner::coinbase_transaction(
+            reward_split.outputs(self.request.reward),
+            timestamp,
+            Miner::coinbase_data(self.request.height, coinbase_tag, &[]),
+        );
+        self.merkle_root = self
+            .merkle_builder
+            .merkle_root(&self.request.transactions[0]);
+        self.timestamp = timestamp;
+        self.next_nonce = Some(0);
+    }
 }
 
 pub struct MinerChannel {
-    miner_requests: Sender<MinerRequest>,
+    miner_commands: Sender<MinerCommand>,
     miner_responses: Receiver<MinerResponse>,
-    num_outstanding_requests: u32,
+    // Set when a new command is sent, so that the miner abandons its current batch of nonces.
+    cancel: Arc<AtomicBool>,
 }
 
 impl MinerChannel {
+    /// Sends the new request to the miner, which replaces the one it's currently working on
+    /// (if any).
     pub fn send(&mut self, request: MinerRequest) -> Result<(), String> {
-        let result = self.miner_requests.send(request).map_err(|e| e.to_string());
-        if result.is_ok() {
-            self.num_outstanding_requests += 1;
-        }
-        result
+        self.cancel.store(true, Ordering::SeqCst);
+        self.miner_commands
+            .send(MinerCommand::Mine(request))
+            .map_err(|e| e.to_string())
+    }
+
+    /// Stops the miner from working on its current request, e.g. because it is stale.
+    pub fn stop(&mut self) -> Result<(), String> {
+        self.cancel.store(true, Ordering::SeqCst);
+        self.miner_commands
+            .send(MinerCommand::Stop)
+            .map_err(|e| e.to_string())
     }
 
     pub fn read(&mut self) -> Result<MinerResponse, TryRecvError> {
-        let result = self.miner_responses.try_recv();
-        if result.is_ok() {
-            self.num_outstanding_requests -= 1;
+        self.miner_responses.try_recv()
+    }
+}
+
+/// Limits the CPU usage of the miner by pausing between batches of nonces, based on the
+/// hash rate measured while mining the batch.
+pub struct MinerThrottle {
+    // The maximum number of hashes per second, if limited.
+    max_hashrate: Option<u64>,
+    // The fraction of time spent mining, in (0, 1].
+    duty_cycle: f64,
+}
+
+impl MinerThrottle {
+    pub fn new(max_hashrate: Option<u64>, duty_cycle: f64) -> Result<Self, String> {
+        if max_hashrate == Some(0) {
+            return Err("Maximum hash rate must be positive.".to_string());
+        }
+        if !(duty_cycle > 0.0 && duty_cycle <= 1.0) {
+            return Err(format!(
+                "Duty cycle: {} must be greater than 0 and at most 1.",
+                duty_cycle
+            ));
+        }
+        Ok(Self {
+            max_hashrate,
+            duty_cycle,
+        })
+    }
+
+    /// Returns the number of nonces to try in a batch, such that the batch takes no longer than
+    /// a fraction of a second at the maximum hash rate.
+    pub fn batch_size(&self, max_batch_size: u32) -> u32 {
+        match self.max_hashrate {
+            None => max_batch_size,
+            Some(max_hashrate) => (max_hashrate / 10).clamp(1, max_batch_size as u64) as u32,
+        }
+    }
+
+    /// Returns how long the miner should pause after computing the given number of hashes
+    /// in the given time.
+    pub fn pause(&self, hashes: u64, elapsed: Duration) -> Duration {
+        let duty_cycle_pause = elapsed.mul_f64((1.0 - self.duty_cycle) / self.duty_cycle);
+        let hashrate_pause = match self.max_hashrate {
+            None => Duration::ZERO,
+            Some(max_hashrate) => {
+                Duration::from_secs_f64(hashes as f64 / max_hashrate as f64).saturating_sub(elapsed)
+            }
+        };
+        duty_cycle_pause.max(hashrate_pause)
+    }
+}
+
+/// Measures the hash rate of the miner over a sliding window of time.
+pub struct HashrateMeter {
+    window: Duration,
+    // The number of hashes computed in each batch, by the time when the batch has finished.
+    batches: VecDeque<(Instant, u64)>,
+    // The time when the measurement has started.
+    start: Instant,
+}
+
+impl HashrateMeter {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        Self {
+            window,
+            batches: VecDeque::new(),
+            start: now,
         }
-        result
     }
 
-    pub fn num_outstanding_requests(&self) -> u32 {
-        self.num_outstanding_requests
+    pub fn record(&mut self, hashes: u64, now: Instant) {
+        self.batches.push_back((now, hashes));
+    }
+
+    /// Returns the number of hashes per second computed within the window.
+    pub fn hashrate(&mut self, now: Instant) -> u64 {
+        while let Some((time, _)) = self.batches.front() {
+            if now.saturating_duration_since(*time) <= self.window {
+                break;
+            }
+            self.batches.pop_front();
+        }
+        // The window is shorter until the miner has been running for long enough.
+        let window = self.window.min(now.saturating_duration_since(self.start));
+        if window.is_zero() {
+            return 0;
+        }
+        let hashes = self.batches.iter().map(|(_, hashes)| hashes).sum::<u64>();
+        (hashes as f64 / window.as_secs_f64()) as u64
     }
 }
 
 impl Miner {
-    pub fn start_async(coinbase_address: Address, reward: Coolcoin) -> MinerChannel {
+    /// Starts the miner in a separate thread.
+    /// The miner checks for new requests between batches of nonces, and abandons the current
+    /// batch as soon as a new request is sent, so that it promptly switches to the new request
+    /// when the old one becomes stale, e.g. when the active blockchain changes.
+    /// The block timestamp is refreshed periodically, so that a block that takes long to mine
+    /// doesn't end up with a timestamp that is too far in the past.
+    /// The coinbase tag is embedded in the coinbase data of the mined blocks.
+    /// The waker is woken up whenever there is a new response to read.
+    pub fn start_async(
+        reward_split: RewardSplit,
+        coinbase_tag: Vec<u8>,
+        waker: Waker,
+    ) -> MinerChannel {
         const TIMEOUT: Duration = Duration::from_secs(1);
-        let (miner_requests, rx) = mpsc::channel();
+        const NONCE_BATCH_SIZE: u32 = 1_000_000;
+        const TIMESTAMP_REFRESH_INTERVAL: u32 = 60;
+        let (miner_commands, rx) = mpsc::channel();
         let (tx, miner_responses) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let miner_cancel = cancel.clone();
 
-        thread::spawn(move || loop {
-            // todo!("Flush all, keep only the last request.");
-
-            match rx.recv_timeout(TIMEOUT) {
-                Ok(request) => {
-                    println!("Miner received a new request: {:#?}", request);
-                    let MinerRequest {
-                        previous_block_hash,
-                        mut transactions,
-                        difficulty_target,
-                    } = request;
-
-                    let timestamp = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as u32;
-
-                    let coinbase_transaction = Transaction::new(
-                        vec![TransactionInput::new_coinbase()],
-                        vec![TransactionOutput::new(coinbase_address.clone(), reward)],
-                        timestamp,
-                    )
-                    .unwrap();
-
-                    transactions.insert(0, coinbase_transaction);
-
-                    let merkle_root = merkle_tree_from_transactions(&transactions);
-                    let block_nonce = Self::pow(
-                        &previous_block_hash,
-                        &merkle_root,
-                        timestamp,
-                        difficulty_target,
-                    );
-                    let response = match block_nonce {
-                        None => MinerResponse::None(MinerRequest {
-                            previous_block_hash,
-                            transactions,
-                            difficulty_target,
-                        }),
-                        Some(nonce) => {
-                            let header = BlockHeader::new(
-                                previous_block_hash,
-                                merkle_root,
-                                timestamp,
-                                difficulty_target,
-                                nonce,
-                            );
-                            MinerResponse::Mined(Block::new(header, transactions))
-                        }
-                    };
-                    tx.send(response).unwrap();
+        thread::spawn(move || {
+            let cancel = miner_cancel;
+            let mut work: Option<MinerWork> = None;
+            loop {
+                // Cleared before reading the commands, so that a command sent afterwards
+                // cancels the next batch.
+                cancel.store(false, Ordering::SeqCst);
+                // Block while idle, otherwise only check whether there is a new command.
+                let mut command = match work {
+                    None => rx.recv_timeout(TIMEOUT).ok(),
+                    Some(_) => rx.try_recv().ok(),
+                };
+                // Only the most recent command matters.
+                while let Ok(newer_command) = rx.try_recv() {
+                    command = Some(newer_command);
+                }
+                match command {
+                    Some(MinerCommand::Mine(request)) => {
+                        debug!(target: "miner", "Miner received a new request: {:#?}", request);
+                        work = Some(Self::new_work(request, &reward_split, &coinbase_tag));
+                    }
+                    Some(MinerCommand::Stop) => work = None,
+                    None => {}
                 }
-                Err(_e) => {
-                    // eprintln!("{}", _e.to_string());
-                    continue;
+
+                let current = match work.as_mut() {
+                    None => continue,
+                    Some(current) => current,
+                };
+                let now = Self::current_time();
+                if now >= current.timestamp + TIMESTAMP_REFRESH_INTERVAL {
+                    current.set_timestamp(now, &reward_split, &coinbase_tag);
                 }
+                let start_nonce = current.next_nonce.unwrap();
+                let batch_size = NONCE_BATCH_SIZE.min(u32::MAX - start_nonce);
+                let (block_nonce, last_nonce) = Self::pow_batch_cancellable(
+                    &current.request.previous_block_hash,
+                    &current.merkle_root,
+                    current.timestamp,
+                    current.request.difficulty_target,
+                    start_nonce,
+                    batch_size,
+                    &cancel,
+                );
+                current.next_nonce = last_nonce.checked_add(1);
+                current.attempts += (last_nonce - start_nonce) as u64 + 1;
+                let response = match (block_nonce, current.next_nonce) {
+                    (Some(nonce), _) => {
+                        let MinerWork {
+                            request,
+                            merkle_root,
+                            timestamp,
+                            started,
+                            attempts,
+                            ..
+                        } = work.take().unwrap();
+                        let header = BlockHeader::new(
+                            request.previous_block_hash,
+                            merkle_root,
+                            timestamp,
+                            request.difficulty_target,
+                            nonce,
+                        );
+                        let stats = MiningStats {
+                            time_to_find: started.elapsed(),
+                            attempts,
+                        };
+                        MinerResponse::Mined(Block::new(header, request.transactions), stats)
+                    }
+                    (None, None) => MinerResponse::None(work.take().unwrap().request),
+                    (None, Some(_)) => continue,
+                };
+                tx.send(response).unwrap();
+                waker.wake();
             }
         });
 
         MinerChannel {
-            miner_requests,
+            miner_commands,
             miner_responses,
-            num_outstanding_requests: 0,
+            cancel,
         }
     }
 
-    pub fn pow(
-        parent_hash: &BlockHash,
-        merkle_root: &MerkleHash,
+    fn new_work(
+        request: MinerRequest,
+        reward_split: &RewardSplit,
+        coinbase_tag: &[u8],
+    ) -> MinerWork {
+        let MinerRequest {
+            previous_block_hash,
+            height,
+            mut transactions,
+            reward,
+            difficulty_target,
+        } = request;
+
+        let timestamp = Self::current_time();
+        let merkle_builder = MerkleBuilder::new(&transactions);
+        transactions.insert(
+            0,
+            Self::coinbase_transaction(
+                reward_split.outputs(reward),
+                timestamp,
+                Self::coinbase_data(height, coinbase_tag, &[]),
+            ),
+        );
+
+        let merkle_root = merkle_builder.merkle_root(&transactions[0]);
+        MinerWork {
+            request: MinerRequest {
+                previous_block_hash,
+                height,
+                transactions,
+                reward,
+                difficulty_target,
+            },
+            merkle_builder,
+            merkle_root,
+            timestamp,
+            next_nonce: Some(0),
+            started: Instant::now(),
+            attempts: 0,
+        }
+    }
+
+    /// Synchronously mines the block on top of the given block, at the same difficulty target.
+    /// The coinbase transaction pays the reward to the given address.
+    /// Meant for tests that need to extend the blockchain without running the miner thread,
+    /// so the difficulty target should be low.
+    pub fn mine_block(
+        previous_block: &Block,
+        height: u32,
+        transactions: Vec<Transaction>,
+        coinbase_address: &Address,
+        reward: Coolcoin,
         timestamp: u32,
-        difficulty_target: u32,
-    ) -> Option<u32> {
+    ) -> Block {
+        let previous_block_hash = previous_block.header().hash();
+        let difficulty_target = previous_block.header().difficulty_target();
         let target_hash = target_hash(difficulty_target);
-        let mut nonce = 0 as u32;
+        let mut timestamp = timestamp;
         loop {
-            if Self::test_nonce(
-                parent_hash,
-                merkle_root,
+            let mut block_transactions = vec![Self::coinbase_transaction(
+                vec![TransactionOutput::new(coinbase_address.clone(), reward)],
+                timestamp,
+                Self::coinbase_data(height, &[], &[]),
+            )];
+            block_transactions.extend(transactions.iter().cloned());
+            let merkle_root = merkle_tree_from_transactions(&block_transactions);
+            let mut start_nonce = 0;
+            while let Some(nonce) = Self::pow_batch(
+                &previous_block_hash,
+                &merkle_root,
                 timestamp,
                 difficulty_target,
-                nonce,
-                &target_hash,
+                start_nonce,
+                u32::MAX - start_nonce,
             ) {
-                return Some(nonce);
-            }
-
-            if nonce == u32::MAX {
-                break;
+                let header = BlockHeader::new(
+                    previous_block_hash,
+                    merkle_root.clone(),
+                    timestamp,
+                    difficulty_target,
+                    nonce,
+                );
+                // Blocks must be strictly below the target to be valid.
+                if header.hash() < target_hash {
+                    return Block::new(header, block_transactions);
+                }
+                match nonce.checked_add(1) {
+                    Some(next_nonce) => start_nonce = next_nonce,
+                    None => break,
+                }
             }
-            nonce += 1;
+            timestamp += 1;
         }
-        None
     }
 
-    fn test_nonce(
+    /// Creates the coinbase transaction for the block with the given timestamp.
+    /// The coinbase data can be used to make the coinbase, and hence the merkle root, unique,
+    /// e.g. when multiple miners work on the same template.
+    pub fn coinbase_transaction(
+        outputs: Vec<TransactionOutput>,
+        timestamp: u32,
+        coinbase_data: Vec<u8>,
+    ) -> Transaction {
+        Transaction::new(
+            vec![TransactionInput::new_coinbase_with_data(coinbase_data)],
+            outputs,
+            timestamp,
+        )
+        .unwrap()
+    }
+
+    /// Returns the coinbase data for the block at the given height: the height, which makes
+    /// the coinbase transaction unique across blocks, followed by the miner's tag and the
+    /// extranonce (if any).
+    pub fn coinbase_data(height: u32, coinbase_tag: &[u8], extranonce: &[u8]) -> Vec<u8> {
+        let mut coinbase_data = height.to_le_bytes().to_vec();
+        coinbase_data.extend_from_slice(coinbase_tag);
+        coinbase_data.extend_from_slice(extranonce);
+        coinbase_data
+    }
+
+    pub fn current_time() -> u32 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32
+    }
+
+    pub fn pow(
         parent_hash: &BlockHash,
         merkle_root: &MerkleHash,
         timestamp: u32,
         difficulty_target: u32,
-        nonce: u32,
-        target_hash: &BlockHash,
-    ) -> bool {
-        let block = BlockHeader::new(
-            parent_hash.clone(),
+    ) -> Option<u32> {
+        Self::pow_batch(
+            parent_hash,
+            merkle_root,
+            timestamp,
+            difficulty_target,
+            0,
+            u32::MAX,
+        )
+    }
+
+    /// Tries the nonces from `start_nonce` to `start_nonce + batch_size` (inclusive).
+    pub fn pow_batch(
+        parent_hash: &BlockHash,
+        merkle_root: &MerkleHash,
+        timestamp: u32,
+        difficulty_target: u32,
+        start_nonce: u32,
+        batch_size: u32,
+    ) -> Option<u32> {
+        Self::pow_share_batch(
+            parent_hash,
+            merkle_root,
+            timestamp,
+            difficulty_target,
+            difficulty_target,
+            start_nonce,
+            batch_size,
+        )
+    }
+
+    /// Same as `pow_batch`, but looks for the header of the block with the given difficulty
+    /// target, whose hash satisfies the (lower) share difficulty target.
+    pub fn pow_share_batch(
+        parent_hash: &BlockHash,
+        merkle_root: &MerkleHash,
+        timestamp: u32,
+        difficulty_target: u32,
+        share_difficulty_target: u32,
+        start_nonce: u32,
+        batch_size: u32,
+    ) -> Option<u32> {
+        let header = BlockHeader::new(
+            *parent_hash,
+            merkle_root.clone(),
+            timestamp,
+            difficulty_target,
+            0,
+        );
+        let target_hash = target_hash(share_difficulty_target);
+        Self::search_nonces(&header, &target_hash, start_nonce, batch_size, None).0
+    }
+
+    /// Same as `pow_batch`, but gives up on the batch once the cancel flag is set.
+    /// Returns the found nonce (if any) and the last nonce that has been tried.
+    pub fn pow_batch_cancellable(
+        parent_hash: &BlockHash,
+        merkle_root: &MerkleHash,
+        timestamp: u32,
+        difficulty_target: u32,
+        start_nonce: u32,
+        batch_size: u32,
+        cancel: &AtomicBool,
+    ) -> (Option<u32>, u32) {
+        let header = BlockHeader::new(
+            *parent_hash,
             merkle_root.clone(),
             timestamp,
             difficulty_target,
-            nonce,
+            0,
         );
-        match block.hash().cmp(target_hash) {
-            Ordering::Less | Ordering::Equal => true,
-            Ordering::Greater => false,
+        let target_hash = target_hash(difficulty_target);
+        Self::search_nonces(&header, &target_hash, start_nonce, batch_size, Some(cancel))
+    }
+
+    fn search_nonces(
+        header: &BlockHeader,
+        target_hash: &BlockHash,
+        start_nonce: u32,
+        batch_size: u32,
+        cancel: Option<&AtomicBool>,
+    ) -> (Option<u32>, u32) {
+        // The cancel flag is checked rarely enough not to slow down hashing.
+        const CANCEL_CHECK_INTERVAL: u32 = 4096;
+        let last_nonce = start_nonce.saturating_add(batch_size);
+        // Only the nonce changes between the attempts, so the rest of the header is
+        // hashed once.
+        let hasher = HeaderHasher::new(header);
+        let mut nonce = start_nonce;
+        loop {
+            if hasher.hash(nonce) <= *target_hash {
+                return (Some(nonce), nonce);
+            }
+
+            let is_cancelled = || cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+            if nonce == last_nonce
+                || ((nonce - start_nonce).is_multiple_of(CANCEL_CHECK_INTERVAL) && is_cancelled())
+            {
+                return (None, nonce);
+            }
+            nonce += 1;
         }
     }
+
+    /// Mines a fixed header with the given difficulty target on the given number of threads
+    /// for the given duration, and returns the average hash rate per thread, in hashes per
+    /// second.
+    pub fn benchmark(difficulty_target: u32, num_threads: usize, duration: Duration) -> u64 {
+        const NONCE_BATCH_SIZE: u32 = 100_000;
+        let start = Instant::now();
+        let total_hashes = thread::scope(|scope| {
+            let threads = (0..num_threads)
+                .map(|i| {
+                    scope.spawn(move || {
+                        // Each thread mines a different header.
+                        let parent_hash = BlockHash::new(Sha256::new([i as u8; 32]));
+                        let merkle_root = MerkleHash::new(Sha256::new([0; 32]));
+                        let mut hashes = 0u64;
+                        let mut nonce = 0;
+                        while start.elapsed() < duration {
+                            let found = Self::pow_batch(
+                                &parent_hash,
+                                &merkle_root,
+                                0,
+                                difficulty_target,
+                                nonce,
+                                NONCE_BATCH_SIZE,
+                            );
+                            let last_nonce = found.unwrap_or(nonce + NONCE_BATCH_SIZE);
+                            hashes += (last_nonce - nonce) as u64 + 1;
+                            // Wrap around long before running out of nonces.
+                            nonce = (last_nonce + 1) % (u32::MAX / 2);
+                        }
+                        hashes
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .sum::<u64>()
+        });
+        let elapsed = start.elapsed().as_secs_f64();
+        (total_hashes as f64 / elapsed / num_threads as f64) as u64
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::core::{as_hex, BlockchainManager};
+    use crate::core::{as_hex, BlockValidator, BlockchainManager};
 
     use super::*;
 
@@ -201,7 +691,7 @@ mod tests {
         let block_hash = pow_difficulty(1);
         assert_eq!(
             block_hash,
-            "00b505a7e489ca039fe9197b7e7217e03f4c3003e9418266d3c1eb2f373b276f"
+            "5c7209b093bc7b8419126f326bd8f97295016db59d68dcf4a200b99b62024f8f"
         )
     }
     #[test]
@@ -209,7 +699,7 @@ mod tests {
         let block_hash = pow_difficulty(4);
         assert_eq!(
             block_hash,
-            "00a13221f144959b8665fdab0921577255ec34df40869f2139535599094de23a"
+            "0b7841c30e33a7313e9da23419a5bb837ab8a7bf75919d7cda89e9a7c4381f27"
         )
     }
 
@@ -218,7 +708,7 @@ mod tests {
         let block_hash = pow_difficulty(8);
         assert_eq!(
             block_hash,
-            "0000a8bc60c45f850d65260794f72edad849cc878388ba7f8f5cb26ba4bce463"
+            "000ac69f173b62e51cc6263fa20c22d22107389404a28693308c851435fa59dd"
         )
     }
 
@@ -227,7 +717,7 @@ mod tests {
         let block_hash = pow_difficulty(16);
         assert_eq!(
             block_hash,
-            "000000746e4dd118ca13ecb03b47f8b35deaa4c5fa933b850e9ff8cf9b785779"
+            "00001bd990f19b5538b3e5243956079edc1da1dd6ab76ea8d424aa0b31534aaa"
         )
     }
 
@@ -236,7 +726,7 @@ mod tests {
         let block_hash = pow_difficulty(28);
         assert_eq!(
             block_hash,
-            "008a3fefacbe3cedc3f2d336d2f6d8684f440935888d3f818a1e9edd02619f36"
+            "0000000335a9f2c16ed0ed64d3fa4c6f8c613e72e2af44cae0b40ba34ffe8692"
         )
     }
 
@@ -250,7 +740,7 @@ mod tests {
         let genesis = BlockchainManager::genesis_block();
         let header = genesis.header();
 
-        let mut total_nonces = 0 as u64;
+        let mut total_nonces: u64 = 0;
         for timestamp in 0..(BLOCKS_TO_MINE as u32) {
             let nonce = Miner::pow(
                 header.previous_block_hash(),
@@ -263,15 +753,201 @@ mod tests {
         }
 
         println!("Data: {} {}", EXPECTED_TOTAL_HASHES, total_nonces);
-        let diff = if EXPECTED_TOTAL_HASHES >= total_nonces {
-            EXPECTED_TOTAL_HASHES - total_nonces
-        } else {
-            total_nonces - EXPECTED_TOTAL_HASHES
-        };
+        let diff = EXPECTED_TOTAL_HASHES.abs_diff(total_nonces);
         assert!(diff < EXPECTED_TOTAL_HASHES_ERROR);
     }
     // TODO: Probability test that roughly every 1/D hashes result in a block.
 
+    #[test]
+    fn pow_batch_searches_only_given_nonces() {
+        let genesis = BlockchainManager::genesis_block();
+        let header = genesis.header();
+        let pow_batch = |start_nonce, batch_size| {
+            Miner::pow_batch(
+                header.previous_block_hash(),
+                header.merkle_root(),
+                header.timestamp(),
+                8,
+                start_nonce,
+                batch_size,
+            )
+        };
+        let nonce = pow_batch(0, u32::MAX).unwrap();
+        assert!(nonce > 0);
+        assert_eq!(pow_batch(0, nonce - 1), None);
+        assert_eq!(pow_batch(nonce, 0), Some(nonce));
+        assert_eq!(pow_batch(1, nonce - 1), Some(nonce));
+    }
+
+    #[test]
+    fn throttle_pauses_to_limit_hashrate_and_duty_cycle() {
+        let unlimited = MinerThrottle::new(None, 1.0).unwrap();
+        assert_eq!(unlimited.batch_size(1000), 1000);
+        assert_eq!(
+            unlimited.pause(1000, Duration::from_secs(1)),
+            Duration::ZERO
+        );
+
+        let half_duty_cycle = MinerThrottle::new(None, 0.5).unwrap();
+        assert_eq!(
+            half_duty_cycle.pause(1000, Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+
+        // 1000 hashes take 1 second at 1000 hashes per second.
+        let max_hashrate = MinerThrottle::new(Some(1000), 1.0).unwrap();
+        assert_eq!(max_hashrate.batch_size(1_000_000), 100);
+        assert_eq!(
+            max_hashrate.pause(1000, Duration::from_millis(100)),
+            Duration::from_millis(900)
+        );
+        assert_eq!(
+            max_hashrate.pause(1000, Duration::from_secs(2)),
+            Duration::ZERO
+        );
+
+        assert!(MinerThrottle::new(Some(0), 1.0).is_err());
+        assert!(MinerThrottle::new(None, 0.0).is_err());
+        assert!(MinerThrottle::new(None, 1.5).is_err());
+    }
+
+    #[test]
+    fn pow_batch_matches_header_hash() {
+        let parent_hash = BlockchainManager::genesis_block().header().hash();
+        let merkle_root = MerkleHash::new(Sha256::new([7; 32]));
+        let target_hash = target_hash(4);
+        for nonce in 0..1000 {
+            let header = BlockHeader::new(parent_hash, merkle_root.clone(), 100, 4, nonce);
+            assert_eq!(
+                Miner::pow_batch(&parent_hash, &merkle_root, 100, 4, nonce, 0).is_some(),
+                header.hash() <= target_hash
+            );
+        }
+    }
+
+    #[test]
+    fn cancelled_batch_stops_early() {
+        let genesis = BlockchainManager::genesis_block();
+        let (parent_hash, merkle_root) = (genesis.header().hash(), genesis.header().merkle_root());
+        let cancel = AtomicBool::new(true);
+        let (nonce, last_nonce) =
+            Miner::pow_batch_cancellable(&parent_hash, merkle_root, 0, 64, 10, 1_000_000, &cancel);
+        assert_eq!(nonce, None);
+        assert_eq!(last_nonce, 10);
+
+        cancel.store(false, Ordering::SeqCst);
+        let (nonce, last_nonce) =
+            Miner::pow_batch_cancellable(&parent_hash, merkle_root, 0, 64, 10, 100, &cancel);
+        assert_eq!(nonce, None);
+        assert_eq!(last_nonce, 110);
+    }
+
+    #[test]
+    fn mines_valid_block_synchronously() {
+        let mut blockchain_manager = BlockchainManager::new();
+        let genesis = BlockchainManager::genesis_block();
+        let address = Address::new("miner".to_string());
+        let timestamp = genesis.header().timestamp() + 600;
+        let block = Miner::mine_block(&genesis, 1, vec![], &address, 50.into(), timestamp);
+
+        BlockValidator::validate_no_context(&block, timestamp).unwrap();
+        assert_eq!(block.header().previous_block_hash(), genesis.id());
+        assert_eq!(
+            block.transactions()[0].inputs()[0].coinbase_data(),
+            [1, 0, 0, 0]
+        );
+        blockchain_manager.new_block(Arc::new(block.clone()));
+        assert_eq!(blockchain_manager.tip(), block.id());
+
+        let next_block = Miner::mine_block(&block, 2, vec![], &address, 50.into(), timestamp);
+        blockchain_manager.new_block(Arc::new(next_block.clone()));
+        assert_eq!(blockchain_manager.tip(), next_block.id());
+    }
+
+    #[test]
+    fn benchmark_reports_hashrate_per_thread() {
+        assert!(Miner::benchmark(8, 1, Duration::from_millis(100)) > 0);
+        assert!(Miner::benchmark(32, 2, Duration::from_millis(100)) > 0);
+    }
+
+    #[test]
+    fn splits_reward_by_percentage() {
+        let address = |name: &str| Address::new(name.to_string());
+        let reward_split = RewardSplit::new(
+            address("miner"),
+            vec![(address("operator"), 2), (address("charity"), 10)],
+        )
+        .unwrap();
+        let outputs = reward_split
+            .outputs(155.into())
+            .iter()
+            .map(|output| (output.to().as_str().to_string(), output.amount()))
+            .collect::<Vec<(String, Coolcoin)>>();
+        assert_eq!(
+            outputs,
+            vec![
+                ("operator".to_string(), 3.into()),
+                ("charity".to_string(), 15.into()),
+                ("miner".to_string(), 137.into())
+            ]
+        );
+
+        assert!(RewardSplit::new(address("miner"), vec![(address("operator"), 101)]).is_err());
+        assert!(RewardSplit::new(address("miner"), vec![(address("operator"), 0)]).is_err());
+        // The percentages that would wrap around to 4% when added up.
+        assert!(RewardSplit::new(
+            address("miner"),
+            vec![(address("operator"), u32::MAX), (address("pool"), 5)]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn measures_hashrate_over_sliding_window() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut meter = HashrateMeter::new(Duration::from_secs(10), start);
+        assert_eq!(meter.hashrate(start), 0);
+
+        meter.record(1000, at(1));
+        meter.record(1000, at(2));
+        assert_eq!(meter.hashrate(at(2)), 1000);
+        meter.record(1000, at(10));
+        assert_eq!(meter.hashrate(at(10)), 300);
+        // The first batch is out of the window.
+        assert_eq!(meter.hashrate(at(12)), 200);
+        assert_eq!(meter.hashrate(at(30)), 0);
+    }
+
+    #[test]
+    fn refreshing_timestamp_rederives_header() {
+        let genesis = BlockchainManager::genesis_block();
+        let reward_split = RewardSplit::new(Address::new("miner".to_string()), vec![]).unwrap();
+        let request = MinerRequest::new(*genesis.id(), 1, vec![], 55.into(), 8);
+        let mut work = Miner::new_work(request, &reward_split, b"tag");
+        work.next_nonce = None;
+
+        let timestamp = work.timestamp + 60;
+        work.set_timestamp(timestamp, &reward_split, b"tag");
+        assert_eq!(work.timestamp, timestamp);
+        assert_eq!(work.next_nonce, Some(0));
+        assert_eq!(work.request.transactions.len(), 1);
+        assert_eq!(work.request.transactions[0].locktime(), timestamp);
+        // The coinbase pays the reward, including the fees.
+        assert_eq!(
+            work.request.transactions[0].outputs()[0].amount(),
+            55.into()
+        );
+        assert_eq!(
+            work.request.transactions[0].inputs()[0].coinbase_data(),
+            [1, 0, 0, 0, b't', b'a', b'g']
+        );
+        assert_eq!(
+            work.merkle_root.raw(),
+            merkle_tree_from_transactions(&work.request.transactions).raw()
+        );
+    }
+
     fn pow_difficulty(difficulty: u32) -> String {
         // Use genesis block to avoid manually constructing transactions and other data.
         // Then override data we care about, i.e. difficulty.
@@ -281,12 +957,14 @@ mod tests {
         let timestamp = genesis.header().timestamp();
         let pow_nonce = Miner::pow(parent_hash, merkle_root, timestamp, difficulty).unwrap();
         let pow_block = BlockHeader::new(
-            parent_hash.clone(),
+            *parent_hash,
             merkle_root.clone(),
             timestamp,
             difficulty,
             pow_nonce,
         );
+        // Each expected hash must also meet its difficulty, which the stale vectors didn't.
+        assert!(pow_block.hash() <= target_hash(difficulty));
         as_hex(pow_block.hash().as_slice())
     }
 }
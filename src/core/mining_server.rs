@@ -0,0 +1,592 @@
+use crate::core::block::{BlockHash, BlockHeader};
+use crate::core::coolcoin_network::NetworkParams;
+use crate::core::hash::{MerkleBuilder, MerkleHash};
+use crate::core::miner::{Miner, RewardSplit};
+use crate::core::peer_connection::PeerMessage;
+use crate::core::transaction::{TransactionInput, TransactionOutput};
+use crate::core::{
+    target_hash, Address, Block, Coolcoin, CoolcoinNetwork, PayoutLedger, Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::os::unix::io::RawFd;
+use tracing::{error, info, warn};
+
+/// The block header that the miner should find the nonce for.
+/// The merkle root commits to the coinbase transaction with the miner's extranonce,
+/// so miners that work on the same job never search the same headers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MiningJob {
+    job_id: u64,
+    previous_block_hash: BlockHash,
+    merkle_root: MerkleHash,
+    timestamp: u32,
+    difficulty_target: u32,
+    // The difficulty target that the submitted shares must satisfy, which is lower than
+    // the difficulty target of the block in the pool mode.
+    share_difficulty_target: u32,
+}
+
+impl MiningJob {
+    pub fn job_id(&self) -> u64 {
+        self.job_id
+    }
+
+    pub fn previous_block_hash(&self) -> &BlockHash {
+        &self.previous_block_hash
+    }
+
+    pub fn merkle_root(&self) -> &MerkleHash {
+        &self.merkle_root
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    pub fn difficulty_target(&self) -> u32 {
+        self.difficulty_target
+    }
+
+    pub fn share_difficulty_target(&self) -> u32 {
+        self.share_difficulty_target
+    }
+}
+
+/// The solution for the job, submitted by the miner.
+/// Miners may change the timestamp of the job, e.g. when they run out of nonces.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct MiningShare {
+    job_id: u64,
+    timestamp: u32,
+    nonce: u32,
+}
+
+impl MiningShare {
+    pub fn new(job_id: u64, timestamp: u32, nonce: u32) -> Self {
+        Self {
+            job_id,
+            timestamp,
+            nonce,
+        }
+    }
+}
+
+/// The outcome of the submitted share, which lets miners distinguish stale work from
+/// invalid solutions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubmitResult {
+    // The share has been accepted, but it doesn't satisfy the block's difficulty target.
+    ShareAccepted,
+    // The share is a block that has been accepted by the node.
+    Accepted,
+    // The share or the block has already been submitted.
+    Duplicate,
+    // The job is unknown, or the block no longer builds on the tip of the active blockchain.
+    Stale,
+    Invalid(String),
+}
+
+/// A miner connected to the mining server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MinerInfo {
+    // Network address of the miner.
+    address: String,
+    extranonce: u32,
+    payout_address: Option<Address>,
+    // The most recent hash rate reported by the miner, in hashes per second.
+    hashrate: u64,
+}
+
+impl MinerInfo {
+    pub fn hashrate(&self) -> u64 {
+        self.hashrate
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MiningInfo {
+    // Height of the active blockchain.
+    blocks: u32,
+    difficulty_target: u32,
+    // Number of transactions in the block template, if any.
+    current_block_transactions: Option<usize>,
+    miners: Vec<MinerInfo>,
+    // Aggregate hash rate reported by the miners, in hashes per second.
+    hashrate: u64,
+}
+
+impl MiningInfo {
+    pub fn new(
+        blocks: u32,
+        difficulty_target: u32,
+        current_block_transactions: Option<usize>,
+        miners: Vec<MinerInfo>,
+    ) -> Self {
+        let hashrate = miners.iter().map(|miner| miner.hashrate).sum();
+        Self {
+            blocks,
+            difficulty_target,
+            current_block_transactions,
+            miners,
+            hashrate,
+        }
+    }
+}
+
+pub struct MiningServerParams {
+    network_params: NetworkParams,
+    // The difficulty target of the shares, if the server runs in the pool mode.
+    pool_share_difficulty_target: Option<u32>,
+}
+
+impl MiningServerParams {
+    pub fn new(network_params: NetworkParams, pool_share_difficulty_target: Option<u32>) -> Self {
+        Self {
+            network_params,
+            pool_share_difficulty_target,
+        }
+    }
+}
+
+struct MiningPool {
+    share_difficulty_target: u32,
+    ledger: PayoutLedger,
+}
+
+/// The block template from which the jobs are derived, one per miner.
+struct JobTemplate {
+    previous_block_hash: BlockHash,
+    // Transactions without the coinbase transaction.
+    transactions: Vec<Transaction>,
+    // Computes the merkle root for each miner's coinbase transaction.
+    merkle_builder: MerkleBuilder,
+    difficulty_target: u32,
+    share_difficulty_target: u32,
+    coinbase_outputs: Vec<TransactionOutput>,
+    // The coinbase data with the block height and the coinbase tag, followed by the miner's
+    // extranonce.
+    coinbase_data: Vec<u8>,
+    // The pool shares that the coinbase outputs pay for.
+    paid_shares: HashMap<Address, u64>,
+    // The timestamp at which the coinbase transaction is locked.
+    timestamp: u32,
+    // Shares that have been submitted for this job, by extranonce, timestamp and nonce.
+    submitted_shares: HashSet<(u32, u32, u32)>,
+}
+
+impl JobTemplate {
+    fn coinbase_transaction_for(&self, extranonce: u32) -> Transaction {
+        let mut coinbase_data = self.coinbase_data.clone();
+        coinbase_data.extend_from_slice(&extranonce.to_le_bytes());
+        Transaction::new(
+            vec![TransactionInput::new_coinbase_with_data(coinbase_data)],
+            self.coinbase_outputs.clone(),
+            self.timestamp,
+        )
+        .unwrap()
+    }
+
+    fn job_for(&self, job_id: u64, extranonce: u32) -> MiningJob {
+        MiningJob {
+            job_id,
+            previous_block_hash: self.previous_block_hash,
+            merkle_root: self
+                .merkle_builder
+                .merkle_root(&self.coinbase_transaction_for(extranonce)),
+            timestamp: self.timestamp,
+            difficulty_target: self.difficulty_target,
+            share_difficulty_target: self.share_difficulty_target,
+        }
+    }
+
+    /// Checks the share submitted by the miner with the given extranonce, and returns the block
+    /// if the share also satisfies the difficulty target of the block.
+    fn check_share(
+        &mut self,
+        extranonce: u32,
+        share: &MiningShare,
+    ) -> Result<Option<Block>, SubmitResult> {
+        let coinbase_transaction = self.coinbase_transaction_for(extranonce);
+        let header = BlockHeader::new(
+            self.previous_block_hash,
+            self.merkle_builder.merkle_root(&coinbase_transaction),
+            share.timestamp,
+            self.difficulty_target,
+            share.nonce,
+        );
+        let hash = header.hash();
+        if hash > target_hash(self.share_difficulty_target) {
+            return Err(SubmitResult::Invalid(format!(
+                "Share: {:?} doesn't satisfy the difficulty target: {}",
+                share, self.share_difficulty_target
+            )));
+        }
+        if !self
+            .submitted_shares
+            .insert((extranonce, share.timestamp, share.nonce))
+        {
+            return Err(SubmitResult::Duplicate);
+        }
+        match hash > target_hash(self.difficulty_target) {
+            true => Ok(None),
+            false => {
+                let mut transactions = vec![coinbase_transaction];
+                transactions.extend(self.transactions.iter().cloned());
+                Ok(Some(Block::new(header, transactions)))
+            }
+        }
+    }
+}
+
+/// Serves mining jobs to external miner processes over a dedicated port.
+///
+/// The protocol works as follows:
+///   - The miner subscribes and the server assigns it a unique extranonce, which goes into
+///     the coinbase data of its blocks.
+///   - The server notifies the subscribed miners whenever there is a new block template,
+///     or when they should stop mining.
+///   - The miner submits the nonce (and timestamp) for the job, and the server responds
+///     whether the share or the block has been accepted.
+///
+/// In the pool mode, the miners subscribe with their payout address, and submit shares that
+/// satisfy a lower difficulty target than the block. The coinbase transaction splits
+/// the block reward between the miners proportionally to the shares they have submitted
+/// since the previous block found by the pool.
+pub struct MiningServer {
+    network: CoolcoinNetwork,
+    reward_split: RewardSplit,
+    coinbase_tag: Vec<u8>,
+    pool: Option<MiningPool>,
+    // Subscribed miners, by their network address.
+    miners: HashMap<String, MinerInfo>,
+    next_extranonce: u32,
+    // Jobs that build on the current tip. Older jobs are discarded when the tip changes.
+    jobs: HashMap<u64, JobTemplate>,
+    current_job_id: Option<u64>,
+    next_job_id: u64,
+}
+
+impl MiningServer {
+    pub fn bind(
+        params: &MiningServerParams,
+        reward_split: RewardSplit,
+        coinbase_tag: Vec<u8>,
+        ledger: PayoutLedger,
+    ) -> Result<Self, String> {
+        let pool = params
+            .pool_share_difficulty_target
+            .map(|share_difficulty_target| MiningPool {
+                share_difficulty_target,
+                ledger,
+            });
+        Ok(Self {
+            network: CoolcoinNetwork::connect(&params.network_params)?,
+            reward_split,
+            coinbase_tag,
+            pool,
+            miners: HashMap::new(),
+            // The node's own miner uses no extranonce.
+            next_extranonce: 1,
+            jobs: HashMap::new(),
+            current_job_id: None,
+            next_job_id: 0,
+        })
+    }
+
+    /// The sockets that become readable when there is a new miner or a new message.
+    pub fn raw_fds(&self) -> Vec<RawFd> {
+        self.network.raw_fds()
+    }
+
+    /// The sockets of the miners with messages waiting to be written.
+    pub fn pending_send_fds(&self) -> Vec<RawFd> {
+        self.network.pending_send_fds()
+    }
+
+    pub fn is_pool(&self) -> bool {
+        self.pool.is_some()
+    }
+
+    pub fn miners(&self) -> Vec<MinerInfo> {
+        let mut miners = self.miners.values().cloned().collect::<Vec<MinerInfo>>();
+        miners.sort_by_key(|miner| miner.extranonce);
+        miners
+    }
+
+    /// Sends the job for the new block template to all subscribed miners.
+    pub fn new_job(
+        &mut self,
+        previous_block_hash: BlockHash,
+        height: u32,
+        transactions: Vec<Transaction>,
+        difficulty_target: u32,
+        reward: Coolcoin,
+        timestamp: u32,
+    ) {
+        self.jobs
+            .retain(|_, job| job.previous_block_hash == previous_block_hash);
+        let (share_difficulty_target, paid_shares) = match &self.pool {
+            None => (difficulty_target, HashMap::new()),
+            Some(pool) => (
+                pool.share_difficulty_target.min(difficulty_target),
+                pool.ledger.shares().clone(),
+            ),
+        };
+        // The payees of the reward split are paid first, and the rest of the reward is split
+        // between the pool miners.
+        let (mut coinbase_outputs, remainder) = self.reward_split.split(reward);
+        coinbase_outputs.extend(PayoutLedger::payouts(
+            &paid_shares,
+            remainder,
+            self.reward_split.coinbase_address(),
+        ));
+        let job_id = self.next_job_id;
+        self.next_job_id += 1;
+        self.jobs.insert(
+            job_id,
+            JobTemplate {
+                previous_block_hash,
+                merkle_builder: MerkleBuilder::new(&transactions),
+                transactions,
+                difficulty_target,
+                share_difficulty_target,
+                coinbase_outputs,
+                coinbase_data: Miner::coinbase_data(height, &self.coinbase_tag, &[]),
+                paid_shares,
+                timestamp,
+                submitted_shares: HashSet::new(),
+            },
+        );
+        self.current_job_id = Some(job_id);
+        for miner in self.miners.keys().cloned().collect::<Vec<String>>() {
+            self.notify(&miner);
+        }
+    }
+
+    /// Tells all subscribed miners to stop mining, e.g. because there is nothing to mine.
+    pub fn stop(&mut self) {
+        self.jobs.clear();
+        self.current_job_id = None;
+        for miner in self.miners.keys().cloned().collect::<Vec<String>>() {
+            self.notify(&miner);
+        }
+    }
+
+    /// Stops the miners and saves the payout ledger, so the shares of the current round
+    /// are not lost.
+    pub fn shutdown(&mut self) -> Result<(), String> {
+        self.stop();
+        match &self.pool {
+            Some(pool) => pool.ledger.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Accepts new miners and processes their messages.
+    /// Returns the blocks submitted by the miners, along with the ids of their jobs.
+    /// The caller is expected to report the result of processing each block via
+    /// `on_block_processed`.
+    pub fn receive_blocks(&mut self) -> Vec<(String, u64, Block)> {
+        if let Err(e) = self.network.accept_new_peers() {
+            error!(target: "miner", "Error while accepting new miners: {}", e);
+        }
+        let mut blocks = vec![];
+        for (sender, message) in self.network.receive_all() {
+            match message {
+                PeerMessage::MiningSubscribe(payout_address) => {
+                    self.on_subscribe(&sender, payout_address)
+                }
+                PeerMessage::MiningSubmit(share) => match self.on_submit(&sender, &share) {
+                    Ok(Some(block)) => blocks.push((sender, share.job_id, block)),
+                    Ok(None) => self.send_to(
+                        &sender,
+                        PeerMessage::ResponseMiningSubmit(SubmitResult::ShareAccepted),
+                    ),
+                    Err(result) => self.send_to(&sender, PeerMessage::ResponseMiningSubmit(result)),
+                },
+                PeerMessage::MiningReportHashrate(hashrate) => {
+                    if let Some(miner) = self.miners.get_mut(&sender) {
+                        miner.hashrate = hashrate;
+                    }
+                }
+                message => warn!(target: "miner",
+                    "Unexpected message on the mining server from: {}: {:?}",
+                    sender, message
+                ),
+            }
+        }
+        // Forget the miners whose connections have been lost.
+        let network = &self.network;
+        self.miners
+            .retain(|address, _| network.is_connected(address));
+        blocks
+    }
+
+    /// Pays out the pool shares if the block has been accepted, and responds to the miner.
+    pub fn on_block_processed(&mut self, miner: &str, job_id: u64, result: SubmitResult) {
+        if let (SubmitResult::Accepted, Some(pool), Some(job)) =
+            (&result, self.pool.as_mut(), self.jobs.get(&job_id))
+        {
+            pool.ledger
+                .complete_round(&job.paid_shares, &job.coinbase_outputs);
+            if let Err(e) = pool.ledger.save() {
+                error!(target: "miner", "Error while saving the payout ledger: {}", e);
+            }
+        }
+        self.send_to(miner, PeerMessage::ResponseMiningSubmit(result));
+    }
+
+    fn on_subscribe(&mut self, miner: &str, payout_address: Option<Address>) {
+        if self.is_pool() && payout_address.is_none() {
+            warn!(target: "miner",
+                "Miner: {} has subscribed without the payout address.",
+                miner
+            );
+            self.send_to(
+                miner,
+                PeerMessage::ResponseMiningSubscribe(Err(
+                    "The payout address is required by the mining pool.".to_string(),
+                )),
+            );
+            return;
+        }
+        let extranonce = self.next_extranonce;
+        self.next_extranonce += 1;
+        self.miners.insert(
+            miner.to_string(),
+            MinerInfo {
+                address: miner.to_string(),
+                extranonce,
+                payout_address,
+                hashrate: 0,
+            },
+        );
+        info!(target: "miner",
+            "Miner: {} has subscribed with extranonce: {}",
+            miner, extranonce
+        );
+        self.send_to(miner, PeerMessage::ResponseMiningSubscribe(Ok(extranonce)));
+        self.notify(miner);
+    }
+
+    fn on_submit(
+        &mut self,
+        miner: &str,
+        share: &MiningShare,
+    ) -> Result<Option<Block>, SubmitResult> {
+        let miner = self
+            .miners
+            .get(miner)
+            .ok_or_else(|| SubmitResult::Invalid(format!("Miner: {} hasn't subscribed.", miner)))?;
+        // Jobs that don't build on the tip are forgotten as soon as the tip changes.
+        let job = self
+            .jobs
+            .get_mut(&share.job_id)
+            .ok_or(SubmitResult::Stale)?;
+        let block = job.check_share(miner.extranonce, share)?;
+        if let (Some(pool), Some(payout_address)) = (self.pool.as_mut(), &miner.payout_address) {
+            pool.ledger.add_share(payout_address);
+        }
+        Ok(block)
+    }
+
+    fn notify(&mut self, miner: &str) {
+        let extranonce = match self.miners.get(miner) {
+            None => return,
+            Some(miner) => miner.extranonce,
+        };
+        let job = self
+            .current_job_id
+            .map(|job_id| self.jobs.get(&job_id).unwrap().job_for(job_id, extranonce));
+        self.send_to(miner, PeerMessage::MiningNotify(job));
+    }
+
+    fn send_to(&mut self, miner: &str, message: PeerMessage) {
+        if let Err(e) = self.network.send_to(miner, message) {
+            warn!(target: "miner", "Dropping miner: {}: {}", miner, e);
+            self.miners.remove(miner);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::BlockchainManager;
+
+    fn template(difficulty_target: u32, share_difficulty_target: u32) -> JobTemplate {
+        let genesis = BlockchainManager::genesis_block();
+        let address = Address::new("pool".to_string());
+        JobTemplate {
+            previous_block_hash: *genesis.id(),
+            transactions: vec![],
+            merkle_builder: MerkleBuilder::new(&[]),
+            difficulty_target,
+            share_difficulty_target,
+            coinbase_outputs: vec![TransactionOutput::new(address, 50.into())],
+            coinbase_data: Miner::coinbase_data(1, b"tag", &[]),
+            paid_shares: HashMap::new(),
+            timestamp: genesis.header().timestamp() + 1,
+            submitted_shares: HashSet::new(),
+        }
+    }
+
+    fn find_nonce(job: &MiningJob, share_difficulty_target: u32, start_nonce: u32) -> u32 {
+        Miner::pow_share_batch(
+            job.previous_block_hash(),
+            job.merkle_root(),
+            job.timestamp(),
+            job.difficulty_target(),
+            share_difficulty_target,
+            start_nonce,
+            u32::MAX - start_nonce,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn accepts_only_shares_that_satisfy_difficulty() {
+        let mut template = template(8, 8);
+
+        // Miners with different extranonces search different headers.
+        let job = template.job_for(0, 1);
+        assert_ne!(
+            job.merkle_root().raw(),
+            template.job_for(0, 2).merkle_root().raw()
+        );
+
+        let nonce = find_nonce(&job, 8, 0);
+        let share = MiningShare::new(0, job.timestamp(), nonce);
+        let block = template.check_share(1, &share).unwrap().unwrap();
+        assert_eq!(block.header().nonce(), nonce);
+        assert_eq!(
+            block.transactions()[0].inputs()[0].coinbase_data(),
+            [1, 0, 0, 0, b't', b'a', b'g', 1, 0, 0, 0]
+        );
+
+        let share = MiningShare::new(0, job.timestamp(), nonce.wrapping_add(1));
+        assert!(matches!(
+            template.check_share(1, &share),
+            Err(SubmitResult::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn accepts_each_share_once() {
+        let mut template = template(16, 4);
+        let job = template.job_for(0, 1);
+
+        // Find a share that doesn't satisfy the block difficulty.
+        let mut nonce = find_nonce(&job, 4, 0);
+        while find_nonce(&job, 16, nonce) == nonce {
+            nonce = find_nonce(&job, 4, nonce + 1);
+        }
+        let share = MiningShare::new(0, job.timestamp(), nonce);
+        assert!(template.check_share(1, &share).unwrap().is_none());
+        assert!(matches!(
+            template.check_share(1, &share),
+            Err(SubmitResult::Duplicate)
+        ));
+    }
+}
@@ -1,26 +1,69 @@
+pub mod account_balances;
 pub mod address;
+pub mod address_history;
+pub mod ban_list;
 pub mod block;
 pub mod blockchain_manager;
 pub mod blocktree;
+pub mod chain_builder;
+pub mod chain_export;
+pub mod chain_params;
 pub mod coolcoin;
+pub mod coolcoin_client;
 pub mod coolcoin_network;
 pub mod coolcoin_node;
+pub mod explorer_server;
+pub mod fee_estimator;
+pub mod fee_rate;
+pub mod fork_log;
+pub mod graphviz;
 pub mod hash;
+pub mod hash_display;
+pub mod json_rpc;
+pub mod log_filter;
+pub mod message_capture;
+pub mod metrics;
+pub mod metrics_server;
+pub mod mined_block_log;
 pub mod miner;
+pub mod mining_server;
+pub mod node_event;
 pub mod orphaned_blocks;
 pub mod orphaned_transaction_pool;
+pub mod payout_ledger;
 pub mod peer_connection;
+pub mod peer_log;
+pub mod pid_file;
+pub mod poller;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod recently_seen;
+pub mod rpc_server;
+pub mod serialize;
+pub mod storage;
+#[cfg(test)]
+mod test_vectors;
+pub mod testkit;
+pub mod timers;
 pub mod transaction;
 pub mod transaction_pool;
+pub mod transaction_rebroadcaster;
 pub mod utxo_pool;
 pub mod validation;
+pub mod wallet;
+pub mod websocket_server;
 
 pub use self::{
     address::Address, block::Block, blockchain_manager::BlockchainManager, blocktree::BlockTree,
-    coolcoin::Coolcoin, coolcoin_network::CoolcoinNetwork, coolcoin_node::CoolcoinNode,
-    hash::as_hex, hash::merkle_tree, hash::target_hash, hash::Sha256,
+    coolcoin::Coolcoin, coolcoin_client::CoolcoinClient, coolcoin_network::CoolcoinNetwork,
+    coolcoin_node::CoolcoinNode, explorer_server::ExplorerServer, fee_estimator::FeeEstimator,
+    fee_rate::FeeRate, hash::as_hex, hash::merkle_tree, hash::target_hash, hash::Sha256,
+    metrics_server::MetricsServer, mined_block_log::MinedBlockLog, mining_server::MiningServer,
     orphaned_blocks::OrphanedBlocks, orphaned_transaction_pool::OrphanedTransactionPool,
-    peer_connection::PeerConnection, transaction::Transaction, transaction_pool::TransactionPool,
-    utxo_pool::UtxoPool, validation::BlockValidator, validation::ChainContext,
-    validation::UtxoContext,
+    payout_ledger::PayoutLedger, peer_connection::PeerConnection, rpc_server::RpcServer,
+    transaction::Transaction, transaction_pool::TransactionPool,
+    transaction_rebroadcaster::TransactionRebroadcaster, utxo_pool::UtxoPool,
+    validation::BlockValidator, validation::ChainContext, validation::SpentOutput,
+    validation::TransactionPoolContext, validation::TransactionValidator, validation::UtxoContext,
+    websocket_server::WebSocketServer,
 };
@@ -0,0 +1,126 @@
+use crate::core::ban_list::Subnet;
+use crate::core::block::BlockHash;
+use crate::core::transaction::TransactionId;
+use crate::core::transaction_pool::RemovalReason;
+use crate::core::Transaction;
+use serde::Serialize;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Changes of the node's state, published to all subscribers, e.g. the WebSocket server or
+/// the metrics, so that they don't need to poll the node, nor to be called from its main loop.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum NodeEvent {
+    // The block has been connected to the active blockchain at the given height.
+    BlockConnected {
+        hash: BlockHash,
+        height: u32,
+    },
+    // The block at the given height has been disconnected from the active blockchain by
+    // the reorg, which is published before the disconnected blocks.
+    BlockDisconnected {
+        hash: BlockHash,
+        height: u32,
+    },
+    // The transaction has been accepted to the transaction pool.
+    TransactionAccepted {
+        transaction: Transaction,
+    },
+    // The transaction has been removed from the transaction pool without being confirmed.
+    TransactionEvicted {
+        transaction_id: TransactionId,
+        reason: RemovalReason,
+    },
+    // The active blockchain has switched to another branch.
+    // Blocks are ordered from the tips towards the fork, and are followed by the
+    // `BlockDisconnected` and `BlockConnected` events for each of them.
+    Reorg {
+        old_tip: BlockHash,
+        new_tip: BlockHash,
+        disconnected: Vec<BlockHash>,
+        connected: Vec<BlockHash>,
+    },
+    // The peer or the client has connected to the node.
+    PeerConnected {
+        address: String,
+    },
+    // The subnet has been banned until the given time, and its peers are disconnected.
+    PeerBanned {
+        subnet: Subnet,
+        until: u32,
+    },
+}
+
+impl NodeEvent {
+    /// The name of the event, to which the clients subscribe.
+    pub fn topic(&self) -> &'static str {
+        match self {
+            NodeEvent::BlockConnected { .. } => "newBlock",
+            NodeEvent::BlockDisconnected { .. } => "blockDisconnected",
+            NodeEvent::TransactionAccepted { .. } => "newTransaction",
+            NodeEvent::TransactionEvicted { .. } => "transactionEvicted",
+            NodeEvent::Reorg { .. } => "reorg",
+            NodeEvent::PeerConnected { .. } => "peerConnected",
+            NodeEvent::PeerBanned { .. } => "peerBanned",
+        }
+    }
+}
+
+/// Delivers the node events to all subscribers, in the order in which they're published.
+/// The clones share the subscribers, so that the events can be subscribed to from other
+/// threads, even once the node is running.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Sender<NodeEvent>>>>,
+}
+
+impl EventBus {
+    /// Returns the receiver of all events published from now on.
+    pub fn subscribe(&self) -> Receiver<NodeEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: NodeEvent) {
+        // Subscribers that have dropped their receiver are no longer interested.
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivers_events_to_current_subscribers() {
+        let bus = EventBus::default();
+        let early = bus.subscribe();
+        let dropped = bus.subscribe();
+        drop(dropped);
+        bus.publish(NodeEvent::PeerConnected {
+            address: "127.0.0.1:8333".to_string(),
+        });
+        let late = bus.clone().subscribe();
+        bus.publish(NodeEvent::PeerConnected {
+            address: "127.0.0.1:8334".to_string(),
+        });
+
+        let addresses = |receiver: &Receiver<NodeEvent>| {
+            receiver
+                .try_iter()
+                .map(|event| match event {
+                    NodeEvent::PeerConnected { address } => address,
+                    unexpected => panic!("Unexpected event: {:?}", unexpected),
+                })
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(addresses(&early), vec!["127.0.0.1:8333", "127.0.0.1:8334"]);
+        assert_eq!(addresses(&late), vec!["127.0.0.1:8334"]);
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 2);
+    }
+}
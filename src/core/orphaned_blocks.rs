@@ -2,13 +2,15 @@ use crate::core::block::BlockHash;
 use crate::core::Block;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 // Blocks without a parent in the network.
 // E.g. this may happen when two blocks are mined quickly one after the other,
 // and the child arrives before the parent.
+#[derive(Default)]
 pub struct OrphanedBlocks {
     // Orphaned blocks indexed by their parent hash.
-    orphaned_blocks: HashMap<BlockHash, Vec<Block>>,
+    orphaned_blocks: HashMap<BlockHash, Vec<Arc<Block>>>,
 }
 
 impl OrphanedBlocks {
@@ -18,9 +20,9 @@ impl OrphanedBlocks {
         }
     }
 
-    pub fn all(&self) -> Vec<Block> {
+    pub fn all(&self) -> Vec<Arc<Block>> {
         let mut all_blocks = vec![];
-        for (_, blocks) in &self.orphaned_blocks {
+        for blocks in self.orphaned_blocks.values() {
             for block in blocks {
                 all_blocks.push(block.clone());
             }
@@ -30,7 +32,7 @@ impl OrphanedBlocks {
 
     /// Inserts the block.
     /// If the block with the same hash already exists, this function has no effect.
-    pub fn insert(&mut self, block: Block) {
+    pub fn insert(&mut self, block: Arc<Block>) {
         match self
             .orphaned_blocks
             .entry(*block.header().previous_block_hash())
@@ -56,9 +58,7 @@ impl OrphanedBlocks {
     }
 
     /// Removes all children for the given parent hash.
-    pub fn remove(&mut self, parent_hash: &BlockHash) -> Vec<Block> {
-        self.orphaned_blocks
-            .remove(parent_hash)
-            .unwrap_or_else(|| vec![])
+    pub fn remove(&mut self, parent_hash: &BlockHash) -> Vec<Arc<Block>> {
+        self.orphaned_blocks.remove(parent_hash).unwrap_or_default()
     }
 }
@@ -1 +1,295 @@
-pub struct OrphanedTransactionPool {}
+use crate::core::transaction::TransactionId;
+use crate::core::Transaction;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+struct OrphanedTransaction {
+    transaction: Transaction,
+    // The peer from which the transaction has been received.
+    sender: String,
+    // Transactions whose outputs are spent by this transaction, but are unknown to the node.
+    missing_parents: HashSet<TransactionId>,
+    // The orphan is dropped after this time if its parents haven't arrived.
+    expires_at: Instant,
+}
+
+/// Transactions that spend outputs of transactions unknown to the node.
+/// E.g. this may happen when the child transaction is relayed to the node before its parent.
+/// Orphaned transactions are kept until their parents arrive, either through relay or in a
+/// new block.
+pub struct OrphanedTransactionPool {
+    orphans: HashMap<TransactionId, OrphanedTransaction>,
+    // Ids of orphaned transactions indexed by the ids of their missing parents.
+    orphans_by_missing_parent: HashMap<TransactionId, HashSet<TransactionId>>,
+    // Number of orphaned transactions received from each peer's IP address.
+    orphans_per_peer: HashMap<String, usize>,
+    // The maximum number of orphaned transactions that a single peer can have in the pool,
+    // which prevents peers from flooding the node with transactions that can't be validated.
+    // Peers are counted by their IP address, so reconnecting from another port doesn't help.
+    max_orphans_per_peer: usize,
+    // The maximum number of orphaned transactions in the pool, across all peers.
+    // When the pool is full, a random orphan is evicted to make room for the new one.
+    max_orphans: usize,
+    // How long an orphan is kept while waiting for its parents.
+    expiry: Duration,
+}
+
+impl OrphanedTransactionPool {
+    pub fn new(max_orphans_per_peer: usize, max_orphans: usize, expiry: Duration) -> Self {
+        Self {
+            orphans: HashMap::new(),
+            orphans_by_missing_parent: HashMap::new(),
+            orphans_per_peer: HashMap::new(),
+            max_orphans_per_peer,
+            max_orphans,
+            expiry,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    pub fn contains(&self, transaction_id: &TransactionId) -> bool {
+        self.orphans.contains_key(transaction_id)
+    }
+
+    /// Inserts the orphaned transaction received from the given peer.
+    /// Fails if the peer has reached its limit of orphaned transactions.
+    pub fn insert(
+        &mut self,
+        transaction: Transaction,
+        sender: &str,
+        missing_parents: HashSet<TransactionId>,
+        now: Instant,
+    ) -> Result<(), String> {
+        if self.contains(transaction.id()) {
+            return Ok(());
+        }
+        let peer = Self::peer_ip(sender);
+        let num_orphans = self.orphans_per_peer.get(&peer).copied().unwrap_or(0);
+        if num_orphans >= self.max_orphans_per_peer {
+            return Err(format!(
+                "Peer: {} has too many orphaned transactions: {}. Dropping transaction: {}",
+                sender,
+                num_orphans,
+                transaction.id()
+            ));
+        }
+        if self.orphans.len() >= self.max_orphans {
+            self.evict_random()?;
+        }
+
+        for parent in &missing_parents {
+            self.orphans_by_missing_parent
+                .entry(*parent)
+                .or_default()
+                .insert(*transaction.id());
+        }
+        *self.orphans_per_peer.entry(peer).or_insert(0) += 1;
+        self.orphans.insert(
+            *transaction.id(),
+            OrphanedTransaction {
+                transaction,
+                sender: sender.to_string(),
+                missing_parents,
+                expires_at: now + self.expiry,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes the orphaned transactions whose parents haven't arrived in time.
+    /// Returns the number of removed transactions.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let expired: Vec<TransactionId> = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.expires_at <= now)
+            .map(|(transaction_id, _)| *transaction_id)
+            .collect();
+        for transaction_id in &expired {
+            self.remove(transaction_id);
+        }
+        expired.len()
+    }
+
+    /// Removes the orphaned transactions received from the peer that has disconnected.
+    /// Returns the number of removed transactions.
+    pub fn remove_for_peer(&mut self, peer_address: &str) -> usize {
+        let removed: Vec<TransactionId> = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.sender == peer_address)
+            .map(|(transaction_id, _)| *transaction_id)
+            .collect();
+        for transaction_id in &removed {
+            self.remove(transaction_id);
+        }
+        removed.len()
+    }
+
+    /// Removes and returns the orphaned transactions that spend outputs of the given parent,
+    /// along with the peers from which they have been received.
+    /// The caller is expected to re-attempt their acceptance, which may orphan them again
+    /// if they have other missing parents.
+    pub fn remove_children(&mut self, parent: &TransactionId) -> Vec<(Transaction, String)> {
+        let children = self
+            .orphans_by_missing_parent
+            .remove(parent)
+            .unwrap_or_default();
+        children
+            .iter()
+            .filter_map(|child| self.remove(child))
+            .collect()
+    }
+
+    fn remove(&mut self, transaction_id: &TransactionId) -> Option<(Transaction, String)> {
+        let orphan = self.orphans.remove(transaction_id)?;
+        for parent in &orphan.missing_parents {
+            if let Entry::Occupied(mut e) = self.orphans_by_missing_parent.entry(*parent) {
+                e.get_mut().remove(transaction_id);
+                if e.get().is_empty() {
+                    e.remove();
+                }
+            }
+        }
+        if let Entry::Occupied(mut e) = self.orphans_per_peer.entry(Self::peer_ip(&orphan.sender)) {
+            *e.get_mut() -= 1;
+            if *e.get() == 0 {
+                e.remove();
+            }
+        }
+        Some((orphan.transaction, orphan.sender))
+    }
+
+    fn evict_random(&mut self) -> Result<(), String> {
+        let mut bytes = [0; 8];
+        getrandom::getrandom(&mut bytes).map_err(|e| e.to_string())?;
+        let index = (u64::from_le_bytes(bytes) % self.orphans.len() as u64) as usize;
+        if let Some(transaction_id) = self.orphans.keys().nth(index).copied() {
+            self.remove(&transaction_id);
+        }
+        Ok(())
+    }
+
+    // Peers are identified by their IP address, without the port.
+    fn peer_ip(peer_address: &str) -> String {
+        peer_address
+            .parse::<SocketAddr>()
+            .map(|address| address.ip().to_string())
+            .unwrap_or_else(|_| peer_address.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::transaction::{OutputIndex, TransactionInput, TransactionOutput};
+    use crate::core::{Address, Sha256};
+
+    fn transaction(parents: &[TransactionId]) -> Transaction {
+        let inputs = parents
+            .iter()
+            .map(|parent| TransactionInput::new(*parent, OutputIndex::new(0)))
+            .collect();
+        let outputs = vec![TransactionOutput::new(
+            Address::new("address".to_string()),
+            10.into(),
+        )];
+        Transaction::new(inputs, outputs, 0).unwrap()
+    }
+
+    fn parent(seed: u8) -> TransactionId {
+        TransactionId::new(Sha256::new([seed; 32]))
+    }
+
+    fn insert(pool: &mut OrphanedTransactionPool, seed: u8, sender: &str) -> Result<(), String> {
+        let missing = vec![parent(seed)].into_iter().collect();
+        pool.insert(
+            transaction(&[parent(seed)]),
+            sender,
+            missing,
+            Instant::now(),
+        )
+    }
+
+    #[test]
+    fn releases_children_when_parent_arrives() {
+        let mut pool = OrphanedTransactionPool::new(10, 10, Duration::from_secs(60));
+        let child = transaction(&[parent(1), parent(2)]);
+        let missing = vec![parent(1), parent(2)].into_iter().collect();
+        pool.insert(child.clone(), "peer", missing, Instant::now())
+            .unwrap();
+
+        assert!(pool.remove_children(&parent(3)).is_empty());
+        let released = pool.remove_children(&parent(1));
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].0.id(), child.id());
+        assert_eq!(released[0].1, "peer");
+        assert!(pool.is_empty());
+        // The child is no longer waiting for its other parent either.
+        assert!(pool.remove_children(&parent(2)).is_empty());
+    }
+
+    #[test]
+    fn limits_orphans_per_peer() {
+        let mut pool = OrphanedTransactionPool::new(2, 10, Duration::from_secs(60));
+        insert(&mut pool, 0, "10.0.0.1:8333").unwrap();
+        insert(&mut pool, 1, "10.0.0.1:8333").unwrap();
+        assert!(insert(&mut pool, 2, "10.0.0.1:8333").is_err());
+        // Reconnecting from another port doesn't reset the quota.
+        assert!(insert(&mut pool, 2, "10.0.0.1:9333").is_err());
+
+        // Other peers are not affected.
+        insert(&mut pool, 2, "10.0.0.2:8333").unwrap();
+
+        // Releasing an orphan makes room for the peer.
+        pool.remove_children(&parent(0));
+        insert(&mut pool, 3, "10.0.0.1:9333").unwrap();
+        assert_eq!(pool.len(), 3);
+    }
+
+    #[test]
+    fn evicts_random_orphan_when_full() {
+        let mut pool = OrphanedTransactionPool::new(10, 3, Duration::from_secs(60));
+        for seed in 0..10 {
+            insert(&mut pool, seed, &format!("10.0.0.{}:8333", seed)).unwrap();
+            assert!(pool.len() <= 3);
+        }
+        assert_eq!(pool.len(), 3);
+        assert!(pool.contains(transaction(&[parent(9)]).id()));
+        // The per-peer counts follow the evictions.
+        assert_eq!(pool.orphans_per_peer.values().sum::<usize>(), 3);
+    }
+
+    #[test]
+    fn expires_orphans_and_drops_them_with_their_peer() {
+        let mut pool = OrphanedTransactionPool::new(10, 10, Duration::from_secs(60));
+        let now = Instant::now();
+        let missing = vec![parent(0)].into_iter().collect();
+        pool.insert(transaction(&[parent(0)]), "10.0.0.1:8333", missing, now)
+            .unwrap();
+        let missing = vec![parent(1)].into_iter().collect();
+        let later = now + Duration::from_secs(30);
+        pool.insert(transaction(&[parent(1)]), "10.0.0.1:8333", missing, later)
+            .unwrap();
+        insert(&mut pool, 2, "10.0.0.2:8333").unwrap();
+
+        assert_eq!(pool.expire(now + Duration::from_secs(59)), 0);
+        assert_eq!(pool.expire(now + Duration::from_secs(60)), 1);
+        assert!(!pool.contains(transaction(&[parent(0)]).id()));
+
+        // Only the orphans of the disconnected peer are dropped, not those of its IP address.
+        assert_eq!(pool.remove_for_peer("10.0.0.1:9333"), 0);
+        assert_eq!(pool.remove_for_peer("10.0.0.1:8333"), 1);
+        assert_eq!(pool.len(), 1);
+        assert!(!pool.orphans_per_peer.contains_key("10.0.0.1"));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::core::storage::{self, FileStorage};
+use crate::core::transaction::TransactionOutput;
+use crate::core::{Address, Coolcoin};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Keeps track of the shares submitted by the pool miners, and splits the block reward
+/// between them proportionally to their shares.
+/// Shares are accumulated in rounds: a round ends when a block paying for its shares is
+/// accepted, after which the miners start accumulating shares for the next block.
+///
+/// Blocks that are disconnected from the active blockchain after being paid out are not
+/// taken into account.
+#[derive(Default, Serialize, Deserialize)]
+pub struct PayoutLedger {
+    // Shares submitted in the current round, by the address of the miner.
+    shares: HashMap<Address, u64>,
+    // Total amount paid to each miner.
+    paid: HashMap<Address, Coolcoin>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl PayoutLedger {
+    /// Loads the ledger from the given file, or starts with an empty one if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut ledger =
+            storage::load_json::<Self>(&FileStorage, path, "payout ledger")?.unwrap_or_default();
+        ledger.path = path.to_path_buf();
+        Ok(ledger)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        storage::save(&FileStorage, &self.path, contents.as_bytes())
+    }
+
+    pub fn shares(&self) -> &HashMap<Address, u64> {
+        &self.shares
+    }
+
+    pub fn paid(&self, miner: &Address) -> Coolcoin {
+        self.paid.get(miner).copied().unwrap_or_else(Coolcoin::zero)
+    }
+
+    pub fn add_share(&mut self, miner: &Address) {
+        *self.shares.entry(miner.clone()).or_insert(0) += 1;
+    }
+
+    /// Splits the reward between the miners proportionally to the given shares.
+    /// The remainder of the integer division goes to the pool, as does the whole reward if
+    /// there are no shares.
+    pub fn payouts(
+        shares: &HashMap<Address, u64>,
+        reward: Coolcoin,
+        pool_address: &Address,
+    ) -> Vec<TransactionOutput> {
+        let total_shares = shares.values().sum::<u64>() as i128;
+        let mut miners = shares.iter().collect::<Vec<(&Address, &u64)>>();
+        // Sorted, so that the coinbase transaction doesn't depend on the order of the map.
+        miners.sort_by_key(|(miner, _)| *miner);
+
+        let mut outputs = vec![];
+        let mut remainder = reward;
+        for (miner, miner_shares) in miners {
+            let amount = Coolcoin::new(
+                (reward.value() as i128 * *miner_shares as i128 / total_shares) as i64,
+            );
+            if amount > Coolcoin::zero() {
+                outputs.push(TransactionOutput::new(miner.clone(), amount));
+                remainder = remainder - amount;
+            }
+        }
+        if remainder > Coolcoin::zero() || outputs.is_empty() {
+            outputs.push(TransactionOutput::new(pool_address.clone(), remainder));
+        }
+        outputs
+    }
+
+    /// Records that the block paying the given outputs for the given shares has been accepted.
+    /// The paid shares are removed from the current round, while the shares submitted after
+    /// the block template has been created are kept for the next block.
+    pub fn complete_round(
+        &mut self,
+        paid_shares: &HashMap<Address, u64>,
+        payouts: &[TransactionOutput],
+    ) {
+        for (miner, paid_shares) in paid_shares {
+            if let Some(shares) = self.shares.get_mut(miner) {
+                *shares = shares.saturating_sub(*paid_shares);
+                if *shares == 0 {
+                    self.shares.remove(miner);
+                }
+            }
+        }
+        for output in payouts {
+            let paid = self
+                .paid
+                .entry(output.to().clone())
+                .or_insert(Coolcoin::zero());
+            *paid = *paid + output.amount();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(name: &str) -> Address {
+        Address::new(name.to_string())
+    }
+
+    #[test]
+    fn splits_reward_proportionally() {
+        let pool = address("pool");
+        let shares = vec![(address("alice"), 2), (address("bob"), 1)]
+            .into_iter()
+            .collect();
+        let payouts = PayoutLedger::payouts(&shares, 50.into(), &pool);
+        let payouts = payouts
+            .iter()
+            .map(|output| (output.to().as_str(), output.amount()))
+            .collect::<Vec<(&str, Coolcoin)>>();
+        assert_eq!(
+            payouts,
+            vec![("alice", 33.into()), ("bob", 16.into()), ("pool", 1.into())]
+        );
+
+        let payouts = PayoutLedger::payouts(&HashMap::new(), 50.into(), &pool);
+        assert_eq!(payouts.len(), 1);
+        assert_eq!(payouts[0].amount(), 50.into());
+    }
+
+    #[test]
+    fn keeps_shares_submitted_after_the_template() {
+        let mut ledger = PayoutLedger::default();
+        ledger.add_share(&address("alice"));
+        ledger.add_share(&address("bob"));
+        let paid_shares = ledger.shares().clone();
+        let payouts = PayoutLedger::payouts(&paid_shares, 50.into(), &address("pool"));
+        ledger.add_share(&address("bob"));
+
+        ledger.complete_round(&paid_shares, &payouts);
+        assert_eq!(ledger.shares().len(), 1);
+        assert_eq!(ledger.shares().get(&address("bob")), Some(&1));
+        assert_eq!(ledger.paid(&address("alice")), 25.into());
+        assert_eq!(ledger.paid(&address("bob")), 25.into());
+    }
+}
@@ -1,123 +1,488 @@
+use crate::core::address_history::AddressTransaction;
+use crate::core::ban_list::{Ban, SetBanCommand, Subnet};
 use crate::core::block::BlockHash;
-use crate::core::{Block, Transaction};
+use crate::core::blocktree::{BlockHeaderInfo, ChainTip, TransactionInfo};
+use crate::core::coolcoin_network::{NetTotals, PeerInfo};
+use crate::core::coolcoin_node::NodeInfo;
+use crate::core::fork_log::ForkStats;
+use crate::core::mined_block_log::MinedBlock;
+use crate::core::mining_server::{MiningInfo, MiningJob, MiningShare, SubmitResult};
+use crate::core::peer_log::PeerEvent;
+use crate::core::serialize::{self, Decodable, Encodable, Reader};
+use crate::core::transaction::TransactionId;
+use crate::core::transaction_pool::TransactionPoolInfo;
+use crate::core::utxo_pool::UnspentOutput;
+use crate::core::{Address, Block, Coolcoin, FeeRate, Transaction};
 use serde::{Deserialize, Serialize};
-use std::fmt::{Display, Formatter};
-use std::io::{Error, ErrorKind, Read, Write};
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
 use std::net::{SocketAddr, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::info;
 
-macro_rules! log_info {
-    () => (println!());
-    ($($arg:tt)*) => ({
-        println!($($arg)*);
-    })
-}
-
-#[derive(Copy, Clone, Serialize, Deserialize)]
+/// Precedes each message, with the little-endian size of the payload, which is encoded
+/// by the `WireEncoding`.
+#[derive(Copy, Clone)]
 struct PeerMessageHeader {
     payload_size: u32,
 }
 
+// The size of the encoded header, in bytes.
+const HEADER_SIZE: usize = 4;
+
+impl Encodable for PeerMessageHeader {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.payload_size.encode(output);
+    }
+}
+
+impl Decodable for PeerMessageHeader {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self {
+            payload_size: u32::decode(input)?,
+        })
+    }
+}
+
+/// The encoding of the messages' payloads.
+pub trait PeerMessageEncoding {
+    fn encode(message: &PeerMessage) -> Vec<u8>;
+
+    fn decode(payload: &[u8]) -> Result<PeerMessage, String>;
+}
+
+/// The node's own encoding: bincode, except for the blocks and transactions within
+/// the messages, which have the canonical encoding.
+pub struct BincodeEncoding;
+
+impl PeerMessageEncoding for BincodeEncoding {
+    fn encode(message: &PeerMessage) -> Vec<u8> {
+        bincode::serialize(message).unwrap()
+    }
+
+    fn decode(payload: &[u8]) -> Result<PeerMessage, String> {
+        bincode::deserialize(payload).map_err(|e| e.to_string())
+    }
+}
+
+/// The encoding of the messages sent over the network, which the node shares with its peers
+/// and clients. The protobuf encoding, see proto/peer.proto, is used instead with
+/// the `protobuf` feature.
+#[cfg(not(feature = "protobuf"))]
+pub type WireEncoding = BincodeEncoding;
+#[cfg(feature = "protobuf")]
+pub type WireEncoding = crate::core::protobuf::ProtobufEncoding;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum PeerMessage {
     GetInventory(),
-    ResponseInventory(Vec<Block>),
+    ResponseInventory(Vec<Arc<Block>>),
     GetBlock(BlockHash),
-    ResponseBlock(Option<Block>),
+    ResponseBlock(Option<Arc<Block>>),
     SendTransaction(Transaction),
     GetFullBlockchain,
-    ResponseFullBlockchain(Vec<BlockHash>, Vec<Block>),
-    ResponseTransaction,
-    RelayBlock(Block),
+    ResponseFullBlockchain(Vec<BlockHash>, Vec<Arc<Block>>),
+    // The id of the accepted transaction, or the reason why it has been rejected.
+    ResponseTransaction(Result<TransactionId, String>),
+    RelayBlock(Arc<Block>),
     RelayTransaction(Transaction),
+    // Estimates the fee rate for the transaction to be confirmed within the target number of
+    // blocks.
+    EstimateSmartFee(u32),
+    // The estimated fee rate, if there is enough data.
+    ResponseFeeEstimate(Option<FeeRate>),
+    GetTransactionPoolInfo,
+    ResponseTransactionPoolInfo(TransactionPoolInfo),
+    // Messages exchanged with external miners on the mining server.
+    // Subscribes with the payout address, which is required by the mining pool.
+    MiningSubscribe(Option<Address>),
+    // The extranonce assigned to the subscribed miner.
+    ResponseMiningSubscribe(Result<u32, String>),
+    // The job to mine, replacing the previous one, or None if the miner should stop mining.
+    MiningNotify(Option<MiningJob>),
+    MiningSubmit(MiningShare),
+    ResponseMiningSubmit(SubmitResult),
+    // The hash rate of the miner, in hashes per second.
+    MiningReportHashrate(u64),
+    GetMiningInfo,
+    ResponseMiningInfo(MiningInfo),
+    ListMinedBlocks,
+    ResponseListMinedBlocks(Vec<MinedBlock>),
+    // The height of the active blockchain, i.e. the number of blocks after the genesis block.
+    GetBlockCount,
+    ResponseBlockCount(u32),
+    // The hash of the last block in the active blockchain.
+    GetBestBlockHash,
+    ResponseBestBlockHash(BlockHash),
+    // The hash of the block at the given height in the active blockchain, if any.
+    GetBlockHash(u32),
+    ResponseBlockHash(Option<BlockHash>),
+    // The unspent outputs with the number of confirmations in the given range (inclusive),
+    // sent to any of the given addresses. No addresses means all outputs.
+    ListUnspent(u32, u32, Vec<Address>),
+    ResponseListUnspent(Vec<UnspentOutput>),
+    // Returns the UTXO set, optionally only the outputs sent to the addresses, with at least
+    // the given amount. The node responds with ResponseListUnspent.
+    GetUtxos(Vec<Address>, Coolcoin),
+    // Shuts the node down. Only allowed for the clients on the same machine.
+    Stop,
+    ResponseStop(Result<(), String>),
+    GetInfo,
+    ResponseInfo(NodeInfo),
+    // Seconds since the node has started.
+    Uptime,
+    ResponseUptime(u64),
+    // The difficulty of the tip as a multiple of the minimum difficulty.
+    GetDifficulty,
+    ResponseDifficulty(f64),
+    GetChainTips,
+    ResponseChainTips(Vec<ChainTip>),
+    GetBlockHeader(BlockHash),
+    ResponseBlockHeader(Option<BlockHeaderInfo>),
+    // Finds the transaction in the transaction pool or the active blockchain.
+    GetTransaction(TransactionId),
+    ResponseTransactionInfo(Option<TransactionInfo>),
+    ListBanned,
+    ResponseListBanned(Vec<Ban>),
+    // Bans or unbans the subnet. Only allowed for the clients on the same machine.
+    SetBan(Subnet, SetBanCommand),
+    ResponseSetBan(Result<(), String>),
+    // Adds the fee delta to the transaction when selecting transactions for the next block.
+    // Only allowed for the clients on the same machine.
+    PrioritiseTransaction(TransactionId, Coolcoin),
+    ResponsePrioritiseTransaction(Result<(), String>),
+    // The most recent transactions (at most the given number) that send coins to, or spend
+    // the outputs of, any of the addresses, including the unconfirmed ones.
+    ListTransactions(Vec<Address>, u32),
+    ResponseListTransactions(Vec<AddressTransaction>),
+    // The connected peers, including the clients.
+    GetPeerInfo,
+    ResponsePeerInfo(Vec<PeerInfo>),
+    GetNetTotals,
+    ResponseNetTotals(NetTotals),
+    // The name of the file in the node's data directory to which the state is written.
+    DumpState(Option<String>),
+    // The path of the written file.
+    ResponseDumpState(Result<String, String>),
+    // The log categories to include, as CATEGORY[=LEVEL], and to return to the default level.
+    Logging(Vec<String>, Vec<String>),
+    // The level of each category.
+    ResponseLogging(Result<BTreeMap<String, String>, String>),
+    // The number of the most recent blocks of the active blockchain, all of them by default.
+    GetForkStats(Option<u32>),
+    ResponseForkStats(ForkStats),
+    // The number of the most recent events of the peer log, all of them by default.
+    GetPeerHistory(Option<usize>),
+    ResponsePeerHistory(Vec<PeerEvent>),
+}
+
+impl PeerMessage {
+    /// The name of the message's type, e.g. for the traffic statistics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PeerMessage::GetInventory() => "GetInventory",
+            PeerMessage::ResponseInventory(..) => "ResponseInventory",
+            PeerMessage::GetBlock(..) => "GetBlock",
+            PeerMessage::ResponseBlock(..) => "ResponseBlock",
+            PeerMessage::SendTransaction(..) => "SendTransaction",
+            PeerMessage::GetFullBlockchain => "GetFullBlockchain",
+            PeerMessage::ResponseFullBlockchain(..) => "ResponseFullBlockchain",
+            PeerMessage::ResponseTransaction(..) => "ResponseTransaction",
+            PeerMessage::RelayBlock(..) => "RelayBlock",
+            PeerMessage::RelayTransaction(..) => "RelayTransaction",
+            PeerMessage::EstimateSmartFee(..) => "EstimateSmartFee",
+            PeerMessage::ResponseFeeEstimate(..) => "ResponseFeeEstimate",
+            PeerMessage::GetTransactionPoolInfo => "GetTransactionPoolInfo",
+            PeerMessage::ResponseTransactionPoolInfo(..) => "ResponseTransactionPoolInfo",
+            PeerMessage::MiningSubscribe(..) => "MiningSubscribe",
+            PeerMessage::ResponseMiningSubscribe(..) => "ResponseMiningSubscribe",
+            PeerMessage::MiningNotify(..) => "MiningNotify",
+            PeerMessage::MiningSubmit(..) => "MiningSubmit",
+            PeerMessage::ResponseMiningSubmit(..) => "ResponseMiningSubmit",
+            PeerMessage::MiningReportHashrate(..) => "MiningReportHashrate",
+            PeerMessage::GetMiningInfo => "GetMiningInfo",
+            PeerMessage::ResponseMiningInfo(..) => "ResponseMiningInfo",
+            PeerMessage::ListMinedBlocks => "ListMinedBlocks",
+            PeerMessage::ResponseListMinedBlocks(..) => "ResponseListMinedBlocks",
+            PeerMessage::GetBlockCount => "GetBlockCount",
+            PeerMessage::ResponseBlockCount(..) => "ResponseBlockCount",
+            PeerMessage::GetBestBlockHash => "GetBestBlockHash",
+            PeerMessage::ResponseBestBlockHash(..) => "ResponseBestBlockHash",
+            PeerMessage::GetBlockHash(..) => "GetBlockHash",
+            PeerMessage::ResponseBlockHash(..) => "ResponseBlockHash",
+            PeerMessage::ListUnspent(..) => "ListUnspent",
+            PeerMessage::ResponseListUnspent(..) => "ResponseListUnspent",
+            PeerMessage::GetUtxos(..) => "GetUtxos",
+            PeerMessage::Stop => "Stop",
+            PeerMessage::ResponseStop(..) => "ResponseStop",
+            PeerMessage::GetInfo => "GetInfo",
+            PeerMessage::ResponseInfo(..) => "ResponseInfo",
+            PeerMessage::Uptime => "Uptime",
+            PeerMessage::ResponseUptime(..) => "ResponseUptime",
+            PeerMessage::GetDifficulty => "GetDifficulty",
+            PeerMessage::ResponseDifficulty(..) => "ResponseDifficulty",
+            PeerMessage::GetChainTips => "GetChainTips",
+            PeerMessage::ResponseChainTips(..) => "ResponseChainTips",
+            PeerMessage::GetBlockHeader(..) => "GetBlockHeader",
+            PeerMessage::ResponseBlockHeader(..) => "ResponseBlockHeader",
+            PeerMessage::GetTransaction(..) => "GetTransaction",
+            PeerMessage::ResponseTransactionInfo(..) => "ResponseTransactionInfo",
+            PeerMessage::ListBanned => "ListBanned",
+            PeerMessage::ResponseListBanned(..) => "ResponseListBanned",
+            PeerMessage::SetBan(..) => "SetBan",
+            PeerMessage::ResponseSetBan(..) => "ResponseSetBan",
+            PeerMessage::PrioritiseTransaction(..) => "PrioritiseTransaction",
+            PeerMessage::ResponsePrioritiseTransaction(..) => "ResponsePrioritiseTransaction",
+            PeerMessage::ListTransactions(..) => "ListTransactions",
+            PeerMessage::ResponseListTransactions(..) => "ResponseListTransactions",
+            PeerMessage::GetPeerInfo => "GetPeerInfo",
+            PeerMessage::ResponsePeerInfo(..) => "ResponsePeerInfo",
+            PeerMessage::GetNetTotals => "GetNetTotals",
+            PeerMessage::ResponseNetTotals(..) => "ResponseNetTotals",
+            PeerMessage::DumpState(..) => "DumpState",
+            PeerMessage::ResponseDumpState(..) => "ResponseDumpState",
+            PeerMessage::Logging(..) => "Logging",
+            PeerMessage::ResponseLogging(..) => "ResponseLogging",
+            PeerMessage::GetForkStats(..) => "GetForkStats",
+            PeerMessage::ResponseForkStats(..) => "ResponseForkStats",
+            PeerMessage::GetPeerHistory(..) => "GetPeerHistory",
+            PeerMessage::ResponsePeerHistory(..) => "ResponsePeerHistory",
+        }
+    }
+}
+
+/// The number of messages of a type, and their total size including the headers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageStats {
+    pub messages: u64,
+    pub bytes: u64,
 }
 
+/// The messages exchanged with the peers, by type and direction.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrafficStats {
+    pub sent: BTreeMap<String, MessageStats>,
+    pub received: BTreeMap<String, MessageStats>,
+}
+
+impl TrafficStats {
+    pub fn add(&mut self, other: &TrafficStats) {
+        for (totals, stats) in [
+            (&mut self.sent, &other.sent),
+            (&mut self.received, &other.received),
+        ] {
+            for (name, stats) in stats {
+                let total = totals.entry(name.clone()).or_default();
+                total.messages += stats.messages;
+                total.bytes += stats.bytes;
+            }
+        }
+    }
+
+    fn record(stats: &mut BTreeMap<String, MessageStats>, message: &PeerMessage, bytes: usize) {
+        let stats = stats.entry(message.name().to_string()).or_default();
+        stats.messages += 1;
+        stats.bytes += bytes as u64;
+    }
+}
+
+// The maximum number of bytes waiting to be written to the socket, above which new messages
+// are refused until the peer reads the pending ones. A single message may be larger.
+const MAX_SEND_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+// The number of bytes the buffer grows by, at most, for each read from the socket.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+// The maximum size of a received message, so that a peer can't make the node buffer
+// gigabytes for a message that never arrives. Larger than any full blockchain response.
+pub const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Splits the received bytes, which may arrive in pieces of any size, into messages.
+/// The messages are decoded directly from the buffer, and the decoded ones are only dropped
+/// when more bytes are read, so a single read may contain any number of messages.
+#[derive(Default)]
+pub struct MessageDecoder {
+    buffer: Vec<u8>,
+    // Start of the first message in the buffer that hasn't been decoded yet.
+    position: usize,
+}
+
+impl MessageDecoder {
+    /// Reads the available bytes into the buffer, and returns their number,
+    /// which is zero when the connection is shutdown.
+    pub fn read_from(&mut self, reader: &mut impl Read) -> std::io::Result<usize> {
+        if self.position > 0 {
+            self.buffer.drain(..self.position);
+            self.position = 0;
+        }
+        let len = self.buffer.len();
+        self.buffer.resize(len + READ_CHUNK_SIZE, 0);
+        let result = reader.read(&mut self.buffer[len..]);
+        let read_bytes = *result.as_ref().unwrap_or(&0);
+        self.buffer.truncate(len + read_bytes);
+        result
+    }
+
+    /// Returns the next message, or None if it hasn't been received in full yet.
+    pub fn next_message(&mut self) -> Result<Option<PeerMessage>, String> {
+        Ok(self
+            .next_message_with_size()?
+            .map(|(message, _size)| message))
+    }
+
+    /// Returns the next message with its size including the header.
+    fn next_message_with_size(&mut self) -> Result<Option<(PeerMessage, usize)>, String> {
+        let header_size = HEADER_SIZE;
+        let pending = &self.buffer[self.position..];
+        if pending.len() < header_size {
+            return Ok(None);
+        }
+        let header = serialize::deserialize::<PeerMessageHeader>(&pending[..header_size])
+            .map_err(|e| format!("Invalid message header: {}", e))?;
+        let message_size = header_size + header.payload_size as usize;
+        if message_size > MAX_MESSAGE_SIZE {
+            return Err(format!(
+                "Message of {} bytes is larger than the maximum: {}",
+                message_size, MAX_MESSAGE_SIZE
+            ));
+        }
+        if pending.len() < message_size {
+            return Ok(None);
+        }
+        let message = WireEncoding::decode(&pending[header_size..message_size])
+            .map_err(|e| format!("Invalid message: {}", e))?;
+        self.position += message_size;
+        Ok(Some((message, message_size)))
+    }
+}
+
+/// The byte stream that the messages are exchanged over: a TCP connection between the nodes,
+/// or a socket pair between the nodes running in the same process, e.g. in tests.
+pub trait Transport: Read + Write + AsRawFd + Send {}
+
+impl Transport for TcpStream {}
+
+impl Transport for UnixStream {}
+
 pub struct PeerConnection {
     peer_address: String,
     enable_logging: bool,
-    tcp_stream: TcpStream,
-    last_header: Option<PeerMessageHeader>,
+    stream: Box<dyn Transport>,
+    decoder: MessageDecoder,
+    // The sent messages that haven't been written to the socket in full yet.
+    send_buffer: Vec<u8>,
+    // Number of bytes at the start of the send buffer that have already been written.
+    send_position: usize,
+    // Total number of bytes written to and read from the socket.
+    bytes_sent: u64,
+    bytes_received: u64,
+    traffic: TrafficStats,
+    connected_at: Instant,
+    // When the last message has been received, if any.
+    last_received_at: Option<Instant>,
 }
 
 impl PeerConnection {
     pub fn connect(peer_address: String, enable_logging: bool) -> Result<Self, String> {
-        let mut tcp_stream = TcpStream::connect(&peer_address).map_err(|e| e.to_string())?;
+        let tcp_stream = TcpStream::connect(&peer_address).map_err(|e| e.to_string())?;
         tcp_stream
             .set_nonblocking(true)
             .map_err(|e| e.to_string())?;
-        Ok(Self {
+        Ok(Self::from_transport(
             peer_address,
+            Box::new(tcp_stream),
             enable_logging,
-            tcp_stream,
-            last_header: None,
-        })
+        ))
+    }
+
+    /// Returns the ends of the connection between A and B in the same process, each named by
+    /// the address of the other end, i.e. A's end first.
+    pub fn pair(address_a: &str, address_b: &str) -> Result<(Self, Self), String> {
+        let (stream_a, stream_b) = UnixStream::pair().map_err(|e| e.to_string())?;
+        stream_a.set_nonblocking(true).map_err(|e| e.to_string())?;
+        stream_b.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok((
+            Self::from_transport(address_b.to_string(), Box::new(stream_a), false),
+            Self::from_transport(address_a.to_string(), Box::new(stream_b), false),
+        ))
     }
 
     pub fn address(&self) -> &str {
         &self.peer_address
     }
 
+    /// The socket of the connection, which the poller waits on.
+    pub fn raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+
     pub fn from_tcp_stream(
         address: SocketAddr,
         tcp_stream: TcpStream,
         enable_logging: bool,
+    ) -> Self {
+        Self::from_transport(address.to_string(), Box::new(tcp_stream), enable_logging)
+    }
+
+    /// The stream must be in the non-blocking mode.
+    pub fn from_transport(
+        peer_address: String,
+        stream: Box<dyn Transport>,
+        enable_logging: bool,
     ) -> Self {
         Self {
-            peer_address: address.to_string(),
+            peer_address,
             enable_logging,
-            tcp_stream,
-            last_header: None,
+            stream,
+            decoder: MessageDecoder::default(),
+            send_buffer: vec![],
+            send_position: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            traffic: TrafficStats::default(),
+            connected_at: Instant::now(),
+            last_received_at: None,
         }
     }
 
     pub fn receive(&mut self) -> Result<Option<PeerMessage>, String> {
-        // Read header then read message.
-        let header_size = std::mem::size_of::<PeerMessageHeader>();
-        let mut header_buffer = Vec::with_capacity(header_size);
-        header_buffer.resize(header_size, 0);
-
-        let header: PeerMessageHeader = match &self.last_header {
-            Some(header) => header.clone(),
-            None => match self.tcp_stream.read(&mut header_buffer[..]) {
-                Ok(0) => {
-                    // TcpStream::read returns zero when the connection is shutdown.
-                    return Err(format!(
-                        "Connection to peer: {} has been lost.",
-                        self.peer_address
-                    ));
-                }
+        // The peer may be waiting for the rest of the sent messages before it responds.
+        self.flush()?;
+        // The messages that have already been received are returned before reading more.
+        let mut is_open = true;
+        let mut payload = self.decoder.next_message_with_size()?;
+        while payload.is_none() && is_open {
+            match self.decoder.read_from(&mut self.stream) {
+                // The read returns zero when the connection is shutdown.
+                Ok(0) => is_open = false,
                 Ok(read_bytes) => {
-                    // TODO: Handle malicious peers.
-                    assert_eq!(read_bytes, header_size);
-                    bincode::deserialize::<PeerMessageHeader>(&header_buffer).unwrap()
+                    self.bytes_received += read_bytes as u64;
+                    payload = self.decoder.next_message_with_size()?
                 }
                 Err(e) => match e.kind() {
-                    // TODO: Consider dropping the peer if it would block.
                     ErrorKind::WouldBlock => return Ok(None),
+                    ErrorKind::Interrupted => {}
                     _ => return Err(e.to_string()),
                 },
-            },
-        };
-
-        let mut payload_buffer = Vec::with_capacity(header.payload_size as usize);
-        payload_buffer.resize(header.payload_size as usize, 0);
-        let payload = match self.tcp_stream.read(&mut payload_buffer[..]) {
-            Ok(read_bytes) => {
-                assert_eq!(read_bytes as u32, header.payload_size);
-                bincode::deserialize::<PeerMessage>(&payload_buffer).unwrap()
             }
-            Err(e) => {
-                return match e.kind() {
-                    ErrorKind::WouldBlock => {
-                        self.last_header = Some(header);
-                        Ok(None)
-                    }
-                    _ => Err(e.to_string()),
-                }
+        }
+        let payload = match payload {
+            Some((payload, size)) => {
+                TrafficStats::record(&mut self.traffic.received, &payload, size);
+                payload
+            }
+            None => {
+                return Err(format!(
+                    "Connection to peer: {} has been lost.",
+                    self.peer_address
+                ))
             }
         };
-        self.last_header = None;
+        self.last_received_at = Some(Instant::now());
         if self.enable_logging {
-            log_info!(
+            info!(
+                target: "net",
                 "Recv [{}] {}",
                 self.peer_address,
                 serde_json::to_string_pretty(&payload).unwrap()
@@ -139,37 +504,238 @@ impl PeerConnection {
         Ok(messages)
     }
 
+    /// Queues the message, and writes as much of it as the socket accepts. The rest is written
+    /// by the subsequent calls of send, receive or flush, in order.
+    /// Returns false, without queueing the message, if the send buffer is full,
+    /// i.e. the peer doesn't keep up with reading the messages.
     pub fn send(&mut self, payload: &PeerMessage) -> Result<bool, String> {
-        let header_size = std::mem::size_of::<PeerMessageHeader>();
-        let payload_size = bincode::serialized_size(&payload).unwrap() as usize;
-        let total_size = header_size + payload_size;
-
-        let mut buffer = Vec::with_capacity(total_size as usize);
-        buffer.resize(total_size, 0);
-        bincode::serialize_into(
-            &mut buffer[..header_size],
-            &PeerMessageHeader {
-                payload_size: payload_size as u32,
-            },
-        )
-        .unwrap();
-        bincode::serialize_into(&mut buffer[header_size..], &payload).unwrap();
-
-        match self.tcp_stream.write(&buffer[..]) {
-            Ok(_) => {
-                if self.enable_logging {
-                    log_info!(
-                        "Send [{}] {}",
-                        self.peer_address,
-                        serde_json::to_string_pretty(&payload).unwrap()
-                    );
+        let message = encode(payload);
+        let pending = self.send_buffer.len() - self.send_position;
+        if pending > 0 && pending + message.len() > MAX_SEND_BUFFER_SIZE {
+            return Ok(false);
+        }
+        self.send_buffer.drain(..self.send_position);
+        self.send_position = 0;
+        self.send_buffer.extend_from_slice(&message);
+        TrafficStats::record(&mut self.traffic.sent, payload, message.len());
+        if self.enable_logging {
+            info!(
+                target: "net",
+                "Send [{}] {}",
+                self.peer_address,
+                serde_json::to_string_pretty(&payload).unwrap()
+            );
+        }
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Writes as much of the queued messages as the socket accepts, and returns whether all of
+    /// them have been written.
+    pub fn flush(&mut self) -> Result<bool, String> {
+        while self.send_position < self.send_buffer.len() {
+            match self.stream.write(&self.send_buffer[self.send_position..]) {
+                Ok(0) => {
+                    return Err(format!(
+                        "Connection to peer: {} has been lost.",
+                        self.peer_address
+                    ))
+                }
+                Ok(written_bytes) => {
+                    self.send_position += written_bytes;
+                    self.bytes_sent += written_bytes as u64;
                 }
-                Ok(true)
+                Err(e) => match e.kind() {
+                    ErrorKind::WouldBlock => return Ok(false),
+                    ErrorKind::Interrupted => {}
+                    _ => return Err(e.to_string()),
+                },
             }
-            Err(e) => match e.kind() {
-                ErrorKind::WouldBlock => Ok(false),
-                _ => Err(e.to_string()),
-            },
         }
+        self.send_buffer.clear();
+        self.send_position = 0;
+        Ok(true)
+    }
+
+    /// Whether some of the sent messages are waiting for the socket to become writable.
+    pub fn has_pending_sends(&self) -> bool {
+        self.send_position < self.send_buffer.len()
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// The messages that have been sent and received over the connection.
+    pub fn traffic(&self) -> &TrafficStats {
+        &self.traffic
+    }
+
+    pub fn connected_at(&self) -> Instant {
+        self.connected_at
+    }
+
+    pub fn last_received_at(&self) -> Option<Instant> {
+        self.last_received_at
+    }
+}
+
+/// Returns the message as it's sent over the network: the header followed by the payload.
+pub fn encode(payload: &PeerMessage) -> Vec<u8> {
+    let payload = WireEncoding::encode(payload);
+    let mut buffer = Vec::with_capacity(HEADER_SIZE + payload.len());
+    PeerMessageHeader {
+        payload_size: payload.len() as u32,
+    }
+    .encode(&mut buffer);
+    buffer.extend_from_slice(&payload);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::transaction::{TransactionInput, TransactionOutput};
+    use crate::core::Sha256;
+
+    fn decode_all(decoder: &mut MessageDecoder) -> Vec<String> {
+        let mut messages = vec![];
+        while let Some(message) = decoder.next_message().unwrap() {
+            messages.push(serde_json::to_string(&message).unwrap());
+        }
+        messages
+    }
+
+    #[test]
+    fn writes_large_messages_in_pieces() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sender =
+            PeerConnection::connect(listener.local_addr().unwrap().to_string(), false).unwrap();
+        let (tcp_stream, address) = listener.accept().unwrap();
+        tcp_stream.set_nonblocking(true).unwrap();
+        let mut receiver = PeerConnection::from_tcp_stream(address, tcp_stream, false);
+
+        // Much larger than the socket buffers, so that it can't be written at once.
+        let addresses = (0..500_000)
+            .map(|i| Address::new(format!("address{}", i)))
+            .collect::<Vec<_>>();
+        assert!(sender
+            .send(&PeerMessage::ListTransactions(addresses, 1))
+            .unwrap());
+        assert!(sender.has_pending_sends());
+        assert!(sender.send(&PeerMessage::GetBlockCount).unwrap());
+
+        let mut received = vec![];
+        while received.len() < 2 {
+            sender.flush().unwrap();
+            received.extend(receiver.receive_all().unwrap());
+        }
+        assert!(!sender.has_pending_sends());
+        match &received[..] {
+            [PeerMessage::ListTransactions(addresses, 1), PeerMessage::GetBlockCount] => {
+                assert_eq!(addresses.len(), 500_000);
+                assert_eq!(addresses[499_999].as_str(), "address499999");
+            }
+            messages => panic!("Unexpected messages: {:?}", messages.len()),
+        }
+    }
+
+    #[test]
+    fn decodes_messages_received_in_any_pieces() {
+        let messages = [
+            PeerMessage::GetBlockCount,
+            PeerMessage::GetBlock(BlockHash::new(Sha256::new([7; 32]))),
+            PeerMessage::ResponseBlockCount(42),
+            PeerMessage::ListTransactions(vec![Address::new("alice".to_string())], 10),
+        ];
+        let bytes = messages.iter().flat_map(encode).collect::<Vec<u8>>();
+
+        // All messages in a single read.
+        let mut decoder = MessageDecoder::default();
+        assert_eq!(decoder.read_from(&mut &bytes[..]).unwrap(), bytes.len());
+        let expected = decode_all(&mut decoder);
+        assert_eq!(expected.len(), messages.len());
+
+        // One byte per read.
+        let mut decoder = MessageDecoder::default();
+        let mut decoded = vec![];
+        for i in 0..bytes.len() {
+            assert_eq!(decoder.read_from(&mut &bytes[i..i + 1]).unwrap(), 1);
+            decoded.extend(decode_all(&mut decoder));
+        }
+        assert_eq!(decoded, expected);
+        assert_eq!(decoder.read_from(&mut &bytes[..0]).unwrap(), 0);
+        assert!(decoder.next_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn counts_traffic_by_message_type() {
+        let (mut a, mut b) = PeerConnection::pair("a", "b").unwrap();
+        let get_block = PeerMessage::GetBlock(BlockHash::new(Sha256::new([7; 32])));
+        for message in [&PeerMessage::GetBlockCount, &get_block, &get_block] {
+            assert!(a.send(message).unwrap());
+        }
+        let mut received = 0;
+        while received < 3 {
+            a.flush().unwrap();
+            received += b.receive_all().unwrap().len();
+        }
+
+        let get_block_bytes = 2 * encode(&get_block).len() as u64;
+        let expected = [
+            (
+                "GetBlock".to_string(),
+                MessageStats {
+                    messages: 2,
+                    bytes: get_block_bytes,
+                },
+            ),
+            (
+                "GetBlockCount".to_string(),
+                MessageStats {
+                    messages: 1,
+                    bytes: encode(&PeerMessage::GetBlockCount).len() as u64,
+                },
+            ),
+        ]
+        .iter()
+        .cloned()
+        .collect::<BTreeMap<_, _>>();
+        assert_eq!(a.traffic().sent, expected);
+        assert!(a.traffic().received.is_empty());
+        assert_eq!(b.traffic().received, expected);
+        assert_eq!(a.bytes_sent(), b.bytes_received());
+
+        let mut totals = a.traffic().clone();
+        totals.add(b.traffic());
+        assert_eq!(totals.sent["GetBlock"].bytes, get_block_bytes);
+        assert_eq!(totals.received["GetBlock"].messages, 2);
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        // The header of a message that is too large is rejected before its payload arrives.
+        let mut decoder = MessageDecoder::default();
+        decoder.read_from(&mut &u32::MAX.to_le_bytes()[..]).unwrap();
+        assert!(decoder.next_message().is_err());
+
+        // A transaction without inputs can't be decoded, rather than failing in validation.
+        let transaction = Transaction::new(
+            vec![TransactionInput::new_coinbase()],
+            vec![TransactionOutput::new(
+                Address::new("alice".to_string()),
+                1.into(),
+            )],
+            0,
+        )
+        .unwrap();
+        let mut json = serde_json::to_value(PeerMessage::RelayTransaction(transaction)).unwrap();
+        json["RelayTransaction"]["inputs"] = serde_json::json!([]);
+        let message = serde_json::from_value::<PeerMessage>(json).unwrap_err();
+        assert!(message.to_string().contains("has no inputs"), "{}", message);
     }
 }
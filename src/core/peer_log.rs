@@ -0,0 +1,106 @@
+use crate::core::ban_list::Subnet;
+use crate::core::storage::{FileStorage, RecordLog};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The changes of the node's connections, as recorded in the peer log.
+/// The node has no version handshake, so the peers that don't speak its protocol are
+/// disconnected with the error of the message that couldn't be decoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerEvent {
+    // The peer or the client has connected to the node, or the node to the peer.
+    Connected {
+        // Time (seconds from Unix Epoch) of the event.
+        time: u32,
+        address: String,
+    },
+    Disconnected {
+        time: u32,
+        address: String,
+        reason: String,
+    },
+    // The subnet has been banned until the given time by the operator.
+    Banned {
+        time: u32,
+        subnet: Subnet,
+        until: u32,
+        // Address of the client that has set the ban.
+        by: String,
+    },
+    Unbanned {
+        time: u32,
+        subnet: Subnet,
+        by: String,
+    },
+}
+
+/// Log of the connects, disconnects and bans, with their reasons, e.g. to find out why
+/// the nodes of a local network keep losing each other.
+/// The log is stored as a file with one JSON record per line, which is kept across restarts.
+pub struct PeerLog {
+    log: RecordLog<PeerEvent>,
+}
+
+impl PeerLog {
+    /// Loads the log from the given file, or starts with an empty one if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Ok(Self {
+            log: RecordLog::load(Arc::new(FileStorage), path, "peer events")?,
+        })
+    }
+
+    pub fn record(&mut self, event: PeerEvent) -> Result<(), String> {
+        self.log.record(event)
+    }
+
+    /// Returns the given number of the most recent events, or all of them, the oldest first.
+    pub fn history(&self, count: Option<usize>) -> Vec<PeerEvent> {
+        let events = self.log.records();
+        let count = count.unwrap_or(events.len()).min(events.len());
+        events[events.len() - count..].to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn returns_most_recent_events() {
+        let dir = std::env::temp_dir().join(format!("peer_log_{}", std::process::id()));
+        let path = dir.join("peers.jsonl");
+        let _ = fs::remove_dir_all(&dir);
+        let address = "127.0.0.1:8335".to_string();
+        let events = vec![
+            PeerEvent::Connected {
+                time: 100,
+                address: address.clone(),
+            },
+            PeerEvent::Banned {
+                time: 101,
+                subnet: "127.0.0.1".parse().unwrap(),
+                until: 200,
+                by: "127.0.0.1:50000".to_string(),
+            },
+            PeerEvent::Disconnected {
+                time: 101,
+                address,
+                reason: "Banned".to_string(),
+            },
+        ];
+
+        let mut log = PeerLog::load(&path).unwrap();
+        for event in &events {
+            log.record(event.clone()).unwrap();
+        }
+
+        let log = PeerLog::load(&path).unwrap();
+        assert_eq!(log.history(None), events);
+        assert_eq!(log.history(Some(2)), events[1..]);
+        assert_eq!(log.history(Some(10)), events);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
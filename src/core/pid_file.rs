@@ -0,0 +1,90 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// The file with the id of the running node's process, which prevents starting another node
+/// with the same data directory. The file is removed when the node stops.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the id of the current process to the file, unless another process is running.
+    pub fn acquire(path: &Path) -> Result<Self, String> {
+        Self::check_not_running(path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, std::process::id().to_string()).map_err(|e| e.to_string())?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn check_not_running(path: &Path) -> Result<(), String> {
+        match Self::running_pid(path)? {
+            Some(pid) => Err(format!(
+                "Coolcoin server is already running with pid: {}. Remove: {} if it's not.",
+                pid,
+                path.display()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the id of the process in the file, if it's still running.
+    /// The file may be left behind if the process has been killed.
+    pub fn running_pid(path: &Path) -> Result<Option<u32>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let pid = contents
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| format!("Invalid pid file: {}: {}", path.display(), e))?;
+        Ok(Some(pid).filter(|pid| is_running(*pid)))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove pid file: {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn is_running(pid: u32) -> bool {
+    let proc = Path::new("/proc");
+    if proc.exists() {
+        proc.join(pid.to_string()).exists()
+    } else {
+        // Without procfs there is no way to tell, so the process is assumed to be running.
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prevents_second_instance() {
+        let path = std::env::temp_dir().join(format!("coolcoin_{}.pid", std::process::id()));
+        let pid_file = PidFile::acquire(&path).unwrap();
+        assert_eq!(
+            PidFile::running_pid(&path).unwrap(),
+            Some(std::process::id())
+        );
+        assert!(PidFile::acquire(&path).is_err());
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // The file of the process that no longer runs is replaced.
+        if Path::new("/proc").exists() {
+            fs::write(&path, u32::MAX.to_string()).unwrap();
+            let _pid_file = PidFile::acquire(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Wakes the poller up from another thread, e.g. when the miner has found a block.
+#[derive(Clone)]
+pub struct Waker {
+    stream: Arc<UnixStream>,
+}
+
+impl Waker {
+    pub fn wake(&self) {
+        // The write only fails if the buffer is full, in which case the poller is awake anyway.
+        let _ = (&*self.stream).write(&[1]);
+    }
+}
+
+/// Waits for the sockets to become ready, so that the events are processed as soon as they
+/// happen, without polling the sockets in a loop.
+pub struct Poller {
+    // Becomes readable when the waker is woken up.
+    wake_receiver: UnixStream,
+    waker: Waker,
+}
+
+impl Poller {
+    pub fn new() -> Result<Self, String> {
+        let (sender, receiver) = UnixStream::pair().map_err(|e| e.to_string())?;
+        sender.set_nonblocking(true).map_err(|e| e.to_string())?;
+        receiver.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(Self {
+            wake_receiver: receiver,
+            waker: Waker {
+                stream: Arc::new(sender),
+            },
+        })
+    }
+
+    pub fn waker(&self) -> Waker {
+        self.waker.clone()
+    }
+
+    /// Blocks until any of the readable sockets has data (or has been closed), any of
+    /// the writable sockets has space in its buffer, the waker is woken up,
+    /// or the timeout expires.
+    pub fn wait(
+        &mut self,
+        readable: &[RawFd],
+        writable: &[RawFd],
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let mut fds = readable
+            .iter()
+            .chain(std::iter::once(&self.wake_receiver.as_raw_fd()))
+            .map(|fd| poll_fd(*fd, libc::POLLIN))
+            .chain(writable.iter().map(|fd| poll_fd(*fd, libc::POLLOUT)))
+            .collect::<Vec<libc::pollfd>>();
+        poll(&mut fds, timeout)?;
+
+        // Many wake-ups count as one.
+        let mut buffer = [0; 64];
+        while let Ok(read_bytes) = self.wake_receiver.read(&mut buffer) {
+            if read_bytes == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Blocks until the socket has data (or has been closed), or the timeout expires.
+pub fn wait_readable(fd: RawFd, timeout: Duration) -> Result<(), String> {
+    poll(&mut [poll_fd(fd, libc::POLLIN)], timeout)
+}
+
+/// Blocks until the socket has space in its buffer, or the timeout expires.
+pub fn wait_writable(fd: RawFd, timeout: Duration) -> Result<(), String> {
+    poll(&mut [poll_fd(fd, libc::POLLOUT)], timeout)
+}
+
+fn poll(fds: &mut [libc::pollfd], timeout: Duration) -> Result<(), String> {
+    // Rounded up, so that the timer is due when the poll returns.
+    let timeout_millis = timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+    // The pointer and the length describe the slice, which outlives the call.
+    let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_millis) };
+    if result < 0 {
+        let e = io::Error::last_os_error();
+        // Interrupted by a signal, the caller checks for the events anyway.
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(format!("Failed to wait for the sockets: {}", e));
+        }
+    }
+    Ok(())
+}
+
+fn poll_fd(fd: RawFd, events: libc::c_short) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn wakes_up_on_data_or_waker() {
+        let mut poller = Poller::new().unwrap();
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+
+        let start = Instant::now();
+        poller
+            .wait(&[receiver.as_raw_fd()], &[], Duration::from_millis(50))
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        sender.write_all(&[1]).unwrap();
+        let start = Instant::now();
+        poller
+            .wait(&[receiver.as_raw_fd()], &[], Duration::from_secs(10))
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let waker = poller.waker();
+        let wake_thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            waker.wake();
+            waker.wake();
+        });
+        let start = Instant::now();
+        poller.wait(&[], &[], Duration::from_secs(10)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        wake_thread.join().unwrap();
+    }
+}
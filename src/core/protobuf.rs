@@ -0,0 +1,397 @@
+use crate::core::block::{BlockHash, BlockHeader};
+use crate::core::hash::MerkleHash;
+use crate::core::peer_connection::{BincodeEncoding, PeerMessage, PeerMessageEncoding};
+use crate::core::transaction::{OutputIndex, TransactionId, TransactionInput, TransactionOutput};
+use crate::core::{Address, Block, Sha256, Transaction};
+use prost::Message;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// The messages of proto/peer.proto, as prost-build would generate them.
+mod schema {
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct TransactionInput {
+        #[prost(bytes = "vec", tag = "1")]
+        pub utxo_id: Vec<u8>,
+        #[prost(int32, tag = "2")]
+        pub output_index: i32,
+        #[prost(bytes = "vec", tag = "3")]
+        pub coinbase_data: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct TransactionOutput {
+        #[prost(string, tag = "1")]
+        pub to: String,
+        #[prost(int64, tag = "2")]
+        pub amount: i64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Transaction {
+        #[prost(message, repeated, tag = "1")]
+        pub inputs: Vec<TransactionInput>,
+        #[prost(message, repeated, tag = "2")]
+        pub outputs: Vec<TransactionOutput>,
+        #[prost(uint32, tag = "3")]
+        pub locktime: u32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct BlockHeader {
+        #[prost(bytes = "vec", tag = "1")]
+        pub previous_block_hash: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub merkle_root: Vec<u8>,
+        #[prost(uint32, tag = "3")]
+        pub timestamp: u32,
+        #[prost(uint32, tag = "4")]
+        pub difficulty_target: u32,
+        #[prost(uint32, tag = "5")]
+        pub nonce: u32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Block {
+        #[prost(message, optional, tag = "1")]
+        pub header: Option<BlockHeader>,
+        #[prost(message, repeated, tag = "2")]
+        pub transactions: Vec<Transaction>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Empty {}
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Blocks {
+        #[prost(message, repeated, tag = "1")]
+        pub blocks: Vec<Block>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct OptionalBlock {
+        #[prost(message, optional, tag = "1")]
+        pub block: Option<Block>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct FullBlockchain {
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub active_blockchain: Vec<Vec<u8>>,
+        #[prost(message, repeated, tag = "2")]
+        pub blocks: Vec<Block>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct TransactionResult {
+        #[prost(oneof = "transaction_result::Result", tags = "1, 2")]
+        pub result: Option<transaction_result::Result>,
+    }
+
+    pub mod transaction_result {
+        #[derive(Clone, PartialEq, ::prost::Oneof)]
+        pub enum Result {
+            #[prost(bytes, tag = "1")]
+            TransactionId(Vec<u8>),
+            #[prost(string, tag = "2")]
+            Error(String),
+        }
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PeerMessage {
+        #[prost(
+            oneof = "peer_message::Message",
+            tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 15"
+        )]
+        pub message: Option<peer_message::Message>,
+    }
+
+    pub mod peer_message {
+        #[derive(Clone, PartialEq, ::prost::Oneof)]
+        pub enum Message {
+            #[prost(message, tag = "1")]
+            GetInventory(super::Empty),
+            #[prost(message, tag = "2")]
+            ResponseInventory(super::Blocks),
+            #[prost(bytes, tag = "3")]
+            GetBlock(Vec<u8>),
+            #[prost(message, tag = "4")]
+            ResponseBlock(super::OptionalBlock),
+            #[prost(message, tag = "5")]
+            SendTransaction(super::Transaction),
+            #[prost(message, tag = "6")]
+            GetFullBlockchain(super::Empty),
+            #[prost(message, tag = "7")]
+            ResponseFullBlockchain(super::FullBlockchain),
+            #[prost(message, tag = "8")]
+            ResponseTransaction(super::TransactionResult),
+            #[prost(message, tag = "9")]
+            RelayBlock(super::Block),
+            #[prost(message, tag = "10")]
+            RelayTransaction(super::Transaction),
+            #[prost(bytes, tag = "15")]
+            Other(Vec<u8>),
+        }
+    }
+}
+
+use schema::peer_message::Message as ProtoMessage;
+use schema::transaction_result::Result as ProtoTransactionResult;
+
+/// The schema-driven encoding of the peer-to-peer messages, defined by proto/peer.proto.
+/// The other messages, e.g. the clients' requests, are embedded in the node's own encoding.
+pub struct ProtobufEncoding;
+
+impl PeerMessageEncoding for ProtobufEncoding {
+    fn encode(message: &PeerMessage) -> Vec<u8> {
+        let message = match message {
+            PeerMessage::GetInventory() => ProtoMessage::GetInventory(schema::Empty {}),
+            PeerMessage::ResponseInventory(blocks) => {
+                ProtoMessage::ResponseInventory(schema::Blocks {
+                    blocks: blocks.iter().map(|block| to_proto_block(block)).collect(),
+                })
+            }
+            PeerMessage::GetBlock(hash) => ProtoMessage::GetBlock(hash.as_slice().to_vec()),
+            PeerMessage::ResponseBlock(block) => {
+                ProtoMessage::ResponseBlock(schema::OptionalBlock {
+                    block: block.as_ref().map(|block| to_proto_block(block)),
+                })
+            }
+            PeerMessage::SendTransaction(transaction) => {
+                ProtoMessage::SendTransaction(to_proto_transaction(transaction))
+            }
+            PeerMessage::GetFullBlockchain => ProtoMessage::GetFullBlockchain(schema::Empty {}),
+            PeerMessage::ResponseFullBlockchain(active_blockchain, blocks) => {
+                ProtoMessage::ResponseFullBlockchain(schema::FullBlockchain {
+                    active_blockchain: active_blockchain
+                        .iter()
+                        .map(|hash| hash.as_slice().to_vec())
+                        .collect(),
+                    blocks: blocks.iter().map(|block| to_proto_block(block)).collect(),
+                })
+            }
+            PeerMessage::ResponseTransaction(result) => {
+                ProtoMessage::ResponseTransaction(schema::TransactionResult {
+                    result: Some(match result {
+                        Ok(id) => ProtoTransactionResult::TransactionId(id.raw().bytes().to_vec()),
+                        Err(e) => ProtoTransactionResult::Error(e.clone()),
+                    }),
+                })
+            }
+            PeerMessage::RelayBlock(block) => ProtoMessage::RelayBlock(to_proto_block(block)),
+            PeerMessage::RelayTransaction(transaction) => {
+                ProtoMessage::RelayTransaction(to_proto_transaction(transaction))
+            }
+            other => ProtoMessage::Other(BincodeEncoding::encode(other)),
+        };
+        schema::PeerMessage {
+            message: Some(message),
+        }
+        .encode_to_vec()
+    }
+
+    fn decode(payload: &[u8]) -> Result<PeerMessage, String> {
+        let message = schema::PeerMessage::decode(payload)
+            .map_err(|e| e.to_string())?
+            .message
+            .ok_or_else(|| "The message isn't set.".to_string())?;
+        Ok(match message {
+            ProtoMessage::GetInventory(_) => PeerMessage::GetInventory(),
+            ProtoMessage::ResponseInventory(blocks) => {
+                PeerMessage::ResponseInventory(from_proto_blocks(blocks.blocks)?)
+            }
+            ProtoMessage::GetBlock(hash) => PeerMessage::GetBlock(BlockHash::new(sha256(&hash)?)),
+            ProtoMessage::ResponseBlock(block) => PeerMessage::ResponseBlock(match block.block {
+                Some(block) => Some(Arc::new(from_proto_block(block)?)),
+                None => None,
+            }),
+            ProtoMessage::SendTransaction(transaction) => {
+                PeerMessage::SendTransaction(from_proto_transaction(transaction)?)
+            }
+            ProtoMessage::GetFullBlockchain(_) => PeerMessage::GetFullBlockchain,
+            ProtoMessage::ResponseFullBlockchain(blockchain) => {
+                PeerMessage::ResponseFullBlockchain(
+                    blockchain
+                        .active_blockchain
+                        .iter()
+                        .map(|hash| Ok(BlockHash::new(sha256(hash)?)))
+                        .collect::<Result<Vec<_>, String>>()?,
+                    from_proto_blocks(blockchain.blocks)?,
+                )
+            }
+            ProtoMessage::ResponseTransaction(result) => {
+                PeerMessage::ResponseTransaction(match result.result {
+                    Some(ProtoTransactionResult::TransactionId(id)) => {
+                        Ok(TransactionId::new(sha256(&id)?))
+                    }
+                    Some(ProtoTransactionResult::Error(e)) => Err(e),
+                    None => return Err("The transaction result isn't set.".to_string()),
+                })
+            }
+            ProtoMessage::RelayBlock(block) => {
+                PeerMessage::RelayBlock(Arc::new(from_proto_block(block)?))
+            }
+            ProtoMessage::RelayTransaction(transaction) => {
+                PeerMessage::RelayTransaction(from_proto_transaction(transaction)?)
+            }
+            ProtoMessage::Other(payload) => BincodeEncoding::decode(&payload)?,
+        })
+    }
+}
+
+fn sha256(bytes: &[u8]) -> Result<Sha256, String> {
+    <[u8; 32]>::try_from(bytes)
+        .map(Sha256::new)
+        .map_err(|_| format!("Expected a 32 byte hash, but got {} bytes.", bytes.len()))
+}
+
+fn to_proto_transaction(transaction: &Transaction) -> schema::Transaction {
+    schema::Transaction {
+        inputs: transaction
+            .inputs()
+            .iter()
+            .map(|input| schema::TransactionInput {
+                utxo_id: input.utxo_id().raw().bytes().to_vec(),
+                output_index: input.output_index().value(),
+                coinbase_data: input.coinbase_data().to_vec(),
+            })
+            .collect(),
+        outputs: transaction
+            .outputs()
+            .iter()
+            .map(|output| schema::TransactionOutput {
+                to: output.to().to_string(),
+                amount: output.amount().value(),
+            })
+            .collect(),
+        locktime: transaction.locktime(),
+    }
+}
+
+fn from_proto_transaction(transaction: schema::Transaction) -> Result<Transaction, String> {
+    let inputs = transaction
+        .inputs
+        .into_iter()
+        .map(|input| {
+            let decoded = TransactionInput::new(
+                TransactionId::new(sha256(&input.utxo_id)?),
+                OutputIndex::new(input.output_index),
+            );
+            match (decoded.is_coinbase(), input.coinbase_data.is_empty()) {
+                (true, _) => Ok(TransactionInput::new_coinbase_with_data(
+                    input.coinbase_data,
+                )),
+                (false, true) => Ok(decoded),
+                (false, false) => {
+                    Err("Only the coinbase input may have coinbase data.".to_string())
+                }
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let outputs = transaction
+        .outputs
+        .into_iter()
+        .map(|output| {
+            Ok(TransactionOutput::new(
+                Address::try_from(output.to)?,
+                output.amount.into(),
+            ))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Transaction::new(inputs, outputs, transaction.locktime)
+}
+
+fn to_proto_block(block: &Block) -> schema::Block {
+    let header = block.header();
+    schema::Block {
+        header: Some(schema::BlockHeader {
+            previous_block_hash: header.previous_block_hash().as_slice().to_vec(),
+            merkle_root: header.merkle_root().as_slice().to_vec(),
+            timestamp: header.timestamp(),
+            difficulty_target: header.difficulty_target(),
+            nonce: header.nonce(),
+        }),
+        transactions: block
+            .transactions()
+            .iter()
+            .map(to_proto_transaction)
+            .collect(),
+    }
+}
+
+fn from_proto_block(block: schema::Block) -> Result<Block, String> {
+    let header = block
+        .header
+        .ok_or_else(|| "The block header isn't set.".to_string())?;
+    let header = BlockHeader::new(
+        BlockHash::new(sha256(&header.previous_block_hash)?),
+        MerkleHash::new(sha256(&header.merkle_root)?),
+        header.timestamp,
+        header.difficulty_target,
+        header.nonce,
+    );
+    let transactions = block
+        .transactions
+        .into_iter()
+        .map(from_proto_transaction)
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(Block::new(header, transactions))
+}
+
+fn from_proto_blocks(blocks: Vec<schema::Block>) -> Result<Vec<Arc<Block>>, String> {
+    blocks
+        .into_iter()
+        .map(|block| Ok(Arc::new(from_proto_block(block)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::BlockchainManager;
+
+    #[test]
+    fn round_trips_messages() {
+        let genesis = Arc::new(BlockchainManager::genesis_block());
+        let coinbase = genesis.transactions()[0].clone();
+        let spend = Transaction::new(
+            vec![TransactionInput::new(*coinbase.id(), OutputIndex::new(0))],
+            vec![TransactionOutput::new(
+                Address::new("bob".to_string()),
+                10.into(),
+            )],
+            7,
+        )
+        .unwrap();
+        let messages = vec![
+            PeerMessage::GetInventory(),
+            PeerMessage::ResponseInventory(vec![genesis.clone()]),
+            PeerMessage::GetBlock(*genesis.id()),
+            PeerMessage::ResponseBlock(None),
+            PeerMessage::ResponseBlock(Some(genesis.clone())),
+            PeerMessage::SendTransaction(spend.clone()),
+            PeerMessage::GetFullBlockchain,
+            PeerMessage::ResponseFullBlockchain(vec![*genesis.id()], vec![genesis.clone()]),
+            PeerMessage::ResponseTransaction(Ok(*spend.id())),
+            PeerMessage::ResponseTransaction(Err("Double spend.".to_string())),
+            PeerMessage::RelayBlock(genesis.clone()),
+            PeerMessage::RelayTransaction(coinbase),
+            // Embedded in the node's own encoding.
+            PeerMessage::GetBlockHash(3),
+        ];
+        for message in &messages {
+            let decoded = ProtobufEncoding::decode(&ProtobufEncoding::encode(message)).unwrap();
+            assert_eq!(
+                serde_json::to_string(&decoded).unwrap(),
+                serde_json::to_string(message).unwrap()
+            );
+        }
+
+        let other = schema::PeerMessage {
+            message: Some(ProtoMessage::GetBlock(vec![0; 31])),
+        };
+        assert!(ProtobufEncoding::decode(&other.encode_to_vec()).is_err());
+        assert!(ProtobufEncoding::decode(&[]).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::core::Sha256;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+/// Remembers the hashes of the recently seen transactions and blocks, in constant memory,
+/// so that the node doesn't process and announce the same item again when it's relayed by
+/// more than one peer.
+///
+/// It's a bloom filter that rolls over: the items are inserted to the current generation, and
+/// when it's full, the previous generation is cleared and becomes the current one. An item is
+/// remembered for at least `capacity` insertions.
+/// There are no false negatives within that window, but there are rare false positives,
+/// so it's only used where skipping an unseen item is harmless.
+pub struct RecentlySeen {
+    generations: [Vec<u64>; 2],
+    // Index of the generation that the items are inserted to.
+    current: usize,
+    // Number of items in the current generation.
+    count: usize,
+    capacity: usize,
+    num_hashes: u32,
+    // Salted, so that the peers can't craft the hashes that collide in every node.
+    hasher: RandomState,
+}
+
+impl RecentlySeen {
+    /// Creates the filter that remembers at least `capacity` items, with at most
+    /// `false_positive_rate` of the unseen items reported as seen.
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        // The optimal number of bits and hash functions for the capacity and the rate.
+        // Both generations are checked, which roughly doubles the rate, so each one
+        // has half of it.
+        let num_bits =
+            (-(capacity as f64) * (false_positive_rate / 2.0).ln() / (ln2 * ln2)).ceil() as usize;
+        let num_words = (num_bits / 64).max(1) + 1;
+        let num_hashes = ((num_words * 64) as f64 / capacity as f64 * ln2).round() as u32;
+        Self {
+            generations: [vec![0; num_words], vec![0; num_words]],
+            current: 0,
+            count: 0,
+            capacity,
+            num_hashes: num_hashes.clamp(1, 32),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Returns whether the item has been seen recently.
+    pub fn contains(&self, hash: &Sha256) -> bool {
+        self.generations.iter().any(|generation| {
+            self.bits(hash, generation.len())
+                .all(|(word, bit)| generation[word] & bit != 0)
+        })
+    }
+
+    /// Remembers the item, and returns whether it hasn't been seen recently.
+    pub fn insert(&mut self, hash: &Sha256) -> bool {
+        if self.contains(hash) {
+            return false;
+        }
+        if self.count == self.capacity {
+            self.current = 1 - self.current;
+            self.generations[self.current]
+                .iter_mut()
+                .for_each(|word| *word = 0);
+            self.count = 0;
+        }
+        let num_words = self.generations[self.current].len();
+        let bits = self.bits(hash, num_words).collect::<Vec<_>>();
+        let generation = &mut self.generations[self.current];
+        for (word, bit) in bits {
+            generation[word] |= bit;
+        }
+        self.count += 1;
+        true
+    }
+
+    // Returns the word index and the mask of each bit of the item.
+    fn bits<'a>(
+        &'a self,
+        hash: &'a Sha256,
+        num_words: usize,
+    ) -> impl Iterator<Item = (usize, u64)> + 'a {
+        let num_bits = num_words as u64 * 64;
+        // Two hashes are combined to simulate the rest (Kirsch and Mitzenmacher).
+        let h1 = self.hasher.hash_one((0u8, hash.bytes()));
+        let h2 = self.hasher.hash_one((1u8, hash.bytes())) | 1;
+        (0..self.num_hashes as u64).map(move |i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % num_bits;
+            ((bit / 64) as usize, 1 << (bit % 64))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::hash::hash;
+
+    #[test]
+    fn remembers_recent_items() {
+        let mut seen = RecentlySeen::new(100, 0.000_000_001);
+        let items = (0..250u32)
+            .map(|i| hash(&i.to_le_bytes()))
+            .collect::<Vec<_>>();
+
+        for item in &items[..100] {
+            assert!(seen.insert(item));
+        }
+        assert!(items[..100].iter().all(|item| seen.contains(item)));
+        assert!(!seen.insert(&items[0]));
+
+        // The first generation is still remembered while the second one fills up.
+        for item in &items[100..200] {
+            assert!(seen.insert(item));
+        }
+        assert!(items[..200].iter().all(|item| seen.contains(item)));
+
+        // The first generation is forgotten once the third one starts.
+        for item in &items[200..] {
+            seen.insert(item);
+        }
+        assert!(items[100..].iter().all(|item| seen.contains(item)));
+        let false_positives = items[..100]
+            .iter()
+            .filter(|item| seen.contains(item))
+            .count();
+        assert_eq!(false_positives, 0, "false positives: {}", false_positives);
+    }
+}
@@ -0,0 +1,444 @@
+use crate::core::as_hex;
+use crate::core::json_rpc::{
+    JsonRpcError, JsonRpcHandler, JsonRpcRequest, JsonRpcResponse, FORBIDDEN, INVALID_REQUEST,
+    PARSE_ERROR,
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// The maximum size of the request body, which is plenty for any of the requests.
+const MAX_BODY_SIZE: usize = 10_000_000;
+// The maximum length of the request line and of each header, and the number of the headers.
+pub(crate) const MAX_HEADER_LENGTH: usize = 8192;
+pub(crate) const MAX_HEADERS: usize = 100;
+// The number of the connections served at the same time, each in its own thread.
+const MAX_CONNECTIONS: usize = 32;
+// Methods that operate the node rather than query it, which the node allows only for its
+// local clients. Since the requests are forwarded over a local connection, the server checks
+// the caller's address itself.
+const PRIVILEGED_METHODS: [&str; 5] = [
+    "dumpstate",
+    "logging",
+    "prioritisetransaction",
+    "setban",
+    "stop",
+];
+
+/// The name of the file in the data directory with the credentials generated by the daemon,
+/// if they aren't given on the command line.
+pub const COOKIE_FILE: &str = ".cookie";
+const COOKIE_USER: &str = "__cookie__";
+
+/// The HTTP request, of which only the parts needed to serve JSON-RPC are kept.
+#[derive(Debug, PartialEq)]
+pub(crate) struct HttpRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    // The value of the Authorization header, if any.
+    pub(crate) authorization: Option<String>,
+    pub(crate) body: Vec<u8>,
+}
+
+/// The user name and password that the callers authenticate with, using the HTTP basic
+/// authentication, e.g. `curl --user USER:PASSWORD`.
+#[derive(Debug, Clone)]
+pub struct RpcCredentials {
+    user: String,
+    password: String,
+}
+
+impl RpcCredentials {
+    pub fn new(user: String, password: String) -> Self {
+        Self { user, password }
+    }
+
+    /// Generates the random password for this run of the daemon, and writes the credentials
+    /// as USER:PASSWORD to the cookie file in the data directory, which only the user running
+    /// the daemon can read.
+    pub fn generate_cookie(data_dir: &Path) -> Result<Self, String> {
+        let mut password = [0; 32];
+        getrandom::getrandom(&mut password).map_err(|e| e.to_string())?;
+        let credentials = Self::new(COOKIE_USER.to_string(), as_hex(&password));
+        let path = data_dir.join(COOKIE_FILE);
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| write!(file, "{}:{}", credentials.user, credentials.password))
+            .map_err(|e| format!("Failed to write the cookie file: {}: {}", path.display(), e))?;
+        Ok(credentials)
+    }
+
+    // Checks the value of the Authorization header, e.g. "Basic dXNlcjpwYXNzd29yZA==".
+    fn authenticate(&self, authorization: Option<&str>) -> bool {
+        let decoded = match authorization
+            .and_then(|authorization| authorization.strip_prefix("Basic "))
+            .and_then(|encoded| BASE64.decode(encoded.trim()).ok())
+        {
+            Some(decoded) => decoded,
+            None => return false,
+        };
+        let expected = format!("{}:{}", self.user, self.password);
+        // Compares all bytes, so that the time doesn't tell how much of the password matches.
+        decoded.len() == expected.len()
+            && decoded
+                .iter()
+                .zip(expected.as_bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Limits the number of the connections that the server serves at the same time,
+/// so that it doesn't spawn a thread for each of an unbounded number of connections.
+#[derive(Clone)]
+pub(crate) struct ConnectionLimit {
+    connections: Arc<AtomicUsize>,
+    max_connections: usize,
+}
+
+/// Counts the connection towards the limit until it's dropped.
+pub(crate) struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionLimit {
+    pub(crate) fn new(max_connections: usize) -> Self {
+        Self {
+            connections: Arc::new(AtomicUsize::new(0)),
+            max_connections,
+        }
+    }
+
+    /// Returns the slot for the new connection, or None if the limit has been reached.
+    pub(crate) fn acquire(&self) -> Option<ConnectionSlot> {
+        self.connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |connections| {
+                (connections < self.max_connections).then(|| connections + 1)
+            })
+            .ok()
+            .map(|_| ConnectionSlot(self.connections.clone()))
+    }
+
+    /// Serves each accepted connection in its own thread, as long as the limit allows,
+    /// otherwise the connection is turned away with 503 Service Unavailable.
+    pub(crate) fn serve_incoming(
+        self,
+        listener: TcpListener,
+        name: &'static str,
+        serve: impl Fn(TcpStream) -> Result<(), String> + Send + Sync + 'static,
+    ) {
+        let serve = Arc::new(serve);
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(target: "rpc", "Error while accepting the {} connection: {}", name, e);
+                    continue;
+                }
+            };
+            let slot = match self.acquire() {
+                Some(slot) => slot,
+                None => {
+                    warn!(target: "rpc", "Too many {} connections, the connection is refused.", name);
+                    let _ = stream.write_all(
+                        b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    );
+                    continue;
+                }
+            };
+            let serve = serve.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve(stream) {
+                    warn!(target: "rpc", "Error while serving the {} request: {}", name, e);
+                }
+                drop(slot);
+            });
+        }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Serves JSON-RPC requests over plain HTTP (POST /), so that standard tools like curl
+/// and JSON-RPC client libraries can talk to the node.
+/// Each connection is served in its own thread, up to `MAX_CONNECTIONS` at a time, and
+/// the authenticated requests are forwarded to the node.
+pub struct RpcServer {}
+
+impl RpcServer {
+    /// Starts serving the requests in a separate thread. The address may be only the port,
+    /// in which case the server listens on the loopback interface.
+    pub fn start_async(
+        rpc_address: &str,
+        node_address: String,
+        credentials: RpcCredentials,
+        enable_logging: bool,
+    ) -> Result<(), String> {
+        const NODE_TIMEOUT: Duration = Duration::from_secs(10);
+        let listener =
+            TcpListener::bind(Self::bind_address(rpc_address)).map_err(|e| e.to_string())?;
+        let handler = JsonRpcHandler::new(node_address, NODE_TIMEOUT);
+        thread::spawn(move || {
+            ConnectionLimit::new(MAX_CONNECTIONS).serve_incoming(listener, "RPC", move |stream| {
+                Self::serve(stream, &handler, &credentials, enable_logging)
+            })
+        });
+        Ok(())
+    }
+
+    /// Returns the address to listen on, which is on the loopback interface if only the port
+    /// is given.
+    pub(crate) fn bind_address(address: &str) -> String {
+        match address.parse::<u16>() {
+            Ok(port) => format!("127.0.0.1:{}", port),
+            Err(_) => address.to_string(),
+        }
+    }
+
+    fn serve(
+        stream: TcpStream,
+        handler: &JsonRpcHandler,
+        credentials: &RpcCredentials,
+        enable_logging: bool,
+    ) -> Result<(), String> {
+        const READ_TIMEOUT: Duration = Duration::from_secs(30);
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let is_local = stream
+            .peer_addr()
+            .map(|address| address.ip().is_loopback())
+            .map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut stream = stream;
+        let request = match Self::read_request(&mut reader) {
+            Ok(request) => request,
+            Err(e) => return Self::write_response(&mut stream, "400 Bad Request", e.as_bytes()),
+        };
+        if !credentials.authenticate(request.authorization.as_deref()) {
+            return Self::write_response(&mut stream, "401 Unauthorized", b"");
+        }
+        if request.method != "POST" {
+            return Self::write_response(&mut stream, "405 Method Not Allowed", b"");
+        }
+        if request.path != "/" {
+            return Self::write_response(&mut stream, "404 Not Found", b"");
+        }
+        if enable_logging {
+            info!(target: "rpc", "RPC request: {}", String::from_utf8_lossy(&request.body));
+        }
+        let response = Self::handle_body(&request.body, handler, is_local);
+        let body = serde_json::to_vec(&response).map_err(|e| e.to_string())?;
+        Self::write_response(&mut stream, "200 OK", &body)
+    }
+
+    fn handle_body(body: &[u8], handler: &JsonRpcHandler, is_local: bool) -> JsonRpcResponse {
+        let value = match serde_json::from_slice::<Value>(body) {
+            Ok(value) => value,
+            Err(e) => {
+                return JsonRpcResponse::new(
+                    Value::Null,
+                    Err(JsonRpcError::new(PARSE_ERROR, e.to_string())),
+                )
+            }
+        };
+        let id = value.get("id").cloned().unwrap_or(Value::Null);
+        match serde_json::from_value::<JsonRpcRequest>(value) {
+            Ok(request) if !is_local && Self::is_privileged(&request) => JsonRpcResponse::new(
+                id,
+                Err(JsonRpcError::new(
+                    FORBIDDEN,
+                    format!(
+                        "Method: {} is only allowed from the local host.",
+                        request.method
+                    ),
+                )),
+            ),
+            Ok(request) => handler.handle(&request),
+            Err(e) => {
+                JsonRpcResponse::new(id, Err(JsonRpcError::new(INVALID_REQUEST, e.to_string())))
+            }
+        }
+    }
+
+    // Anyone can query the log levels, but only the local callers can change them.
+    fn is_privileged(request: &JsonRpcRequest) -> bool {
+        PRIVILEGED_METHODS.contains(&request.method.as_str())
+            && !(request.method == "logging" && request.params.is_empty())
+    }
+
+    pub(crate) fn read_request(reader: &mut impl BufRead) -> Result<HttpRequest, String> {
+        let request_line = Self::read_line(reader)?;
+        let mut tokens = request_line.split_whitespace();
+        let (method, path) = match (tokens.next(), tokens.next()) {
+            (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+            _ => return Err(format!("Invalid request line: {}", request_line.trim_end())),
+        };
+
+        let mut content_length = 0;
+        let mut authorization = None;
+        for num_headers in 0.. {
+            let header = Self::read_line(reader)?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if num_headers == MAX_HEADERS {
+                return Err(format!("More than: {} headers.", MAX_HEADERS));
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("authorization") {
+                    authorization = Some(value.trim().to_string());
+                } else if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|e| format!("Invalid content length: {}", e))?;
+                }
+            }
+        }
+        if content_length > MAX_BODY_SIZE {
+            return Err(format!(
+                "Request body of: {} bytes is too large.",
+                content_length
+            ));
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+        Ok(HttpRequest {
+            method,
+            path,
+            authorization,
+            body,
+        })
+    }
+
+    // Reads the line of the request's head, which can't be longer than `MAX_HEADER_LENGTH`.
+    pub(crate) fn read_line(reader: &mut impl BufRead) -> Result<String, String> {
+        let mut line = String::new();
+        reader
+            .by_ref()
+            .take(MAX_HEADER_LENGTH as u64 + 1)
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())?;
+        if line.len() > MAX_HEADER_LENGTH {
+            return Err(format!(
+                "Request line or header is longer than: {} bytes.",
+                MAX_HEADER_LENGTH
+            ));
+        }
+        Ok(line)
+    }
+
+    fn write_response(stream: &mut TcpStream, status: &str, body: &[u8]) -> Result<(), String> {
+        // The clients ask for the credentials when they are challenged.
+        let challenge = match status {
+            "401 Unauthorized" => "WWW-Authenticate: Basic realm=\"jsonrpc\"\r\n",
+            _ => "",
+        };
+        let header = format!(
+            "HTTP/1.1 {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            challenge,
+            body.len()
+        );
+        stream
+            .write_all(header.as_bytes())
+            .and_then(|()| stream.write_all(body))
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::json_rpc::{METHOD_NOT_FOUND, NODE_UNAVAILABLE};
+
+    #[test]
+    fn reads_http_request() {
+        let body = r#"{"id":1,"method":"getmininginfo"}"#;
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic dTpw\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let request = RpcServer::read_request(&mut request.as_bytes()).unwrap();
+        assert_eq!(
+            request,
+            HttpRequest {
+                method: "POST".to_string(),
+                path: "/".to_string(),
+                authorization: Some("Basic dTpw".to_string()),
+                body: body.as_bytes().to_vec(),
+            }
+        );
+
+        assert!(RpcServer::read_request(&mut "\r\n".as_bytes()).is_err());
+        let long_header = format!(
+            "GET / HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_LENGTH)
+        );
+        assert!(RpcServer::read_request(&mut long_header.as_bytes()).is_err());
+        let many_headers = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X: a\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(RpcServer::read_request(&mut many_headers.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn authenticates_and_limits_callers() {
+        let credentials = RpcCredentials::new("u".to_string(), "p".to_string());
+        assert!(credentials.authenticate(Some("Basic dTpw")));
+        assert!(!credentials.authenticate(Some("Basic dTpx")));
+        assert!(!credentials.authenticate(Some("Bearer dTpw")));
+        assert!(!credentials.authenticate(None));
+
+        // The node would take the forwarded request as local.
+        let handler = JsonRpcHandler::new("127.0.0.1:0".to_string(), Duration::from_secs(1));
+        let stop = br#"{"id":1,"method":"stop"}"#;
+        let response = RpcServer::handle_body(stop, &handler, false);
+        assert_eq!(response.error.unwrap().code, FORBIDDEN);
+        let response = RpcServer::handle_body(stop, &handler, true);
+        assert_eq!(response.error.unwrap().code, NODE_UNAVAILABLE);
+        let query = br#"{"id":2,"method":"logging"}"#;
+        let response = RpcServer::handle_body(query, &handler, false);
+        assert_eq!(response.error.unwrap().code, NODE_UNAVAILABLE);
+
+        let limit = ConnectionLimit::new(1);
+        let slot = limit.acquire().unwrap();
+        assert!(limit.acquire().is_none());
+        drop(slot);
+        assert!(limit.acquire().is_some());
+        assert_eq!(RpcServer::bind_address("8335"), "127.0.0.1:8335");
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let handler = JsonRpcHandler::new("127.0.0.1:0".to_string(), Duration::from_secs(1));
+        let response = RpcServer::handle_body(b"{", &handler, true);
+        assert_eq!(response.error.unwrap().code, PARSE_ERROR);
+        let response = RpcServer::handle_body(br#"{"id":3}"#, &handler, true);
+        assert_eq!(response.id, Value::from(3));
+        assert_eq!(response.error.unwrap().code, INVALID_REQUEST);
+        let response = RpcServer::handle_body(br#"{"id":4,"method":"foo"}"#, &handler, true);
+        assert_eq!(response.error.unwrap().code, METHOD_NOT_FOUND);
+    }
+}
@@ -0,0 +1,271 @@
+use serde::de::{Error, Visitor};
+use serde::{Deserializer, Serializer};
+use std::fmt::Formatter;
+
+/// The data with the canonical binary encoding, in the style of Bitcoin's wire format:
+/// the integers are little-endian, and the lengths of the variable-size fields are prefixed
+/// as compact sizes. Unlike bincode's, the encoding doesn't depend on the layout of the Rust
+/// types, so it's stable across the versions of the crates, and the sizes of the blocks and
+/// the transactions, e.g. for the fees, are measured in it.
+pub trait Encodable {
+    fn encode(&self, output: &mut Vec<u8>);
+
+    fn encoded_size(&self) -> usize {
+        let mut output = vec![];
+        self.encode(&mut output);
+        output.len()
+    }
+}
+
+pub trait Decodable: Sized {
+    fn decode(input: &mut Reader) -> Result<Self, String>;
+}
+
+/// The encoded bytes, which are consumed as they're decoded.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err(format!(
+                "Unexpected end of data, expected {} more bytes, but only {} are left.",
+                len,
+                self.bytes.len()
+            ));
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+/// Returns the canonical encoding of the value.
+pub fn serialize<T: Encodable>(value: &T) -> Vec<u8> {
+    let mut output = vec![];
+    value.encode(&mut output);
+    output
+}
+
+/// Decodes the value from all of the bytes, rejecting the trailing ones.
+pub fn deserialize<T: Decodable>(bytes: &[u8]) -> Result<T, String> {
+    let mut input = Reader::new(bytes);
+    let value = T::decode(&mut input)?;
+    match input.remaining() {
+        0 => Ok(value),
+        remaining => Err(format!("{} unexpected bytes after the data.", remaining)),
+    }
+}
+
+/// Writes the number in 1, 3, 5 or 9 bytes: the values below 0xfd as a single byte, and
+/// the larger ones as 0xfd, 0xfe or 0xff followed by the 2, 4 or 8 byte little-endian value.
+pub fn write_compact_size(output: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => output.push(value as u8),
+        0xfd..=0xffff => {
+            output.push(0xfd);
+            output.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            output.push(0xfe);
+            output.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        _ => {
+            output.push(0xff);
+            output.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Reads the number written by `write_compact_size`. The numbers that aren't written in
+/// the fewest bytes are rejected, so that each value has a single encoding.
+pub fn read_compact_size(input: &mut Reader) -> Result<u64, String> {
+    let (value, min) = match input.read_array::<1>()?[0] {
+        0xfd => (u16::from_le_bytes(input.read_array()?) as u64, 0xfd),
+        0xfe => (u32::from_le_bytes(input.read_array()?) as u64, 0x10000),
+        0xff => (u64::from_le_bytes(input.read_array()?), 0x1_0000_0000),
+        value => (value as u64, 0),
+    };
+    match value >= min {
+        true => Ok(value),
+        false => Err(format!("Non-canonical compact size: {}", value)),
+    }
+}
+
+// The number of the elements or bytes that follows, which can't be more than the remaining
+// bytes, so that a forged length doesn't allocate more memory than the data.
+fn read_length(input: &mut Reader) -> Result<usize, String> {
+    let length = read_compact_size(input)?;
+    match length <= input.remaining() as u64 {
+        true => Ok(length as usize),
+        false => Err(format!(
+            "Length: {} is larger than the remaining {} bytes.",
+            length,
+            input.remaining()
+        )),
+    }
+}
+
+macro_rules! impl_integer {
+    ($($integer:ty),*) => {
+        $(
+            impl Encodable for $integer {
+                fn encode(&self, output: &mut Vec<u8>) {
+                    output.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decodable for $integer {
+                fn decode(input: &mut Reader) -> Result<Self, String> {
+                    Ok(Self::from_le_bytes(input.read_array()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(u8, u32, i32, u64, i64);
+
+impl Encodable for [u8; 32] {
+    fn encode(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(self);
+    }
+}
+
+impl Decodable for [u8; 32] {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        input.read_array()
+    }
+}
+
+impl Encodable for String {
+    fn encode(&self, output: &mut Vec<u8>) {
+        write_compact_size(output, self.len() as u64);
+        output.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decodable for String {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        let length = read_length(input)?;
+        String::from_utf8(input.read_bytes(length)?.to_vec())
+            .map_err(|e| format!("Invalid string: {}", e))
+    }
+}
+
+/// The number of the elements followed by the elements, e.g. the bytes or the transactions.
+impl<T: Encodable> Encodable for Vec<T> {
+    fn encode(&self, output: &mut Vec<u8>) {
+        write_compact_size(output, self.len() as u64);
+        for element in self {
+            element.encode(output);
+        }
+    }
+}
+
+impl<T: Decodable> Decodable for Vec<T> {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        // Each element takes at least one byte.
+        let length = read_length(input)?;
+        let mut elements = Vec::with_capacity(length);
+        for _ in 0..length {
+            elements.push(T::decode(input)?);
+        }
+        Ok(elements)
+    }
+}
+
+/// Serializes the value with serde as its canonical encoding, as a byte array, e.g. for
+/// the blocks and transactions in the peer messages, which are otherwise encoded by bincode.
+pub fn serialize_bytes<T: Encodable, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(&serialize(value))
+}
+
+/// Deserializes the value serialized by `serialize_bytes`.
+pub fn deserialize_bytes<'de, T: Decodable, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    let bytes = deserializer.deserialize_byte_buf(BytesVisitor)?;
+    deserialize(&bytes).map_err(D::Error::custom)
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a byte array")
+    }
+
+    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_compact_sizes_canonically() {
+        for (value, hex) in [
+            (0, "00"),
+            (0xfc, "fc"),
+            (0xfd, "fdfd00"),
+            (0xffff, "fdffff"),
+            (0x10000, "fe00000100"),
+            (0xffff_ffff, "feffffffff"),
+            (0x1_0000_0000, "ff0000000001000000"),
+        ] {
+            let mut output = vec![];
+            write_compact_size(&mut output, value);
+            assert_eq!(hex::encode(&output), hex);
+            assert_eq!(
+                read_compact_size(&mut Reader::new(&output)),
+                Ok(value),
+                "{}",
+                hex
+            );
+        }
+        // The values that fit into fewer bytes.
+        for hex in ["fdfc00", "feffff0000", "ffffffffff00000000"] {
+            let bytes = hex::decode(hex).unwrap();
+            assert!(read_compact_size(&mut Reader::new(&bytes)).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_and_trailing_data() {
+        let strings = vec!["coolcoin".to_string(), String::new()];
+        let bytes = serialize(&strings);
+        assert_eq!(hex::encode(&bytes), "0208636f6f6c636f696e00");
+        assert_eq!(deserialize::<Vec<String>>(&bytes), Ok(strings));
+        assert!(deserialize::<Vec<String>>(&bytes[..bytes.len() - 1]).is_err());
+        assert!(deserialize::<Vec<String>>(&[&bytes[..], &[0]].concat()).is_err());
+        // The length that is larger than the data isn't allocated.
+        assert!(deserialize::<Vec<u8>>(&hex::decode("ff00000000000000ff").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The files in which the node keeps its data between the restarts. They are accessed through
+/// the trait, so that the tests can simulate the failing disk, and check that the data
+/// survives the crashes, or that the damage is detected.
+pub trait Storage: Send + Sync {
+    /// Returns the contents of the file, or None if it doesn't exist.
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>, String>;
+
+    /// Creates or truncates the file, writes the contents and flushes them to the disk.
+    /// If it fails, the file may be left with only a part of the contents.
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<(), String>;
+
+    /// Appends the contents to the file, which is created if it doesn't exist, and flushes
+    /// them to the disk. If it fails, only a part of the contents may have been appended.
+    fn append(&self, path: &Path, contents: &[u8]) -> Result<(), String>;
+
+    /// Replaces the destination with the source file atomically.
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), String>;
+}
+
+/// The storage in the local file system.
+pub struct FileStorage;
+
+impl Storage for FileStorage {
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>, String> {
+        match fs::read(path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read: {}: {}", path.display(), e)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<(), String> {
+        create_parent_dir(path)?;
+        let mut file = File::create(path).map_err(|e| e.to_string())?;
+        file.write_all(contents).map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> Result<(), String> {
+        create_parent_dir(path)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| e.to_string())?;
+        file.write_all(contents).map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), String> {
+        fs::rename(from, to).map_err(|e| e.to_string())
+    }
+}
+
+fn create_parent_dir(path: &Path) -> Result<(), String> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Parses the file saved by `save`, or returns None if it doesn't exist.
+/// The file that can't be parsed, e.g. because it has been damaged, is an error rather than
+/// treated as missing, so that its data isn't lost when the file is saved again.
+pub fn load_json<T: serde::de::DeserializeOwned>(
+    storage: &dyn Storage,
+    path: &Path,
+    name: &str,
+) -> Result<Option<T>, String> {
+    match storage.read(path)? {
+        Some(contents) => serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|e| format!("Failed to load {} from: {}: {}", name, path.display(), e)),
+        None => Ok(None),
+    }
+}
+
+/// Writes the contents to a temporary file, which then replaces the file, so that the file has
+/// either the previous or the new contents, even if the node crashes while saving it.
+pub fn save(storage: &dyn Storage, path: &Path, contents: &[u8]) -> Result<(), String> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    let temporary_path = PathBuf::from(temporary_path);
+    storage.write(&temporary_path, contents)?;
+    storage.rename(&temporary_path, path)
+}
+
+/// Reads the records of the file, which are appended one per line by `append_record`.
+/// The last record without the line ending has been torn by a crash while it was appended,
+/// so it's dropped, and the file is repaired, so that the next record starts on its own line.
+pub fn read_records(storage: &dyn Storage, path: &Path) -> Result<Vec<String>, String> {
+    let contents = match storage.read(path)? {
+        Some(contents) => contents,
+        None => return Ok(vec![]),
+    };
+    let complete = match contents.iter().rposition(|byte| *byte == b'\n') {
+        Some(position) => &contents[..position + 1],
+        None => &contents[..0],
+    };
+    if complete.len() < contents.len() {
+        save(storage, path, complete)?;
+    }
+    String::from_utf8(complete.to_vec())
+        .map_err(|e| format!("Failed to read: {}: {}", path.display(), e))
+        .map(|records| records.lines().map(str::to_string).collect())
+}
+
+/// Appends the record, which must not contain line endings, to the file.
+pub fn append_record(storage: &dyn Storage, path: &Path, record: &str) -> Result<(), String> {
+    storage.append(path, format!("{}\n", record).as_bytes())
+}
+
+/// The records that are kept in memory, and appended to the file one JSON object per line
+/// as they are recorded, e.g. the blocks found by the node's miner.
+pub struct RecordLog<T> {
+    records: Vec<T>,
+    path: PathBuf,
+    storage: Arc<dyn Storage>,
+    // Whether the last record may have been appended in part, in which case the file is
+    // rewritten before the next one.
+    is_torn: bool,
+}
+
+impl<T: Serialize + DeserializeOwned> RecordLog<T> {
+    /// Loads the records from the file in the storage, or starts with none if it doesn't exist.
+    /// The name of the records is used in the errors.
+    pub fn load(storage: Arc<dyn Storage>, path: &Path, name: &str) -> Result<Self, String> {
+        let mut records = vec![];
+        for line in read_records(storage.as_ref(), path)? {
+            if line.is_empty() {
+                continue;
+            }
+            records.push(
+                serde_json::from_str(&line).map_err(|e| {
+                    format!("Failed to load {} from: {}: {}", name, path.display(), e)
+                })?,
+            );
+        }
+        Ok(Self {
+            records,
+            path: path.to_path_buf(),
+            storage,
+            is_torn: false,
+        })
+    }
+
+    pub fn records(&self) -> &Vec<T> {
+        &self.records
+    }
+
+    pub fn record(&mut self, record: T) -> Result<(), String> {
+        if self.is_torn {
+            let mut contents = String::new();
+            for record in &self.records {
+                contents += &serde_json::to_string(record).map_err(|e| e.to_string())?;
+                contents += "\n";
+            }
+            save(self.storage.as_ref(), &self.path, contents.as_bytes())?;
+            self.is_torn = false;
+        }
+        let line = serde_json::to_string(&record).map_err(|e| e.to_string())?;
+        if let Err(e) = append_record(self.storage.as_ref(), &self.path, &line) {
+            self.is_torn = true;
+            return Err(e);
+        }
+        self.records.push(record);
+        Ok(())
+    }
+}
+
+/// The storage in memory that fails the way the disks do, for the tests.
+#[derive(Default)]
+pub struct FaultyStorage {
+    state: Mutex<FaultyStorageState>,
+}
+
+#[derive(Default)]
+struct FaultyStorageState {
+    files: HashMap<PathBuf, Vec<u8>>,
+    // The number of bytes that are written before the writes start failing.
+    bytes_until_failure: Option<usize>,
+    // The number of bytes of each write that are stored, the rest is lost silently.
+    max_write_size: Option<usize>,
+}
+
+impl FaultyStorage {
+    /// Fails the write that goes past the given number of bytes, after storing the bytes before
+    /// it, and each write after it, as if the node has crashed or the disk has filled up.
+    pub fn fail_at_byte(&self, bytes: usize) {
+        self.state.lock().unwrap().bytes_until_failure = Some(bytes);
+    }
+
+    /// Only stores the given number of bytes of each write, but reports it as successful,
+    /// like the disk that loses the writes that it hasn't flushed before the power went out.
+    pub fn short_writes(&self, max_write_size: usize) {
+        self.state.lock().unwrap().max_write_size = Some(max_write_size);
+    }
+
+    /// Makes the writes succeed again, as if the node has been restarted.
+    pub fn clear_faults(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.bytes_until_failure = None;
+        state.max_write_size = None;
+    }
+
+    /// Truncates the file, as if the machine has crashed before all of it reached the disk.
+    pub fn tear(&self, path: &Path, len: usize) {
+        if let Some(file) = self.state.lock().unwrap().files.get_mut(path) {
+            file.truncate(len);
+        }
+    }
+
+    /// Flips the bits of the byte in the file.
+    pub fn corrupt(&self, path: &Path, offset: usize) {
+        if let Some(byte) = self
+            .state
+            .lock()
+            .unwrap()
+            .files
+            .get_mut(path)
+            .and_then(|file| file.get_mut(offset))
+        {
+            *byte = !*byte;
+        }
+    }
+
+    // Stores as much of the contents as the faults allow at the end of the file.
+    fn store(&self, path: &Path, contents: &[u8], truncate: bool) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let mut len = contents
+            .len()
+            .min(state.max_write_size.unwrap_or(usize::MAX));
+        let mut result = Ok(());
+        if let Some(bytes_until_failure) = state.bytes_until_failure.as_mut() {
+            if len > *bytes_until_failure {
+                len = *bytes_until_failure;
+                result = Err(format!("Failed to write: {}", path.display()));
+            }
+            *bytes_until_failure -= len;
+        }
+        let file = state.files.entry(path.to_path_buf()).or_default();
+        if truncate {
+            file.clear();
+        }
+        file.extend_from_slice(&contents[..len]);
+        result
+    }
+}
+
+impl Storage for FaultyStorage {
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.state.lock().unwrap().files.get(path).cloned())
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<(), String> {
+        self.store(path, contents, true)
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> Result<(), String> {
+        self.store(path, contents, false)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if state.bytes_until_failure == Some(0) {
+            return Err(format!("Failed to rename: {}", from.display()));
+        }
+        let file = state
+            .files
+            .remove(from)
+            .ok_or_else(|| format!("File not found: {}", from.display()))?;
+        state.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "data/file.json";
+
+    #[test]
+    fn save_keeps_previous_contents_if_interrupted() {
+        let path = Path::new(PATH);
+        let new_contents = b"[\"new\", \"contents\"]";
+        for failure_byte in 0..=new_contents.len() {
+            let storage = FaultyStorage::default();
+            save(&storage, path, b"[\"old\"]").unwrap();
+            storage.fail_at_byte(failure_byte);
+            assert!(save(&storage, path, new_contents).is_err());
+
+            storage.clear_faults();
+            assert_eq!(
+                load_json::<Vec<String>>(&storage, path, "test").unwrap(),
+                Some(vec!["old".to_string()])
+            );
+        }
+
+        let storage = FaultyStorage::default();
+        storage.fail_at_byte(new_contents.len() + 1);
+        save(&storage, path, new_contents).unwrap();
+        assert_eq!(
+            load_json::<Vec<String>>(&storage, path, "test").unwrap(),
+            Some(vec!["new".to_string(), "contents".to_string()])
+        );
+    }
+
+    #[test]
+    fn detects_damaged_files() {
+        let path = Path::new(PATH);
+        let storage = FaultyStorage::default();
+        assert_eq!(load_json::<Vec<u32>>(&storage, path, "test"), Ok(None));
+
+        storage.short_writes(5);
+        save(&storage, path, b"[1, 2, 3]").unwrap();
+        assert!(load_json::<Vec<u32>>(&storage, path, "test")
+            .unwrap_err()
+            .starts_with("Failed to load test from: data/file.json"));
+
+        storage.clear_faults();
+        save(&storage, path, b"[1, 2, 3]").unwrap();
+        storage.corrupt(path, 3);
+        assert!(load_json::<Vec<u32>>(&storage, path, "test").is_err());
+    }
+
+    #[test]
+    fn drops_and_repairs_torn_record() {
+        let path = Path::new(PATH);
+        let storage = FaultyStorage::default();
+        append_record(&storage, path, "first").unwrap();
+        append_record(&storage, path, "second").unwrap();
+        storage.fail_at_byte(3);
+        assert!(append_record(&storage, path, "third").is_err());
+
+        storage.clear_faults();
+        assert_eq!(
+            read_records(&storage, path).unwrap(),
+            vec!["first", "second"]
+        );
+        append_record(&storage, path, "fourth").unwrap();
+        assert_eq!(
+            read_records(&storage, path).unwrap(),
+            vec!["first", "second", "fourth"]
+        );
+
+        // The file torn by a crash before it reached the disk.
+        storage.tear(path, 2);
+        assert!(read_records(&storage, path).unwrap().is_empty());
+    }
+}
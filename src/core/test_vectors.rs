@@ -0,0 +1,206 @@
+use crate::core::block::{BlockHash, BlockHeader};
+use crate::core::chain_params::ChainParams;
+use crate::core::hash::{merkle_tree_from_transactions, MerkleHash};
+use crate::core::serialize;
+use crate::core::transaction::{OutputIndex, TransactionId, TransactionInput, TransactionOutput};
+use crate::core::{as_hex, Address, Block, Sha256, Transaction};
+use serde::{Deserialize, Serialize};
+
+/// The canonical encodings of the transactions, the headers and the blocks with their hashes,
+/// which are checked against the committed file, so that any change of the serialization or
+/// the hashing is caught. After an intentional change, the file is rewritten by the ignored
+/// test `regenerate`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct TestVectors {
+    transactions: Vec<TestVector>,
+    headers: Vec<TestVector>,
+    blocks: Vec<TestVector>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct TestVector {
+    name: String,
+    // The hex-encoded canonical binary serialization.
+    hex: String,
+    // The transaction id or the block hash.
+    hash: String,
+}
+
+const TEST_VECTORS_PATH: &str = "src/core/test_vectors.json";
+
+impl TestVector {
+    fn new(name: &str, bytes: Vec<u8>, hash: &Sha256) -> Self {
+        Self {
+            name: name.to_string(),
+            hex: as_hex(&bytes),
+            hash: as_hex(hash.bytes()),
+        }
+    }
+}
+
+fn generate() -> TestVectors {
+    let alice = Address::new("alice".to_string());
+    let bob = Address::new("bob".to_string());
+    let coinbase = Transaction::new(
+        vec![TransactionInput::new_coinbase_with_data(
+            b"coolcoin".to_vec(),
+        )],
+        vec![TransactionOutput::new(alice.clone(), 50.into())],
+        1,
+    )
+    .unwrap();
+    let spend = Transaction::new(
+        vec![
+            TransactionInput::new(*coinbase.id(), OutputIndex::new(0)),
+            TransactionInput::new(
+                TransactionId::new(Sha256::new([7; 32])),
+                OutputIndex::new(3),
+            ),
+        ],
+        vec![
+            TransactionOutput::new(bob, 30.into()),
+            TransactionOutput::new(alice, 19.into()),
+        ],
+        0,
+    )
+    .unwrap();
+    // Locked until the timestamp rather than the height.
+    let locked = Transaction::new(
+        vec![TransactionInput::new(*spend.id(), OutputIndex::new(1))],
+        vec![TransactionOutput::new(
+            Address::new("carol".to_string()),
+            i64::MAX.into(),
+        )],
+        1_700_000_000,
+    )
+    .unwrap();
+    let transactions = [
+        ("coinbase", &coinbase),
+        ("spend", &spend),
+        ("locked", &locked),
+    ]
+    .iter()
+    .map(|(name, transaction)| {
+        TestVector::new(
+            name,
+            Transaction::serialize(transaction),
+            transaction.id().raw(),
+        )
+    })
+    .collect();
+
+    let max_header = BlockHeader::new(
+        BlockHash::new(Sha256::new([0xff; 32])),
+        MerkleHash::new(Sha256::new([0xee; 32])),
+        u32::MAX,
+        u32::MAX,
+        u32::MAX,
+    );
+    let main_genesis = ChainParams::main().genesis_block();
+    let regtest_genesis = ChainParams::regtest().genesis_block();
+    let block_transactions = vec![coinbase, spend, locked];
+    let block = Block::new(
+        BlockHeader::new(
+            *regtest_genesis.id(),
+            merkle_tree_from_transactions(&block_transactions),
+            regtest_genesis.header().timestamp() + 600,
+            0,
+            42,
+        ),
+        block_transactions,
+    );
+    let headers = [
+        ("main_genesis", main_genesis.header()),
+        ("regtest_genesis", regtest_genesis.header()),
+        ("max_fields", &max_header),
+    ]
+    .iter()
+    .map(|(name, header)| TestVector::new(name, serialize::serialize(*header), header.hash().raw()))
+    .collect();
+    let blocks = [
+        ("main_genesis", &main_genesis),
+        ("regtest_genesis", &regtest_genesis),
+        ("three_transactions", &block),
+    ]
+    .iter()
+    .map(|(name, block)| TestVector::new(name, Block::serialize(block), block.id().raw()))
+    .collect();
+
+    TestVectors {
+        transactions,
+        headers,
+        blocks,
+    }
+}
+
+fn load() -> TestVectors {
+    serde_json::from_str(include_str!("test_vectors.json")).unwrap()
+}
+
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn decodes_test_vectors() {
+        let vectors = load();
+        for vector in &vectors.transactions {
+            let transaction = Transaction::deserialize(&hex::decode(&vector.hex).unwrap()).unwrap();
+            assert_eq!(
+                as_hex(transaction.id().raw().bytes()),
+                vector.hash,
+                "{}",
+                vector.name
+            );
+            assert_eq!(
+                as_hex(&transaction.serialize()),
+                vector.hex,
+                "{}",
+                vector.name
+            );
+        }
+        for vector in &vectors.headers {
+            let header =
+                serialize::deserialize::<BlockHeader>(&hex::decode(&vector.hex).unwrap()).unwrap();
+            assert_eq!(
+                as_hex(header.hash().as_slice()),
+                vector.hash,
+                "{}",
+                vector.name
+            );
+            assert_eq!(
+                as_hex(&serialize::serialize(&header)),
+                vector.hex,
+                "{}",
+                vector.name
+            );
+        }
+        for vector in &vectors.blocks {
+            let block = Block::deserialize(&hex::decode(&vector.hex).unwrap()).unwrap();
+            assert_eq!(
+                as_hex(block.id().as_slice()),
+                vector.hash,
+                "{}",
+                vector.name
+            );
+            assert_eq!(as_hex(&block.serialize()), vector.hex, "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn encodes_test_vectors() {
+        assert_eq!(
+            generate(),
+            load(),
+            "The encodings have changed, run the ignored test `regenerate` if it's intentional."
+        );
+    }
+
+    #[test]
+    #[ignore = "rewrites the test vectors, run with --ignored after an intentional change"]
+    fn regenerate() {
+        let json = serde_json::to_string_pretty(&generate()).unwrap();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(TEST_VECTORS_PATH);
+        std::fs::write(path, json + "\n").unwrap();
+    }
+}
@@ -0,0 +1,701 @@
+use crate::core::block::BlockHash;
+use crate::core::chain_params::ChainParams;
+use crate::core::coolcoin_node::NodeParams;
+use crate::core::metrics::SlowThresholds;
+use crate::core::miner::RewardSplit;
+use crate::core::peer_connection::{encode, MessageDecoder, PeerMessage};
+use crate::core::poller::Poller;
+use crate::core::transaction_pool::PackageLimits;
+use crate::core::{
+    Address, Block, CoolcoinClient, CoolcoinNetwork, CoolcoinNode, FeeRate, PeerConnection,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How long the requests and the conditions are waited for before the test fails.
+const TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const MAX_TRANSACTION_POOL_USAGE: usize = 10_000_000;
+
+// Each node is known to its peers by its index. The addresses are only names,
+// nothing is bound to them. The client's address is a loopback one, so it can stop the node.
+fn node_address(index: usize) -> String {
+    format!("127.0.0.1:{}", 8000 + index)
+}
+const CLIENT_ADDRESS: &str = "127.0.0.1:9000";
+const RELAY_ADDRESS: &str = "127.0.0.1:9001";
+
+// Distinguishes the data directories of the networks created by the tests running in parallel.
+static NEXT_NETWORK_ID: AtomicUsize = AtomicUsize::new(0);
+// Seeds the faults of each link, so that they are the same in each run.
+static NEXT_LINK_SEED: AtomicUsize = AtomicUsize::new(1);
+
+/// The conditions of the link between two nodes, which can be changed while the nodes run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkFaults {
+    // The delay of each message.
+    pub latency: Duration,
+    // A random delay of up to this much is added to each message, so the messages sent
+    // within it may arrive in a different order.
+    pub jitter: Duration,
+    // The fraction of the messages that are lost.
+    pub drop_rate: f64,
+    // If true, all messages are lost until the partition heals.
+    pub partitioned: bool,
+}
+
+struct TestNode {
+    // Taken when the node is started.
+    node: Option<CoolcoinNode>,
+    client: CoolcoinClient,
+    // Relays the blocks to the node as if they came from a peer.
+    relay: PeerConnection,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Nodes that run in the same process, connected with socket pairs instead of TCP, so that
+/// the tests can exercise the relay, the synchronization and the reorganizations of the
+/// active blockchain without binding any ports.
+///
+/// Each node runs in its own thread once started, and is queried with the client over its own
+/// connection. The nodes join the regtest network, so they mine the transactions sent to them
+/// instantly. They only mine when there are transactions in their pools, so otherwise the blocks
+/// of the test are the only ones on the network.
+///
+/// The messages between the nodes pass through the links, whose faults the tests set to
+/// check that the nodes converge despite them. The clients and the relays aren't affected.
+pub struct TestNetwork {
+    nodes: Vec<TestNode>,
+    // Indexed by the pair of the node indexes, the smaller one first.
+    links: HashMap<(usize, usize), Arc<Mutex<LinkFaults>>>,
+    data_dir: PathBuf,
+}
+
+impl TestNetwork {
+    /// Creates the nodes of the regtest network, connected with the links between the pairs
+    /// of the node indexes, without starting them.
+    pub fn new(num_nodes: usize, links: &[(usize, usize)]) -> Result<Self, String> {
+        let data_dir = std::env::temp_dir().join(format!(
+            "coolcoin_testkit_{}_{}",
+            std::process::id(),
+            NEXT_NETWORK_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut peer_connections = (0..num_nodes).map(|_| vec![]).collect::<Vec<_>>();
+        let mut link_faults = HashMap::new();
+        for &(a, b) in links {
+            let faults = Arc::new(Mutex::new(LinkFaults::default()));
+            let (connection_a, connection_b) =
+                faulty_connection_pair(&node_address(a), &node_address(b), faults.clone())?;
+            peer_connections[a].push(connection_a);
+            peer_connections[b].push(connection_b);
+            link_faults.insert((a.min(b), a.max(b)), faults);
+        }
+
+        let mut nodes = vec![];
+        for (index, connections) in peer_connections.into_iter().enumerate() {
+            let node_data_dir = data_dir.join(index.to_string());
+            fs::create_dir_all(&node_data_dir).map_err(|e| e.to_string())?;
+            nodes.push(Self::create_node(index, node_data_dir, connections)?);
+        }
+        Ok(Self {
+            nodes,
+            links: link_faults,
+            data_dir,
+        })
+    }
+
+    fn create_node(
+        index: usize,
+        data_dir: PathBuf,
+        mut connections: Vec<PeerConnection>,
+    ) -> Result<TestNode, String> {
+        let (client, node_client) = PeerConnection::pair(CLIENT_ADDRESS, &node_address(index))?;
+        let (relay, node_relay) = PeerConnection::pair(RELAY_ADDRESS, &node_address(index))?;
+        connections.push(node_client);
+        connections.push(node_relay);
+        let node = CoolcoinNode::with_network(
+            CoolcoinNetwork::with_connections(connections, false),
+            None,
+            NodeParams {
+                reward_split: RewardSplit::new(Address::new(format!("node{}", index)), vec![])?,
+                coinbase_tag: vec![],
+                package_limits: PackageLimits::default(),
+                max_transaction_pool_usage: MAX_TRANSACTION_POOL_USAGE,
+                min_relay_fee_rate: FeeRate::zero(),
+                data_dir,
+                chain_params: ChainParams::regtest(),
+                status_interval: None,
+                slow_thresholds: SlowThresholds::default(),
+            },
+        )?;
+        Ok(TestNode {
+            node: Some(node),
+            client: CoolcoinClient::with_connection(client, TIMEOUT),
+            relay,
+            thread: None,
+        })
+    }
+
+    /// The directory in which the node keeps its persistent data.
+    pub fn data_dir(&self, index: usize) -> PathBuf {
+        self.data_dir.join(index.to_string())
+    }
+
+    /// Stops the started node, and waits until it has shut down.
+    pub fn stop(&mut self, index: usize) -> Result<(), String> {
+        let thread = self.nodes[index].thread.take().expect("node isn't started");
+        self.nodes[index].client.stop()?;
+        thread
+            .join()
+            .map_err(|_| format!("Node: {} has panicked.", index))
+    }
+
+    /// Creates the stopped node again from its data directory, and starts it.
+    /// The restarted node isn't connected to its peers.
+    pub fn restart(&mut self, index: usize) -> Result<(), String> {
+        self.nodes[index] = Self::create_node(index, self.data_dir(index), vec![])?;
+        self.start(index);
+        Ok(())
+    }
+
+    /// Starts the node, which then requests the inventory from its peers.
+    /// The messages sent to the node before are processed once it starts.
+    pub fn start(&mut self, index: usize) {
+        let node = self.nodes[index]
+            .node
+            .take()
+            .expect("node is already started");
+        self.nodes[index].thread = Some(thread::spawn(move || node.run()));
+    }
+
+    pub fn start_all(&mut self) {
+        for index in 0..self.nodes.len() {
+            self.start(index);
+        }
+    }
+
+    /// Sets the faults of the link between the nodes, which apply to the messages sent from now on.
+    pub fn set_faults(&mut self, a: usize, b: usize, faults: LinkFaults) -> Result<(), String> {
+        *self.link(a, b)?.lock().unwrap() = faults;
+        Ok(())
+    }
+
+    /// Cuts the link between the nodes, keeping its other faults.
+    pub fn partition(&mut self, a: usize, b: usize) -> Result<(), String> {
+        self.link(a, b)?.lock().unwrap().partitioned = true;
+        Ok(())
+    }
+
+    /// Restores the link between the nodes. The messages lost in the meantime aren't resent.
+    pub fn heal(&mut self, a: usize, b: usize) -> Result<(), String> {
+        self.link(a, b)?.lock().unwrap().partitioned = false;
+        Ok(())
+    }
+
+    fn link(&self, a: usize, b: usize) -> Result<&Mutex<LinkFaults>, String> {
+        self.links
+            .get(&(a.min(b), a.max(b)))
+            .map(|faults| &**faults)
+            .ok_or_else(|| format!("Nodes: {} and {} aren't linked.", a, b))
+    }
+
+    /// The client of the node's requests.
+    pub fn client(&mut self, index: usize) -> &mut CoolcoinClient {
+        &mut self.nodes[index].client
+    }
+
+    /// Sends the block to the node as if a peer has relayed it.
+    pub fn relay_block(&mut self, index: usize, block: Block) -> Result<(), String> {
+        let relay = &mut self.nodes[index].relay;
+        // The node relays the blocks back, which are dropped so that the buffers don't fill up.
+        relay.receive_all()?;
+        match relay.send(&PeerMessage::RelayBlock(Arc::new(block)))? {
+            true => Ok(()),
+            false => Err(format!("Failed to relay the block to node: {}", index)),
+        }
+    }
+
+    /// Waits until the condition, which queries the node with the client, holds.
+    pub fn wait_until(
+        &mut self,
+        index: usize,
+        mut condition: impl FnMut(&mut CoolcoinClient) -> Result<bool, String>,
+    ) -> Result<(), String> {
+        let start = Instant::now();
+        while !condition(self.client(index))? {
+            if start.elapsed() > TIMEOUT {
+                return Err(format!(
+                    "Node: {} has timed out waiting for the condition.",
+                    index
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    /// Waits until the active blockchains of all started nodes end with the block.
+    pub fn wait_for_tip(&mut self, tip: &BlockHash) -> Result<(), String> {
+        for index in 0..self.nodes.len() {
+            if self.nodes[index].thread.is_some() {
+                self.wait_until(index, |client| Ok(client.get_best_block_hash()? == *tip))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TestNetwork {
+    fn drop(&mut self) {
+        for node in &mut self.nodes {
+            if let Some(thread) = node.thread.take() {
+                // If the node doesn't stop, the test fails anyway, and the thread is detached.
+                if node.client.stop().is_ok() {
+                    let _ = thread.join();
+                }
+            }
+        }
+        let _ = fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+// Returns the ends of the connection between A and B, as PeerConnection::pair does, with the link
+// in between, which forwards the messages in both directions subject to its faults.
+fn faulty_connection_pair(
+    address_a: &str,
+    address_b: &str,
+    faults: Arc<Mutex<LinkFaults>>,
+) -> Result<(PeerConnection, PeerConnection), String> {
+    let (stream_a, link_a) = UnixStream::pair().map_err(|e| e.to_string())?;
+    let (stream_b, link_b) = UnixStream::pair().map_err(|e| e.to_string())?;
+    for stream in [&stream_a, &stream_b, &link_a, &link_b] {
+        stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+    }
+    let seed = NEXT_LINK_SEED.fetch_add(1, Ordering::Relaxed) as u64;
+    thread::spawn(move || forward(link_a, link_b, &faults, seed));
+    Ok((
+        PeerConnection::from_transport(address_b.to_string(), Box::new(stream_a), false),
+        PeerConnection::from_transport(address_a.to_string(), Box::new(stream_b), false),
+    ))
+}
+
+// The messages read from one end of the link, which are written to the other one once
+// they are due.
+struct LinkDirection {
+    from: UnixStream,
+    decoder: MessageDecoder,
+    // The messages and the times at which they are due, in the order in which they were read.
+    in_flight: Vec<(Instant, Vec<u8>)>,
+    // The due messages that haven't been written in full yet.
+    send_buffer: Vec<u8>,
+}
+
+impl LinkDirection {
+    fn new(from: UnixStream) -> Self {
+        Self {
+            from,
+            decoder: MessageDecoder::default(),
+            in_flight: vec![],
+            send_buffer: vec![],
+        }
+    }
+
+    // Reads the available messages, and delays or drops them. Returns false once the end is
+    // closed, or it sends the bytes that aren't a message.
+    fn read(&mut self, faults: &Mutex<LinkFaults>, random: &mut Random, now: Instant) -> bool {
+        match self.decoder.read_from(&mut self.from) {
+            Ok(0) => return false,
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => return false,
+        }
+        loop {
+            let message = match self.decoder.next_message() {
+                Ok(Some(message)) => message,
+                Ok(None) => return true,
+                Err(_) => return false,
+            };
+            let faults = *faults.lock().unwrap();
+            if faults.partitioned || random.next() < faults.drop_rate {
+                continue;
+            }
+            let delay = faults.latency + faults.jitter.mul_f64(random.next());
+            self.in_flight.push((now + delay, encode(&message)));
+        }
+    }
+
+    // Writes the due messages to the other end. Returns false once it's closed.
+    fn write(&mut self, to: &mut UnixStream, now: Instant) -> bool {
+        // The messages due at the same time keep their order.
+        self.in_flight.sort_by_key(|(due, _)| *due);
+        let num_due = self.in_flight.partition_point(|(due, _)| *due <= now);
+        for (_, bytes) in self.in_flight.drain(..num_due) {
+            self.send_buffer.extend(bytes);
+        }
+        while !self.send_buffer.is_empty() {
+            match to.write(&self.send_buffer) {
+                Ok(0) => return false,
+                Ok(written) => {
+                    self.send_buffer.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.in_flight.iter().map(|(due, _)| *due).min()
+    }
+}
+
+// Forwards the messages between the ends of the link until either of them is closed.
+fn forward(end_a: UnixStream, end_b: UnixStream, faults: &Mutex<LinkFaults>, seed: u64) {
+    let mut poller = match Poller::new() {
+        Ok(poller) => poller,
+        Err(_) => return,
+    };
+    // Spreads the small seeds over all bits, otherwise their first numbers are close to zero.
+    let mut random = Random(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    let (mut to_a, mut to_b) = match (end_a.try_clone(), end_b.try_clone()) {
+        (Ok(to_a), Ok(to_b)) => (to_a, to_b),
+        _ => return,
+    };
+    let mut a_to_b = LinkDirection::new(end_a);
+    let mut b_to_a = LinkDirection::new(end_b);
+    loop {
+        let now = Instant::now();
+        if !a_to_b.read(faults, &mut random, now)
+            || !b_to_a.read(faults, &mut random, now)
+            || !a_to_b.write(&mut to_b, now)
+            || !b_to_a.write(&mut to_a, now)
+        {
+            return;
+        }
+        let mut writable = vec![];
+        if !a_to_b.send_buffer.is_empty() {
+            writable.push(to_b.as_raw_fd());
+        }
+        if !b_to_a.send_buffer.is_empty() {
+            writable.push(to_a.as_raw_fd());
+        }
+        let timeout = [a_to_b.next_due(), b_to_a.next_due()]
+            .iter()
+            .flatten()
+            .min()
+            .map_or(TIMEOUT, |due| due.saturating_duration_since(now));
+        let readable = [a_to_b.from.as_raw_fd(), b_to_a.from.as_raw_fd()];
+        if poller.wait(&readable, &writable, timeout).is_err() {
+            return;
+        }
+    }
+}
+
+// A small generator of the faults (xorshift), so that the tests don't depend on a crate for it.
+struct Random(u64);
+
+impl Random {
+    // Returns the number in [0, 1).
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::miner::Miner;
+    use crate::core::transaction::{OutputIndex, TransactionInput, TransactionOutput};
+    use crate::core::Transaction;
+    use std::path::Path;
+
+    // Mines the chain on top of the regtest genesis block. Chains with different offsets
+    // between the timestamps are forks.
+    fn mine_chain(length: u32, timestamp_offset: u32) -> Vec<Block> {
+        let address = Address::new("miner".to_string());
+        let mut chain = vec![ChainParams::regtest().genesis_block()];
+        for height in 1..=length {
+            let parent = chain.last().unwrap();
+            let timestamp = parent.header().timestamp() + timestamp_offset;
+            let block = Miner::mine_block(parent, height, vec![], &address, 50.into(), timestamp);
+            chain.push(block);
+        }
+        chain.split_off(1)
+    }
+
+    #[test]
+    fn relays_blocks_and_reorganizes() {
+        // A line of nodes, so the blocks are relayed through the node in the middle.
+        let mut network = TestNetwork::new(3, &[(0, 1), (1, 2)]).unwrap();
+        network.start_all();
+
+        let chain = mine_chain(2, 1);
+        for block in &chain {
+            network.relay_block(0, block.clone()).unwrap();
+        }
+        network.wait_for_tip(chain[1].id()).unwrap();
+        assert_eq!(network.client(2).get_block_count(), Ok(2));
+
+        // A longer fork from the other end of the line replaces the active blockchain.
+        let fork = mine_chain(3, 2);
+        for block in &fork {
+            network.relay_block(2, block.clone()).unwrap();
+        }
+        network.wait_for_tip(fork[2].id()).unwrap();
+        assert_eq!(network.client(0).get_block_count(), Ok(3));
+        assert_eq!(network.client(0).get_chain_tips().unwrap().len(), 2);
+        // The first two blocks of the fork are stale until the third one switches to it.
+        let stats = network.client(0).get_fork_stats(None).unwrap();
+        assert_eq!((stats.reorgs(), stats.stale_blocks()), (1, 2));
+    }
+
+    #[test]
+    fn keeps_transaction_pool_across_restarts() {
+        let mut network = TestNetwork::new(1, &[]).unwrap();
+        network.start_all();
+        let chain = mine_chain(2, 1);
+        for block in &chain {
+            network.relay_block(0, block.clone()).unwrap();
+        }
+        network.wait_for_tip(chain[1].id()).unwrap();
+
+        // The node mines the transactions instantly, so its pool is empty at shutdown.
+        network.stop(0).unwrap();
+        let path = network.data_dir(0).join("mempool.json");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[]");
+        assert_eq!(
+            fs::read_to_string(network.data_dir(0).join("saved_peers.json")).unwrap(),
+            "[]"
+        );
+
+        let bob = Address::new("bob".to_string());
+        let coinbase = &chain[0].transactions()[0];
+        let spend = Transaction::new(
+            vec![TransactionInput::new(*coinbase.id(), OutputIndex::new(0))],
+            vec![TransactionOutput::new(bob.clone(), 40.into())],
+            0,
+        )
+        .unwrap();
+        fs::write(&path, serde_json::to_string(&vec![spend]).unwrap()).unwrap();
+        network.restart(0).unwrap();
+
+        // The blockchain isn't saved, so the transaction waits until its parent is relayed
+        // again, and is then mined.
+        for block in &chain {
+            network.relay_block(0, block.clone()).unwrap();
+        }
+        network
+            .wait_until(0, |client| {
+                let outputs = client.list_unspent(1, u32::MAX, vec![bob.clone()])?;
+                Ok(client.get_block_count()? == 3 && outputs.len() == 1)
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn dumps_state_to_file() {
+        let mut network = TestNetwork::new(2, &[(0, 1)]).unwrap();
+        network.start_all();
+        let chain = mine_chain(1, 1);
+        network.relay_block(0, chain[0].clone()).unwrap();
+        network.wait_for_tip(chain[0].id()).unwrap();
+
+        let path = network.data_dir.join("0").join("state.json");
+        let written = network
+            .client(0)
+            .dump_state(Some("state.json".to_string()))
+            .unwrap();
+        assert_eq!(written, path.display().to_string());
+        let state: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(state["info"]["blocks"], 1);
+        assert_eq!(
+            state["best_block_hash"],
+            serde_json::to_value(chain[0].id()).unwrap()
+        );
+        assert_eq!(state["chain_tips"].as_array().unwrap().len(), 1);
+        let peers = state["peers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|peer| peer["address"].as_str().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            peers,
+            vec![node_address(1).as_str(), CLIENT_ADDRESS, RELAY_ADDRESS]
+        );
+
+        // The state can't be written outside of the data directory.
+        for file_name in ["../state.json", "/tmp/state.json", "..", ".cookie", ""] {
+            assert!(network
+                .client(0)
+                .dump_state(Some(file_name.to_string()))
+                .is_err());
+        }
+
+        // Without the file name, the state is written to the node's data directory.
+        let written = network.client(1).dump_state(None).unwrap();
+        assert!(written.starts_with(&network.data_dir.join("1").display().to_string()));
+        assert!(Path::new(&written).exists());
+    }
+
+    #[test]
+    fn survives_unexpected_responses() {
+        let mut network = TestNetwork::new(1, &[]).unwrap();
+        network.start_all();
+        let relay = &mut network.nodes[0].relay;
+        for message in [
+            PeerMessage::ResponseBlockCount(7),
+            PeerMessage::ResponseStop(Ok(())),
+            PeerMessage::ResponseUptime(1),
+        ] {
+            assert!(relay.send(&message).unwrap());
+        }
+        assert_eq!(network.client(0).get_block_count(), Ok(0));
+    }
+
+    #[test]
+    fn synchronizes_node_started_later() {
+        let mut network = TestNetwork::new(2, &[(0, 1)]).unwrap();
+        network.start(0);
+        let chain = mine_chain(3, 1);
+        for block in &chain {
+            network.relay_block(0, block.clone()).unwrap();
+        }
+        network.wait_for_tip(chain[2].id()).unwrap();
+
+        network.start(1);
+        network.wait_for_tip(chain[2].id()).unwrap();
+        assert_eq!(
+            network.client(1).get_block_hash(1),
+            Ok(Some(*chain[0].id()))
+        );
+    }
+
+    #[test]
+    fn mines_spends_and_reorganizes() {
+        let mut network = TestNetwork::new(2, &[(0, 1)]).unwrap();
+        network.start_all();
+        // The coinbase output of the first block matures once the second one is mined.
+        let chain = mine_chain(2, 1);
+        for block in &chain {
+            network.relay_block(0, block.clone()).unwrap();
+        }
+        network.wait_for_tip(chain[1].id()).unwrap();
+
+        let bob = Address::new("bob".to_string());
+        let coinbase = &chain[0].transactions()[0];
+        let spend = Transaction::new(
+            vec![TransactionInput::new(*coinbase.id(), OutputIndex::new(0))],
+            vec![TransactionOutput::new(bob.clone(), 40.into())],
+            0,
+        )
+        .unwrap();
+        network.client(0).send_transaction(spend).unwrap();
+        // The node mines the spend instantly, and relays the block.
+        network
+            .wait_until(1, |client| Ok(client.get_block_count()? == 3))
+            .unwrap();
+        let outputs = network
+            .client(1)
+            .list_unspent(1, u32::MAX, vec![bob.clone()])
+            .unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].amount(), 40.into());
+
+        // A longer fork replaces the chain with the spend. The fork's first coinbase pays
+        // the same as the spent one, so it has the same id, and the spend returns to the pool
+        // and is mined again on top of the fork.
+        let fork = mine_chain(4, 2);
+        for block in &fork {
+            network.relay_block(1, block.clone()).unwrap();
+        }
+        for index in 0..2 {
+            network
+                .wait_until(index, |client| {
+                    let outputs = client.list_unspent(1, u32::MAX, vec![bob.clone()])?;
+                    Ok(client.get_block_count()? == 5 && outputs.len() == 1)
+                })
+                .unwrap();
+            let block_hash = network.client(index).get_block_hash(4).unwrap();
+            assert_eq!(block_hash, Some(*fork[3].id()));
+        }
+    }
+
+    #[test]
+    fn heals_after_partition() {
+        let mut network = TestNetwork::new(2, &[(0, 1)]).unwrap();
+        network.start_all();
+        network.partition(0, 1).unwrap();
+
+        // Each side of the partition extends its own chain.
+        let chain = mine_chain(2, 1);
+        for block in &chain {
+            network.relay_block(0, block.clone()).unwrap();
+        }
+        let fork = mine_chain(4, 2);
+        for block in &fork[..3] {
+            network.relay_block(1, block.clone()).unwrap();
+        }
+        network
+            .wait_until(0, |client| {
+                Ok(client.get_best_block_hash()? == *chain[1].id())
+            })
+            .unwrap();
+        network
+            .wait_until(1, |client| {
+                Ok(client.get_best_block_hash()? == *fork[2].id())
+            })
+            .unwrap();
+        assert_eq!(network.client(0).get_chain_tips().unwrap().len(), 1);
+
+        // The next block of the heavier chain is an orphan to the other side, which then
+        // catches up with it.
+        network.heal(0, 1).unwrap();
+        network.relay_block(1, fork[3].clone()).unwrap();
+        network.wait_for_tip(fork[3].id()).unwrap();
+        assert_eq!(network.client(0).get_chain_tips().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn converges_over_lossy_link() {
+        let mut network = TestNetwork::new(2, &[(0, 1)]).unwrap();
+        let faults = LinkFaults {
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(20),
+            drop_rate: 0.3,
+            partitioned: false,
+        };
+        network.start_all();
+        let chain = mine_chain(21, 1);
+        // The nodes have synchronized on startup before the link degrades.
+        network.relay_block(0, chain[0].clone()).unwrap();
+        network.wait_for_tip(chain[0].id()).unwrap();
+
+        // The blocks are lost or arrive out of order.
+        network.set_faults(0, 1, faults).unwrap();
+        for block in &chain[1..20] {
+            network.relay_block(0, block.clone()).unwrap();
+        }
+        network
+            .wait_until(0, |client| Ok(client.get_block_count()? == 20))
+            .unwrap();
+        // Once the link recovers, the next block brings the node up to date.
+        network.set_faults(0, 1, LinkFaults::default()).unwrap();
+        network.relay_block(0, chain[20].clone()).unwrap();
+        network.wait_for_tip(chain[20].id()).unwrap();
+        assert!(network.set_faults(0, 2, faults).is_err());
+    }
+}
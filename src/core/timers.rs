@@ -0,0 +1,87 @@
+use std::time::{Duration, Instant};
+
+struct Timer<T> {
+    timer: T,
+    interval: Duration,
+    deadline: Instant,
+}
+
+/// The periodic tasks, each with its own interval. The owner waits for the events until
+/// the next deadline, and then runs the tasks that are due.
+pub struct Timers<T> {
+    timers: Vec<Timer<T>>,
+}
+
+impl<T: Copy> Timers<T> {
+    pub fn new() -> Self {
+        Self { timers: vec![] }
+    }
+
+    /// Adds the task, which is first due after the interval.
+    pub fn add(&mut self, timer: T, interval: Duration, now: Instant) {
+        self.timers.push(Timer {
+            timer,
+            interval,
+            deadline: now + interval,
+        });
+    }
+
+    /// Returns the time until the next task is due, if any.
+    pub fn time_until_next(&self, now: Instant) -> Option<Duration> {
+        self.timers
+            .iter()
+            .map(|timer| timer.deadline.saturating_duration_since(now))
+            .min()
+    }
+
+    /// Returns the tasks that are due, and schedules their next run.
+    pub fn expired(&mut self, now: Instant) -> Vec<T> {
+        let mut expired = vec![];
+        for timer in &mut self.timers {
+            if timer.deadline <= now {
+                expired.push(timer.timer);
+                // The runs that have been missed, e.g. because the previous one took long,
+                // are skipped.
+                timer.deadline = now + timer.interval;
+            }
+        }
+        expired
+    }
+}
+
+impl<T: Copy> Default for Timers<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_timers_when_due() {
+        let start = Instant::now();
+        let mut timers = Timers::new();
+        assert_eq!(timers.time_until_next(start), None);
+        timers.add('a', Duration::from_secs(1), start);
+        timers.add('b', Duration::from_secs(3), start);
+        assert_eq!(timers.time_until_next(start), Some(Duration::from_secs(1)));
+
+        assert!(timers.expired(start).is_empty());
+        assert_eq!(timers.expired(start + Duration::from_secs(1)), vec!['a']);
+        assert_eq!(
+            timers.time_until_next(start + Duration::from_secs(1)),
+            Some(Duration::from_secs(1))
+        );
+        // Missed runs of 'a' are skipped.
+        assert_eq!(
+            timers.expired(start + Duration::from_secs(5)),
+            vec!['a', 'b']
+        );
+        assert_eq!(
+            timers.time_until_next(start + Duration::from_secs(7)),
+            Some(Duration::from_secs(0))
+        );
+    }
+}
@@ -1,7 +1,11 @@
-use crate::core::hash::hash;
+use crate::core::hash::{as_hex, hash};
+use crate::core::serialize::{self, Decodable, Encodable, Reader};
 use crate::core::{Address, Coolcoin, Sha256};
-use serde::{Deserialize, Serialize};
+use serde::de::Error;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_big_array::big_array;
+use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
 
 big_array! {BigArray;}
@@ -16,6 +20,18 @@ impl Display for TransactionId {
     }
 }
 
+impl Encodable for TransactionId {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.0.encode(output);
+    }
+}
+
+impl Decodable for TransactionId {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self(Sha256::decode(input)?))
+    }
+}
+
 impl TransactionId {
     pub fn new(data: Sha256) -> Self {
         Self(data)
@@ -26,7 +42,7 @@ impl TransactionId {
 }
 
 /// 4 bytes representing the index of the transaction output.
-#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub struct OutputIndex(i32);
 
 impl Display for OutputIndex {
@@ -35,35 +51,75 @@ impl Display for OutputIndex {
     }
 }
 
+impl Encodable for OutputIndex {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.0.encode(output);
+    }
+}
+
+impl Decodable for OutputIndex {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self(i32::decode(input)?))
+    }
+}
+
 impl OutputIndex {
     pub const fn new(index: i32) -> Self {
         Self(index)
     }
+
+    pub const fn value(&self) -> i32 {
+        self.0
+    }
 }
 
 // Set all bits to 0.
 const COINBASE_UTXO_ID: TransactionId = TransactionId(Sha256::new([0; 32]));
 // Set all bits to 1.
 const COINBASE_OUTPUT_INDEX: OutputIndex = OutputIndex::new(-1);
+// The maximum size of the coinbase data in bytes.
+const MAX_COINBASE_DATA_SIZE: usize = 100;
 
-// TODO: Coinbase transaction input has coinbase data size and coinbase data, which is
-// arbitrary data used for extra nonce and mining tags.
-// This is used instead of the unlocking script.
-// Question: How to model this as an object?
-// Potential solution: store encoded values as bytes, so this allows both to be modelled with
-// the same data type. It is also how the actual bitcoin transaction is modelled.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionInput {
     // 32 bytes. A pointer to the transaction containing the UTXO to be spent.
     utxo_id: TransactionId,
     // 4 bytes. The number of the UTXO to be spent, first one is 0.
     output_index: OutputIndex,
+    // Arbitrary data used by miners, e.g. for extra nonce. Only the coinbase input may have it.
+    // This is used instead of the unlocking script.
+    coinbase_data: Vec<u8>,
     // TODO: Add unlocking script.
 }
 
 impl Display for TransactionInput {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", self.utxo_id, self.output_index)
+        write!(
+            f,
+            "{}{}{}",
+            self.utxo_id,
+            self.output_index,
+            as_hex(&self.coinbase_data)
+        )
+    }
+}
+
+/// 32 bytes of the UTXO id, 4 bytes of the output index, and the coinbase data.
+impl Encodable for TransactionInput {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.utxo_id.encode(output);
+        self.output_index.encode(output);
+        self.coinbase_data.encode(output);
+    }
+}
+
+impl Decodable for TransactionInput {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Ok(Self {
+            utxo_id: TransactionId::decode(input)?,
+            output_index: OutputIndex::decode(input)?,
+            coinbase_data: Vec::decode(input)?,
+        })
     }
 }
 
@@ -72,6 +128,7 @@ impl TransactionInput {
         Self {
             utxo_id,
             output_index,
+            coinbase_data: vec![],
         }
     }
 
@@ -83,12 +140,21 @@ impl TransactionInput {
     }
 
     pub fn new_coinbase() -> Self {
+        Self::new_coinbase_with_data(vec![])
+    }
+
+    pub fn new_coinbase_with_data(coinbase_data: Vec<u8>) -> Self {
         Self {
             utxo_id: COINBASE_UTXO_ID,
             output_index: COINBASE_OUTPUT_INDEX,
+            coinbase_data,
         }
     }
 
+    pub fn coinbase_data(&self) -> &[u8] {
+        &self.coinbase_data
+    }
+
     pub fn is_coinbase(&self) -> bool {
         self.utxo_id == COINBASE_UTXO_ID && self.output_index == COINBASE_OUTPUT_INDEX
     }
@@ -107,6 +173,24 @@ impl Display for TransactionOutput {
     }
 }
 
+/// 8 bytes of the amount, followed by the address, like Bitcoin's value and script.
+impl Encodable for TransactionOutput {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.amount.encode(output);
+        self.to.encode(output);
+    }
+}
+
+impl Decodable for TransactionOutput {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        let amount = Coolcoin::decode(input)?;
+        Ok(Self {
+            to: Address::decode(input)?,
+            amount,
+        })
+    }
+}
+
 impl TransactionOutput {
     pub fn new(to: Address, amount: Coolcoin) -> Self {
         Self { to, amount }
@@ -121,7 +205,7 @@ impl TransactionOutput {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct Transaction {
     id: TransactionId,
     inputs: Vec<TransactionInput>,
@@ -133,6 +217,103 @@ pub struct Transaction {
     locktime: u32,
 }
 
+// Well-formed transactions with arbitrary inputs and outputs, for the fuzz targets.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Transaction {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let inputs = u
+            .arbitrary_iter::<(bool, [u8; 32], i32, Vec<u8>)>()?
+            .map(|input| {
+                let (is_coinbase, utxo_id, output_index, coinbase_data) = input?;
+                Ok(match is_coinbase {
+                    true => TransactionInput::new_coinbase_with_data(coinbase_data),
+                    false => TransactionInput::new(
+                        TransactionId::new(Sha256::new(utxo_id)),
+                        OutputIndex::new(output_index),
+                    ),
+                })
+            })
+            .collect::<arbitrary::Result<Vec<_>>>()?;
+        let outputs = u
+            .arbitrary_iter::<(String, i64)>()?
+            .map(|output| {
+                let (to, amount) = output?;
+                let to = Address::try_from(to).map_err(|_| arbitrary::Error::IncorrectFormat)?;
+                Ok(TransactionOutput::new(to, amount.into()))
+            })
+            .collect::<arbitrary::Result<Vec<_>>>()?;
+        Self::new(inputs, outputs, u.arbitrary()?).map_err(|_| arbitrary::Error::IncorrectFormat)
+    }
+}
+
+/// The transaction in JSON, whose id is ignored in favor of the hash of its data,
+/// and whose format is validated.
+#[derive(Deserialize)]
+struct SerializedTransaction {
+    #[allow(dead_code)]
+    id: TransactionId,
+    inputs: Vec<TransactionInput>,
+    outputs: Vec<TransactionOutput>,
+    locktime: u32,
+}
+
+impl TryFrom<SerializedTransaction> for Transaction {
+    type Error = String;
+
+    fn try_from(transaction: SerializedTransaction) -> Result<Self, Self::Error> {
+        Self::new(
+            transaction.inputs,
+            transaction.outputs,
+            transaction.locktime,
+        )
+    }
+}
+
+/// The inputs, the outputs and the locktime. The id isn't encoded, but recomputed.
+impl Encodable for Transaction {
+    fn encode(&self, output: &mut Vec<u8>) {
+        self.inputs.encode(output);
+        self.outputs.encode(output);
+        self.locktime.encode(output);
+    }
+}
+
+impl Decodable for Transaction {
+    fn decode(input: &mut Reader) -> Result<Self, String> {
+        Self::new(
+            Vec::decode(input)?,
+            Vec::decode(input)?,
+            u32::decode(input)?,
+        )
+    }
+}
+
+/// The transaction is serialized as its canonical encoding by the binary formats, e.g. in
+/// the peer messages, and with its fields, including the id, by the human readable ones.
+impl Serialize for Transaction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serialize::serialize_bytes(self, serializer);
+        }
+        let mut state = serializer.serialize_struct("Transaction", 4)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("inputs", &self.inputs)?;
+        state.serialize_field("outputs", &self.outputs)?;
+        state.serialize_field("locktime", &self.locktime)?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Transaction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.is_human_readable() {
+            true => Self::try_from(SerializedTransaction::deserialize(deserializer)?)
+                .map_err(D::Error::custom),
+            false => serialize::deserialize_bytes(deserializer),
+        }
+    }
+}
+
 impl Transaction {
     pub fn new(
         inputs: Vec<TransactionInput>,
@@ -162,27 +343,81 @@ impl Transaction {
         &self.outputs
     }
 
+    pub fn locktime(&self) -> u32 {
+        self.locktime
+    }
+
+    /// Returns the size of the transaction in its canonical binary serialization, in bytes.
+    pub fn serialized_size(&self) -> usize {
+        self.encoded_size()
+    }
+
+    /// Returns the canonical binary serialization of the transaction.
+    pub fn serialize(&self) -> Vec<u8> {
+        serialize::serialize(self)
+    }
+
+    /// Parses the transaction from its canonical binary serialization.
+    /// The transaction id is recomputed rather than trusted, and the format is validated,
+    /// as for every deserialized transaction.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+        serialize::deserialize::<Self>(bytes)
+            .map_err(|e| format!("Invalid transaction encoding: {}", e))
+    }
+
+    /// Returns the hex-encoded canonical serialization, i.e. the raw transaction.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.serialize())
+    }
+
+    /// Parses the raw transaction, see `deserialize`.
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        let bytes = hex::decode(hex).map_err(|e| format!("Invalid hex: {}", e))?;
+        Self::deserialize(&bytes)
+    }
+
     pub fn is_coinbase(&self) -> bool {
-        self.inputs.get(0).unwrap().is_coinbase()
+        self.inputs
+            .first()
+            .is_some_and(TransactionInput::is_coinbase)
     }
 
     /// Checks if the format of the transaction is valid, i.e.
     /// Format is valid if any of the following are satisfied:
     ///   - A transaction contains no coinbase inputs
-    ///   - A transaction contains exactly 1 coinbase input and exactly one output.
+    ///   - A transaction contains exactly 1 coinbase input and at least one output.
+    ///
+    /// Additionally, every transaction has at least one input, and only the coinbase input
+    /// may have coinbase data, up to 100 bytes.
     fn validate_format(&self) -> Result<(), String> {
+        if self.inputs.is_empty() {
+            return Err(format!("Transaction: {} has no inputs.", self.id));
+        }
         let contains_coinbase_inputs = self.inputs.iter().any(TransactionInput::is_coinbase);
-        let coinbase_requirements_satisfied = self.inputs.len() == 1 && self.outputs.len() == 1;
+        let coinbase_requirements_satisfied = self.inputs.len() == 1 && !self.outputs.is_empty();
         if contains_coinbase_inputs && !coinbase_requirements_satisfied {
-            Err(format!("Transaction: {} has the coinbase input, but it doesn't satisfy all coinbase requirements.", self.id))
-        } else {
-            Ok(())
+            return Err(format!("Transaction: {} has the coinbase input, but it doesn't satisfy all coinbase requirements.", self.id));
+        }
+        for input in &self.inputs {
+            let max_size = match input.is_coinbase() {
+                true => MAX_COINBASE_DATA_SIZE,
+                false => 0,
+            };
+            if input.coinbase_data.len() > max_size {
+                return Err(format!(
+                    "Transaction: {} has an input with {} bytes of coinbase data, but at most {} are allowed.",
+                    self.id,
+                    input.coinbase_data.len(),
+                    max_size
+                ));
+            }
         }
+        Ok(())
     }
 
     fn hash_transaction_data(
-        inputs: &Vec<TransactionInput>,
-        outputs: &Vec<TransactionOutput>,
+        inputs: &[TransactionInput],
+        outputs: &[TransactionOutput],
     ) -> TransactionId {
         let data = format!(
             "{}{}",
@@ -200,3 +435,83 @@ impl Transaction {
         TransactionId(hash(data.as_bytes()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::address::MAX_ADDRESS_LENGTH;
+
+    fn spend(num_inputs: usize, outputs: Vec<TransactionOutput>, locktime: u32) -> Transaction {
+        let inputs = (0..num_inputs)
+            .map(|i| {
+                TransactionInput::new(
+                    TransactionId::new(Sha256::new([i as u8; 32])),
+                    OutputIndex::new(i as i32),
+                )
+            })
+            .collect();
+        Transaction::new(inputs, outputs, locktime).unwrap()
+    }
+
+    #[test]
+    fn round_trips_raw_transactions() {
+        let output = |address_len: usize, amount: i64| {
+            TransactionOutput::new(Address::new("a".repeat(address_len)), amount.into())
+        };
+        let mut transactions = vec![];
+        // The lengths around the boundaries of the compact sizes, and the extreme values.
+        for coinbase_data_len in [0, 1, MAX_COINBASE_DATA_SIZE] {
+            transactions.push(
+                Transaction::new(
+                    vec![TransactionInput::new_coinbase_with_data(vec![
+                        0xab;
+                        coinbase_data_len
+                    ])],
+                    vec![output(5, 50)],
+                    0,
+                )
+                .unwrap(),
+            );
+        }
+        for num_inputs in [1, 2, 0xfc, 0xfd] {
+            transactions.push(spend(num_inputs, vec![output(5, 1)], 1));
+        }
+        for num_outputs in [0, 1, 0xfc, 0xfd] {
+            transactions.push(spend(1, vec![output(3, 2); num_outputs], 2));
+        }
+        for address_len in [1, MAX_ADDRESS_LENGTH] {
+            transactions.push(spend(1, vec![output(address_len, 3)], 3));
+        }
+        for amount in [0, -1, i64::MIN, i64::MAX] {
+            transactions.push(spend(1, vec![output(5, amount)], 4));
+        }
+        for locktime in [0, 500_000_000, u32::MAX] {
+            transactions.push(spend(1, vec![output(5, 5)], locktime));
+        }
+
+        for transaction in &transactions {
+            let hex = transaction.to_hex();
+            assert_eq!(hex, hex::encode(transaction.serialize()));
+            for hex in [hex.clone(), hex.to_uppercase()] {
+                let decoded = Transaction::from_hex(&hex).unwrap();
+                assert_eq!(decoded.id(), transaction.id());
+                assert_eq!(decoded.serialize(), transaction.serialize());
+                assert_eq!(decoded.serialized_size(), hex.len() / 2);
+            }
+            if hex.len() < 1000 {
+                for len in (0..hex.len()).step_by(2) {
+                    assert!(Transaction::from_hex(&hex[..len]).is_err(), "{}", len);
+                }
+            }
+            assert!(Transaction::from_hex(&(hex.clone() + "00")).is_err());
+            assert!(Transaction::from_hex(&hex[1..]).is_err());
+            assert!(Transaction::from_hex(&hex.replacen(&hex[..1], "g", 1)).is_err());
+        }
+
+        // Well-encoded, but with the invalid format.
+        let mut no_inputs = spend(1, vec![output(5, 1)], 0).serialize();
+        no_inputs.drain(..1 + 32 + 4 + 1);
+        no_inputs.insert(0, 0);
+        assert!(Transaction::deserialize(&no_inputs).is_err());
+    }
+}
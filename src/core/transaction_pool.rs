@@ -1,6 +1,132 @@
-use crate::core::transaction::TransactionId;
-use crate::core::{Block, Transaction};
-use std::collections::HashMap;
+use crate::core::transaction::{OutputIndex, TransactionId, TransactionInput, TransactionOutput};
+use crate::core::{Block, Coolcoin, FeeRate, Transaction};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::mem::size_of;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Limits on the size of in-pool dependency chains (packages).
+/// The counts and sizes include the transaction itself, e.g. a transaction without in-pool
+/// parents has exactly one ancestor.
+#[derive(Debug, Copy, Clone)]
+pub struct PackageLimits {
+    // The maximum number of in-pool ancestors.
+    pub max_ancestor_count: usize,
+    // The maximum total serialized size of in-pool ancestors (in bytes).
+    pub max_ancestor_size: usize,
+    // The maximum number of in-pool descendants.
+    pub max_descendant_count: usize,
+    // The maximum total serialized size of in-pool descendants (in bytes).
+    pub max_descendant_size: usize,
+}
+
+impl Default for PackageLimits {
+    /// The same defaults as Bitcoin Core.
+    fn default() -> Self {
+        Self {
+            max_ancestor_count: 25,
+            max_ancestor_size: 101_000,
+            max_descendant_count: 25,
+            max_descendant_size: 101_000,
+        }
+    }
+}
+
+/// Changes of the transaction pool, published to all subscribers, e.g. the wallet or metrics,
+/// so that they don't need to poll the pool.
+/// Transactions in the pool are never replaced because there is no replace-by-fee, so a
+/// replacement shows up as the removal of the conflicting transaction.
+#[derive(Debug, Clone)]
+pub enum TransactionPoolEvent {
+    Added(Transaction),
+    Removed(TransactionId, RemovalReason),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub enum RemovalReason {
+    // The transaction has been included in the block in the active blockchain.
+    Confirmed,
+    // The transaction spends the same output as a transaction in the active blockchain,
+    // or it descends from such a transaction.
+    Conflict,
+    // The transaction spends outputs of the transaction that is no longer valid,
+    // e.g. after a reorganization.
+    Invalidated,
+    // The transaction has been evicted because the pool exceeded its memory limit.
+    SizeLimit,
+}
+
+/// The summary of the transaction pool state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionPoolInfo {
+    // The number of transactions.
+    pub size: usize,
+    // The total size of the serialized transactions in bytes.
+    pub bytes: usize,
+    // The estimated memory usage of the pool in bytes.
+    pub usage: usize,
+    // The maximum memory usage of the pool in bytes.
+    pub max_usage: usize,
+}
+
+/// The transactions selected from the pool for the next block.
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    // Transactions without the coinbase transaction, parents preceding their children.
+    transactions: Vec<Transaction>,
+    // The total fee paid by the transactions, which the miner collects in the coinbase.
+    fees: Coolcoin,
+    // The total size of the serialized transactions in bytes.
+    size: usize,
+}
+
+impl BlockTemplate {
+    pub fn transactions(&self) -> &Vec<Transaction> {
+        &self.transactions
+    }
+
+    pub fn fees(&self) -> Coolcoin {
+        self.fees
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn into_transactions(self) -> Vec<Transaction> {
+        self.transactions
+    }
+}
+
+struct TransactionPoolEntry {
+    transaction: Transaction,
+    // Fee paid by the transaction.
+    fee: Coolcoin,
+    // Size of the serialized transaction in bytes.
+    size: usize,
+    // The estimated memory used by the entry, including the pool's indices, in bytes.
+    memory_usage: usize,
+    // In-pool transactions whose outputs are spent by this transaction.
+    parents: HashSet<TransactionId>,
+    // In-pool transactions that spend outputs of this transaction.
+    children: HashSet<TransactionId>,
+    // The number, the total size and the total fee (including the fee deltas) of the
+    // transaction together with its in-pool ancestors, i.e. of its package.
+    ancestor_count: usize,
+    ancestor_size: usize,
+    ancestor_fee: Coolcoin,
+}
+
+/// The transaction in the pool, as written to the state dump.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionPoolEntryInfo {
+    pub transaction_id: TransactionId,
+    pub fee: Coolcoin,
+    pub size: usize,
+    // In-pool transactions whose outputs are spent by this transaction.
+    pub parents: Vec<TransactionId>,
+}
 
 /// An unordered collection of transactions that are not in blocks in the main chain,
 /// but for which we have input transactions.
@@ -8,42 +134,1022 @@ use std::collections::HashMap;
 /// from the genesis block.
 /// Instead, it only contains the transactions received from the network since the node
 /// was started.
+///
+/// Transactions may spend outputs of other transactions in the pool, so the pool keeps track
+/// of the dependencies between them. A transaction together with its in-pool ancestors is
+/// called a package, and it can only be mined as a whole.
+///
+/// When the memory used by the pool exceeds its limit, the transactions with the lowest fee rate
+/// (together with their descendants) are evicted.
 pub struct TransactionPool {
-    transactions: HashMap<TransactionId, Transaction>,
+    entries: HashMap<TransactionId, TransactionPoolEntry>,
+    // Outputs spent by the transactions in the pool, mapped to the spending transaction.
+    // There is no replace-by-fee, so at most one transaction in the pool may spend an output.
+    spent_outputs: HashMap<(TransactionId, OutputIndex), TransactionId>,
+    limits: PackageLimits,
+    // The total size of the serialized transactions in the pool.
+    total_size: usize,
+    // The estimated memory used by the pool, and its upper limit.
+    memory_usage: usize,
+    max_memory_usage: usize,
+    subscribers: Vec<Sender<TransactionPoolEvent>>,
+    // Fee deltas set by the operator, which the block template and the eviction add to the
+    // fee paid by the transaction. They are kept until the transaction is confirmed,
+    // so a transaction can be prioritised before it enters the pool.
+    fee_deltas: HashMap<TransactionId, Coolcoin>,
+    // The number of changes of the pool, which tells whether the pool has changed since
+    // the previous observation.
+    sequence: u64,
+}
+
+impl Default for TransactionPool {
+    /// The same default memory limit as Bitcoin Core (300 MB).
+    fn default() -> Self {
+        Self::new(PackageLimits::default(), 300_000_000)
+    }
 }
 
 impl TransactionPool {
-    pub fn new() -> Self {
+    pub fn new(limits: PackageLimits, max_memory_usage: usize) -> Self {
         Self {
-            transactions: HashMap::new(),
+            entries: HashMap::new(),
+            spent_outputs: HashMap::new(),
+            limits,
+            total_size: 0,
+            memory_usage: 0,
+            max_memory_usage,
+            subscribers: Vec::new(),
+            fee_deltas: HashMap::new(),
+            sequence: 0,
+        }
+    }
+
+    /// Adds the delta to the fee of the transaction as seen by the block template and the
+    /// eviction, which doesn't change the fee actually paid by the transaction.
+    pub fn prioritise(&mut self, transaction_id: TransactionId, fee_delta: Coolcoin) {
+        let delta = self
+            .fee_deltas
+            .entry(transaction_id)
+            .or_insert_with(Coolcoin::zero);
+        *delta = *delta + fee_delta;
+        if *delta == Coolcoin::zero() {
+            self.fee_deltas.remove(&transaction_id);
+        }
+        if self.contains(&transaction_id) {
+            let mut changed = self.descendants(&transaction_id);
+            changed.insert(transaction_id);
+            self.update_ancestor_state(changed);
+        }
+        // The block template may have changed.
+        self.sequence += 1;
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn info(&self) -> TransactionPoolInfo {
+        TransactionPoolInfo {
+            size: self.entries.len(),
+            bytes: self.total_size,
+            usage: self.memory_usage,
+            max_usage: self.max_memory_usage,
         }
     }
 
+    /// Returns the receiver of all subsequent changes of the pool.
+    pub fn subscribe(&mut self) -> Receiver<TransactionPoolEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.transactions.is_empty()
+        self.entries.is_empty()
     }
 
     pub fn all(&self) -> Vec<Transaction> {
-        self.transactions.values().map(|t| t.clone()).collect()
+        self.entries
+            .values()
+            .map(|entry| entry.transaction.clone())
+            .collect()
+    }
+
+    /// Returns the entries of the pool, ordered by the transaction id.
+    pub fn entries(&self) -> Vec<TransactionPoolEntryInfo> {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(transaction_id, entry)| {
+                let mut parents = entry.parents.iter().cloned().collect::<Vec<_>>();
+                parents.sort_by_key(TransactionId::to_string);
+                TransactionPoolEntryInfo {
+                    transaction_id: *transaction_id,
+                    fee: entry.fee,
+                    size: entry.size,
+                    parents,
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.transaction_id.to_string());
+        entries
+    }
+
+    pub fn get(&self, transaction_id: &TransactionId) -> Option<&Transaction> {
+        self.entries
+            .get(transaction_id)
+            .map(|entry| &entry.transaction)
+    }
+
+    pub fn contains(&self, transaction_id: &TransactionId) -> bool {
+        self.entries.contains_key(transaction_id)
     }
 
     /// Ensures that the transaction exists in the pool.
-    pub fn insert(&mut self, transaction: Transaction) {
-        self.transactions.insert(*transaction.id(), transaction);
+    /// Fails if the transaction spends an output that is already spent by another transaction
+    /// in the pool, or if inserting the transaction would exceed any of the package limits,
+    /// either for the transaction itself or for any of its in-pool ancestors.
+    ///
+    /// The pool may already contain children of the transaction, e.g. when the transaction
+    /// is resurrected from a disconnected block, in which case they are linked to it.
+    ///
+    /// Also fails if the pool is full and the transaction pays too little to replace
+    /// the transactions in it.
+    pub fn insert(&mut self, transaction: Transaction, fee: Coolcoin) -> Result<(), String> {
+        if self.contains(transaction.id()) {
+            return Ok(());
+        }
+        self.check_conflicts(&transaction)?;
+
+        let size = transaction.serialized_size();
+        let parents = transaction
+            .inputs()
+            .iter()
+            .map(|input| *input.utxo_id())
+            .filter(|utxo_id| self.contains(utxo_id))
+            .collect::<HashSet<TransactionId>>();
+        let ancestors = self.collect_related(parents.iter().copied(), |entry| &entry.parents);
+        self.check_package_limits(transaction.id(), size, &ancestors)?;
+
+        let children = (0..transaction.outputs().len())
+            .filter_map(|index| {
+                self.spender(transaction.id(), &OutputIndex::new(index as i32))
+                    .copied()
+            })
+            .collect::<HashSet<TransactionId>>();
+
+        for parent in &parents {
+            self.entries
+                .get_mut(parent)
+                .unwrap()
+                .children
+                .insert(*transaction.id());
+        }
+        for child in &children {
+            self.entries
+                .get_mut(child)
+                .unwrap()
+                .parents
+                .insert(*transaction.id());
+        }
+        for input in transaction.inputs() {
+            self.spent_outputs
+                .insert((*input.utxo_id(), *input.output_index()), *transaction.id());
+        }
+        self.publish(TransactionPoolEvent::Added(transaction.clone()));
+        let transaction_id = *transaction.id();
+        let memory_usage = Self::memory_usage(&transaction);
+        self.total_size += size;
+        self.memory_usage +=
+            memory_usage + Self::links_memory_usage(parents.len() + children.len());
+        self.entries.insert(
+            transaction_id,
+            TransactionPoolEntry {
+                transaction,
+                fee,
+                size,
+                memory_usage,
+                parents,
+                children,
+                ancestor_count: 0,
+                ancestor_size: 0,
+                ancestor_fee: Coolcoin::zero(),
+            },
+        );
+        // The transaction becomes an ancestor of the children that are already in the pool.
+        let mut changed = self.descendants(&transaction_id);
+        changed.insert(transaction_id);
+        self.update_ancestor_state(changed);
+
+        self.trim_to_max_memory_usage();
+        if self.contains(&transaction_id) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Transaction: {} has been evicted because the pool is full.",
+                transaction_id
+            ))
+        }
+    }
+
+    /// Selects the transactions to be included in the block, whose total size doesn't exceed
+    /// the given maximum, in an order in which the parents precede their children.
+    ///
+    /// Transactions are selected greedily by the fee rate of their package, i.e. the transaction
+    /// together with its ancestors that haven't been selected yet, so a high-fee child can pull
+    /// its low-fee parents into the block (child pays for parent).
+    ///
+    /// The packages start from the ancestor state kept in the entries. Once a package is
+    /// selected, only the packages of its descendants change, so their state without the
+    /// selected ancestors is kept aside (as the modified entries), and they are re-ordered.
+    pub fn block_template(&self, max_size: usize) -> BlockTemplate {
+        let mut modified: HashMap<TransactionId, (Coolcoin, usize)> = HashMap::new();
+        let package_state = |modified: &HashMap<TransactionId, (Coolcoin, usize)>,
+                             transaction_id: &TransactionId| {
+            modified.get(transaction_id).copied().unwrap_or_else(|| {
+                let entry = self.entries.get(transaction_id).unwrap();
+                (entry.ancestor_fee, entry.ancestor_size)
+            })
+        };
+        // Ordered by the fee rate of the package. Ties are broken deterministically, in favour
+        // of the smaller transaction id.
+        let candidate = |modified: &HashMap<TransactionId, (Coolcoin, usize)>,
+                         transaction_id: &TransactionId| {
+            let (fee, size) = package_state(modified, transaction_id);
+            (FeeRate::new(fee, size), Reverse(*transaction_id.raw()))
+        };
+        let mut candidates = self
+            .entries
+            .keys()
+            .map(|transaction_id| candidate(&modified, transaction_id))
+            .collect::<BTreeSet<_>>();
+
+        let mut selected = HashSet::new();
+        let mut selected_transactions = vec![];
+        let mut total_size = 0;
+        let mut total_fees = Coolcoin::zero();
+        while let Some((_fee_rate, Reverse(raw_id))) = candidates.pop_last() {
+            let transaction_id = TransactionId::new(raw_id);
+            let (_modified_fee, size) = package_state(&modified, &transaction_id);
+            if total_size + size > max_size {
+                // Neither the transaction, nor its descendants, fit into the block.
+                for descendant in self.descendants(&transaction_id) {
+                    candidates.remove(&candidate(&modified, &descendant));
+                }
+                continue;
+            }
+
+            let mut package = self.ancestors(&transaction_id);
+            package.retain(|ancestor| !selected.contains(ancestor));
+            package.insert(transaction_id);
+            // Ancestors have fewer in-pool ancestors than their descendants.
+            let mut package = package.into_iter().collect::<Vec<TransactionId>>();
+            package.sort_by_key(|id| self.entries.get(id).unwrap().ancestor_count);
+            for id in &package {
+                candidates.remove(&candidate(&modified, id));
+                selected_transactions.push(self.entries.get(id).unwrap().transaction.clone());
+                selected.insert(*id);
+            }
+            total_size += size;
+            total_fees = total_fees + self.fee(&package);
+
+            // The selected transactions no longer count towards the packages of their
+            // descendants. The skipped descendants are no longer candidates, and stay out.
+            for id in &package {
+                let entry = self.entries.get(id).unwrap();
+                let fee = entry.fee
+                    + self
+                        .fee_deltas
+                        .get(id)
+                        .copied()
+                        .unwrap_or_else(Coolcoin::zero);
+                for descendant in self.descendants(id) {
+                    if selected.contains(&descendant)
+                        || !candidates.remove(&candidate(&modified, &descendant))
+                    {
+                        continue;
+                    }
+                    let (package_fee, package_size) = package_state(&modified, &descendant);
+                    modified.insert(descendant, (package_fee - fee, package_size - entry.size));
+                    candidates.insert(candidate(&modified, &descendant));
+                }
+            }
+        }
+        BlockTemplate {
+            transactions: selected_transactions,
+            fees: total_fees,
+            size: total_size,
+        }
+    }
+
+    /// Returns the in-pool ancestors of the given transaction, excluding the transaction itself.
+    pub fn ancestors(&self, transaction_id: &TransactionId) -> HashSet<TransactionId> {
+        match self.entries.get(transaction_id) {
+            None => HashSet::new(),
+            Some(entry) => {
+                self.collect_related(entry.parents.iter().copied(), |entry| &entry.parents)
+            }
+        }
+    }
+
+    /// Returns the in-pool descendants of the given transaction, excluding the transaction
+    /// itself.
+    pub fn descendants(&self, transaction_id: &TransactionId) -> HashSet<TransactionId> {
+        match self.entries.get(transaction_id) {
+            None => HashSet::new(),
+            Some(entry) => {
+                self.collect_related(entry.children.iter().copied(), |entry| &entry.children)
+            }
+        }
+    }
+
+    /// Returns the transaction in the pool that spends the given output, if any.
+    pub fn spender(
+        &self,
+        utxo_id: &TransactionId,
+        output_index: &OutputIndex,
+    ) -> Option<&TransactionId> {
+        self.spent_outputs.get(&(*utxo_id, *output_index))
     }
 
     pub fn new_active_block(&mut self, block: &Block) {
         for transaction in block.transactions() {
-            self.transactions.remove(transaction.id());
             // Previous transaction may not exist, e.g. because the node was started later.
+            self.remove(transaction.id(), RemovalReason::Confirmed);
+        }
+
+        // Transactions that spend the same outputs as the block's transactions can never be
+        // confirmed, and neither can their descendants.
+        for transaction in block.transactions() {
+            for input in transaction.inputs() {
+                if let Some(conflict) = self.spender(input.utxo_id(), input.output_index()) {
+                    let conflict = *conflict;
+                    self.remove_with_descendants(&conflict, RemovalReason::Conflict);
+                }
+            }
+        }
+    }
+
+    /// Removes the transactions that spend outputs of the given transaction, along with their
+    /// descendants, e.g. when the transaction has been disconnected from the active blockchain,
+    /// and it is no longer valid.
+    pub fn remove_spenders(&mut self, transaction: &Transaction) {
+        for index in 0..transaction.outputs().len() {
+            if let Some(spender) = self.spender(transaction.id(), &OutputIndex::new(index as i32)) {
+                let spender = *spender;
+                self.remove_with_descendants(&spender, RemovalReason::Invalidated);
+            }
+        }
+    }
+
+    /// Removes the transaction and all of its descendants from the pool.
+    fn remove_with_descendants(&mut self, transaction_id: &TransactionId, reason: RemovalReason) {
+        for descendant in self.descendants(transaction_id) {
+            self.remove(&descendant, reason);
+        }
+        self.remove(transaction_id, reason);
+    }
+
+    /// Removes the transaction from the pool, leaving its descendants in the pool.
+    fn remove(
+        &mut self,
+        transaction_id: &TransactionId,
+        reason: RemovalReason,
+    ) -> Option<Transaction> {
+        let entry = self.entries.remove(transaction_id)?;
+        if reason == RemovalReason::Confirmed {
+            self.fee_deltas.remove(transaction_id);
+        }
+        self.publish(TransactionPoolEvent::Removed(*transaction_id, reason));
+        self.total_size -= entry.size;
+        self.memory_usage -= entry.memory_usage
+            + Self::links_memory_usage(entry.parents.len() + entry.children.len());
+        for input in entry.transaction.inputs() {
+            self.spent_outputs
+                .remove(&(*input.utxo_id(), *input.output_index()));
+        }
+        for parent in &entry.parents {
+            if let Some(parent) = self.entries.get_mut(parent) {
+                parent.children.remove(transaction_id);
+            }
+        }
+        for child in &entry.children {
+            if let Some(child) = self.entries.get_mut(child) {
+                child.parents.remove(transaction_id);
+            }
+        }
+        // The transaction is no longer an ancestor of the descendants left in the pool.
+        let descendants =
+            self.collect_related(entry.children.iter().copied(), |entry| &entry.children);
+        self.update_ancestor_state(descendants);
+        Some(entry.transaction)
+    }
+
+    /// Recomputes the ancestor state of the given transactions, whose ancestors have changed.
+    fn update_ancestor_state(&mut self, transaction_ids: HashSet<TransactionId>) {
+        for transaction_id in transaction_ids {
+            let mut package = self.ancestors(&transaction_id);
+            package.insert(transaction_id);
+            let ancestor_size = self.total_size(&package);
+            let ancestor_fee = self.modified_fee(&package);
+            if let Some(entry) = self.entries.get_mut(&transaction_id) {
+                entry.ancestor_count = package.len();
+                entry.ancestor_size = ancestor_size;
+                entry.ancestor_fee = ancestor_fee;
+            }
         }
     }
 
-    pub fn undo_active_block(&mut self, block: &Block) {
-        let transactions = block.transactions().to_vec();
-        for transaction in transactions {
-            let previous = self.transactions.insert(*transaction.id(), transaction);
-            assert!(previous.is_some());
+    /// Evicts the transactions with the lowest fee rate, together with their descendants,
+    /// until the pool fits within its memory limit.
+    /// The fee rate of the transaction includes its descendants, so that a low-fee parent
+    /// isn't evicted while its high-fee child would pay for it.
+    // The transactions are sorted by their descendant scores once, rather than after each
+    // eviction, so the ancestors of the evicted transactions keep the scores of their packages
+    // before the eviction.
+    fn trim_to_max_memory_usage(&mut self) {
+        if self.memory_usage <= self.max_memory_usage {
+            return;
         }
+        let mut scores = self
+            .entries
+            .keys()
+            .map(|transaction_id| {
+                let mut package = self.descendants(transaction_id);
+                package.insert(*transaction_id);
+                (
+                    FeeRate::new(self.modified_fee(&package), self.total_size(&package)),
+                    *transaction_id.raw(),
+                )
+            })
+            .collect::<Vec<_>>();
+        scores.sort();
+        for (_fee_rate, raw_id) in scores {
+            if self.memory_usage <= self.max_memory_usage {
+                break;
+            }
+            let transaction_id = TransactionId::new(raw_id);
+            if self.contains(&transaction_id) {
+                self.remove_with_descendants(&transaction_id, RemovalReason::SizeLimit);
+            }
+        }
+    }
+
+    /// Returns the estimated memory used by the links between the parents and their children
+    /// in the pool. Each link is in both the child's parents and the parent's children, and
+    /// it's counted when either of them is added, until either of them is removed.
+    fn links_memory_usage(num_links: usize) -> usize {
+        num_links * 2 * size_of::<TransactionId>()
+    }
+
+    /// Returns the estimated memory used by the pool entry of the given transaction,
+    /// including its heap allocations and the pool's indices, except its links.
+    fn memory_usage(transaction: &Transaction) -> usize {
+        let inputs = transaction.inputs().len()
+            * (size_of::<TransactionInput>()
+                + size_of::<((TransactionId, OutputIndex), TransactionId)>());
+        let outputs = transaction
+            .outputs()
+            .iter()
+            .map(|output| size_of::<TransactionOutput>() + output.to().as_str().len())
+            .sum::<usize>();
+        size_of::<(TransactionId, TransactionPoolEntry)>() + inputs + outputs
+    }
+
+    /// Checks that none of the outputs spent by the transaction are spent by another
+    /// transaction in the pool, or more than once by the transaction itself.
+    fn check_conflicts(&self, transaction: &Transaction) -> Result<(), String> {
+        let mut spent = HashSet::new();
+        for input in transaction.inputs() {
+            let output = (*input.utxo_id(), *input.output_index());
+            if !spent.insert(output) {
+                return Err(format!(
+                    "Transaction: {} spends output: {}:{} more than once.",
+                    transaction.id(),
+                    input.utxo_id().raw(),
+                    input.output_index()
+                ));
+            }
+            if let Some(spender) = self.spent_outputs.get(&output) {
+                return Err(format!(
+                    "Transaction: {} spends output: {}:{} which is already spent by transaction: {} in the pool.",
+                    transaction.id(),
+                    input.utxo_id().raw(),
+                    input.output_index(),
+                    spender
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that adding a transaction with the given size and in-pool ancestors doesn't
+    /// exceed the package limits.
+    fn check_package_limits(
+        &self,
+        transaction_id: &TransactionId,
+        size: usize,
+        ancestors: &HashSet<TransactionId>,
+    ) -> Result<(), String> {
+        let ancestor_count = ancestors.len() + 1;
+        let ancestor_size = size + self.total_size(ancestors);
+        if ancestor_count > self.limits.max_ancestor_count {
+            return Err(format!(
+                "Transaction: {} has too many unconfirmed ancestors: {} > {}",
+                transaction_id, ancestor_count, self.limits.max_ancestor_count
+            ));
+        }
+        if ancestor_size > self.limits.max_ancestor_size {
+            return Err(format!(
+                "Transaction: {} exceeds the ancestor size limit: {} > {}",
+                transaction_id, ancestor_size, self.limits.max_ancestor_size
+            ));
+        }
+
+        for ancestor in ancestors {
+            let descendants = self.descendants(ancestor);
+            let descendant_count = descendants.len() + 2;
+            let descendant_size =
+                self.entries.get(ancestor).unwrap().size + self.total_size(&descendants) + size;
+            if descendant_count > self.limits.max_descendant_count {
+                return Err(format!(
+                    "Transaction: {} would give its ancestor: {} too many descendants: {} > {}",
+                    transaction_id, ancestor, descendant_count, self.limits.max_descendant_count
+                ));
+            }
+            if descendant_size > self.limits.max_descendant_size {
+                return Err(format!(
+                    "Transaction: {} would exceed the descendant size limit of its ancestor: {}: {} > {}",
+                    transaction_id, ancestor, descendant_size, self.limits.max_descendant_size
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Traverses the pool starting from the given transactions and following the links
+    /// returned by `next`. Returns all visited transactions.
+    fn collect_related<I, F>(&self, start: I, next: F) -> HashSet<TransactionId>
+    where
+        I: Iterator<Item = TransactionId>,
+        F: Fn(&TransactionPoolEntry) -> &HashSet<TransactionId>,
+    {
+        let mut visited = HashSet::new();
+        let mut stack = start.collect::<Vec<TransactionId>>();
+        while let Some(transaction_id) = stack.pop() {
+            if visited.insert(transaction_id) {
+                if let Some(entry) = self.entries.get(&transaction_id) {
+                    stack.extend(next(entry).iter().copied());
+                }
+            }
+        }
+        visited
+    }
+
+    fn total_size(&self, transaction_ids: &HashSet<TransactionId>) -> usize {
+        transaction_ids
+            .iter()
+            .filter_map(|transaction_id| self.entries.get(transaction_id))
+            .map(|entry| entry.size)
+            .sum()
+    }
+
+    /// Returns the total fee paid by the given transactions.
+    fn fee<'a>(&self, transaction_ids: impl IntoIterator<Item = &'a TransactionId>) -> Coolcoin {
+        transaction_ids
+            .into_iter()
+            .filter_map(|transaction_id| self.entries.get(transaction_id))
+            .map(|entry| entry.fee)
+            .sum()
+    }
+
+    /// Returns the total fee of the given transactions, including the operator's fee deltas.
+    fn modified_fee(&self, transaction_ids: &HashSet<TransactionId>) -> Coolcoin {
+        let fee_deltas = transaction_ids
+            .iter()
+            .filter_map(|transaction_id| self.fee_deltas.get(transaction_id))
+            .copied()
+            .sum::<Coolcoin>();
+        self.fee(transaction_ids) + fee_deltas
+    }
+
+    /// Sends the event to all subscribers, and drops the ones that are no longer listening.
+    fn publish(&mut self, event: TransactionPoolEvent) {
+        self.sequence += 1;
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::{BlockHash, BlockHeader};
+    use crate::core::hash::MerkleHash;
+    use crate::core::transaction::{TransactionInput, TransactionOutput};
+    use crate::core::{Address, Sha256};
+
+    fn transaction(inputs: Vec<(TransactionId, i32)>, num_outputs: u8) -> Transaction {
+        let inputs = inputs
+            .into_iter()
+            .map(|(utxo_id, index)| TransactionInput::new(utxo_id, OutputIndex::new(index)))
+            .collect();
+        let outputs = (0..num_outputs)
+            .map(|i| TransactionOutput::new(Address::new(format!("address-{}", i)), 10.into()))
+            .collect();
+        Transaction::new(inputs, outputs, 0).unwrap()
+    }
+
+    fn confirmed_utxo(seed: u8) -> TransactionId {
+        TransactionId::new(Sha256::new([seed; 32]))
+    }
+
+    fn sorted(transaction_ids: HashSet<TransactionId>) -> Vec<String> {
+        let mut transaction_ids = transaction_ids
+            .iter()
+            .map(|id| id.raw().to_string())
+            .collect::<Vec<String>>();
+        transaction_ids.sort();
+        transaction_ids
+    }
+
+    fn ids(transactions: &[&Transaction]) -> Vec<String> {
+        sorted(transactions.iter().map(|t| *t.id()).collect())
+    }
+
+    #[test]
+    fn tracks_ancestors_and_descendants() {
+        let mut pool = TransactionPool::default();
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 2);
+        let b = transaction(vec![(*a.id(), 0)], 1);
+        let c = transaction(vec![(*a.id(), 1), (confirmed_utxo(2), 0)], 1);
+        let d = transaction(vec![(*b.id(), 0), (*c.id(), 0)], 1);
+        for tx in [&a, &b, &c, &d] {
+            pool.insert((*tx).clone(), Coolcoin::zero()).unwrap();
+        }
+
+        assert_eq!(sorted(pool.ancestors(a.id())), ids(&[]));
+        assert_eq!(sorted(pool.ancestors(d.id())), ids(&[&a, &b, &c]));
+        assert_eq!(sorted(pool.ancestors(c.id())), ids(&[&a]));
+        assert_eq!(sorted(pool.descendants(a.id())), ids(&[&b, &c, &d]));
+        assert_eq!(sorted(pool.descendants(b.id())), ids(&[&d]));
+        assert_eq!(sorted(pool.descendants(d.id())), ids(&[]));
+    }
+
+    #[test]
+    fn confirmed_parent_is_no_longer_an_ancestor() {
+        let mut pool = TransactionPool::default();
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let b = transaction(vec![(*a.id(), 0)], 1);
+        pool.insert(a.clone(), Coolcoin::zero()).unwrap();
+        pool.insert(b.clone(), Coolcoin::zero()).unwrap();
+
+        pool.remove(a.id(), RemovalReason::Confirmed);
+
+        assert_eq!(sorted(pool.ancestors(b.id())), ids(&[]));
+        assert!(pool.contains(b.id()));
+    }
+
+    #[test]
+    fn rejects_too_many_ancestors() {
+        let mut pool = TransactionPool::new(
+            PackageLimits {
+                max_ancestor_count: 3,
+                ..PackageLimits::default()
+            },
+            usize::MAX,
+        );
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let b = transaction(vec![(*a.id(), 0)], 1);
+        let c = transaction(vec![(*b.id(), 0)], 1);
+        let d = transaction(vec![(*c.id(), 0)], 1);
+        pool.insert(a, Coolcoin::zero()).unwrap();
+        pool.insert(b, Coolcoin::zero()).unwrap();
+        pool.insert(c, Coolcoin::zero()).unwrap();
+
+        assert!(pool.insert(d.clone(), Coolcoin::zero()).is_err());
+        assert!(!pool.contains(d.id()));
+    }
+
+    #[test]
+    fn rejects_too_many_descendants() {
+        let mut pool = TransactionPool::new(
+            PackageLimits {
+                max_descendant_count: 3,
+                ..PackageLimits::default()
+            },
+            usize::MAX,
+        );
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 3);
+        let b = transaction(vec![(*a.id(), 0)], 1);
+        let c = transaction(vec![(*a.id(), 1)], 1);
+        let d = transaction(vec![(*a.id(), 2)], 1);
+        pool.insert(a.clone(), Coolcoin::zero()).unwrap();
+        pool.insert(b, Coolcoin::zero()).unwrap();
+        pool.insert(c, Coolcoin::zero()).unwrap();
+
+        assert!(pool.insert(d.clone(), Coolcoin::zero()).is_err());
+        assert_eq!(pool.descendants(a.id()).len(), 2);
+    }
+
+    #[test]
+    fn rejects_packages_exceeding_size_limits() {
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let b = transaction(vec![(*a.id(), 0)], 1);
+        let a_size = a.serialized_size();
+        let b_size = b.serialized_size();
+
+        let mut pool = TransactionPool::new(
+            PackageLimits {
+                max_ancestor_size: a_size + b_size - 1,
+                ..PackageLimits::default()
+            },
+            usize::MAX,
+        );
+        pool.insert(a.clone(), Coolcoin::zero()).unwrap();
+        assert!(pool.insert(b.clone(), Coolcoin::zero()).is_err());
+
+        let mut pool = TransactionPool::new(
+            PackageLimits {
+                max_descendant_size: a_size + b_size - 1,
+                ..PackageLimits::default()
+            },
+            usize::MAX,
+        );
+        pool.insert(a, Coolcoin::zero()).unwrap();
+        assert!(pool.insert(b, Coolcoin::zero()).is_err());
+    }
+
+    #[test]
+    fn rejects_double_spends() {
+        let mut pool = TransactionPool::default();
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let conflict = transaction(vec![(confirmed_utxo(1), 0), (confirmed_utxo(2), 0)], 1);
+        let spends_twice = transaction(vec![(confirmed_utxo(3), 0), (confirmed_utxo(3), 0)], 1);
+        pool.insert(a.clone(), Coolcoin::zero()).unwrap();
+
+        assert!(pool.insert(conflict.clone(), Coolcoin::zero()).is_err());
+        assert!(pool.insert(spends_twice, Coolcoin::zero()).is_err());
+        assert_eq!(
+            pool.spender(&confirmed_utxo(1), &OutputIndex::new(0)),
+            Some(a.id())
+        );
+        assert_eq!(pool.spender(&confirmed_utxo(2), &OutputIndex::new(0)), None);
+    }
+
+    #[test]
+    fn block_evicts_conflicting_transactions_and_their_descendants() {
+        let mut pool = TransactionPool::default();
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let b = transaction(vec![(*a.id(), 0)], 1);
+        let unrelated = transaction(vec![(confirmed_utxo(2), 0)], 1);
+        pool.insert(a.clone(), Coolcoin::zero()).unwrap();
+        pool.insert(b.clone(), Coolcoin::zero()).unwrap();
+        pool.insert(unrelated.clone(), Coolcoin::zero()).unwrap();
+
+        let conflict = transaction(vec![(confirmed_utxo(1), 0)], 2);
+        let header = BlockHeader::new(
+            BlockHash::new(Sha256::new([0; 32])),
+            MerkleHash::new(Sha256::new([0; 32])),
+            0,
+            0,
+            0,
+        );
+        pool.new_active_block(&Block::new(header, vec![conflict]));
+
+        assert!(!pool.contains(a.id()));
+        assert!(!pool.contains(b.id()));
+        assert!(pool.contains(unrelated.id()));
+        assert_eq!(pool.spender(&confirmed_utxo(1), &OutputIndex::new(0)), None);
+    }
+
+    #[test]
+    fn resurrected_parent_is_linked_to_its_children() {
+        let mut pool = TransactionPool::default();
+        // The parent has been disconnected, while the child remained in the pool.
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let b = transaction(vec![(*a.id(), 0)], 1);
+        let c = transaction(vec![(*b.id(), 0)], 1);
+        pool.insert(b.clone(), Coolcoin::zero()).unwrap();
+        pool.insert(c.clone(), Coolcoin::zero()).unwrap();
+
+        pool.insert(a.clone(), Coolcoin::zero()).unwrap();
+        assert_eq!(sorted(pool.ancestors(c.id())), ids(&[&a, &b]));
+        assert_eq!(sorted(pool.descendants(a.id())), ids(&[&b, &c]));
+
+        // The parent can't be resurrected, so its descendants are no longer valid.
+        let mut pool = TransactionPool::default();
+        pool.insert(b.clone(), Coolcoin::zero()).unwrap();
+        pool.insert(c.clone(), Coolcoin::zero()).unwrap();
+        pool.remove_spenders(&a);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn child_pays_for_parent() {
+        let mut pool = TransactionPool::default();
+        let parent = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let child = transaction(vec![(*parent.id(), 0)], 1);
+        let unrelated = transaction(vec![(confirmed_utxo(2), 0)], 1);
+        pool.insert(parent.clone(), 1.into()).unwrap();
+        pool.insert(child.clone(), 100.into()).unwrap();
+        pool.insert(unrelated.clone(), 10.into()).unwrap();
+
+        let template = pool.block_template(usize::MAX);
+        assert_eq!(template.fees(), 111.into());
+        let selected = template.into_transactions();
+        assert_eq!(
+            ids(&selected.iter().collect::<Vec<&Transaction>>()),
+            ids(&[&parent, &child, &unrelated])
+        );
+        // Parent precedes its child.
+        assert_eq!(selected[0].id(), parent.id());
+        assert_eq!(selected[1].id(), child.id());
+
+        // The package of parent and child pays more than the unrelated transaction.
+        let package_size = parent.serialized_size() + child.serialized_size();
+        let template = pool.block_template(package_size);
+        assert_eq!(template.fees(), 101.into());
+        assert_eq!(template.size(), package_size);
+        let selected = template.into_transactions();
+        assert_eq!(
+            ids(&selected.iter().collect::<Vec<&Transaction>>()),
+            ids(&[&parent, &child])
+        );
+
+        // The package doesn't fit, so the unrelated transaction is selected instead.
+        let selected = pool.block_template(package_size - 1).into_transactions();
+        assert_eq!(
+            ids(&selected.iter().collect::<Vec<&Transaction>>()),
+            ids(&[&unrelated])
+        );
+    }
+
+    #[test]
+    fn keeps_ancestor_state_of_packages() {
+        let mut pool = TransactionPool::default();
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 2);
+        let b = transaction(vec![(*a.id(), 0)], 1);
+        let c = transaction(vec![(*a.id(), 1)], 1);
+        let d = transaction(vec![(*b.id(), 0), (*c.id(), 0)], 1);
+        // The parent arrives last, e.g. it has been resurrected from a disconnected block.
+        for (transaction, fee) in [(&b, 2), (&c, 3), (&d, 4), (&a, 1)] {
+            pool.insert(transaction.clone(), fee.into()).unwrap();
+        }
+        let state = |pool: &TransactionPool, transaction: &Transaction| {
+            let entry = pool.entries.get(transaction.id()).unwrap();
+            (
+                entry.ancestor_count,
+                entry.ancestor_size,
+                entry.ancestor_fee,
+            )
+        };
+        let size = |transactions: &[&Transaction]| {
+            transactions
+                .iter()
+                .map(|transaction| transaction.serialized_size())
+                .sum::<usize>()
+        };
+        assert_eq!(state(&pool, &d), (4, size(&[&a, &b, &c, &d]), 10.into()));
+
+        pool.prioritise(*a.id(), 10.into());
+        assert_eq!(state(&pool, &b), (2, size(&[&a, &b]), 13.into()));
+        assert_eq!(state(&pool, &d), (4, size(&[&a, &b, &c, &d]), 20.into()));
+
+        // The confirmed parent no longer counts towards the packages.
+        let header = BlockHeader::new(
+            BlockHash::new(Sha256::new([0; 32])),
+            MerkleHash::new(Sha256::new([0; 32])),
+            0,
+            0,
+            0,
+        );
+        pool.new_active_block(&Block::new(header, vec![a.clone()]));
+        assert_eq!(state(&pool, &c), (1, size(&[&c]), 3.into()));
+        assert_eq!(state(&pool, &d), (3, size(&[&b, &c, &d]), 9.into()));
+    }
+
+    #[test]
+    fn selects_descendants_by_their_remaining_package() {
+        let mut pool = TransactionPool::default();
+        let parent = transaction(vec![(confirmed_utxo(1), 0)], 2);
+        let child = transaction(vec![(*parent.id(), 0)], 1);
+        let sibling = transaction(vec![(*parent.id(), 1)], 1);
+        let unrelated = transaction(vec![(confirmed_utxo(2), 0)], 1);
+        pool.insert(parent.clone(), 1.into()).unwrap();
+        pool.insert(child.clone(), 100.into()).unwrap();
+        pool.insert(sibling.clone(), 5.into()).unwrap();
+        pool.insert(unrelated.clone(), 6.into()).unwrap();
+
+        // Once the parent is selected with its child, the sibling pays only for itself,
+        // which is less than the unrelated transaction.
+        let max_size =
+            parent.serialized_size() + child.serialized_size() + unrelated.serialized_size();
+        let template = pool.block_template(max_size);
+        assert_eq!(template.fees(), 107.into());
+        let selected = template.into_transactions();
+        assert_eq!(selected[0].id(), parent.id());
+        assert_eq!(selected[1].id(), child.id());
+        assert_eq!(selected[2].id(), unrelated.id());
+
+        let template = pool.block_template(usize::MAX);
+        assert_eq!(template.fees(), 112.into());
+        assert_eq!(template.transactions()[3].id(), sibling.id());
+    }
+
+    #[test]
+    fn prioritised_transaction_is_selected_first() {
+        let mut pool = TransactionPool::default();
+        let cheap = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let expensive = transaction(vec![(confirmed_utxo(2), 0)], 1);
+        pool.insert(cheap.clone(), 1.into()).unwrap();
+        pool.insert(expensive.clone(), 10.into()).unwrap();
+        let sequence = pool.sequence();
+        pool.prioritise(*cheap.id(), 20.into());
+        assert_ne!(pool.sequence(), sequence);
+
+        let template = pool.block_template(cheap.serialized_size());
+        // The delta affects the selection, but not the fee collected by the miner.
+        assert_eq!(template.fees(), 1.into());
+        assert_eq!(
+            ids(&template.transactions().iter().collect::<Vec<_>>()),
+            ids(&[&cheap])
+        );
+
+        pool.prioritise(*cheap.id(), Coolcoin::new(-20));
+        let template = pool.block_template(expensive.serialized_size());
+        assert_eq!(template.fees(), 10.into());
+    }
+
+    #[test]
+    fn evicts_lowest_fee_rate_when_full() {
+        let parent = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let child = transaction(vec![(*parent.id(), 0)], 1);
+        let unrelated = transaction(vec![(confirmed_utxo(2), 0)], 1);
+        let usage = |transactions: &[&Transaction]| {
+            transactions
+                .iter()
+                .map(|t| TransactionPool::memory_usage(t))
+                .sum::<usize>()
+        };
+        let mut pool = TransactionPool::new(
+            PackageLimits::default(),
+            usage(&[&parent, &child, &unrelated]) - 1,
+        );
+        pool.insert(parent.clone(), 1.into()).unwrap();
+        pool.insert(child.clone(), 100.into()).unwrap();
+        assert_eq!(pool.info().size, 2);
+        assert_eq!(
+            pool.info().usage,
+            usage(&[&parent, &child]) + TransactionPool::links_memory_usage(1)
+        );
+        assert_eq!(
+            pool.info().bytes,
+            parent.serialized_size() + child.serialized_size()
+        );
+
+        // The parent pays the least, but its child pays for it.
+        assert!(pool.insert(unrelated.clone(), 10.into()).is_err());
+        assert_eq!(
+            ids(&pool.all().iter().collect::<Vec<&Transaction>>()),
+            ids(&[&parent, &child])
+        );
+
+        let mut pool = TransactionPool::new(
+            PackageLimits::default(),
+            usage(&[&parent, &child, &unrelated]) - 1,
+        );
+        pool.insert(parent.clone(), 0.into()).unwrap();
+        pool.insert(child.clone(), 1.into()).unwrap();
+        pool.insert(unrelated.clone(), 10.into()).unwrap();
+        assert_eq!(
+            ids(&pool.all().iter().collect::<Vec<&Transaction>>()),
+            ids(&[&unrelated])
+        );
+        assert_eq!(pool.info().usage, usage(&[&unrelated]));
+    }
+
+    #[test]
+    fn publishes_events_to_subscribers() {
+        let mut pool = TransactionPool::default();
+        let events = pool.subscribe();
+        let dropped = pool.subscribe();
+        drop(dropped);
+
+        let a = transaction(vec![(confirmed_utxo(1), 0)], 1);
+        let b = transaction(vec![(*a.id(), 0)], 1);
+        pool.insert(a.clone(), Coolcoin::zero()).unwrap();
+        pool.insert(b.clone(), Coolcoin::zero()).unwrap();
+        pool.remove_spenders(&a);
+
+        let events = events.try_iter().collect::<Vec<TransactionPoolEvent>>();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], TransactionPoolEvent::Added(t) if t.id() == a.id()));
+        assert!(matches!(&events[1], TransactionPoolEvent::Added(t) if t.id() == b.id()));
+        assert!(matches!(
+            &events[2],
+            TransactionPoolEvent::Removed(id, RemovalReason::Invalidated) if id == b.id()
+        ));
+        assert_eq!(pool.subscribers.len(), 1);
     }
 }
@@ -0,0 +1,86 @@
+use crate::core::transaction::TransactionId;
+use std::collections::HashMap;
+
+struct BroadcastInfo {
+    // Height of the active blockchain at the time of the last broadcast.
+    height: u32,
+    // Time of the last broadcast, in seconds since the unix epoch.
+    time: u32,
+}
+
+/// Keeps track of the transactions sent by the node's clients (i.e. the wallet), and decides when
+/// to re-announce them to peers if they haven't been confirmed, since the original relay may
+/// have been missed.
+pub struct TransactionRebroadcaster {
+    transactions: HashMap<TransactionId, BroadcastInfo>,
+    // The number of blocks without confirmation after which the transaction is rebroadcast.
+    rebroadcast_after_blocks: u32,
+    // The minimum time between two broadcasts of the same transaction, in seconds.
+    min_interval: u32,
+}
+
+impl TransactionRebroadcaster {
+    pub fn new(rebroadcast_after_blocks: u32, min_interval: u32) -> Self {
+        Self {
+            transactions: HashMap::new(),
+            rebroadcast_after_blocks,
+            min_interval,
+        }
+    }
+
+    /// Starts tracking the transaction that has just been broadcast.
+    pub fn track(&mut self, transaction_id: TransactionId, height: u32, current_time: u32) {
+        self.transactions.insert(
+            transaction_id,
+            BroadcastInfo {
+                height,
+                time: current_time,
+            },
+        );
+    }
+
+    /// Stops tracking the transaction, e.g. because it has been confirmed.
+    pub fn forget(&mut self, transaction_id: &TransactionId) {
+        self.transactions.remove(transaction_id);
+    }
+
+    /// Returns the transactions that are due for rebroadcast, and marks them as broadcast.
+    pub fn due(&mut self, height: u32, current_time: u32) -> Vec<TransactionId> {
+        let mut due = vec![];
+        for (transaction_id, info) in self.transactions.iter_mut() {
+            if height >= info.height + self.rebroadcast_after_blocks
+                && current_time >= info.time + self.min_interval
+            {
+                info.height = height;
+                info.time = current_time;
+                due.push(*transaction_id);
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Sha256;
+
+    #[test]
+    fn rebroadcasts_unconfirmed_transactions() {
+        let mut rebroadcaster = TransactionRebroadcaster::new(2, 60);
+        let a = TransactionId::new(Sha256::new([1; 32]));
+        let b = TransactionId::new(Sha256::new([2; 32]));
+        rebroadcaster.track(a, 10, 1000);
+        rebroadcaster.track(b, 10, 1000);
+
+        // Not enough blocks, or not enough time.
+        assert!(rebroadcaster.due(11, 2000).is_empty());
+        assert!(rebroadcaster.due(12, 1059).is_empty());
+
+        rebroadcaster.forget(&b);
+        assert_eq!(rebroadcaster.due(12, 1060), vec![a]);
+        // The transaction has just been rebroadcast.
+        assert!(rebroadcaster.due(13, 2000).is_empty());
+        assert_eq!(rebroadcaster.due(14, 2000), vec![a]);
+    }
+}
@@ -1,17 +1,497 @@
+use crate::core::block::BlockHash;
 use crate::core::transaction::{OutputIndex, TransactionId, TransactionOutput};
-use std::collections::HashMap;
+use crate::core::{Address, Block, Coolcoin};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+type OutputId = (TransactionId, OutputIndex);
+
+/// An unspent transaction output, together with the information about the transaction that
+/// created it.
+#[derive(Debug, Clone)]
+pub struct Utxo {
+    output: TransactionOutput,
+    // Height of the block in which the transaction has been confirmed.
+    height: u32,
+    is_coinbase: bool,
+}
+
+impl Utxo {
+    pub fn output(&self) -> &TransactionOutput {
+        &self.output
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.is_coinbase
+    }
+}
+
+/// The unspent transaction output as listed for the clients, e.g. by listunspent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnspentOutput {
+    txid: TransactionId,
+    vout: OutputIndex,
+    address: Address,
+    amount: Coolcoin,
+    confirmations: u32,
+    is_coinbase: bool,
+    // Whether the output can be spent in the next block, which the clients can't tell without
+    // knowing the coinbase maturity of the network.
+    is_mature: bool,
+}
+
+impl UnspentOutput {
+    pub fn new(
+        txid: TransactionId,
+        vout: OutputIndex,
+        address: Address,
+        amount: Coolcoin,
+        confirmations: u32,
+        is_coinbase: bool,
+        coinbase_maturity: u32,
+    ) -> Self {
+        Self {
+            txid,
+            vout,
+            address,
+            amount,
+            confirmations,
+            is_coinbase,
+            is_mature: !is_coinbase || confirmations >= coinbase_maturity,
+        }
+    }
+
+    pub fn txid(&self) -> &TransactionId {
+        &self.txid
+    }
+
+    pub fn vout(&self) -> OutputIndex {
+        self.vout
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    pub fn amount(&self) -> Coolcoin {
+        self.amount
+    }
+
+    pub fn confirmations(&self) -> u32 {
+        self.confirmations
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.is_coinbase
+    }
+
+    pub fn is_mature(&self) -> bool {
+        self.is_mature
+    }
+}
 
 /// A pool of confirmed and unspent transaction outputs.
+#[derive(Default)]
 pub struct UtxoPool {
     // Unspent transaction outputs, indexed by their transaction ID and their index in the
     // transaction.
-    utxos: HashMap<(TransactionId, OutputIndex), TransactionOutput>,
+    utxos: HashMap<OutputId, Utxo>,
+    // Unspent transaction outputs of each address, so they can be listed without a scan.
+    by_address: HashMap<Address, HashSet<OutputId>>,
+    // Outputs spent by each connected block, which are restored when the block is disconnected.
+    spent_by_block: HashMap<BlockHash, Vec<(OutputId, Utxo)>>,
 }
 
 impl UtxoPool {
     pub fn new() -> Self {
         Self {
             utxos: HashMap::new(),
+            by_address: HashMap::new(),
+            spent_by_block: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, utxo_id: &TransactionId, output_index: &OutputIndex) -> Option<&Utxo> {
+        self.utxos.get(&(*utxo_id, *output_index))
+    }
+
+    pub fn contains(&self, utxo_id: &TransactionId, output_index: &OutputIndex) -> bool {
+        self.utxos.contains_key(&(*utxo_id, *output_index))
+    }
+
+    /// Returns the unspent outputs sent to any of the given addresses,
+    /// or all unspent outputs if no address is given.
+    pub fn unspent_outputs(&self, addresses: &[Address]) -> Vec<(OutputId, &Utxo)> {
+        if addresses.is_empty() {
+            return self.utxos.iter().map(|(key, utxo)| (*key, utxo)).collect();
+        }
+        addresses
+            .iter()
+            .collect::<HashSet<&Address>>()
+            .into_iter()
+            .filter_map(|address| self.by_address.get(address))
+            .flatten()
+            .map(|key| (*key, &self.utxos[key]))
+            .collect()
+    }
+
+    /// Connects the block in its own view, see `UtxoView::connect_block`, and applies the changes
+    /// if the block spends only the existing outputs, otherwise the pool is left unchanged.
+    pub fn connect_block(&mut self, block: &Block, height: u32) -> Result<(), String> {
+        let mut view = UtxoView::new(self);
+        view.connect_block(block, height)?;
+        let changes = view.into_changes();
+        self.apply_changes(changes);
+        Ok(())
+    }
+
+    /// Reverts the effects of `connect_block`.
+    /// The block is expected to be the last block that has been connected.
+    pub fn disconnect_block(&mut self, block: &Block) {
+        let mut view = UtxoView::new(self);
+        view.disconnect_block(block);
+        let changes = view.into_changes();
+        self.apply_changes(changes);
+    }
+
+    /// Writes the changes made in the view over this pool, in one batch.
+    pub fn apply_changes(&mut self, changes: UtxoChanges) {
+        for (key, utxo) in changes.utxos {
+            self.remove(&key);
+            if let Some(utxo) = utxo {
+                self.insert(key, utxo);
+            }
+        }
+        for (block_hash, spent) in changes.spent_by_block {
+            match spent {
+                Some(spent) => self.spent_by_block.insert(block_hash, spent),
+                None => self.spent_by_block.remove(&block_hash),
+            };
+        }
+    }
+
+    fn insert(&mut self, key: OutputId, utxo: Utxo) {
+        self.by_address
+            .entry(utxo.output.to().clone())
+            .or_default()
+            .insert(key);
+        self.utxos.insert(key, utxo);
+    }
+
+    fn remove(&mut self, key: &OutputId) -> Option<Utxo> {
+        let utxo = self.utxos.remove(key)?;
+        let address = utxo.output.to();
+        if let Some(keys) = self.by_address.get_mut(address) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.by_address.remove(address);
+            }
+        }
+        Some(utxo)
+    }
+}
+
+/// The entries of the UTXO pool changed in the view, which shadow the pool's entries.
+/// None means that the entry has been removed.
+#[derive(Default)]
+pub struct UtxoChanges {
+    utxos: HashMap<OutputId, Option<Utxo>>,
+    spent_by_block: HashMap<BlockHash, Option<Vec<(OutputId, Utxo)>>>,
+}
+
+impl UtxoChanges {
+    /// The number of the added and removed entries.
+    pub fn num_utxos(&self) -> usize {
+        self.utxos.len()
+    }
+}
+
+/// A layer of changes over the UTXO pool, e.g. the blocks connected and disconnected during
+/// the reorganization of the active blockchain. The pool itself is only read, so the changes
+/// are either applied to it in one batch once all blocks have been connected successfully,
+/// or discarded together with the view.
+pub struct UtxoView<'a> {
+    pool: &'a UtxoPool,
+    changes: UtxoChanges,
+}
+
+impl<'a> UtxoView<'a> {
+    pub fn new(pool: &'a UtxoPool) -> Self {
+        Self {
+            pool,
+            changes: UtxoChanges::default(),
+        }
+    }
+
+    pub fn get(&self, utxo_id: &TransactionId, output_index: &OutputIndex) -> Option<&Utxo> {
+        match self.changes.utxos.get(&(*utxo_id, *output_index)) {
+            Some(utxo) => utxo.as_ref(),
+            None => self.pool.get(utxo_id, output_index),
+        }
+    }
+
+    pub fn contains(&self, utxo_id: &TransactionId, output_index: &OutputIndex) -> bool {
+        self.get(utxo_id, output_index).is_some()
+    }
+
+    /// Spends the outputs referenced by the block's transactions and adds their new outputs.
+    /// The block at the given height is expected to extend the chain of blocks that have been
+    /// connected so far. Fails if the block spends an output that doesn't exist, in which case
+    /// the view should be discarded.
+    pub fn connect_block(&mut self, block: &Block, height: u32) -> Result<(), String> {
+        let mut spent = vec![];
+        for transaction in block.transactions() {
+            for input in transaction.inputs() {
+                if input.is_coinbase() {
+                    continue;
+                }
+                let key = (*input.utxo_id(), *input.output_index());
+                let utxo = self.get(&key.0, &key.1).cloned().ok_or_else(|| {
+                    format!(
+                        "Transaction: {} in block: {} spends the output: {}:{} that doesn't exist or has already been spent.",
+                        transaction.id(),
+                        block.id(),
+                        key.0,
+                        key.1
+                    )
+                })?;
+                self.changes.utxos.insert(key, None);
+                spent.push((key, utxo));
+            }
+            for (index, output) in transaction.outputs().iter().enumerate() {
+                self.changes.utxos.insert(
+                    (*transaction.id(), OutputIndex::new(index as i32)),
+                    Some(Utxo {
+                        output: output.clone(),
+                        height,
+                        is_coinbase: transaction.is_coinbase(),
+                    }),
+                );
+            }
+        }
+        self.changes.spent_by_block.insert(*block.id(), Some(spent));
+        Ok(())
+    }
+
+    /// Returns the outputs spent by the block that has been connected to this view,
+    /// in the order of the inputs of its transactions.
+    pub fn spent_outputs(&self, block_hash: &BlockHash) -> Vec<&Utxo> {
+        match self.changes.spent_by_block.get(block_hash) {
+            Some(Some(spent)) => spent.iter().map(|(_, utxo)| utxo).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Reverts the effects of `connect_block`.
+    /// The block is expected to be the last block that has been connected.
+    pub fn disconnect_block(&mut self, block: &Block) {
+        let mut spent = match self.changes.spent_by_block.insert(*block.id(), None) {
+            Some(spent) => spent,
+            None => self.pool.spent_by_block.get(block.id()).cloned(),
+        }
+        .unwrap_or_default();
+        // The transactions are undone in the reverse order, so that the output created and spent
+        // within the block is restored by its child and then removed again with its parent.
+        for transaction in block.transactions().iter().rev() {
+            for index in 0..transaction.outputs().len() {
+                self.changes
+                    .utxos
+                    .insert((*transaction.id(), OutputIndex::new(index as i32)), None);
+            }
+            let num_spent = transaction
+                .inputs()
+                .iter()
+                .filter(|input| !input.is_coinbase())
+                .count();
+            for (key, utxo) in spent.split_off(spent.len().saturating_sub(num_spent)) {
+                self.changes.utxos.insert(key, Some(utxo));
+            }
         }
     }
+
+    /// Returns the changes, which are applied with `UtxoPool::apply_changes`.
+    pub fn into_changes(self) -> UtxoChanges {
+        self.changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::BlockHeader;
+    use crate::core::hash::MerkleHash;
+    use crate::core::transaction::TransactionInput;
+    use crate::core::{Address, Sha256, Transaction};
+
+    fn block(seed: u8, transactions: Vec<Transaction>) -> Block {
+        Block::new(
+            BlockHeader::new(
+                BlockHash::new(Sha256::new([seed; 32])),
+                MerkleHash::new(Sha256::new([0; 32])),
+                0,
+                0,
+                0,
+            ),
+            transactions,
+        )
+    }
+
+    fn output() -> TransactionOutput {
+        TransactionOutput::new(Address::new("address".to_string()), 10.into())
+    }
+
+    #[test]
+    fn lists_unspent_outputs_by_address() {
+        let mut pool = UtxoPool::new();
+        let alice = Address::new("alice".to_string());
+        let bob = Address::new("bob".to_string());
+        let coinbase = Transaction::new(
+            vec![TransactionInput::new_coinbase()],
+            vec![
+                TransactionOutput::new(alice.clone(), 10.into()),
+                TransactionOutput::new(bob.clone(), 20.into()),
+            ],
+            0,
+        )
+        .unwrap();
+        let coinbase_id = *coinbase.id();
+        pool.connect_block(&block(0, vec![coinbase]), 0).unwrap();
+        assert_eq!(pool.unspent_outputs(&[]).len(), 2);
+        let bob_outputs = pool.unspent_outputs(&[bob.clone(), bob.clone()]);
+        assert_eq!(bob_outputs.len(), 1);
+        assert_eq!(bob_outputs[0].0, (coinbase_id, OutputIndex::new(1)));
+
+        let spend = Transaction::new(
+            vec![TransactionInput::new(coinbase_id, OutputIndex::new(1))],
+            vec![TransactionOutput::new(alice.clone(), 20.into())],
+            0,
+        )
+        .unwrap();
+        let block_1 = block(1, vec![spend]);
+        pool.connect_block(&block_1, 1).unwrap();
+        assert!(pool.unspent_outputs(std::slice::from_ref(&bob)).is_empty());
+        assert_eq!(pool.unspent_outputs(&[alice.clone(), bob.clone()]).len(), 2);
+
+        pool.disconnect_block(&block_1);
+        assert_eq!(pool.unspent_outputs(&[bob]).len(), 1);
+        assert_eq!(pool.unspent_outputs(&[alice]).len(), 1);
+    }
+
+    #[test]
+    fn disconnect_restores_spent_outputs() {
+        let mut pool = UtxoPool::new();
+        let coinbase =
+            Transaction::new(vec![TransactionInput::new_coinbase()], vec![output()], 0).unwrap();
+        let coinbase_output = (*coinbase.id(), OutputIndex::new(0));
+        pool.connect_block(&block(0, vec![coinbase]), 0).unwrap();
+        let utxo = pool.get(&coinbase_output.0, &coinbase_output.1).unwrap();
+        assert!(utxo.is_coinbase());
+        assert_eq!(utxo.height(), 0);
+
+        let spend = Transaction::new(
+            vec![TransactionInput::new(coinbase_output.0, coinbase_output.1)],
+            vec![output()],
+            0,
+        )
+        .unwrap();
+        let spend_output = (*spend.id(), OutputIndex::new(0));
+        let block_1 = block(1, vec![spend]);
+        pool.connect_block(&block_1, 1).unwrap();
+        assert!(!pool.contains(&coinbase_output.0, &coinbase_output.1));
+        assert!(pool.contains(&spend_output.0, &spend_output.1));
+
+        pool.disconnect_block(&block_1);
+        assert!(pool.contains(&coinbase_output.0, &coinbase_output.1));
+        assert!(!pool.contains(&spend_output.0, &spend_output.1));
+    }
+
+    #[test]
+    fn disconnect_removes_outputs_spent_within_block() {
+        let mut pool = UtxoPool::new();
+        let coinbase =
+            Transaction::new(vec![TransactionInput::new_coinbase()], vec![output()], 0).unwrap();
+        let coinbase_output = (*coinbase.id(), OutputIndex::new(0));
+        pool.connect_block(&block(0, vec![coinbase]), 0).unwrap();
+
+        let parent = Transaction::new(
+            vec![TransactionInput::new(coinbase_output.0, coinbase_output.1)],
+            vec![output()],
+            0,
+        )
+        .unwrap();
+        let parent_output = (*parent.id(), OutputIndex::new(0));
+        let child = Transaction::new(
+            vec![TransactionInput::new(parent_output.0, parent_output.1)],
+            vec![output()],
+            0,
+        )
+        .unwrap();
+        let block_1 = block(1, vec![parent, child]);
+        pool.connect_block(&block_1, 1).unwrap();
+        assert!(!pool.contains(&parent_output.0, &parent_output.1));
+        assert_eq!(pool.unspent_outputs(&[]).len(), 1);
+
+        pool.disconnect_block(&block_1);
+        assert!(!pool.contains(&parent_output.0, &parent_output.1));
+        assert!(pool.contains(&coinbase_output.0, &coinbase_output.1));
+        assert_eq!(pool.unspent_outputs(&[]).len(), 1);
+    }
+
+    #[test]
+    fn view_changes_are_applied_or_discarded_together() {
+        let mut pool = UtxoPool::new();
+        let coinbase =
+            Transaction::new(vec![TransactionInput::new_coinbase()], vec![output()], 0).unwrap();
+        let coinbase_output = (*coinbase.id(), OutputIndex::new(0));
+        pool.connect_block(&block(0, vec![coinbase]), 0).unwrap();
+
+        let spend = |amount: i64| {
+            Transaction::new(
+                vec![TransactionInput::new(coinbase_output.0, coinbase_output.1)],
+                vec![TransactionOutput::new(
+                    Address::new("address".to_string()),
+                    amount.into(),
+                )],
+                0,
+            )
+            .unwrap()
+        };
+        let block_1 = block(1, vec![spend(1)]);
+        let spend_output = (*block_1.transactions()[0].id(), OutputIndex::new(0));
+
+        // The second block spends the same output again.
+        let mut view = UtxoView::new(&pool);
+        view.connect_block(&block_1, 1).unwrap();
+        assert!(!view.contains(&coinbase_output.0, &coinbase_output.1));
+        assert!(view.contains(&spend_output.0, &spend_output.1));
+        assert!(view.connect_block(&block(2, vec![spend(2)]), 2).is_err());
+        drop(view);
+        assert!(pool.contains(&coinbase_output.0, &coinbase_output.1));
+        assert!(!pool.contains(&spend_output.0, &spend_output.1));
+        assert!(pool
+            .connect_block(&block(2, vec![spend(1), spend(2)]), 1)
+            .is_err());
+        assert!(pool.contains(&coinbase_output.0, &coinbase_output.1));
+
+        // Reorganization from block 1 to the competing block 1' in a single view.
+        pool.connect_block(&block_1, 1).unwrap();
+        let competing_block = block(3, vec![spend(3)]);
+        let mut view = UtxoView::new(&pool);
+        view.disconnect_block(&block_1);
+        assert!(view.contains(&coinbase_output.0, &coinbase_output.1));
+        view.connect_block(&competing_block, 1).unwrap();
+        let changes = view.into_changes();
+        pool.apply_changes(changes);
+        assert!(!pool.contains(&coinbase_output.0, &coinbase_output.1));
+        assert!(!pool.contains(&spend_output.0, &spend_output.1));
+        assert_eq!(pool.unspent_outputs(&[]).len(), 1);
+
+        pool.disconnect_block(&competing_block);
+        assert!(pool.contains(&coinbase_output.0, &coinbase_output.1));
+        assert_eq!(pool.unspent_outputs(&[]).len(), 1);
+    }
 }
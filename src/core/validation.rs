@@ -1,10 +1,59 @@
 use crate::core::block::BlockHash;
-use crate::core::{target_hash, Block};
+use crate::core::transaction::TransactionOutput;
+use crate::core::{target_hash, Block, Coolcoin, FeeRate, Transaction};
 use std::cmp::Ordering;
+use std::num::NonZeroUsize;
+use std::thread;
+
+// Locktime values below the threshold are interpreted as block heights,
+// and the remaining ones as unix timestamps.
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+// The number of blocks after which the outputs of the coinbase transaction can be spent
+// on the main network.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Context required to validate the block's transactions against the outputs they spend.
+pub struct UtxoContext {
+    // Outputs spent by the inputs of the block's transactions, except the coinbase transaction,
+    // in the same order.
+    spent_outputs: Vec<SpentOutput>,
+    // Height of the block.
+    height: u32,
+    // The number of blocks after which the coinbase outputs can be spent.
+    coinbase_maturity: u32,
+    // The new coins that the coinbase transaction may create, in addition to the block's fees.
+    block_reward: Coolcoin,
+}
+
+impl UtxoContext {
+    pub fn new(
+        spent_outputs: Vec<SpentOutput>,
+        height: u32,
+        coinbase_maturity: u32,
+        block_reward: Coolcoin,
+    ) -> Self {
+        Self {
+            spent_outputs,
+            height,
+            coinbase_maturity,
+            block_reward,
+        }
+    }
+}
 
-pub struct UtxoContext {}
 pub struct ChainContext {
     target_hash: BlockHash,
+    // Median time past of the previous block.
+    median_time_past: u32,
+}
+
+impl ChainContext {
+    pub fn new(target_hash: BlockHash, median_time_past: u32) -> Self {
+        Self {
+            target_hash,
+            median_time_past,
+        }
+    }
 }
 // Responsible for performing validation checks on the block.
 // Note that this is a non-exhaustive list of checks.
@@ -19,7 +68,7 @@ impl BlockValidator {
             block.header().timestamp(),
             current_time,
         )?;
-        Self::validate_only_first_transaction_is_coinbase(&block)?;
+        Self::validate_only_first_transaction_is_coinbase(block)?;
         Self::validate_header_hash_less_than_target(
             &block.header().hash(),
             &target_hash(block.header().difficulty_target()),
@@ -35,11 +84,15 @@ impl BlockValidator {
             &block.header().hash(),
             &chain_context.target_hash,
         )?;
-        Ok(())
+        Self::validate_timestamp_greater_than_median_time_past(
+            block.header().timestamp(),
+            chain_context.median_time_past,
+        )
     }
 
     pub fn validate_utxo_context(block: &Block, utxo_context: &UtxoContext) -> Result<(), String> {
-        Self::validate_all_transactions_are_valid(&block, &utxo_context)
+        let fees = Self::validate_all_transactions_are_valid(block, utxo_context)?;
+        Self::validate_coinbase_pays_at_most_reward_and_fees(block, utxo_context.block_reward, fees)
     }
 
     fn validate_header_hash_less_than_target(
@@ -70,6 +123,20 @@ impl BlockValidator {
         }
     }
 
+    fn validate_timestamp_greater_than_median_time_past(
+        header_timestamp: u32,
+        median_time_past: u32,
+    ) -> Result<(), String> {
+        if header_timestamp > median_time_past {
+            Ok(())
+        } else {
+            Err(format!(
+                "Header timestamp: {} is not greater than median time past: {}",
+                header_timestamp, median_time_past
+            ))
+        }
+    }
+
     fn validate_only_first_transaction_is_coinbase(block: &Block) -> Result<(), String> {
         if block.transactions().is_empty() {
             Err(format!(
@@ -91,10 +158,525 @@ impl BlockValidator {
         }
     }
 
+    // The outputs spent by the block are looked up in order when it's connected, including
+    // the ones created earlier in the same block, so each transaction can be checked
+    // independently of the others. The transactions are split between the available cores,
+    // and the block is invalid if any of them is. Returns the total fee of the transactions.
     fn validate_all_transactions_are_valid(
-        _block: &Block,
-        _utxo_context: &UtxoContext,
+        block: &Block,
+        utxo_context: &UtxoContext,
+    ) -> Result<Coolcoin, String> {
+        let mut spent_outputs = utxo_context.spent_outputs.iter();
+        let transactions: Vec<(&Transaction, Vec<&SpentOutput>)> = block
+            .transactions()
+            .iter()
+            .filter(|transaction| !transaction.is_coinbase())
+            .map(|transaction| {
+                let spent = spent_outputs
+                    .by_ref()
+                    .take(transaction.inputs().len())
+                    .collect();
+                (transaction, spent)
+            })
+            .collect();
+        if spent_outputs.next().is_some()
+            || transactions
+                .iter()
+                .any(|(transaction, spent)| spent.len() != transaction.inputs().len())
+        {
+            return Err(format!(
+                "Block: {} spends: {} outputs, which doesn't match the inputs of its transactions.",
+                block.id(),
+                utxo_context.spent_outputs.len()
+            ));
+        }
+
+        let num_threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let chunk_size = transactions.len().div_ceil(num_threads).max(1);
+        let fees_overflow = || format!("Block: {} fees overflow.", block.id());
+        let validate = |chunk: &[(&Transaction, Vec<&SpentOutput>)]| {
+            chunk
+                .iter()
+                .try_fold(Coolcoin::zero(), |fees, (transaction, spent_outputs)| {
+                    TransactionValidator::validate_output_amounts_are_positive(transaction)?;
+                    TransactionValidator::validate_coinbase_outputs_are_mature(
+                        transaction,
+                        spent_outputs,
+                        utxo_context.height,
+                        utxo_context.coinbase_maturity,
+                    )?;
+                    let fee = TransactionValidator::validate_inputs_cover_outputs(
+                        transaction,
+                        spent_outputs,
+                    )?;
+                    Coolcoin::checked_sum([fees, fee]).ok_or_else(fees_overflow)
+                })
+        };
+        if transactions.len() <= chunk_size {
+            return validate(&transactions);
+        }
+        thread::scope(|scope| {
+            let workers: Vec<_> = transactions
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || validate(chunk)))
+                .collect();
+            workers
+                .into_iter()
+                .try_fold(Coolcoin::zero(), |fees, worker| {
+                    let chunk_fees = worker.join().unwrap()?;
+                    Coolcoin::checked_sum([fees, chunk_fees]).ok_or_else(fees_overflow)
+                })
+        })
+    }
+
+    // The coinbase transaction can't create more coins than the block reward, and it collects
+    // the fees of the block's transactions.
+    fn validate_coinbase_pays_at_most_reward_and_fees(
+        block: &Block,
+        block_reward: Coolcoin,
+        fees: Coolcoin,
+    ) -> Result<(), String> {
+        let total_output = Coolcoin::checked_sum(
+            block
+                .transactions()
+                .iter()
+                .filter(|transaction| transaction.is_coinbase())
+                .flat_map(Transaction::outputs)
+                .map(TransactionOutput::amount),
+        )
+        .ok_or_else(|| format!("Block: {} coinbase outputs overflow.", block.id()))?;
+        let max_output = Coolcoin::checked_sum([block_reward, fees])
+            .ok_or_else(|| format!("Block: {} reward overflows.", block.id()))?;
+        if total_output > max_output {
+            return Err(format!(
+                "Block: {} coinbase pays: {}, which is more than the block reward: {} and fees: {}",
+                block.id(),
+                total_output,
+                block_reward,
+                fees
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The output spent by the transaction input.
+pub struct SpentOutput {
+    pub output: TransactionOutput,
+    // Height of the block containing the coinbase transaction that created the output,
+    // or None if the output hasn't been created by the coinbase transaction.
+    pub coinbase_height: Option<u32>,
+}
+
+/// Context required to validate the transaction before it is accepted to the transaction pool.
+pub struct TransactionPoolContext {
+    // Outputs spent by the transaction inputs (in the same order),
+    // or None if the input refers to an output that doesn't exist or has already been spent.
+    spent_outputs: Vec<Option<SpentOutput>>,
+    // Height of the block in which the transaction would be included.
+    next_block_height: u32,
+    // Median time past of the last block in the active blockchain.
+    median_time_past: u32,
+    // The minimum fee rate for the transaction to be relayed.
+    min_relay_fee_rate: FeeRate,
+    // The number of blocks after which the coinbase outputs can be spent.
+    coinbase_maturity: u32,
+}
+
+impl TransactionPoolContext {
+    pub fn new(
+        spent_outputs: Vec<Option<SpentOutput>>,
+        next_block_height: u32,
+        median_time_past: u32,
+        min_relay_fee_rate: FeeRate,
+        coinbase_maturity: u32,
+    ) -> Self {
+        Self {
+            spent_outputs,
+            next_block_height,
+            median_time_past,
+            min_relay_fee_rate,
+            coinbase_maturity,
+        }
+    }
+}
+
+// Responsible for performing validation checks on the transaction before it is accepted to the
+// transaction pool, which ensures that the node never relays invalid transactions.
+// Transactions don't have unlocking scripts yet, so there are no scripts to verify.
+pub struct TransactionValidator {}
+
+impl TransactionValidator {
+    /// Returns the fee paid by the valid transaction.
+    pub fn validate_transaction_pool_context(
+        transaction: &Transaction,
+        context: &TransactionPoolContext,
+    ) -> Result<Coolcoin, String> {
+        Self::validate_not_coinbase(transaction)?;
+        let spent_outputs = Self::validate_spent_outputs_exist(transaction, context)?;
+        Self::validate_coinbase_outputs_are_mature(
+            transaction,
+            &spent_outputs,
+            context.next_block_height,
+            context.coinbase_maturity,
+        )?;
+        Self::validate_output_amounts_are_positive(transaction)?;
+        let fee = Self::validate_fee_at_least_min_relay_fee(transaction, &spent_outputs, context)?;
+        Self::validate_locktime(transaction, context)?;
+        Ok(fee)
+    }
+
+    fn validate_not_coinbase(transaction: &Transaction) -> Result<(), String> {
+        if transaction.is_coinbase() {
+            Err(format!(
+                "Transaction: {} is coinbase, which is only valid as part of the block.",
+                transaction.id()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn validate_spent_outputs_exist<'a>(
+        transaction: &Transaction,
+        context: &'a TransactionPoolContext,
+    ) -> Result<Vec<&'a SpentOutput>, String> {
+        transaction
+            .inputs()
+            .iter()
+            .zip(context.spent_outputs.iter())
+            .map(|(input, output)| {
+                output.as_ref().ok_or_else(|| {
+                    format!(
+                        "Transaction: {} spends output: {}:{} that doesn't exist or has already been spent.",
+                        transaction.id(),
+                        input.utxo_id(),
+                        input.output_index()
+                    )
+                })
+            })
+            .collect()
+    }
+
+    // The transaction is included in the block at the given height.
+    fn validate_coinbase_outputs_are_mature(
+        transaction: &Transaction,
+        spent_outputs: &[&SpentOutput],
+        block_height: u32,
+        coinbase_maturity: u32,
+    ) -> Result<(), String> {
+        for (input, spent_output) in transaction.inputs().iter().zip(spent_outputs.iter()) {
+            if let Some(coinbase_height) = spent_output.coinbase_height {
+                let maturity_height = coinbase_height.saturating_add(coinbase_maturity);
+                if block_height < maturity_height {
+                    return Err(format!(
+                        "Transaction: {} spends coinbase output: {}:{} from block height: {}, which matures at height: {}",
+                        transaction.id(),
+                        input.utxo_id(),
+                        input.output_index(),
+                        coinbase_height,
+                        maturity_height
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_output_amounts_are_positive(transaction: &Transaction) -> Result<(), String> {
+        match transaction
+            .outputs()
+            .iter()
+            .find(|output| output.amount() <= Coolcoin::zero())
+        {
+            None => Ok(()),
+            Some(output) => Err(format!(
+                "Transaction: {} has an output with non-positive amount: {}",
+                transaction.id(),
+                output.amount()
+            )),
+        }
+    }
+
+    fn validate_fee_at_least_min_relay_fee(
+        transaction: &Transaction,
+        spent_outputs: &[&SpentOutput],
+        context: &TransactionPoolContext,
+    ) -> Result<Coolcoin, String> {
+        let fee = Self::validate_inputs_cover_outputs(transaction, spent_outputs)?;
+        let min_relay_fee = context
+            .min_relay_fee_rate
+            .fee(transaction.serialized_size());
+        if fee < min_relay_fee {
+            Err(format!(
+                "Transaction: {} pays fee: {}, which is less than the minimum relay fee: {}",
+                transaction.id(),
+                fee,
+                min_relay_fee
+            ))
+        } else {
+            Ok(fee)
+        }
+    }
+
+    /// Returns the fee paid by the transaction, i.e. the amount by which its inputs exceed
+    /// its outputs.
+    fn validate_inputs_cover_outputs(
+        transaction: &Transaction,
+        spent_outputs: &[&SpentOutput],
+    ) -> Result<Coolcoin, String> {
+        let total_input = Coolcoin::checked_sum(
+            spent_outputs
+                .iter()
+                .map(|spent_output| spent_output.output.amount()),
+        )
+        .ok_or_else(|| format!("Transaction: {} inputs overflow.", transaction.id()))?;
+        let total_output =
+            Coolcoin::checked_sum(transaction.outputs().iter().map(TransactionOutput::amount))
+                .ok_or_else(|| format!("Transaction: {} outputs overflow.", transaction.id()))?;
+        if total_input < total_output {
+            return Err(format!(
+                "Transaction: {} spends: {}, which is more than its inputs: {}",
+                transaction.id(),
+                total_output,
+                total_input
+            ));
+        }
+        Ok(total_input - total_output)
+    }
+
+    fn validate_locktime(
+        transaction: &Transaction,
+        context: &TransactionPoolContext,
     ) -> Result<(), String> {
-        todo!("Transaction validation requires UtxoDatabase to find total coins in inputs")
+        let locktime = transaction.locktime();
+        let (is_final, current) = if locktime < LOCKTIME_THRESHOLD {
+            (
+                locktime <= context.next_block_height,
+                context.next_block_height,
+            )
+        } else {
+            (
+                locktime <= context.median_time_past,
+                context.median_time_past,
+            )
+        };
+        if is_final {
+            Ok(())
+        } else {
+            Err(format!(
+                "Transaction: {} has locktime: {}, which is in the future: {}",
+                transaction.id(),
+                locktime,
+                current
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::transaction::{OutputIndex, TransactionId, TransactionInput};
+    use crate::core::{Address, Sha256};
+
+    fn output(amount: i64) -> TransactionOutput {
+        TransactionOutput::new(Address::new("address".to_string()), amount.into())
+    }
+
+    fn transaction(amount: i64, locktime: u32) -> Transaction {
+        let input = TransactionInput::new(
+            TransactionId::new(Sha256::new([1; 32])),
+            OutputIndex::new(0),
+        );
+        Transaction::new(vec![input], vec![output(amount)], locktime).unwrap()
+    }
+
+    fn spent_output(amount: i64) -> SpentOutput {
+        SpentOutput {
+            output: output(amount),
+            coinbase_height: None,
+        }
+    }
+
+    fn context(spent_amount: Option<i64>, min_relay_fee_rate: i64) -> TransactionPoolContext {
+        TransactionPoolContext::new(
+            vec![spent_amount.map(spent_output)],
+            10,
+            LOCKTIME_THRESHOLD + 100,
+            FeeRate::per_kilobyte(min_relay_fee_rate.into()),
+            COINBASE_MATURITY,
+        )
+    }
+
+    #[test]
+    fn accepts_valid_transaction() {
+        let transaction = transaction(90, 10);
+        TransactionValidator::validate_transaction_pool_context(
+            &transaction,
+            &context(Some(100), 0),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_missing_spent_output() {
+        let transaction = transaction(90, 0);
+        assert!(TransactionValidator::validate_transaction_pool_context(
+            &transaction,
+            &context(None, 0)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_fee_below_min_relay_fee() {
+        let transaction = transaction(100, 0);
+        let size = transaction.serialized_size() as i64;
+        // A fee rate of 1000 coolcoins per 1000 bytes requires a fee of 1 coolcoin per byte.
+        TransactionValidator::validate_transaction_pool_context(
+            &transaction,
+            &context(Some(100 + size), 1000),
+        )
+        .unwrap();
+        assert!(TransactionValidator::validate_transaction_pool_context(
+            &transaction,
+            &context(Some(100 + size - 1), 1000)
+        )
+        .is_err());
+        // Outputs can't exceed inputs.
+        assert!(TransactionValidator::validate_transaction_pool_context(
+            &transaction,
+            &context(Some(99), 0)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_outputs() {
+        let input = TransactionInput::new(
+            TransactionId::new(Sha256::new([1; 32])),
+            OutputIndex::new(0),
+        );
+        let transaction =
+            Transaction::new(vec![input], vec![output(i64::MAX), output(i64::MAX)], 0).unwrap();
+        assert!(TransactionValidator::validate_transaction_pool_context(
+            &transaction,
+            &context(Some(100), 0)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_locktime_in_the_future() {
+        for locktime in &[11, LOCKTIME_THRESHOLD + 101] {
+            let transaction = transaction(90, *locktime);
+            assert!(TransactionValidator::validate_transaction_pool_context(
+                &transaction,
+                &context(Some(100), 0)
+            )
+            .is_err());
+        }
+        let transaction = transaction(90, LOCKTIME_THRESHOLD + 100);
+        TransactionValidator::validate_transaction_pool_context(
+            &transaction,
+            &context(Some(100), 0),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_immature_coinbase_outputs() {
+        let transaction = transaction(90, 0);
+        let context = |coinbase_height| {
+            TransactionPoolContext::new(
+                vec![Some(SpentOutput {
+                    output: output(100),
+                    coinbase_height: Some(coinbase_height),
+                })],
+                COINBASE_MATURITY + 10,
+                0,
+                FeeRate::zero(),
+                COINBASE_MATURITY,
+            )
+        };
+        assert!(TransactionValidator::validate_transaction_pool_context(
+            &transaction,
+            &context(11)
+        )
+        .is_err());
+        TransactionValidator::validate_transaction_pool_context(&transaction, &context(10))
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_block_timestamp_not_after_median_time_past() {
+        assert!(
+            BlockValidator::validate_timestamp_greater_than_median_time_past(100, 100).is_err()
+        );
+        assert!(BlockValidator::validate_timestamp_greater_than_median_time_past(99, 100).is_err());
+        BlockValidator::validate_timestamp_greater_than_median_time_past(101, 100).unwrap();
+    }
+
+    #[test]
+    fn validates_block_transactions_against_spent_outputs() {
+        use crate::core::block::BlockHeader;
+        use crate::core::hash::MerkleHash;
+
+        // Enough transactions to be split between the threads, each paying the fee of 10.
+        // The coinbase collects the reward of 50 and the fees.
+        let block = |coinbase_amount: i64| {
+            let mut transactions = vec![Transaction::new(
+                vec![TransactionInput::new_coinbase()],
+                vec![
+                    output(coinbase_amount / 2),
+                    output(coinbase_amount - coinbase_amount / 2),
+                ],
+                0,
+            )
+            .unwrap()];
+            transactions.extend((0..64).map(|locktime| transaction(90, locktime)));
+            let header = BlockHeader::new(
+                BlockHash::new(Sha256::new([0; 32])),
+                MerkleHash::new(Sha256::new([0; 32])),
+                0,
+                0,
+                0,
+            );
+            Block::new(header, transactions)
+        };
+        let context = |spent_outputs: Vec<SpentOutput>| {
+            UtxoContext::new(
+                spent_outputs,
+                COINBASE_MATURITY + 10,
+                COINBASE_MATURITY,
+                50.into(),
+            )
+        };
+        let spent_outputs = || (0..64).map(|_| spent_output(100)).collect::<Vec<_>>();
+
+        BlockValidator::validate_utxo_context(&block(50 + 640), &context(spent_outputs())).unwrap();
+
+        // The coinbase can't pay out more than the reward and the fees.
+        assert!(BlockValidator::validate_utxo_context(
+            &block(50 + 640 + 1),
+            &context(spent_outputs())
+        )
+        .is_err());
+
+        let block = block(50 + 640);
+
+        // Outputs can't exceed inputs.
+        let mut overspent = spent_outputs();
+        overspent[40] = spent_output(80);
+        assert!(BlockValidator::validate_utxo_context(&block, &context(overspent)).is_err());
+
+        let mut immature = spent_outputs();
+        immature[63].coinbase_height = Some(11);
+        assert!(BlockValidator::validate_utxo_context(&block, &context(immature)).is_err());
+
+        let mut missing = spent_outputs();
+        missing.pop();
+        assert!(BlockValidator::validate_utxo_context(&block, &context(missing)).is_err());
     }
 }
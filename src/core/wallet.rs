@@ -0,0 +1,173 @@
+use crate::core::hash::hash;
+use crate::core::storage::{self, FileStorage};
+use crate::core::transaction::{TransactionInput, TransactionOutput};
+use crate::core::utxo_pool::UnspentOutput;
+use crate::core::{as_hex, Address, Coolcoin, Transaction};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The addresses owned by the user, kept in a file on the client's machine.
+/// Coolcoin transactions aren't signed, so the addresses are all that the wallet needs to know.
+#[derive(Serialize, Deserialize)]
+pub struct Wallet {
+    // The first address receives the change.
+    addresses: Vec<Address>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Wallet {
+    /// Creates the wallet with a new address, unless the file already exists.
+    pub fn create(path: &Path) -> Result<Self, String> {
+        if path.exists() {
+            return Err(format!("Wallet: {} already exists.", path.display()));
+        }
+        let mut wallet = Self {
+            addresses: vec![],
+            path: path.to_path_buf(),
+        };
+        wallet.new_address()?;
+        Ok(wallet)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut wallet = storage::load_json::<Self>(&FileStorage, path, "wallet")?
+            .ok_or_else(|| format!("Wallet: {} doesn't exist.", path.display()))?;
+        wallet.path = path.to_path_buf();
+        Ok(wallet)
+    }
+
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses
+    }
+
+    /// Generates the new address, which is unlikely to be used by anyone else, and saves it.
+    pub fn new_address(&mut self) -> Result<Address, String> {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let seed = format!("{}{}{}", nanos, std::process::id(), self.addresses.len());
+        let address = Address::new(as_hex(&hash(seed.as_bytes()).bytes()[..20]));
+        self.addresses.push(address.clone());
+        self.save()?;
+        Ok(address)
+    }
+
+    /// Builds the transaction paying the amount to the address from the given outputs,
+    /// which are expected to be the wallet's unspent outputs, and the fee to the miner.
+    /// The oldest outputs are spent first, and the change is sent back to the wallet.
+    pub fn create_transaction(
+        &self,
+        outputs: &[UnspentOutput],
+        to: Address,
+        amount: Coolcoin,
+        fee: Coolcoin,
+    ) -> Result<Transaction, String> {
+        if amount <= Coolcoin::zero() || fee < Coolcoin::zero() {
+            return Err("Amount must be positive and fee can't be negative.".to_string());
+        }
+        let mut spendable = outputs
+            .iter()
+            .filter(|output| output.is_mature())
+            .collect::<Vec<_>>();
+        spendable.sort_by_key(|output| std::cmp::Reverse(output.confirmations()));
+
+        let total = amount + fee;
+        let mut inputs = vec![];
+        let mut selected = Coolcoin::zero();
+        for output in spendable {
+            if selected >= total {
+                break;
+            }
+            inputs.push(TransactionInput::new(*output.txid(), output.vout()));
+            selected = selected + output.amount();
+        }
+        if selected < total {
+            return Err(format!(
+                "Insufficient funds: {} spendable, but {} needed.",
+                selected.value(),
+                total.value()
+            ));
+        }
+        let mut transaction_outputs = vec![TransactionOutput::new(to, amount)];
+        let change = selected - total;
+        if change > Coolcoin::zero() {
+            transaction_outputs.push(TransactionOutput::new(self.addresses[0].clone(), change));
+        }
+        Transaction::new(inputs, transaction_outputs, 0)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        storage::save(&FileStorage, &self.path, contents.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::transaction::{OutputIndex, TransactionId};
+    use crate::core::validation::COINBASE_MATURITY;
+    use std::fs;
+
+    fn output(
+        wallet: &Wallet,
+        amount: i64,
+        confirmations: u32,
+        is_coinbase: bool,
+    ) -> UnspentOutput {
+        UnspentOutput::new(
+            TransactionId::new(hash(&amount.to_le_bytes())),
+            OutputIndex::new(0),
+            wallet.addresses()[0].clone(),
+            amount.into(),
+            confirmations,
+            is_coinbase,
+            COINBASE_MATURITY,
+        )
+    }
+
+    #[test]
+    fn spends_oldest_mature_outputs_with_change() {
+        let path = std::env::temp_dir().join(format!("wallet_{}.json", std::process::id()));
+        let mut wallet = Wallet::create(&path).unwrap();
+        wallet.new_address().unwrap();
+        assert!(Wallet::create(&path).is_err());
+        let loaded = Wallet::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.addresses(), wallet.addresses());
+        assert_eq!(wallet.addresses().len(), 2);
+        assert_ne!(wallet.addresses()[0], wallet.addresses()[1]);
+
+        let outputs = vec![
+            output(&wallet, 10, 5, false),
+            output(&wallet, 50, COINBASE_MATURITY - 1, true),
+            output(&wallet, 30, 7, false),
+        ];
+        let bob = Address::new("bob".to_string());
+        let transaction = wallet
+            .create_transaction(&outputs, bob.clone(), 32.into(), 1.into())
+            .unwrap();
+        assert_eq!(transaction.inputs().len(), 2);
+        assert_eq!(transaction.inputs()[0].utxo_id(), outputs[2].txid());
+        let outputs_sent = transaction
+            .outputs()
+            .iter()
+            .map(|output| (output.to().clone(), output.amount()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outputs_sent,
+            vec![
+                (bob.clone(), 32.into()),
+                (wallet.addresses()[0].clone(), 7.into())
+            ]
+        );
+
+        // The immature coinbase output can't be spent.
+        assert!(wallet
+            .create_transaction(&outputs, bob, 45.into(), 0.into())
+            .is_err());
+    }
+}
@@ -0,0 +1,567 @@
+use crate::core::json_rpc::{
+    JsonRpcError, JsonRpcRequest, JsonRpcResponse, INVALID_PARAMS, INVALID_REQUEST,
+    METHOD_NOT_FOUND, PARSE_ERROR,
+};
+use crate::core::node_event::NodeEvent;
+use crate::core::rpc_server::{ConnectionLimit, RpcServer, MAX_HEADERS};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Defined by RFC 6455, appended to the client's key to compute the accept key.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// The maximum size of the message sent by the client, which only subscribes to the events.
+const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+// The number of the clients connected at the same time, each served in its own thread.
+const MAX_CONNECTIONS: usize = 32;
+// The number of the notifications waiting to be written to the client, after which
+// the client is considered to lag behind and is disconnected.
+const MAX_QUEUED_NOTIFICATIONS: usize = 1000;
+const TOPICS: [&str; 7] = [
+    "newBlock",
+    "blockDisconnected",
+    "newTransaction",
+    "transactionEvicted",
+    "reorg",
+    "peerConnected",
+    "peerBanned",
+];
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// The frame received from the client.
+#[derive(Debug, PartialEq)]
+struct Frame {
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// The connected client, together with the events it has subscribed to.
+/// The notifications are queued for the client's own thread to write them, so that a slow
+/// client doesn't hold up the others.
+struct Subscriber {
+    notifications: SyncSender<String>,
+    topics: Arc<Mutex<HashSet<String>>>,
+    // The connection, to shut it down when the client lags behind.
+    connection: TcpStream,
+}
+
+/// Pushes the node events to the clients connected over WebSocket, so that they don't need
+/// to poll the node.
+/// Clients subscribe to the events with JSON-RPC requests:
+/// `{"jsonrpc": "2.0", "id": 1, "method": "subscribe", "params": ["newBlock"]}`,
+/// and receive the notifications: `{"jsonrpc": "2.0", "method": "newBlock", "params": {...}}`.
+pub struct WebSocketServer {}
+
+impl WebSocketServer {
+    /// Starts serving the clients in separate threads, up to `MAX_CONNECTIONS` at a time.
+    pub fn start_async(
+        address: &str,
+        events: Receiver<NodeEvent>,
+        enable_logging: bool,
+    ) -> Result<(), String> {
+        let listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+        let (subscriber_sender, subscriber_receiver) = channel();
+        thread::spawn(move || Self::dispatch(events, subscriber_receiver));
+        thread::spawn(move || {
+            ConnectionLimit::new(MAX_CONNECTIONS).serve_incoming(
+                listener,
+                "WebSocket",
+                move |stream| Self::serve(stream, subscriber_sender.clone(), enable_logging),
+            )
+        });
+        Ok(())
+    }
+
+    /// Sends each event to the clients that have subscribed to it.
+    fn dispatch(events: Receiver<NodeEvent>, new_subscribers: Receiver<Subscriber>) {
+        let mut subscribers: Vec<Subscriber> = vec![];
+        for event in events {
+            subscribers.extend(new_subscribers.try_iter());
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": event.topic(),
+                "params": event,
+            })
+            .to_string();
+            Self::notify(&mut subscribers, event.topic(), &notification);
+        }
+    }
+
+    /// Queues the notification for the clients subscribed to the topic, and drops the clients
+    /// that have disconnected or lag behind.
+    fn notify(subscribers: &mut Vec<Subscriber>, topic: &str, notification: &str) {
+        subscribers.retain(|subscriber| {
+            // The client's thread has stopped serving it.
+            if Arc::strong_count(&subscriber.topics) == 1 {
+                return false;
+            }
+            if !subscriber.topics.lock().unwrap().contains(topic) {
+                return true;
+            }
+            match subscriber.notifications.try_send(notification.to_string()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!(target: "rpc", "WebSocket client lags behind the notifications, disconnecting.");
+                    let _ = subscriber.connection.shutdown(Shutdown::Both);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
+    /// Writes the queued notifications to the client, until it disconnects or the dispatcher
+    /// drops it.
+    fn write_notifications(notifications: Receiver<String>, stream: &Mutex<TcpStream>) {
+        for notification in notifications {
+            let mut stream = stream.lock().unwrap();
+            if Self::write_frame(&mut *stream, OPCODE_TEXT, notification.as_bytes()).is_err() {
+                break;
+            }
+        }
+        let _ = stream.lock().unwrap().shutdown(Shutdown::Both);
+    }
+
+    fn serve(
+        stream: TcpStream,
+        new_subscribers: Sender<Subscriber>,
+        enable_logging: bool,
+    ) -> Result<(), String> {
+        // Clients that don't read their notifications must not hold their threads forever.
+        const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+        // Clients that don't finish the handshake must not hold the connection.
+        const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+        stream
+            .set_write_timeout(Some(WRITE_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut stream = stream;
+        let key = match Self::read_handshake(&mut reader) {
+            Ok(key) => key,
+            Err(e) => {
+                let response = format!(
+                    "HTTP/1.1 400 Bad Request\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    e.len(),
+                    e
+                );
+                return stream
+                    .write_all(response.as_bytes())
+                    .map_err(|e| e.to_string());
+            }
+        };
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(&key)
+        );
+        stream
+            .write_all(response.as_bytes())
+            .map_err(|e| e.to_string())?;
+        // Subscribers may not send anything while they wait for the notifications.
+        stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+
+        let connection = stream.try_clone().map_err(|e| e.to_string())?;
+        let stream = Arc::new(Mutex::new(stream));
+        let topics = Arc::new(Mutex::new(HashSet::new()));
+        let (notifications, queued_notifications) = sync_channel(MAX_QUEUED_NOTIFICATIONS);
+        let writer_stream = stream.clone();
+        thread::spawn(move || Self::write_notifications(queued_notifications, &writer_stream));
+        new_subscribers
+            .send(Subscriber {
+                notifications,
+                topics: topics.clone(),
+                connection: connection.try_clone().map_err(|e| e.to_string())?,
+            })
+            .map_err(|e| e.to_string())?;
+
+        let result = Self::serve_frames(&mut reader, &stream, &topics, enable_logging);
+        // Unblocks the writer, and the dispatcher drops the subscriber once it sees that its
+        // topics are no longer shared.
+        let _ = connection.shutdown(Shutdown::Both);
+        result
+    }
+
+    fn serve_frames(
+        reader: &mut impl Read,
+        stream: &Mutex<TcpStream>,
+        topics: &Mutex<HashSet<String>>,
+        enable_logging: bool,
+    ) -> Result<(), String> {
+        loop {
+            let frame = Self::read_frame(reader)?;
+            match frame.opcode {
+                OPCODE_TEXT => {
+                    if enable_logging {
+                        info!(target: "rpc",
+                            "WebSocket request: {}",
+                            String::from_utf8_lossy(&frame.payload)
+                        );
+                    }
+                    let response = Self::handle_message(&frame.payload, topics);
+                    let body = serde_json::to_vec(&response).map_err(|e| e.to_string())?;
+                    Self::write_frame(&mut *stream.lock().unwrap(), OPCODE_TEXT, &body)?;
+                }
+                OPCODE_PING => {
+                    Self::write_frame(&mut *stream.lock().unwrap(), OPCODE_PONG, &frame.payload)?
+                }
+                OPCODE_CLOSE => {
+                    return Self::write_frame(&mut *stream.lock().unwrap(), OPCODE_CLOSE, &[]);
+                }
+                // Pongs and binary frames are ignored.
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_message(message: &[u8], topics: &Mutex<HashSet<String>>) -> JsonRpcResponse {
+        let request = match serde_json::from_slice::<Value>(message) {
+            Ok(value) => {
+                let id = value.get("id").cloned().unwrap_or(Value::Null);
+                match serde_json::from_value::<JsonRpcRequest>(value) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        return JsonRpcResponse::new(
+                            id,
+                            Err(JsonRpcError::new(INVALID_REQUEST, e.to_string())),
+                        )
+                    }
+                }
+            }
+            Err(e) => {
+                return JsonRpcResponse::new(
+                    Value::Null,
+                    Err(JsonRpcError::new(PARSE_ERROR, e.to_string())),
+                )
+            }
+        };
+        let result = Self::update_topics(&request, &mut topics.lock().unwrap());
+        JsonRpcResponse::new(request.id, result)
+    }
+
+    fn update_topics(
+        request: &JsonRpcRequest,
+        topics: &mut HashSet<String>,
+    ) -> Result<Value, JsonRpcError> {
+        let subscribe = match request.method.as_str() {
+            "subscribe" => true,
+            "unsubscribe" => false,
+            method => {
+                return Err(JsonRpcError::new(
+                    METHOD_NOT_FOUND,
+                    format!("Method not found: {}", method),
+                ))
+            }
+        };
+        let requested = request
+            .params
+            .iter()
+            .map(|param| match param.as_str() {
+                Some(topic) if TOPICS.contains(&topic) => Ok(topic.to_string()),
+                _ => Err(JsonRpcError::new(
+                    INVALID_PARAMS,
+                    format!("Unknown event: {}. Expected one of: {:?}", param, TOPICS),
+                )),
+            })
+            .collect::<Result<Vec<String>, JsonRpcError>>()?;
+        for topic in requested {
+            if subscribe {
+                topics.insert(topic);
+            } else {
+                topics.remove(&topic);
+            }
+        }
+        let mut subscribed = topics.iter().cloned().collect::<Vec<String>>();
+        subscribed.sort();
+        Ok(Value::from(subscribed))
+    }
+
+    /// Reads the client's opening handshake and returns its key.
+    /// The request line and the headers are limited like the RPC server's.
+    fn read_handshake(reader: &mut impl BufRead) -> Result<String, String> {
+        let request_line = RpcServer::read_line(reader)?;
+        if !request_line.starts_with("GET ") {
+            return Err(format!("Invalid request line: {}", request_line.trim_end()));
+        }
+        let mut key = None;
+        let mut is_upgrade = false;
+        for num_headers in 0.. {
+            let header = RpcServer::read_line(reader)?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if num_headers == MAX_HEADERS {
+                return Err(format!("More than: {} headers.", MAX_HEADERS));
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                let name = name.trim();
+                if name.eq_ignore_ascii_case("sec-websocket-key") {
+                    key = Some(value.trim().to_string());
+                } else if name.eq_ignore_ascii_case("upgrade") {
+                    is_upgrade = value.trim().eq_ignore_ascii_case("websocket");
+                }
+            }
+        }
+        match key {
+            Some(key) if is_upgrade => Ok(key),
+            _ => Err("Expected the WebSocket upgrade request.".to_string()),
+        }
+    }
+
+    fn read_frame(reader: &mut impl Read) -> Result<Frame, String> {
+        let mut header = [0; 2];
+        reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+        let is_final = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0F;
+        let is_masked = header[1] & 0x80 != 0;
+        if !is_final {
+            return Err("Fragmented messages are not supported.".to_string());
+        }
+        // Clients must mask all frames.
+        if !is_masked {
+            return Err("Frame is not masked.".to_string());
+        }
+        let length = match header[1] & 0x7F {
+            126 => {
+                let mut length = [0; 2];
+                reader.read_exact(&mut length).map_err(|e| e.to_string())?;
+                u16::from_be_bytes(length) as u64
+            }
+            127 => {
+                let mut length = [0; 8];
+                reader.read_exact(&mut length).map_err(|e| e.to_string())?;
+                u64::from_be_bytes(length)
+            }
+            length => length as u64,
+        };
+        if length > MAX_MESSAGE_SIZE {
+            return Err(format!("Message of: {} bytes is too large.", length));
+        }
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask).map_err(|e| e.to_string())?;
+        let mut payload = vec![0; length as usize];
+        reader.read_exact(&mut payload).map_err(|e| e.to_string())?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        Ok(Frame { opcode, payload })
+    }
+
+    fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> Result<(), String> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            length if length < 126 => frame.push(length as u8),
+            length if length <= u16::MAX as usize => {
+                frame.push(126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        writer.write_all(&frame).map_err(|e| e.to_string())
+    }
+}
+
+/// Computes the Sec-WebSocket-Accept header for the client's key.
+fn accept_key(key: &str) -> String {
+    BASE64.encode(sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+/// SHA-1 as specified by RFC 3174, which is only needed for the WebSocket handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                chunk[4 * i],
+                chunk[4 * i + 1],
+                chunk[4 * i + 2],
+                chunk[4 * i + 3],
+            ]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+    let mut digest = [0; 20];
+    for (i, s) in state.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&s.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::as_hex;
+    use crate::core::rpc_server::MAX_HEADER_LENGTH;
+
+    #[test]
+    fn computes_accept_key() {
+        // The example from RFC 6455.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn computes_sha1() {
+        // The test vectors from RFC 3174 and FIPS 180, which cover the messages that need
+        // another block for the padding, and the ones that span multiple blocks.
+        let vectors = [
+            (b"".to_vec(), "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            (b"abc".to_vec(), "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".to_vec(),
+                "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            ),
+            (
+                b"0123456701234567012345670123456701234567012345670123456701234567".repeat(10),
+                "dea356a2cddd90c7a7ecedc5ebb563934f460452",
+            ),
+            (
+                vec![b'a'; 1_000_000],
+                "34aa973cd4c4daa4f61eeb2bdbad27316534016f",
+            ),
+        ];
+        for (message, digest) in vectors {
+            assert_eq!(as_hex(&sha1(&message)), digest);
+        }
+    }
+
+    #[test]
+    fn reads_handshake_within_limits() {
+        let handshake = |headers: &str| {
+            let request = format!(
+                "GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: key\r\n{}\r\n",
+                headers
+            );
+            WebSocketServer::read_handshake(&mut request.as_bytes())
+        };
+        assert_eq!(handshake(""), Ok("key".to_string()));
+        assert!(handshake(&format!("X: {}\r\n", "a".repeat(MAX_HEADER_LENGTH))).is_err());
+        assert!(handshake(&"X: a\r\n".repeat(MAX_HEADERS)).is_err());
+        // A request that never ends isn't read into memory.
+        let mut endless = std::io::repeat(b'a').take(u64::MAX);
+        assert!(WebSocketServer::read_handshake(&mut BufReader::new(&mut endless)).is_err());
+    }
+
+    #[test]
+    fn drops_subscribers_that_lag_behind() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (connection, _) = listener.accept().unwrap();
+        let subscriber = |topic: &str, queue_size| {
+            let (notifications, queued) = sync_channel(queue_size);
+            let topics = Arc::new(Mutex::new(HashSet::from([topic.to_string()])));
+            let subscriber = Subscriber {
+                notifications,
+                topics: topics.clone(),
+                connection: connection.try_clone().unwrap(),
+            };
+            (subscriber, queued, topics)
+        };
+        let (lagging, _lagging_queue, _lagging_topics) = subscriber("newBlock", 1);
+        let (other, other_queue, _other_topics) = subscriber("newBlock", 2);
+        let (idle, _idle_queue, _idle_topics) = subscriber("reorg", 1);
+        let (gone, _gone_queue, gone_topics) = subscriber("newBlock", 2);
+        drop(gone_topics);
+        let mut subscribers = vec![lagging, other, idle, gone];
+
+        WebSocketServer::notify(&mut subscribers, "newBlock", "first");
+        assert_eq!(subscribers.len(), 3);
+        WebSocketServer::notify(&mut subscribers, "newBlock", "second");
+        // The lagging subscriber's queue was full, and its connection is shut down.
+        assert_eq!(subscribers.len(), 2);
+        assert_eq!(client.peek(&mut [0]).unwrap(), 0);
+        assert_eq!(
+            other_queue.try_iter().collect::<Vec<_>>(),
+            ["first", "second"]
+        );
+    }
+
+    #[test]
+    fn reads_masked_frames_and_subscribes() {
+        let message =
+            br#"{"jsonrpc":"2.0","id":1,"method":"subscribe","params":["newBlock","reorg"]}"#;
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x80 | OPCODE_TEXT, 0x80 | message.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            message
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        let frame = WebSocketServer::read_frame(&mut frame.as_slice()).unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                opcode: OPCODE_TEXT,
+                payload: message.to_vec(),
+            }
+        );
+
+        let topics = Mutex::new(HashSet::new());
+        let response = WebSocketServer::handle_message(&frame.payload, &topics);
+        assert_eq!(response.result, Some(json!(["newBlock", "reorg"])));
+        let response = WebSocketServer::handle_message(
+            br#"{"id":2,"method":"unsubscribe","params":["reorg"]}"#,
+            &topics,
+        );
+        assert_eq!(response.result, Some(json!(["newBlock"])));
+        let response = WebSocketServer::handle_message(
+            br#"{"id":3,"method":"subscribe","params":["newHeader"]}"#,
+            &topics,
+        );
+        assert_eq!(response.error.unwrap().code, INVALID_PARAMS);
+    }
+}
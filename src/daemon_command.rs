@@ -1,85 +1,491 @@
-use crate::core::coolcoin_network::NetworkParams;
-use crate::core::{Address, CoolcoinNetwork, CoolcoinNode};
-use clap::{App, Arg, ArgMatches};
-use std::error::Error;
-
-pub struct DaemonCliOptions {
-    server: String,
-    peers: Vec<String>,
-    enable_logging: bool,
-    coinbase_address: Address,
-}
-
-impl DaemonCliOptions {
-    pub fn parse(matches: &ArgMatches) -> Result<Self, Box<dyn Error>> {
-        let peers = matches
-            .values_of("peers")
-            .map(|v| v.collect())
-            .unwrap_or_else(|| vec![])
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
-        let enable_logging = matches.is_present("enable_logging");
-        let coinbase_address = matches.value_of("coinbase_address").unwrap().to_string();
-
-        Ok(Self {
-            server: matches.value_of("server").unwrap().to_string(),
-            peers,
-            enable_logging,
-            coinbase_address: Address::new(coinbase_address),
-        })
-    }
-}
-
-pub fn daemon_command() -> App<'static> {
-    App::new("daemon")
-        .version("0.1")
-        .about("Coolcoin daemon process.")
-        .arg(
-            Arg::new("server")
-                .short('s')
-                .long("server")
-                .value_name("HOSTNAME:PORT")
-                .about("Address at which the daemon runs servers for peers to connect to.")
-                .takes_value(true)
-                .required(true),
-        )
-        .arg(
-            Arg::new("peers")
-                .long("peers")
-                .value_name("[String]")
-                .about("List of peer addresses to which the node connects to.")
-                .multiple_occurrences(true)
-                .use_delimiter(true)
-                .takes_value(true)
-                .default_values(vec![].as_slice())
-                .required(false),
-        )
-        .arg(
-            Arg::new("enable_logging")
-                .long("enable_logging")
-                .about("If true, the messages sent and received via the network are logged.")
-                .takes_value(false)
-                .required(false),
-        )
-        .arg(
-            Arg::new("coinbase_address")
-                .long("coinbase_address")
-                .value_name("ADDRESS")
-                .about("Address at which the miner gets paid for successfully mining a block.")
-                .takes_value(true)
-                .required(true),
-        )
-}
-
-pub fn run_daemon(options: &DaemonCliOptions) -> Result<(), Box<dyn Error>> {
-    println!("Starting full node!");
-    let network_params = NetworkParams::new(
-        options.server.clone(),
-        options.peers.clone(),
-        options.enable_logging,
-    );
-    let mut node = CoolcoinNode::connect(network_params, options.coinbase_address.clone())?;
-    node.run();
-    Ok(())
-}
+use crate::config_file::conf_arg;
+use crate::core::chain_params::ChainParams;
+use crate::core::coolcoin_network::NetworkParams;
+use crate::core::coolcoin_node::NodeParams;
+use crate::core::metrics::SlowThresholds;
+use crate::core::miner::{RewardSplit, MAX_COINBASE_TAG_SIZE};
+use crate::core::mining_server::MiningServerParams;
+use crate::core::pid_file::PidFile;
+use crate::core::rpc_server::RpcCredentials;
+use crate::core::transaction_pool::PackageLimits;
+use crate::core::{
+    Address, Coolcoin, CoolcoinNode, ExplorerServer, FeeRate, MetricsServer, RpcServer,
+    WebSocketServer,
+};
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tracing::info;
+
+const PID_FILE: &str = "coolcoin.pid";
+const LOG_FILE: &str = "debug.log";
+
+pub struct DaemonCliOptions {
+    server: String,
+    mining_server: Option<String>,
+    rpc_server: Option<String>,
+    // Generated for each run and written to the cookie file if not given.
+    rpc_credentials: Option<RpcCredentials>,
+    websocket_server: Option<String>,
+    metrics_server: Option<String>,
+    explorer_server: Option<String>,
+    pool_share_difficulty: Option<u32>,
+    peers: Vec<String>,
+    enable_logging: bool,
+    reward_split: RewardSplit,
+    coinbase_tag: Vec<u8>,
+    package_limits: PackageLimits,
+    max_transaction_pool_usage: usize,
+    min_relay_fee_rate: FeeRate,
+    data_dir: PathBuf,
+    capture_file: Option<PathBuf>,
+    status_interval: Option<Duration>,
+    slow_thresholds: SlowThresholds,
+    chain_params: ChainParams,
+    daemon: bool,
+}
+
+impl DaemonCliOptions {
+    pub fn parse(matches: &ArgMatches) -> Result<Self, Box<dyn Error>> {
+        let peers = matches
+            .values_of("peers")
+            .map(|v| v.collect::<Vec<&str>>())
+            .unwrap_or_default()
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let enable_logging = matches.is_present("enable_logging");
+        let coinbase_address = matches.value_of("coinbase_address").unwrap().parse()?;
+        let mut payees = vec![];
+        for payee in matches.values_of("coinbase_split").unwrap_or_default() {
+            let (address, percentage) = payee
+                .rsplit_once(':')
+                .ok_or_else(|| format!("Invalid reward split: {}", payee))?;
+            let percentage = percentage
+                .parse::<u32>()
+                .map_err(|e| format!("Invalid reward split: {}: {}", payee, e))?;
+            payees.push((address.parse::<Address>()?, percentage));
+        }
+        let reward_split = RewardSplit::new(coinbase_address, payees)?;
+        let coinbase_tag = matches
+            .value_of("coinbase_tag")
+            .unwrap()
+            .as_bytes()
+            .to_vec();
+        if coinbase_tag.len() > MAX_COINBASE_TAG_SIZE {
+            return Err(format!(
+                "Coinbase tag must be at most {} bytes long.",
+                MAX_COINBASE_TAG_SIZE
+            )
+            .into());
+        }
+        // Size limits are given in kilobytes.
+        let package_limits = PackageLimits {
+            max_ancestor_count: matches.value_of_t("limit_ancestor_count")?,
+            max_ancestor_size: matches.value_of_t::<usize>("limit_ancestor_size")? * 1000,
+            max_descendant_count: matches.value_of_t("limit_descendant_count")?,
+            max_descendant_size: matches.value_of_t::<usize>("limit_descendant_size")? * 1000,
+        };
+        let min_relay_fee_rate =
+            FeeRate::per_kilobyte(Coolcoin::new(matches.value_of_t("min_relay_fee")?));
+
+        Ok(Self {
+            server: matches.value_of("server").unwrap().to_string(),
+            mining_server: matches.value_of("mining_server").map(str::to_string),
+            rpc_server: matches.value_of("rpc_server").map(str::to_string),
+            rpc_credentials: match (
+                matches.value_of("rpc_user"),
+                matches.value_of("rpc_password"),
+            ) {
+                (Some(user), Some(password)) => {
+                    Some(RpcCredentials::new(user.to_string(), password.to_string()))
+                }
+                (None, None) => None,
+                _ => return Err("--rpc_user and --rpc_password must be given together.".into()),
+            },
+            websocket_server: matches.value_of("websocket_server").map(str::to_string),
+            metrics_server: matches.value_of("metrics_server").map(str::to_string),
+            explorer_server: matches.value_of("explorer_server").map(str::to_string),
+            pool_share_difficulty: match matches.is_present("pool_share_difficulty") {
+                true => Some(matches.value_of_t("pool_share_difficulty")?),
+                false => None,
+            },
+            peers,
+            enable_logging,
+            reward_split,
+            coinbase_tag,
+            package_limits,
+            // Given in megabytes.
+            max_transaction_pool_usage: matches.value_of_t::<usize>("max_mempool")? * 1_000_000,
+            min_relay_fee_rate,
+            data_dir: PathBuf::from(matches.value_of("data_dir").unwrap()),
+            capture_file: matches.value_of("capture_file").map(PathBuf::from),
+            // Zero disables the status line.
+            status_interval: match matches.value_of_t::<u64>("status_interval")? {
+                0 => None,
+                seconds => Some(Duration::from_secs(seconds)),
+            },
+            slow_thresholds: SlowThresholds {
+                block_validation: Duration::from_millis(
+                    matches.value_of_t("slow_block_validation_ms")?,
+                ),
+                utxo_flush: Duration::from_millis(matches.value_of_t("slow_utxo_flush_ms")?),
+                message_handling: Duration::from_millis(matches.value_of_t("slow_message_ms")?),
+            },
+            chain_params: match matches.value_of("chain_params") {
+                Some(path) => ChainParams::load(Path::new(path))?,
+                None => ChainParams::by_name(matches.value_of("network").unwrap())?,
+            },
+            daemon: matches.is_present("daemon"),
+        })
+    }
+}
+
+pub fn daemon_command() -> App<'static> {
+    App::new("daemon")
+        .version("0.1")
+        .about("Coolcoin daemon process.")
+        .arg(
+            Arg::new("server")
+                .short('s')
+                .long("server")
+                .value_name("HOSTNAME:PORT")
+                .about("Address at which the daemon runs servers for peers to connect to.")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("mining_server")
+                .long("mining_server")
+                .value_name("HOSTNAME:PORT")
+                .about("Address at which the daemon serves mining jobs to external miners. Disabled if not given.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("rpc_server")
+                .long("rpc_server")
+                .value_name("HOSTNAME:PORT")
+                .about("Address at which the daemon serves JSON-RPC requests over HTTP, or only the port to serve them on the loopback interface. Disabled if not given.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("rpc_user")
+                .long("rpc_user")
+                .value_name("USER")
+                .about("User name that the JSON-RPC callers authenticate with, together with --rpc_password. If not given, the credentials are generated and written to the .cookie file in the data directory.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("rpc_password")
+                .long("rpc_password")
+                .value_name("PASSWORD")
+                .about("Password that the JSON-RPC callers authenticate with, together with --rpc_user.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("websocket_server")
+                .long("websocket_server")
+                .value_name("HOSTNAME:PORT")
+                .about("Address at which the daemon pushes its events, e.g. newBlock, newTransaction and reorg, to the subscribed WebSocket clients. Disabled if not given.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("metrics_server")
+                .long("metrics_server")
+                .value_name("HOSTNAME:PORT")
+                .about("Address at which the daemon serves its metrics for Prometheus at GET /metrics. Disabled if not given.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("explorer_server")
+                .long("explorer_server")
+                .value_name("HOSTNAME:PORT")
+                .about("Address at which the daemon serves the block explorer for the browser, and its REST endpoints under /api, or only the port to serve them on the loopback interface. Disabled if not given.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("pool_share_difficulty")
+                .long("pool_share_difficulty")
+                .value_name("DIFFICULTY")
+                .about("If given, the mining server runs as a mining pool, which accepts shares of the given difficulty (the number of leading zero bits) and splits the block reward between the miners proportionally to their shares.")
+                .takes_value(true)
+                .requires("mining_server")
+                .required(false),
+        )
+        .arg(
+            Arg::new("peers")
+                .long("peers")
+                .value_name("[String]")
+                .about("List of peer addresses to which the node connects to.")
+                .multiple_occurrences(true)
+                .use_delimiter(true)
+                .takes_value(true)
+                .default_values(vec![].as_slice())
+                .required(false),
+        )
+        .arg(
+            Arg::new("enable_logging")
+                .long("enable_logging")
+                .about("If true, the messages sent and received via the network are logged.")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::new("coinbase_address")
+                .long("coinbase_address")
+                .value_name("ADDRESS")
+                .about("Address at which the miner gets paid for successfully mining a block.")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("coinbase_split")
+                .long("coinbase_split")
+                .value_name("ADDRESS:PERCENT")
+                .about("Addresses that get the given percentage of the block reward, e.g. the operator's fee. The rest of the reward goes to the coinbase address.")
+                .multiple_occurrences(true)
+                .use_delimiter(true)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("coinbase_tag")
+                .long("coinbase_tag")
+                .value_name("TAG")
+                .about("Text that the miner embeds in the coinbase transactions of its blocks, after the block height.")
+                .takes_value(true)
+                .required(false)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("limit_ancestor_count")
+                .long("limit_ancestor_count")
+                .value_name("COUNT")
+                .about("Maximum number of in-pool ancestors of a transaction (including itself).")
+                .takes_value(true)
+                .required(false)
+                .default_value("25"),
+        )
+        .arg(
+            Arg::new("limit_ancestor_size")
+                .long("limit_ancestor_size")
+                .value_name("KILOBYTES")
+                .about("Maximum total size of in-pool ancestors of a transaction (including itself).")
+                .takes_value(true)
+                .required(false)
+                .default_value("101"),
+        )
+        .arg(
+            Arg::new("limit_descendant_count")
+                .long("limit_descendant_count")
+                .value_name("COUNT")
+                .about("Maximum number of in-pool descendants of a transaction (including itself).")
+                .takes_value(true)
+                .required(false)
+                .default_value("25"),
+        )
+        .arg(
+            Arg::new("limit_descendant_size")
+                .long("limit_descendant_size")
+                .value_name("KILOBYTES")
+                .about("Maximum total size of in-pool descendants of a transaction (including itself).")
+                .takes_value(true)
+                .required(false)
+                .default_value("101"),
+        )
+        .arg(
+            Arg::new("max_mempool")
+                .long("max_mempool")
+                .value_name("MEGABYTES")
+                .about("Maximum memory usage of the transaction pool. Transactions with the lowest fee rate are evicted when exceeded.")
+                .takes_value(true)
+                .required(false)
+                .default_value("300"),
+        )
+        .arg(
+            Arg::new("min_relay_fee")
+                .long("min_relay_fee")
+                .value_name("COOLCOINS")
+                .about("Minimum fee rate, in coolcoins per 1000 bytes, for transactions to be accepted and relayed.")
+                .takes_value(true)
+                .required(false)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("data_dir")
+                .long("data_dir")
+                .value_name("PATH")
+                .about("Directory in which the node keeps its persistent data, e.g. fee estimates.")
+                .takes_value(true)
+                .required(false)
+                .default_value("./coolcoin_data"),
+        )
+        .arg(
+            Arg::new("capture_file")
+                .long("capture_file")
+                .value_name("PATH")
+                .about("File to which the messages received from the peers are recorded, so that they can be fed to a fresh node with the replay command.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("status_interval")
+                .long("status_interval")
+                .value_name("SECONDS")
+                .about("How often the daemon logs a status line with its height, tip, peers, transaction pool size, blocks waiting for their ancestors and hash rate. Zero disables it.")
+                .takes_value(true)
+                .required(false)
+                .default_value("60"),
+        )
+        .arg(
+            Arg::new("slow_block_validation_ms")
+                .long("slow_block_validation_ms")
+                .value_name("MILLISECONDS")
+                .about("Blocks that take longer to validate and connect are logged as slow, with their hash and size.")
+                .takes_value(true)
+                .required(false)
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("slow_utxo_flush_ms")
+                .long("slow_utxo_flush_ms")
+                .value_name("MILLISECONDS")
+                .about("Updates of the UTXO pool with the connected and disconnected blocks that take longer are logged as slow.")
+                .takes_value(true)
+                .required(false)
+                .default_value("500"),
+        )
+        .arg(
+            Arg::new("slow_message_ms")
+                .long("slow_message_ms")
+                .value_name("MILLISECONDS")
+                .about("Messages from the peers and clients that take longer to handle are logged as slow, with their type and sender.")
+                .takes_value(true)
+                .required(false)
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("chain_params")
+                .long("chain_params")
+                .value_name("PATH")
+                .about("File with the parameters of the network to join, as printed by the genesis command. The coolcoin network by default.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("network")
+                .long("network")
+                .value_name("NAME")
+                .about("The network to join: main, or regtest, where the blocks are mined instantly and the coinbase outputs mature after two blocks, for testing.")
+                .takes_value(true)
+                .required(false)
+                .possible_values(&["main", "regtest"])
+                .default_value("main")
+                .conflicts_with("chain_params"),
+        )
+        .arg(conf_arg())
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .about("If true, the daemon runs in the background and writes its logs to debug.log in the data directory.")
+                .takes_value(false)
+                .required(false),
+        )
+}
+
+pub fn run_daemon(options: &DaemonCliOptions) -> Result<(), Box<dyn Error>> {
+    if options.daemon {
+        return run_in_background(options);
+    }
+    // Removed when the node stops.
+    let _pid_file = PidFile::acquire(&options.data_dir.join(PID_FILE))?;
+    info!("Starting full node!");
+    let mut network_params = NetworkParams::new(
+        options.server.clone(),
+        options.peers.clone(),
+        options.enable_logging,
+    );
+    if let Some(capture_file) = &options.capture_file {
+        network_params = network_params.with_capture_file(capture_file.clone());
+    }
+    let mining_server_params = options.mining_server.clone().map(|address| {
+        MiningServerParams::new(
+            NetworkParams::new(address, vec![], options.enable_logging),
+            options.pool_share_difficulty,
+        )
+    });
+    let node_params = NodeParams {
+        reward_split: options.reward_split.clone(),
+        coinbase_tag: options.coinbase_tag.clone(),
+        package_limits: options.package_limits,
+        max_transaction_pool_usage: options.max_transaction_pool_usage,
+        min_relay_fee_rate: options.min_relay_fee_rate,
+        data_dir: options.data_dir.clone(),
+        chain_params: options.chain_params.clone(),
+        status_interval: options.status_interval,
+        slow_thresholds: options.slow_thresholds,
+    };
+    let node = CoolcoinNode::connect(network_params, mining_server_params, node_params)?;
+    if let Some(rpc_server) = &options.rpc_server {
+        let credentials = match &options.rpc_credentials {
+            Some(credentials) => credentials.clone(),
+            None => RpcCredentials::generate_cookie(&options.data_dir)?,
+        };
+        RpcServer::start_async(
+            rpc_server,
+            options.server.clone(),
+            credentials,
+            options.enable_logging,
+        )?;
+    }
+    if let Some(websocket_server) = &options.websocket_server {
+        WebSocketServer::start_async(websocket_server, node.subscribe(), options.enable_logging)?;
+    }
+    if let Some(metrics_server) = &options.metrics_server {
+        MetricsServer::start_async(metrics_server, node.metrics())?;
+    }
+    if let Some(explorer_server) = &options.explorer_server {
+        ExplorerServer::start_async(explorer_server, options.server.clone())?;
+    }
+    node.run();
+    Ok(())
+}
+
+/// Starts the daemon with the same arguments in another process, which outlives this one,
+/// and redirects its output to the log file.
+fn run_in_background(options: &DaemonCliOptions) -> Result<(), Box<dyn Error>> {
+    // Fail early, rather than in the background.
+    PidFile::check_not_running(&options.data_dir.join(PID_FILE))?;
+    fs::create_dir_all(&options.data_dir)?;
+    let log_path = options.data_dir.join(LOG_FILE);
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)?;
+    let args = std::env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--daemon")
+        .collect::<Vec<_>>();
+    let child = Command::new(std::env::current_exe()?)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .spawn()?;
+    println!(
+        "Coolcoin server starting in the background with pid: {}. Logs are written to: {}",
+        child.id(),
+        log_path.display()
+    );
+    Ok(())
+}
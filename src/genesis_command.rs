@@ -0,0 +1,104 @@
+use crate::core::chain_params::ChainParams;
+use crate::core::{Address, Coolcoin};
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct GenesisCliOptions {
+    message: String,
+    difficulty: u32,
+    reward_address: Address,
+    reward: Coolcoin,
+    timestamp: u32,
+}
+
+impl GenesisCliOptions {
+    pub fn parse(matches: &ArgMatches) -> Result<Self, Box<dyn Error>> {
+        let difficulty = matches.value_of_t("difficulty")?;
+        if difficulty > 32 {
+            return Err(
+                "Difficulty can't be higher than 32, the block would take too long to mine.".into(),
+            );
+        }
+        Ok(Self {
+            message: matches.value_of("message").unwrap().to_string(),
+            difficulty,
+            reward_address: matches.value_of("reward_address").unwrap().parse()?,
+            reward: Coolcoin::new(matches.value_of_t("reward")?),
+            timestamp: match matches.is_present("timestamp") {
+                true => matches.value_of_t("timestamp")?,
+                false => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32,
+            },
+        })
+    }
+}
+
+pub fn genesis_command() -> App<'static> {
+    App::new("genesis")
+        .version("0.1")
+        .about("Mines the genesis block of a new network, and prints the network's parameters, which the daemons load with --chain_params.")
+        .arg(
+            Arg::new("message")
+                .long("message")
+                .value_name("TEXT")
+                .about("Message embedded in the genesis block's coinbase transaction.")
+                .takes_value(true)
+                .required(false)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("difficulty")
+                .long("difficulty")
+                .value_name("BITS")
+                .about("Number of leading zero bits of the block hashes, the same for all blocks in the network.")
+                .takes_value(true)
+                .required(false)
+                .default_value("8"),
+        )
+        .arg(
+            Arg::new("reward_address")
+                .long("reward_address")
+                .value_name("ADDRESS")
+                .about("Address to which the genesis block's reward is paid.")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("reward")
+                .long("reward")
+                .value_name("AMOUNT")
+                .about("Reward for mining a block, including the genesis block.")
+                .takes_value(true)
+                .required(false)
+                .default_value("50"),
+        )
+        .arg(
+            Arg::new("timestamp")
+                .long("timestamp")
+                .value_name("SECONDS")
+                .about("Timestamp of the genesis block, the current time by default.")
+                .takes_value(true)
+                .required(false),
+        )
+}
+
+pub fn run_genesis(options: &GenesisCliOptions) -> Result<(), Box<dyn Error>> {
+    let chain_params = ChainParams::mine_genesis(
+        options.message.clone(),
+        options.timestamp,
+        options.difficulty,
+        options.reward_address.clone(),
+        options.reward,
+    )?;
+    let genesis_block = chain_params.genesis_block();
+    eprintln!("Genesis block: {}", genesis_block.id());
+    eprintln!(
+        "Header: {}",
+        serde_json::to_string_pretty(genesis_block.header())?
+    );
+    eprintln!(
+        "Save the chain params below to a file, and start the daemons with --chain_params <file>:"
+    );
+    print!("{}", chain_params.to_toml());
+    Ok(())
+}
@@ -1,3 +1,11 @@
 pub mod client_command;
+pub mod client_shell;
+pub mod client_top;
+pub mod config_file;
 pub mod core;
 pub mod daemon_command;
+pub mod genesis_command;
+pub mod logging;
+pub mod miner_command;
+pub mod regtest_command;
+pub mod replay_command;
@@ -0,0 +1,127 @@
+use crate::core::log_filter::{self, parse_category, parse_level, LogLevels};
+use clap::{Arg, ArgMatches};
+use std::io::IsTerminal;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+
+pub struct LogOptions {
+    level: LevelFilter,
+    // The levels of the categories that differ from the default level.
+    categories: Vec<(String, LevelFilter)>,
+    json: bool,
+}
+
+impl LogOptions {
+    pub fn parse(matches: &ArgMatches) -> Result<Self, String> {
+        let level = parse_level(matches.value_of("log_level").unwrap())?;
+        let mut categories = vec![];
+        for category in matches.values_of("log_category").unwrap_or_default() {
+            categories.push(parse_category(category)?);
+        }
+        Ok(Self {
+            level,
+            categories,
+            json: matches.is_present("log_json"),
+        })
+    }
+}
+
+/// The options of the log output, shared by all commands.
+pub fn logging_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("log_level")
+            .long("log_level")
+            .value_name("LEVEL")
+            .about("Level of the log messages: off, error, warn, info, debug or trace.")
+            .takes_value(true)
+            .global(true)
+            .required(false)
+            .default_value("info"),
+        Arg::new("log_category")
+            .long("log_category")
+            .value_name("CATEGORY[=LEVEL]")
+            .about("Level of the log messages in the category: net, validation, mempool, miner, rpc or status, e.g. net=debug,mempool=off. The category without the level is logged in full. Can be changed while the daemon runs with the client's logging command.")
+            .multiple_occurrences(true)
+            .use_delimiter(true)
+            .takes_value(true)
+            .global(true)
+            .required(false),
+        Arg::new("log_json")
+            .long("log_json")
+            .about("If true, the log messages are written as JSON objects, one per line.")
+            .takes_value(false)
+            .global(true)
+            .required(false),
+    ]
+}
+
+/// The option of how the hashes are shown in the log messages and the output, shared by
+/// all commands.
+pub fn hash_display_arg() -> Arg<'static> {
+    Arg::new("hash_display")
+        .long("hash_display")
+        .value_name("full|LENGTH")
+        .about("Shows the full hashes of the blocks and transactions, or the given number of their last hex characters, which are extended where they'd look the same.")
+        .takes_value(true)
+        .global(true)
+        .required(false)
+        .default_value("8")
+}
+
+/// Installs the global subscriber, which writes the log messages to stdout,
+/// in color if it's the terminal. The levels of the categories can be changed later,
+/// e.g. by the node's `logging` request.
+pub fn init_logging(options: &LogOptions) -> Result<(), String> {
+    let filter = log_filter::reloadable(LogLevels::new(options.level, &options.categories))?;
+    let result = if options.json {
+        tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer().json().with_filter(filter))
+            .try_init()
+    } else {
+        tracing_subscriber::registry()
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(std::io::stdout().is_terminal())
+                    .with_filter(filter),
+            )
+            .try_init()
+    };
+    result.map_err(|e| format!("Failed to initialize logging: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::App;
+
+    fn parse(args: &[&str]) -> Result<LogOptions, String> {
+        let matches = App::new("coolcoin")
+            .args(logging_args())
+            .try_get_matches_from(args)
+            .unwrap();
+        LogOptions::parse(&matches)
+    }
+
+    #[test]
+    fn parses_category_levels() {
+        let options = parse(&[
+            "coolcoin",
+            "--log_level=warn",
+            "--log_category=net=debug,mempool",
+            "--log_json",
+        ])
+        .unwrap();
+        assert_eq!(options.level, LevelFilter::WARN);
+        assert_eq!(
+            options.categories,
+            vec![
+                ("net".to_string(), LevelFilter::DEBUG),
+                ("mempool".to_string(), LevelFilter::TRACE)
+            ]
+        );
+        assert!(options.json);
+
+        assert!(parse(&["coolcoin", "--log_category=wallet=info"]).is_err());
+        assert!(parse(&["coolcoin", "--log_level=loud"]).is_err());
+    }
+}
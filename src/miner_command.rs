@@ -0,0 +1,351 @@
+use crate::config_file::conf_arg;
+use crate::core::miner::{HashrateMeter, Miner, MinerThrottle};
+use crate::core::mining_server::{MiningJob, MiningShare, SubmitResult};
+use crate::core::peer_connection::PeerMessage;
+use crate::core::{Address, PeerConnection};
+use clap::{App, AppSettings, Arg, ArgMatches};
+use std::convert::Infallible;
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+pub struct MinerCliOptions {
+    server: String,
+    payout_address: Option<Address>,
+    throttle: MinerThrottle,
+    enable_logging: bool,
+}
+
+impl MinerCliOptions {
+    pub fn parse(matches: &ArgMatches) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            server: matches.value_of("server").unwrap().to_string(),
+            payout_address: matches
+                .value_of("payout_address")
+                .map(str::parse)
+                .transpose()?,
+            throttle: MinerThrottle::new(
+                match matches.is_present("max_hashrate") {
+                    true => Some(matches.value_of_t("max_hashrate")?),
+                    false => None,
+                },
+                // Given in percent.
+                matches.value_of_t::<f64>("mine_duty_cycle")? / 100.0,
+            )?,
+            enable_logging: matches.is_present("enable_logging"),
+        })
+    }
+}
+
+pub struct MinerBenchCliOptions {
+    duration: Duration,
+    max_threads: usize,
+}
+
+impl MinerBenchCliOptions {
+    pub fn parse(matches: &ArgMatches) -> Result<Self, Box<dyn Error>> {
+        let max_threads = match matches.is_present("max_threads") {
+            true => matches.value_of_t("max_threads")?,
+            false => thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        if max_threads == 0 {
+            return Err("The maximum number of threads must be positive.".into());
+        }
+        Ok(Self {
+            duration: Duration::from_secs(matches.value_of_t("duration")?),
+            max_threads,
+        })
+    }
+}
+
+pub fn miner_command() -> App<'static> {
+    App::new("miner")
+        .version("0.1")
+        .about("Coolcoin miner process that mines the jobs served by the daemon's mining server.")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            App::new("bench")
+                .about("Measures the hash rate at several difficulties and with several threads, and suggests how many miners to run.")
+                .arg(
+                    Arg::new("duration")
+                        .long("duration")
+                        .value_name("SECONDS")
+                        .about("Duration of each measurement.")
+                        .takes_value(true)
+                        .required(false)
+                        .default_value("3"),
+                )
+                .arg(
+                    Arg::new("max_threads")
+                        .long("max_threads")
+                        .value_name("COUNT")
+                        .about("Maximum number of threads to measure with. Defaults to the number of CPUs.")
+                        .takes_value(true)
+                        .required(false),
+                ),
+        )
+        .arg(
+            Arg::new("server")
+                .short('s')
+                .long("server")
+                .value_name("HOSTNAME:PORT")
+                .about("Address of the daemon's mining server.")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("payout_address")
+                .long("payout_address")
+                .value_name("ADDRESS")
+                .about("Address at which the miner gets paid for its shares, if the daemon runs a mining pool.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("max_hashrate")
+                .long("max_hashrate")
+                .value_name("HASHES_PER_SECOND")
+                .about("Maximum number of hashes per second. The miner pauses between batches of nonces to stay below it.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("mine_duty_cycle")
+                .long("mine_duty_cycle")
+                .value_name("PERCENT")
+                .about("Percentage of time spent mining. The miner pauses between batches of nonces for the rest of the time.")
+                .takes_value(true)
+                .required(false)
+                .default_value("100"),
+        )
+        .arg(
+            Arg::new("enable_logging")
+                .long("enable_logging")
+                .about("If true, the messages sent and received via the network are logged.")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(conf_arg())
+}
+
+/// The job that the miner is currently working on.
+struct Work {
+    job: MiningJob,
+    timestamp: u32,
+    next_nonce: u32,
+}
+
+/// The reason why the miner has stopped mining for the server.
+enum SessionError {
+    // The connection to the server has been lost, so the miner should reconnect.
+    ConnectionLost(String),
+    // The server has rejected the miner, which won't change by reconnecting.
+    Rejected(String),
+}
+
+impl From<String> for SessionError {
+    fn from(e: String) -> Self {
+        SessionError::ConnectionLost(e)
+    }
+}
+
+/// Mines for the server until the miner is rejected by the server.
+/// If the connection to the server is lost, the miner reconnects with an exponential
+/// backoff, subscribes again and waits for a fresh job.
+pub fn run_miner(options: &MinerCliOptions) -> Result<(), Box<dyn Error>> {
+    const HASHRATE_WINDOW: Duration = Duration::from_secs(60);
+    const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+    const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+    let mut hashrate_meter = HashrateMeter::new(HASHRATE_WINDOW, Instant::now());
+    let mut reconnect_delay = MIN_RECONNECT_DELAY;
+    loop {
+        let error = match PeerConnection::connect(options.server.clone(), options.enable_logging) {
+            Ok(mut connection) => {
+                reconnect_delay = MIN_RECONNECT_DELAY;
+                match mine(&mut connection, options, &mut hashrate_meter) {
+                    Err(SessionError::ConnectionLost(e)) => e,
+                    Err(SessionError::Rejected(e)) => return Err(e.into()),
+                }
+            }
+            Err(e) => e,
+        };
+        error!(target: "miner",
+            "Error while mining for the server: {}: {}",
+            options.server, error
+        );
+        info!(target: "miner", "Reconnecting in: {:?}", reconnect_delay);
+        thread::sleep(reconnect_delay);
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+fn mine(
+    connection: &mut PeerConnection,
+    options: &MinerCliOptions,
+    hashrate_meter: &mut HashrateMeter,
+) -> Result<Infallible, SessionError> {
+    const NONCE_BATCH_SIZE: u32 = 1_000_000;
+    const HASHRATE_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+    connection.send(&PeerMessage::MiningSubscribe(
+        options.payout_address.clone(),
+    ))?;
+
+    // Jobs of the previous connection are unknown to the server, so the miner waits for
+    // the server to send a fresh one.
+    let mut work: Option<Work> = None;
+    // The miner keeps processing messages while paused, so it promptly switches to new jobs.
+    let mut paused_until = Instant::now();
+    let mut last_hashrate_report = Instant::now();
+    loop {
+        if last_hashrate_report.elapsed() >= HASHRATE_REPORT_INTERVAL {
+            let hashrate = hashrate_meter.hashrate(Instant::now());
+            info!(target: "miner", "Hash rate: {} H/s", hashrate);
+            connection.send(&PeerMessage::MiningReportHashrate(hashrate))?;
+            last_hashrate_report = Instant::now();
+        }
+
+        for message in connection.receive_all()? {
+            match message {
+                PeerMessage::ResponseMiningSubscribe(result) => {
+                    let extranonce = result.map_err(|e| {
+                        SessionError::Rejected(format!("Failed to subscribe: {}", e))
+                    })?;
+                    info!(target: "miner", "Subscribed with extranonce: {}", extranonce);
+                }
+                PeerMessage::MiningNotify(job) => {
+                    work = job.map(|job| Work {
+                        timestamp: job.timestamp(),
+                        job,
+                        next_nonce: 0,
+                    });
+                }
+                PeerMessage::ResponseMiningSubmit(result) => match result {
+                    SubmitResult::ShareAccepted => {
+                        info!(target: "miner", "Share has been accepted.")
+                    }
+                    SubmitResult::Accepted => info!(target: "miner", "Block has been accepted."),
+                    SubmitResult::Duplicate => {
+                        warn!(target: "miner", "Share has already been submitted.")
+                    }
+                    SubmitResult::Stale => warn!(target: "miner", "Share is stale."),
+                    SubmitResult::Invalid(e) => {
+                        warn!(target: "miner", "Share has been rejected: {}", e)
+                    }
+                },
+                unexpected => warn!(target: "miner", "Unexpected message: {:?}", unexpected),
+            }
+        }
+
+        let now = Instant::now();
+        let current = match work.as_mut() {
+            Some(current) if now >= paused_until => current,
+            _ => {
+                let pause = paused_until.saturating_duration_since(now);
+                thread::sleep(pause.clamp(Duration::from_millis(1), Duration::from_millis(100)));
+                continue;
+            }
+        };
+        let start_nonce = current.next_nonce;
+        let batch_size = options
+            .throttle
+            .batch_size(NONCE_BATCH_SIZE)
+            .min(u32::MAX - start_nonce);
+        // Blocks also satisfy the share difficulty target, which is the same as the block's one
+        // unless the daemon runs a mining pool.
+        let nonce = Miner::pow_share_batch(
+            current.job.previous_block_hash(),
+            current.job.merkle_root(),
+            current.timestamp,
+            current.job.difficulty_target(),
+            current.job.share_difficulty_target(),
+            start_nonce,
+            batch_size,
+        );
+        let hashes = match nonce {
+            Some(nonce) => nonce - start_nonce,
+            None => batch_size,
+        } as u64
+            + 1;
+        hashrate_meter.record(hashes, Instant::now());
+        paused_until = Instant::now() + options.throttle.pause(hashes, now.elapsed());
+        if let Some(nonce) = nonce {
+            let share = MiningShare::new(current.job.job_id(), current.timestamp, nonce);
+            info!(target: "miner", "Found nonce: {} for job: {}", nonce, current.job.job_id());
+            connection.send(&PeerMessage::MiningSubmit(share))?;
+            let is_block = Miner::pow_batch(
+                current.job.previous_block_hash(),
+                current.job.merkle_root(),
+                current.timestamp,
+                current.job.difficulty_target(),
+                nonce,
+                0,
+            )
+            .is_some();
+            if is_block {
+                // Wait for the next job, which builds on the new block.
+                work = None;
+                continue;
+            }
+        }
+        // Keep searching until the next job arrives, e.g. the one building on the new block.
+        let next_nonce = match nonce {
+            Some(nonce) => nonce.checked_add(1),
+            None => start_nonce
+                .checked_add(batch_size)
+                .and_then(|n| n.checked_add(1)),
+        };
+        match next_nonce {
+            Some(next_nonce) => current.next_nonce = next_nonce,
+            None => {
+                // All nonces have been tried, so search again with a newer timestamp.
+                current.timestamp = Miner::current_time().max(current.timestamp + 1);
+                current.next_nonce = 0;
+            }
+        }
+    }
+}
+
+pub fn run_miner_bench(options: &MinerBenchCliOptions) -> Result<(), Box<dyn Error>> {
+    const DIFFICULTY_TARGETS: [u32; 4] = [8, 16, 24, 32];
+    for difficulty_target in DIFFICULTY_TARGETS {
+        let hashrate = Miner::benchmark(difficulty_target, 1, options.duration);
+        println!(
+            "Difficulty: {}, threads: 1, hash rate: {} H/s",
+            difficulty_target, hashrate
+        );
+    }
+
+    // Threads share the CPU caches and the memory bandwidth, so the hash rate per thread
+    // drops as the number of threads grows.
+    let difficulty_target = *DIFFICULTY_TARGETS.last().unwrap();
+    let mut results = vec![];
+    let mut num_threads = 1;
+    loop {
+        let hashrate = Miner::benchmark(difficulty_target, num_threads, options.duration);
+        println!(
+            "Difficulty: {}, threads: {}, hash rate: {} H/s per thread, {} H/s in total",
+            difficulty_target,
+            num_threads,
+            hashrate,
+            hashrate * num_threads as u64
+        );
+        results.push((num_threads, hashrate * num_threads as u64));
+        if num_threads == options.max_threads {
+            break;
+        }
+        num_threads = (num_threads * 2).min(options.max_threads);
+    }
+
+    // The fewest threads that get within 5% of the best total hash rate.
+    let best = results.iter().map(|(_, total)| *total).max().unwrap();
+    let (suggested_threads, _) = results
+        .iter()
+        .find(|(_, total)| *total as f64 >= best as f64 * 0.95)
+        .unwrap();
+    println!(
+        "Suggested number of mining threads: {}. Each miner process mines on a single thread.",
+        suggested_threads
+    );
+    Ok(())
+}
@@ -0,0 +1,274 @@
+use crate::core::rpc_server::COOKIE_FILE;
+use crate::core::CoolcoinClient;
+use clap::{App, Arg, ArgMatches};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::error::Error;
+use std::fs::{self, File};
+use std::net::TcpStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Time to wait for the node to start accepting connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct RegtestCliOptions {
+    num_nodes: u16,
+    base_port: u16,
+    data_dir: PathBuf,
+    miners: bool,
+    rpc: bool,
+    enable_logging: bool,
+}
+
+impl RegtestCliOptions {
+    pub fn parse(matches: &ArgMatches) -> Result<Self, Box<dyn Error>> {
+        let num_nodes = matches.value_of_t::<u16>("nodes")?;
+        if num_nodes == 0 {
+            return Err("At least one node is required.".into());
+        }
+        Ok(Self {
+            num_nodes,
+            base_port: matches.value_of_t("base_port")?,
+            data_dir: PathBuf::from(matches.value_of("data_dir").unwrap()),
+            miners: matches.is_present("miners"),
+            rpc: matches.is_present("rpc"),
+            enable_logging: matches.is_present("enable_logging"),
+        })
+    }
+}
+
+pub fn regtest_command() -> App<'static> {
+    App::new("regtest")
+        .version("0.1")
+        .about("Runs the network of local daemons connected in a ring, each in its own process, until interrupted with Ctrl-C.")
+        .arg(
+            Arg::new("nodes")
+                .long("nodes")
+                .value_name("COUNT")
+                .about("Number of daemons.")
+                .takes_value(true)
+                .required(false)
+                .default_value("3"),
+        )
+        .arg(
+            Arg::new("base_port")
+                .long("base_port")
+                .value_name("PORT")
+                .about("Port of the first daemon's server. The daemon i listens on base_port + i, its mining server on base_port + 100 + i and its JSON-RPC server on base_port + 200 + i.")
+                .takes_value(true)
+                .required(false)
+                .default_value("18444"),
+        )
+        .arg(
+            Arg::new("data_dir")
+                .long("data_dir")
+                .value_name("PATH")
+                .about("Directory with the data directories of the daemons, which also contain their logs.")
+                .takes_value(true)
+                .required(false)
+                .default_value("./regtest_data"),
+        )
+        .arg(
+            Arg::new("miners")
+                .long("miners")
+                .about("If true, each daemon runs the mining server with an external miner connected to it.")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::new("rpc")
+                .long("rpc")
+                .about("If true, each daemon serves JSON-RPC requests over HTTP.")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::new("enable_logging")
+                .long("enable_logging")
+                .about("If true, the daemons log the messages sent and received via the network.")
+                .takes_value(false)
+                .required(false),
+        )
+}
+
+/// A process started by the regtest command.
+struct Process {
+    name: String,
+    child: Child,
+}
+
+pub fn run_regtest(options: &RegtestCliOptions) -> Result<(), Box<dyn Error>> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&interrupted))?;
+    }
+    let mut processes = vec![];
+    let result = start_network(options, &mut processes).and_then(|_| {
+        println!("Regtest network is running. Press Ctrl-C to stop it.");
+        wait_for_exit(&mut processes, &interrupted)
+    });
+    println!("Stopping the regtest network.");
+    stop_network(options, &mut processes);
+    result
+}
+
+fn server(options: &RegtestCliOptions, node: u16) -> String {
+    format!("127.0.0.1:{}", options.base_port + node)
+}
+
+fn mining_server(options: &RegtestCliOptions, node: u16) -> String {
+    format!("127.0.0.1:{}", options.base_port + 100 + node)
+}
+
+fn rpc_server(options: &RegtestCliOptions, node: u16) -> String {
+    format!("127.0.0.1:{}", options.base_port + 200 + node)
+}
+
+/// Starts the daemons one by one, each connecting to the previous one, and the last one to
+/// the first one as well, which closes the ring.
+fn start_network(
+    options: &RegtestCliOptions,
+    processes: &mut Vec<Process>,
+) -> Result<(), Box<dyn Error>> {
+    for node in 0..options.num_nodes {
+        let data_dir = options.data_dir.join(format!("node{}", node));
+        let mut args = vec![
+            "daemon".to_string(),
+            "--network=regtest".to_string(),
+            format!("--server={}", server(options, node)),
+            format!("--coinbase_address=node{}", node),
+            format!("--data_dir={}", data_dir.display()),
+        ];
+        let mut peers = vec![];
+        if node > 0 {
+            peers.push(server(options, node - 1));
+        }
+        if node > 1 && node == options.num_nodes - 1 {
+            peers.push(server(options, 0));
+        }
+        if !peers.is_empty() {
+            args.push(format!("--peers={}", peers.join(",")));
+        }
+        if options.miners {
+            args.push(format!("--mining_server={}", mining_server(options, node)));
+        }
+        if options.rpc {
+            args.push(format!("--rpc_server={}", rpc_server(options, node)));
+        }
+        if options.enable_logging {
+            args.push("--enable_logging".to_string());
+        }
+        let name = format!("node{}", node);
+        processes.push(spawn(&name, &args, &data_dir.join("debug.log"))?);
+        wait_until_listening(&server(options, node))?;
+        println!(
+            "Started {} at: {}, data dir: {}",
+            name,
+            server(options, node),
+            data_dir.display()
+        );
+        if options.rpc {
+            println!(
+                "    JSON-RPC at: http://{}/, credentials in: {}",
+                rpc_server(options, node),
+                data_dir.join(COOKIE_FILE).display()
+            );
+        }
+
+        if options.miners {
+            wait_until_listening(&mining_server(options, node))?;
+            let args = vec![
+                "miner".to_string(),
+                format!("--server={}", mining_server(options, node)),
+            ];
+            let name = format!("miner{}", node);
+            processes.push(spawn(&name, &args, &data_dir.join("miner.log"))?);
+            println!(
+                "Started {} mining for: {}",
+                name,
+                mining_server(options, node)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn spawn(name: &str, args: &[String], log_path: &Path) -> Result<Process, Box<dyn Error>> {
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let log = File::create(log_path)?;
+    let child = Command::new(std::env::current_exe()?)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // Ctrl-C is only delivered to the regtest command, which stops the daemons gracefully.
+        .process_group(0)
+        .spawn()
+        .map_err(|e| format!("Failed to start {}: {}", name, e))?;
+    Ok(Process {
+        name: name.to_string(),
+        child,
+    })
+}
+
+fn wait_until_listening(address: &str) -> Result<(), String> {
+    let started = Instant::now();
+    while started.elapsed() < STARTUP_TIMEOUT {
+        if TcpStream::connect(address).is_ok() {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Err(format!(
+        "Server at: {} hasn't started in {} seconds, see its log.",
+        address,
+        STARTUP_TIMEOUT.as_secs()
+    ))
+}
+
+/// Returns when the regtest command is interrupted, or an error when any of the processes exits.
+fn wait_for_exit(
+    processes: &mut [Process],
+    interrupted: &AtomicBool,
+) -> Result<(), Box<dyn Error>> {
+    while !interrupted.load(Ordering::Relaxed) {
+        for process in processes.iter_mut() {
+            if let Some(status) = process.child.try_wait()? {
+                return Err(
+                    format!("{} has exited with: {}, see its log.", process.name, status).into(),
+                );
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// Asks the daemons to stop, which saves their state, and kills the processes that remain.
+fn stop_network(options: &RegtestCliOptions, processes: &mut [Process]) {
+    for node in 0..options.num_nodes {
+        let mut client = CoolcoinClient::new(server(options, node), Duration::from_secs(1), false);
+        // The daemon may not be running anymore.
+        let _ = client.stop();
+    }
+    let started = Instant::now();
+    for process in processes.iter_mut() {
+        while started.elapsed() < STARTUP_TIMEOUT {
+            match process.child.try_wait() {
+                Ok(None) => thread::sleep(Duration::from_millis(100)),
+                _ => break,
+            }
+        }
+        if let Ok(None) = process.child.try_wait() {
+            let _ = process.child.kill();
+            let _ = process.child.wait();
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::core::chain_params::ChainParams;
+use crate::core::message_capture;
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct ReplayCliOptions {
+    capture_file: PathBuf,
+    chain_params: ChainParams,
+}
+
+impl ReplayCliOptions {
+    pub fn parse(matches: &ArgMatches) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            capture_file: PathBuf::from(matches.value_of("CAPTURE_FILE").unwrap()),
+            chain_params: match matches.value_of("chain_params") {
+                Some(path) => ChainParams::load(Path::new(path))?,
+                None => ChainParams::by_name(matches.value_of("network").unwrap())?,
+            },
+        })
+    }
+}
+
+pub fn replay_command() -> App<'static> {
+    App::new("replay")
+        .version("0.1")
+        .about("Feeds the messages recorded by the daemon with --capture_file to a fresh node, and prints the node's state after it has processed them.")
+        .arg(Arg::new("CAPTURE_FILE").required(true).index(1))
+        .arg(
+            Arg::new("chain_params")
+                .long("chain_params")
+                .value_name("PATH")
+                .about("File with the parameters of the network on which the messages have been captured. The coolcoin network by default.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("network")
+                .long("network")
+                .value_name("NAME")
+                .about("The network on which the messages have been captured: main, or regtest.")
+                .takes_value(true)
+                .required(false)
+                .possible_values(&["main", "regtest"])
+                .default_value("main")
+                .conflicts_with("chain_params"),
+        )
+}
+
+pub fn run_replay(options: &ReplayCliOptions) -> Result<(), Box<dyn Error>> {
+    let messages = message_capture::load(&options.capture_file)?;
+    // The node starts without the data of the previous runs, and its data is thrown away.
+    let data_dir = std::env::temp_dir().join(format!("coolcoin_replay_{}", std::process::id()));
+    let report = message_capture::replay(&messages, options.chain_params.clone(), &data_dir);
+    let _ = fs::remove_dir_all(&data_dir);
+    println!("{}", serde_json::to_string_pretty(&report?)?);
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use coolcoin_lib::core::block::BlockHash;
+use coolcoin_lib::core::peer_connection::PeerMessage;
+use coolcoin_lib::core::{Block, CoolcoinClient, PeerConnection};
+use std::fs::{self, File};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long the nodes and the conditions are waited for before the test fails.
+const TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A node running in its own process, started from the coolcoin binary.
+struct Node {
+    process: Child,
+    server: String,
+    client: CoolcoinClient,
+    // Relays the blocks to the node as if they came from a peer.
+    relay: Option<PeerConnection>,
+    log_path: PathBuf,
+}
+
+/// The network of regtest nodes for the end-to-end tests, which run the same daemon as
+/// the users, and query it with the client over the network.
+///
+/// The nodes listen on random ports, and write their logs to their data directories.
+/// If the test fails, the logs are printed along with the test's output and the directory is
+/// kept, otherwise it's removed when the test ends.
+pub struct FunctionalTest {
+    data_dir: PathBuf,
+    nodes: Vec<Node>,
+}
+
+impl FunctionalTest {
+    /// Creates the test without nodes, with the data directory named after it.
+    pub fn new(name: &str) -> Self {
+        let data_dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+            .join("functional")
+            .join(name);
+        // Left behind by the previous run.
+        let _ = fs::remove_dir_all(&data_dir);
+        Self {
+            data_dir,
+            nodes: vec![],
+        }
+    }
+
+    /// Starts the node connected to the given nodes, waits until it responds to the requests,
+    /// and returns its index.
+    pub fn add_node(&mut self, peers: &[usize]) -> Result<usize, String> {
+        let index = self.nodes.len();
+        let server = format!("127.0.0.1:{}", free_port()?);
+        let data_dir = self.data_dir.join(format!("node{}", index));
+        fs::create_dir_all(&data_dir).map_err(|e| e.to_string())?;
+        let log_path = data_dir.join("debug.log");
+        let log = File::create(&log_path).map_err(|e| e.to_string())?;
+        let mut args = vec![
+            "daemon".to_string(),
+            "--network=regtest".to_string(),
+            format!("--server={}", server),
+            format!("--coinbase_address=node{}", index),
+            format!("--data_dir={}", data_dir.display()),
+            "--enable_logging".to_string(),
+        ];
+        if !peers.is_empty() {
+            let peers = peers
+                .iter()
+                .map(|peer| self.nodes[*peer].server.clone())
+                .collect::<Vec<_>>();
+            args.push(format!("--peers={}", peers.join(",")));
+        }
+        let process = Command::new(env!("CARGO_BIN_EXE_coolcoin"))
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone().map_err(|e| e.to_string())?)
+            .stderr(log)
+            .spawn()
+            .map_err(|e| format!("Failed to start node: {}: {}", index, e))?;
+        self.nodes.push(Node {
+            process,
+            client: CoolcoinClient::new(server.clone(), REQUEST_TIMEOUT, false),
+            server,
+            relay: None,
+            log_path,
+        });
+
+        let start = Instant::now();
+        while let Err(e) = self.client(index).get_block_count() {
+            if let Ok(Some(status)) = self.nodes[index].process.try_wait() {
+                return Err(format!("Node: {} has exited with: {}", index, status));
+            }
+            if start.elapsed() > TIMEOUT {
+                return Err(format!("Node: {} hasn't started: {}", index, e));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(index)
+    }
+
+    pub fn client(&mut self, index: usize) -> &mut CoolcoinClient {
+        &mut self.nodes[index].client
+    }
+
+    /// Sends the block to the node as if it has been mined by its peer.
+    pub fn relay_block(&mut self, index: usize, block: Block) -> Result<(), String> {
+        let node = &mut self.nodes[index];
+        if node.relay.is_none() {
+            node.relay = Some(PeerConnection::connect(node.server.clone(), false)?);
+        }
+        let relay = node.relay.as_mut().unwrap();
+        // The node relays the blocks back, which are dropped so that the buffers don't fill up.
+        relay.receive_all()?;
+        match relay.send(&PeerMessage::RelayBlock(Arc::new(block)))? {
+            true => Ok(()),
+            false => Err(format!("Failed to relay the block to node: {}", index)),
+        }
+    }
+
+    /// Waits until the condition, which queries the node with the client, holds.
+    pub fn wait_until(
+        &mut self,
+        index: usize,
+        mut condition: impl FnMut(&mut CoolcoinClient) -> Result<bool, String>,
+    ) -> Result<(), String> {
+        let start = Instant::now();
+        while !condition(self.client(index))? {
+            if start.elapsed() > TIMEOUT {
+                return Err(format!(
+                    "Node: {} has timed out waiting for the condition.",
+                    index
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    /// Waits until the block is the tip of each node's active blockchain.
+    pub fn wait_for_tip(&mut self, hash: &BlockHash) -> Result<(), String> {
+        for index in 0..self.nodes.len() {
+            self.wait_until(index, |client| Ok(client.get_best_block_hash()? == *hash))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FunctionalTest {
+    fn drop(&mut self) {
+        for node in &mut self.nodes {
+            // Killed if it doesn't stop by itself, e.g. because it's stuck.
+            let _ = node.client.stop();
+            let start = Instant::now();
+            while let Ok(None) = node.process.try_wait() {
+                if start.elapsed() > TIMEOUT {
+                    let _ = node.process.kill();
+                    let _ = node.process.wait();
+                    break;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        if thread::panicking() {
+            // Printed rather than written to stderr, so that the harness captures it with
+            // the output of the failed test.
+            for (index, node) in self.nodes.iter().enumerate() {
+                println!("===== Log of node: {} ({}) =====", index, node.server);
+                println!(
+                    "{}",
+                    fs::read_to_string(&node.log_path).unwrap_or_else(|e| e.to_string())
+                );
+            }
+            println!(
+                "The data of the nodes is kept in: {}",
+                self.data_dir.display()
+            );
+        } else {
+            let _ = fs::remove_dir_all(&self.data_dir);
+        }
+    }
+}
+
+// Returns the port that isn't in use, which is very unlikely to be taken by the time the node
+// binds to it.
+fn free_port() -> Result<u16, String> {
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    Ok(port)
+}
@@ -0,0 +1,6 @@
+//! End-to-end tests, which run the nodes in their own processes and drive them with the client.
+//! Run them with `cargo test --test functional`.
+
+mod framework;
+mod sync;
+mod transactions;
@@ -0,0 +1,86 @@
+use crate::framework::FunctionalTest;
+use coolcoin_lib::core::chain_builder::ChainBuilder;
+use coolcoin_lib::core::chain_params::ChainParams;
+use coolcoin_lib::core::hash::hash;
+use coolcoin_lib::core::transaction::{
+    OutputIndex, TransactionId, TransactionInput, TransactionOutput,
+};
+use coolcoin_lib::core::{Address, Transaction};
+
+#[test]
+fn relays_blocks_and_reorganizes() {
+    let mut test = FunctionalTest::new("relays_blocks_and_reorganizes");
+    // A line of nodes, so the blocks are relayed through the node in the middle.
+    test.add_node(&[]).unwrap();
+    test.add_node(&[0]).unwrap();
+    test.add_node(&[1]).unwrap();
+
+    let mut chain = ChainBuilder::new(ChainParams::regtest());
+    for block in chain.mine_blocks(2) {
+        test.relay_block(0, block).unwrap();
+    }
+    test.wait_for_tip(chain.tip().id()).unwrap();
+
+    // A longer fork from the other end of the line replaces the active blockchain.
+    let mut fork = chain.fork_at(0);
+    for block in fork.mine_blocks(3) {
+        test.relay_block(2, block).unwrap();
+    }
+    test.wait_for_tip(fork.tip().id()).unwrap();
+    assert_eq!(test.client(0).get_block_count(), Ok(3));
+    assert_eq!(test.client(0).get_chain_tips().unwrap().len(), 2);
+}
+
+#[test]
+fn synchronizes_node_started_later() {
+    let mut test = FunctionalTest::new("synchronizes_node_started_later");
+    test.add_node(&[]).unwrap();
+    let mut chain = ChainBuilder::new(ChainParams::regtest());
+    for block in chain.mine_blocks(5) {
+        test.relay_block(0, block).unwrap();
+    }
+    test.wait_for_tip(chain.tip().id()).unwrap();
+
+    test.add_node(&[0]).unwrap();
+    test.wait_for_tip(chain.tip().id()).unwrap();
+    assert_eq!(
+        test.client(1).get_block_hash(1),
+        Ok(Some(*chain.block(1).id()))
+    );
+}
+
+#[test]
+fn keeps_active_blockchain_when_fork_spends_missing_output() {
+    let mut test = FunctionalTest::new("keeps_active_blockchain_when_fork_spends_missing_output");
+    test.add_node(&[]).unwrap();
+    let mut chain = ChainBuilder::new(ChainParams::regtest());
+    for block in chain.mine_blocks(2) {
+        test.relay_block(0, block).unwrap();
+    }
+    test.wait_for_tip(chain.tip().id()).unwrap();
+
+    // The longer fork's second block spends an output that doesn't exist.
+    let mut fork = chain.fork_at(0);
+    let missing = TransactionId::new(hash(b"missing"));
+    let mut blocks = vec![fork.mine()];
+    fork.add_transaction(
+        Transaction::new(
+            vec![TransactionInput::new(missing, OutputIndex::new(0))],
+            vec![TransactionOutput::new(
+                Address::new("alice".to_string()),
+                1.into(),
+            )],
+            0,
+        )
+        .unwrap(),
+    );
+    blocks.extend(fork.mine_blocks(2));
+    for block in blocks {
+        test.relay_block(0, block).unwrap();
+    }
+    // The node goes back to its active blockchain, which it keeps extending.
+    let block = chain.mine();
+    test.relay_block(0, block).unwrap();
+    test.wait_for_tip(chain.tip().id()).unwrap();
+    assert_eq!(test.client(0).get_block_count(), Ok(3));
+}
@@ -0,0 +1,45 @@
+use crate::framework::FunctionalTest;
+use coolcoin_lib::core::chain_builder::ChainBuilder;
+use coolcoin_lib::core::chain_params::ChainParams;
+use coolcoin_lib::core::{Address, Coolcoin};
+
+#[test]
+fn propagates_and_mines_transactions() {
+    let mut test = FunctionalTest::new("propagates_and_mines_transactions");
+    test.add_node(&[]).unwrap();
+    test.add_node(&[0]).unwrap();
+
+    let alice = Address::new("alice".to_string());
+    let bob = Address::new("bob".to_string());
+    let mut chain = ChainBuilder::new(ChainParams::regtest());
+    chain.fund(&alice);
+    for block in chain.blocks().to_vec() {
+        test.relay_block(0, block).unwrap();
+    }
+    test.wait_for_tip(chain.tip().id()).unwrap();
+
+    // Sent to one node, the transaction reaches the other one, and the nodes mine it as soon
+    // as it's in their pools.
+    let spend = chain
+        .spend(&alice, &bob, 30.into(), Coolcoin::zero())
+        .unwrap();
+    assert_eq!(
+        test.client(1).send_transaction(spend.clone()),
+        Ok(*spend.id())
+    );
+    for index in 0..2 {
+        test.wait_until(index, |client| {
+            Ok(client.get_block_count()? > chain.height()
+                && client
+                    .get_transaction(*spend.id())?
+                    .is_some_and(|info| info.confirmations() > 0))
+        })
+        .unwrap();
+        let outputs = test
+            .client(index)
+            .list_unspent(1, u32::MAX, vec![bob.clone()])
+            .unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].amount(), 30.into());
+    }
+}